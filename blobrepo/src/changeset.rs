@@ -9,6 +9,7 @@ use std::collections::BTreeMap;
 
 use futures::future::{BoxFuture, Future, IntoFuture};
 use bincode;
+use bytes::Bytes;
 
 use blobstore::Blobstore;
 
@@ -53,18 +54,13 @@ impl BlobChangeset {
         B::ValueOut: AsRef<[u8]>,
     {
         let nodeid = *nodeid;
-        let key = cskey(&nodeid);
 
-        blobstore
-            .get(&key)
-            .map_err(blobstore_err)
+        load_raw_blob(blobstore, &nodeid)
             .and_then(move |got| match got {
                 None => Ok(None),
-                Some(blob) => {
-                    let RawCSBlob { parents, blob } = bincode::deserialize(blob.as_ref())?;
+                Some((parents, blob)) => {
                     let (p1, p2) = parents.get_nodes();
-                    let blob = Blob::from(blob.into_owned());
-                    let node = BlobNode::new(blob, p1, p2);
+                    let node = BlobNode::new(Blob::from(blob), p1, p2);
                     let cs = BlobChangeset {
                         nodeid: nodeid,
                         revlogcs: RevlogChangeset::new(node)?,
@@ -75,6 +71,18 @@ impl BlobChangeset {
             .boxed()
     }
 
+    /// Fetch the serialized changeset exactly as it's stored, without parsing it. Along with
+    /// the parents, these are the bytes the changeset's nodeid is computed from.
+    pub fn load_raw<B>(blobstore: &B, nodeid: &NodeHash) -> BoxFuture<Option<Bytes>, Error>
+    where
+        B: Blobstore<Key = String>,
+        B::ValueOut: AsRef<[u8]>,
+    {
+        load_raw_blob(blobstore, nodeid)
+            .map(|got| got.map(|(_, blob)| Bytes::from(blob)))
+            .boxed()
+    }
+
     pub fn save<B>(&self, blobstore: B) -> BoxFuture<(), Error>
     where
         B: Blobstore<Key = String> + Send + 'static,
@@ -104,6 +112,28 @@ impl BlobChangeset {
     }
 }
 
+/// Fetch and unpack the envelope stored for changeset `nodeid`, if there is one.
+fn load_raw_blob<B>(
+    blobstore: &B,
+    nodeid: &NodeHash,
+) -> BoxFuture<Option<(Parents, Vec<u8>)>, Error>
+where
+    B: Blobstore<Key = String>,
+    B::ValueOut: AsRef<[u8]>,
+{
+    blobstore
+        .get(&cskey(nodeid))
+        .map_err(blobstore_err)
+        .and_then(|got| match got {
+            None => Ok(None),
+            Some(blob) => {
+                let RawCSBlob { parents, blob } = bincode::deserialize(blob.as_ref())?;
+                Ok(Some((parents, blob.into_owned())))
+            }
+        })
+        .boxed()
+}
+
 impl Changeset for BlobChangeset {
     fn manifestid(&self) -> &NodeHash {
         self.revlogcs.manifestid()
@@ -34,15 +34,40 @@ error_chain! {
             description("Missing Node")
             display("Node id {} is missing", nodeid)
         }
-        ContentMissing(nodeid: NodeHash, sha1: hash::Sha1) {
+        ContentMissing(nodeid: NodeHash, sha1: hash::Sha1, key: String) {
             description("Missing Content")
-            display("Content missing nodeid {} sha1 {}", nodeid, sha1)
+            display("Content missing nodeid {} sha1 {} (key {:?})", nodeid, sha1, key)
+        }
+        SymlinkLoop(nodeid: NodeHash) {
+            description("Symlink loop")
+            display("Symlink loop resolving nodeid {}", nodeid)
+        }
+        InvalidSymlinkTarget(nodeid: NodeHash, target: Vec<u8>) {
+            description("Invalid symlink target")
+            display(
+                "Invalid symlink target {:?} for nodeid {}",
+                String::from_utf8_lossy(target),
+                nodeid
+            )
+        }
+        NotAFile(nodeid: NodeHash) {
+            description("Not a file")
+            display("Node id {} is a tree, not a file", nodeid)
+        }
+        ContentHashMismatch(expected: hash::Sha1, actual: hash::Sha1) {
+            description("Content hash mismatch")
+            display("Content hash mismatch: expected sha1 {} actual {}", expected, actual)
+        }
+        UnknownNodeBlobVersion(version: u8) {
+            description("Unknown node blob version")
+            display("Unknown node blob version {}", version)
         }
     }
 
     links {
         Mercurial(::mercurial::Error, ::mercurial::ErrorKind);
         MercurialTypes(::mercurial_types::Error, ::mercurial_types::ErrorKind);
+        Zstd(::zstdblob::Error, ::zstdblob::ErrorKind);
     }
 
     foreign_links {
@@ -6,14 +6,22 @@
 
 //! Plain files, symlinks
 
-use futures::future::{BoxFuture, Future, IntoFuture};
+use std::cmp;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use futures::future::{self, BoxFuture, Future, IntoFuture, Loop};
+use futures::stream::{self, BoxStream, Stream};
 
 use bincode;
+use bytes::Bytes;
 
 use mercurial_types::{Blob, NodeHash, Parents, Path, hash};
+use mercurial_types::lfs::{self, LfsPointer};
 use mercurial_types::manifest::{Content, Entry, Manifest, Type};
 
 use blobstore::Blobstore;
+use zstdblob;
 
 use errors::*;
 
@@ -26,59 +34,489 @@ pub struct BlobEntry<B> {
     ty: Type,
 }
 
+/// A file node: its parents, the hash of its content blob, and (unless it was imported before
+/// sizes were recorded) the size of that content.
+///
+/// Node blobs are stored under `node:<nodeid>.bincode` in one of these layouts:
+///
+/// - Version 0 (unversioned): the bincode encoding of `LegacyNodeBlob`, ie `parents` then
+///   `blob`. Blobs written before versioning was introduced may also have a bincode `u64` size
+///   appended.
+/// - Version `N >= 1`: the byte `NODE_BLOB_MARKER`, the version byte `N`, then the bincode
+///   encoding of that version's payload. Version 1's payload is `NodeBlobV1`, and version 2's
+///   is `NodeBlobV2`, whose size is optional so that nodes read from version 0 blobs without
+///   one can be written back unchanged.
+///
+/// An unversioned blob starts with the bincode `u32` tag of `Parents`, which is always less
+/// than `NODE_BLOB_MARKER`, so the two can't be confused.
 #[derive(Debug, Copy, Clone)]
-#[derive(Serialize, Deserialize)]
 pub struct RawNodeBlob {
     parents: Parents,
     blob: hash::Sha1,
+    size: Option<u64>,
+}
+
+/// First byte of a versioned node blob.
+const NODE_BLOB_MARKER: u8 = 0xff;
+
+/// Version written by `RawNodeBlob::serialize`.
+const NODE_BLOB_VERSION: u8 = 2;
+
+/// Version 1 node blob payload.
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+struct NodeBlobV1 {
+    parents: Parents,
+    blob: hash::Sha1,
+    size: u64,
+}
+
+/// Version 2 node blob payload.
+#[derive(Serialize, Deserialize)]
+struct NodeBlobV2 {
+    parents: Parents,
+    blob: hash::Sha1,
+    size: Option<u64>,
+}
+
+/// Version 0 node blob, from before the size was recorded.
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+struct LegacyNodeBlob {
+    parents: Parents,
+    blob: hash::Sha1,
+}
+
+impl RawNodeBlob {
+    pub fn new(parents: Parents, blob: hash::Sha1, size: u64) -> Self {
+        RawNodeBlob {
+            parents,
+            blob,
+            size: Some(size),
+        }
+    }
+
+    pub fn parents(&self) -> &Parents {
+        &self.parents
+    }
+
+    pub fn blob(&self) -> &hash::Sha1 {
+        &self.blob
+    }
+
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    /// Serialize in the current versioned layout.
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let payload = NodeBlobV2 {
+            parents: self.parents,
+            blob: self.blob,
+            size: self.size,
+        };
+
+        let mut data = vec![NODE_BLOB_MARKER, NODE_BLOB_VERSION];
+        data.extend(bincode::serialize(&payload, bincode::Infinite)?);
+        Ok(data)
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self> {
+        if data.first() != Some(&NODE_BLOB_MARKER) {
+            return Self::deserialize_v0(data);
+        }
+
+        match data.get(1) {
+            Some(&1) => {
+                let node: NodeBlobV1 = bincode::deserialize(&data[2..])?;
+                Ok(RawNodeBlob {
+                    parents: node.parents,
+                    blob: node.blob,
+                    size: Some(node.size),
+                })
+            }
+            Some(&2) => {
+                let node: NodeBlobV2 = bincode::deserialize(&data[2..])?;
+                Ok(RawNodeBlob {
+                    parents: node.parents,
+                    blob: node.blob,
+                    size: node.size,
+                })
+            }
+            Some(&version) => bail!(ErrorKind::UnknownNodeBlobVersion(version)),
+            None => bail!("truncated node blob header"),
+        }
+    }
+
+    fn deserialize_v0(data: &[u8]) -> Result<Self> {
+        // The sized variant is a strict extension of the original one, so trying it first is
+        // unambiguous.
+        match bincode::deserialize::<NodeBlobV1>(data) {
+            Ok(node) => Ok(RawNodeBlob {
+                parents: node.parents,
+                blob: node.blob,
+                size: Some(node.size),
+            }),
+            Err(_) => {
+                let node: LegacyNodeBlob = bincode::deserialize(data)?;
+                Ok(RawNodeBlob {
+                    parents: node.parents,
+                    blob: node.blob,
+                    size: None,
+                })
+            }
+        }
+    }
+}
+
+fn node_key(nodeid: NodeHash) -> String {
+    format!("node:{}.bincode", nodeid)
 }
 
 fn get_node<B>(blobstore: &B,  nodeid: NodeHash) -> BoxFuture<RawNodeBlob, Error>
 where B: Blobstore<Key = String>,
       B::ValueOut: AsRef<[u8]>,
 {
-    let key = format!("node:{}.bincode", nodeid);
+    let key = node_key(nodeid);
 
     blobstore
         .get(&key)
         .map_err(blobstore_err)
         .and_then(move |got| got.ok_or(ErrorKind::NodeMissing(nodeid).into()))
+        .and_then(move |blob| RawNodeBlob::deserialize(blob.as_ref()))
+        .boxed()
+}
+
+/// Check whether file node `nodeid` exists, without deserializing it. Blobstore errors are
+/// returned as errors rather than being treated as absence.
+pub fn file_node_exists<B>(blobstore: &B, nodeid: NodeHash) -> BoxFuture<bool, Error>
+where
+    B: Blobstore<Key = String>,
+{
+    blobstore
+        .get(&node_key(nodeid))
+        .map(|got| got.is_some())
+        .map_err(blobstore_err)
+        .boxed()
+}
+
+/// Follow the symlink with file node `start` in manifest `manifestid` until it reaches something
+/// which isn't a symlink, and return that path. If `start` isn't a symlink, its own path is
+/// returned.
+///
+/// Targets are resolved relative to the directory containing the link. `None` is returned if
+/// a target doesn't exist or points outside the repo. If more than `max_hops` links have to be
+/// followed, or a link is reached a second time, the result is `ErrorKind::SymlinkLoop`.
+pub fn resolve_symlink<B>(
+    blobstore: B,
+    manifestid: NodeHash,
+    start: NodeHash,
+    max_hops: usize,
+) -> BoxFuture<Option<Path>, Error>
+where
+    B: Blobstore<Key = String> + Clone + Sync,
+    B::ValueOut: AsRef<[u8]>,
+{
+    BlobManifest::load(&blobstore, &manifestid)
+        .and_then(move |mf| mf.ok_or(ErrorKind::ManifestMissing(manifestid).into()))
+        .and_then(move |mf| {
+            let (path, details) = match mf.find_node(&start) {
+                Some((path, details)) => (path.clone(), *details),
+                None => bail!(ErrorKind::NodeMissing(start)),
+            };
+            Ok((mf, path, details))
+        })
+        .and_then(move |(mf, path, details)| {
+            let mf = Arc::new(mf);
+            let mut seen = HashSet::new();
+            seen.insert(path.clone());
+
+            future::loop_fn((path, details, seen), move |(path, details, mut seen)| {
+                if !details.is_symlink() {
+                    return Ok(Loop::Break(Some(path))).into_future().boxed();
+                }
+                if seen.len() > max_hops {
+                    return Err(ErrorKind::SymlinkLoop(start).into()).into_future().boxed();
+                }
+
+                let next = get_content_blob(blobstore.clone(), *details.nodeid()).map({
+                    let mf = mf.clone();
+                    move |target| {
+                        symlink_target(&path, target.as_ref())
+                            .and_then(|path| mf.get_details(&path).map(|d| (path, *d)))
+                    }
+                });
+                next.and_then(move |next| match next {
+                    None => Ok(Loop::Break(None)),
+                    Some((path, details)) => {
+                        if !seen.insert(path.clone()) {
+                            bail!(ErrorKind::SymlinkLoop(start));
+                        }
+                        Ok(Loop::Continue((path, details, seen)))
+                    }
+                }).boxed()
+            })
+        })
+        .boxed()
+}
+
+/// Resolve symlink `target` relative to the directory containing `link`. Returns `None` for
+/// targets outside the repo.
+fn symlink_target(link: &Path, target: &[u8]) -> Option<Path> {
+    if target.starts_with(b"/") {
+        return None;
+    }
+
+    let link = link.to_vec();
+    let mut components: Vec<&[u8]> = link.split(|c| *c == b'/').collect();
+    components.pop();
+
+    for component in target.split(|c| *c == b'/') {
+        match component {
+            b"" | b"." => (),
+            b".." => if components.pop().is_none() {
+                return None;
+            },
+            component => components.push(component),
+        }
+    }
+
+    if components.is_empty() {
+        None
+    } else {
+        Path::new(components.join(&b'/')).ok()
+    }
+}
+
+/// Maximum size of each chunk yielded by the content streams.
+pub const CONTENT_CHUNK_SIZE: usize = 64 * 1024;
+
+fn get_content_blob<B>(blobstore: B, nodeid: NodeHash) -> BoxFuture<B::ValueOut, Error>
+where
+    B: Blobstore<Key = String> + Clone,
+    B::ValueOut: AsRef<[u8]>,
+{
+    get_node(&blobstore, nodeid)
+        .and_then(move |node| get_content_blob_for_node(blobstore, nodeid, node))
+        .boxed()
+}
+
+fn get_content_blob_for_node<B>(
+    blobstore: B,
+    nodeid: NodeHash,
+    node: RawNodeBlob,
+) -> BoxFuture<B::ValueOut, Error>
+where
+    B: Blobstore<Key = String>,
+{
+    let key = format!("sha1:{}", node.blob);
+
+    blobstore
+        .get(&key)
+        .map_err(blobstore_err)
         .and_then(move |blob| {
-            bincode::deserialize(blob.as_ref()).into_future().from_err()
+            blob.ok_or(ErrorKind::ContentMissing(nodeid, node.blob, key).into())
         })
         .boxed()
 }
 
+/// Whether to return file content as it's stored, which may be zstd-compressed in the format
+/// written by `zstdblob::CompressedBlobstore`, or as plain text.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FetchMode {
+    /// Content exactly as stored, for relaying to clients which can decompress it themselves.
+    Raw,
+    /// Plain text content, decompressed if it was stored compressed.
+    Decompressed,
+}
+
+impl Default for FetchMode {
+    fn default() -> Self {
+        FetchMode::Decompressed
+    }
+}
+
+/// Fetch a file's content in the given `mode`.
+///
+/// Decompression is done here, so `blobstore` should return blobs as stored rather than being
+/// a `CompressedBlobstore`, or `Raw` will return plain text anyway. Content which was stored
+/// uncompressed is the same in either mode.
 pub fn fetch_file_blob_from_blobstore<B>(
     blobstore: B,
     nodeid: NodeHash,
+    mode: FetchMode,
+) -> BoxFuture<Vec<u8>, Error>
+where
+    B: Blobstore<Key = String> + Clone,
+    B::ValueOut: AsRef<[u8]>,
+{
+    fetch_file_blob(blobstore, nodeid, false, mode)
+}
+
+/// Fetch the content of several files at once. All the node lookups are issued concurrently,
+/// followed by all the content lookups.
+///
+/// The results are in the same order as `nodeids`. A failure to fetch one file (eg.
+/// `ErrorKind::NodeMissing` for an unknown node) is returned in that file's slot rather than
+/// failing the whole batch.
+pub fn fetch_file_blobs<B>(
+    blobstore: B,
+    nodeids: Vec<NodeHash>,
+) -> BoxFuture<Vec<(NodeHash, Result<Vec<u8>>)>, Error>
+where
+    B: Blobstore<Key = String> + Clone,
+    B::ValueOut: AsRef<[u8]>,
+{
+    let nodes: Vec<_> = nodeids
+        .into_iter()
+        .map(|nodeid| {
+            get_node(&blobstore, nodeid).then(move |node| Ok::<_, Error>((nodeid, node)))
+        })
+        .collect();
+
+    future::join_all(nodes)
+        .and_then(move |nodes| {
+            let blobs: Vec<_> = nodes
+                .into_iter()
+                .map(|(nodeid, node)| match node {
+                    Ok(node) => get_content_blob_for_node(blobstore.clone(), nodeid, node)
+                        .map(|blob| Vec::from(blob.as_ref()))
+                        .then(move |blob| Ok((nodeid, blob)))
+                        .boxed(),
+                    Err(err) => future::ok((nodeid, Err(err))).boxed(),
+                })
+                .collect();
+
+            future::join_all(blobs)
+        })
+        .boxed()
+}
+
+/// Fetch a file's content, and if `verify` is set, check that it actually hashes to the SHA1
+/// recorded in the file node. A mismatch (ie, corruption in the blobstore) is reported as
+/// `ContentHashMismatch`. Verification means hashing the whole file, so is off the hot path.
+pub fn fetch_file_blob_verified<B>(
+    blobstore: B,
+    nodeid: NodeHash,
+    verify: bool,
+) -> BoxFuture<Vec<u8>, Error>
+where
+    B: Blobstore<Key = String> + Clone,
+    B::ValueOut: AsRef<[u8]>,
+{
+    fetch_file_blob(blobstore, nodeid, verify, FetchMode::Decompressed)
+}
+
+/// Fetch a file's content in `mode`, verifying it as for `fetch_file_blob_verified`. The hash is
+/// of the plain text, so verifying `Raw` content means decompressing it anyway.
+fn fetch_file_blob<B>(
+    blobstore: B,
+    nodeid: NodeHash,
+    verify: bool,
+    mode: FetchMode,
 ) -> BoxFuture<Vec<u8>, Error>
 where
     B: Blobstore<Key = String> + Clone,
     B::ValueOut: AsRef<[u8]>,
 {
     get_node(&blobstore, nodeid)
-        .and_then({
-            let blobstore = blobstore.clone();
-            move |node| {
-                let key = format!("sha1:{}", node.blob);
-
-                blobstore
-                    .get(&key)
-                    .map_err(blobstore_err)
-                    .and_then(move |blob| {
-                        blob.ok_or(ErrorKind::ContentMissing(nodeid, node.blob).into())
-                    })
-            }
+        .and_then(move |node| {
+            let expected = node.blob;
+            get_content_blob_for_node(blobstore, nodeid, node).and_then(move |blob| {
+                let stored = blob.as_ref();
+                if !verify && mode == FetchMode::Raw {
+                    return Ok(Vec::from(stored));
+                }
+
+                let content = zstdblob::decompress(stored)?;
+                if verify {
+                    let actual = hash::Sha1::from(content.as_slice());
+                    if actual != expected {
+                        bail!(ErrorKind::ContentHashMismatch(expected, actual));
+                    }
+                }
+                match mode {
+                    FetchMode::Raw => Ok(Vec::from(stored)),
+                    FetchMode::Decompressed => Ok(content),
+                }
+            })
         })
-        .and_then({
-            |blob| {
-                Ok(Vec::from(blob.as_ref()))
-            }
+        .boxed()
+}
+
+/// Fetch a file's content as a stream of chunks of at most `CONTENT_CHUNK_SIZE` bytes.
+///
+/// `Blobstore` has no partial reads yet, so the blob is still fetched in one piece, but it is
+/// never copied in full: only the chunk being yielded is held in addition to the blobstore's own
+/// value. Consumers shouldn't rely on the chunk boundaries.
+pub fn fetch_file_stream_from_blobstore<B>(
+    blobstore: B,
+    nodeid: NodeHash,
+) -> BoxStream<Bytes, Error>
+where
+    B: Blobstore<Key = String> + Clone,
+    B::ValueOut: AsRef<[u8]>,
+{
+    fetch_file_stream_chunked(blobstore, nodeid, CONTENT_CHUNK_SIZE)
+}
+
+fn fetch_file_stream_chunked<B>(
+    blobstore: B,
+    nodeid: NodeHash,
+    chunk_size: usize,
+) -> BoxStream<Bytes, Error>
+where
+    B: Blobstore<Key = String> + Clone,
+    B::ValueOut: AsRef<[u8]>,
+{
+    assert!(chunk_size > 0, "chunk_size must be non-zero");
+
+    get_content_blob(blobstore, nodeid)
+        .map(move |blob| {
+            stream::iter(ContentChunks {
+                blob,
+                offset: 0,
+                chunk_size,
+            })
         })
+        .flatten_stream()
         .boxed()
 }
 
+/// Number of leading bytes of a file `is_binary` looks at.
+pub const BINARY_SNIFF_LEN: usize = 8000;
+
+/// Guess whether `content` is binary rather than text, by looking for a NUL byte in its first
+/// `BINARY_SNIFF_LEN` bytes. This is the same heuristic Git uses; it's cheap, and only needs
+/// the start of the file.
+pub fn is_binary(content: &[u8]) -> bool {
+    let len = cmp::min(content.len(), BINARY_SNIFF_LEN);
+    content[..len].contains(&0)
+}
+
+/// Iterator over a fetched blob, copying out one chunk at a time.
+struct ContentChunks<T> {
+    blob: T,
+    offset: usize,
+    chunk_size: usize,
+}
+
+impl<T: AsRef<[u8]>> Iterator for ContentChunks<T> {
+    type Item = Result<Bytes>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data = self.blob.as_ref();
+        if self.offset >= data.len() {
+            return None;
+        }
+
+        let end = cmp::min(self.offset + self.chunk_size, data.len());
+        let chunk = Bytes::from(&data[self.offset..end]);
+        self.offset = end;
+        Some(Ok(chunk))
+    }
+}
+
 impl<B> BlobEntry<B>
 where
     B: Blobstore<Key = String>,
@@ -93,9 +531,84 @@ where
         }
     }
 
+    /// Return this entry with its type changed, but the same path and file node. Mercurial
+    /// records the executable bit in the manifest rather than the file node, so switching
+    /// between `Type::File` and `Type::Executable` leaves the node, and so `get_hash`, as it is.
+    pub fn with_type(self, ty: Type) -> Self {
+        Self { ty, ..self }
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.ty == Type::Executable
+    }
+
     fn get_node(&self) -> BoxFuture<RawNodeBlob, Error> {
         get_node(&self.blobstore, self.nodeid)
     }
+
+    /// Stream the raw content of this entry in bounded chunks, rather than materializing it
+    /// all at once as `get_content` does. See `fetch_file_stream_from_blobstore`.
+    pub fn get_content_stream(&self) -> BoxStream<Bytes, Error>
+    where
+        B: Clone,
+    {
+        fetch_file_stream_from_blobstore(self.blobstore.clone(), self.nodeid)
+    }
+
+    /// Return `len` bytes of this entry's raw content starting at `offset`, clamped to the end
+    /// of the content, so an offset past the end gives an empty result. For a symlink this
+    /// ranges into the target, and for a file stored in LFS into the pointer. It is an
+    /// `ErrorKind::NotAFile` error for a tree.
+    ///
+    /// `Blobstore` has no partial reads yet, so the whole blob is fetched, but only the range
+    /// is copied out of it.
+    pub fn get_content_range(&self, offset: usize, len: usize) -> BoxFuture<Bytes, Error>
+    where
+        B: Clone,
+    {
+        if self.ty == Type::Tree {
+            return future::err(ErrorKind::NotAFile(self.nodeid).into()).boxed();
+        }
+
+        get_content_blob(self.blobstore.clone(), self.nodeid)
+            .map(move |blob| {
+                let data = blob.as_ref();
+                let start = cmp::min(offset, data.len());
+                let end = cmp::min(start.saturating_add(len), data.len());
+                Bytes::from(&data[start..end])
+            })
+            .boxed()
+    }
+
+    /// Check whether this entry's content is binary, using `is_binary` on the first chunk of
+    /// the content stream. Symlinks and trees are never binary, and their content isn't
+    /// fetched. A file stored in LFS is judged by its pointer, so is always text.
+    pub fn is_binary(&self) -> BoxFuture<bool, Error>
+    where
+        B: Clone,
+    {
+        match self.ty {
+            Type::File | Type::Executable => (),
+            Type::Symlink | Type::Tree => return future::ok(false).boxed(),
+        }
+
+        self.get_content_stream()
+            .into_future()
+            .map(|(chunk, _)| chunk.map_or(false, |chunk| is_binary(chunk.as_ref())))
+            .map_err(|(err, _)| err)
+            .boxed()
+    }
+}
+
+/// Parse the content of symlink `nodeid` as its target path. Targets which are empty or
+/// aren't valid paths are `ErrorKind::InvalidSymlinkTarget`.
+fn parse_symlink_target(nodeid: NodeHash, blob: &[u8]) -> Result<Path> {
+    let invalid = || ErrorKind::InvalidSymlinkTarget(nodeid, blob.to_vec());
+    let path = Path::new(blob).map_err(|_| invalid())?;
+    if path.is_empty() {
+        bail!(invalid());
+    }
+    Ok(path)
 }
 
 impl<B> Entry for BlobEntry<B>
@@ -114,32 +627,27 @@ where
     }
 
     fn get_content(&self) -> BoxFuture<Content<Self::Error>, Self::Error> {
-        let nodeid = self.nodeid;
         let blobstore = self.blobstore.clone();
 
-        self.get_node()
-            .and_then({
-                let blobstore = blobstore.clone();
-                move |node| {
-                    let key = format!("sha1:{}", node.blob);
-
-                    blobstore
-                        .get(&key)
-                        .map_err(blobstore_err)
-                        .and_then(move |blob| {
-                            blob.ok_or(ErrorKind::ContentMissing(nodeid, node.blob).into())
-                        })
-                }
-            })
+        get_content_blob(blobstore.clone(), self.nodeid)
             .and_then({
                 let ty = self.ty;
+                let nodeid = self.nodeid;
                 move |blob| {
                     let blob = blob.as_ref();
 
+                    // Files stored in LFS are represented by a pointer to the real content,
+                    // which callers can choose to resolve separately.
+                    if ty == Type::File || ty == Type::Executable {
+                        if let Some(LfsPointer { oid, size }) = LfsPointer::parse(blob) {
+                            return Ok(Content::LfsPointer { oid, size });
+                        }
+                    }
+
                     let res = match ty {
                         Type::File => Content::File(Blob::from(blob)),
                         Type::Executable => Content::Executable(Blob::from(blob)),
-                        Type::Symlink => Content::Symlink(Path::new(blob)?),
+                        Type::Symlink => Content::Symlink(parse_symlink_target(nodeid, blob)?),
                         Type::Tree => Content::Tree(BlobManifest::parse(blobstore, blob)?.boxed()),
                     };
 
@@ -150,13 +658,45 @@ where
     }
 
     fn get_size(&self) -> BoxFuture<Option<usize>, Self::Error> {
-        self.get_content()
-            .and_then(|content| match content {
-                Content::File(data) | Content::Executable(data) => Ok(data.size()),
-                Content::Symlink(path) => Ok(Some(path.len())),
-                Content::Tree(_) => Ok(None),
-            })
-            .boxed()
+        match self.ty {
+            // Trees have no size, so there's no need to fetch anything.
+            Type::Tree => Ok(None).into_future().boxed(),
+            // The content of a symlink is its target path, so in all other cases the size is
+            // just the size of the content blob, unless it's an LFS pointer, in which case it's
+            // the size of the real content as given by the pointer.
+            Type::File | Type::Executable | Type::Symlink => {
+                let blobstore = self.blobstore.clone();
+                let nodeid = self.nodeid;
+                let ty = self.ty;
+
+                self.get_node()
+                    .and_then(move |node| {
+                        // Only small files need fetching to check whether they're pointers.
+                        let maybe_lfs = ty != Type::Symlink
+                            && node.size
+                                .map_or(true, |size| size < lfs::MAX_POINTER_SIZE as u64);
+                        match node.size {
+                            Some(size) if !maybe_lfs => {
+                                Ok(Some(size as usize)).into_future().boxed()
+                            }
+                            // Node imported before sizes were recorded, or a possible pointer
+                            _ => get_content_blob_for_node(blobstore, nodeid, node)
+                                .map(move |blob| {
+                                    let blob = blob.as_ref();
+                                    let pointer = if maybe_lfs {
+                                        LfsPointer::parse(blob)
+                                    } else {
+                                        None
+                                    };
+                                    // A malformed pointer is just an ordinary file.
+                                    Some(pointer.map_or(blob.len(), |p| p.size as usize))
+                                })
+                                .boxed(),
+                        }
+                    })
+                    .boxed()
+            }
+        }
     }
 
     fn get_hash(&self) -> &NodeHash {
@@ -167,3 +707,607 @@ where
         &self.path
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::future::FutureResult;
+
+    use memblob::Memblob;
+    use zstdblob::CompressedBlobstore;
+
+    /// Blobstore which fails every operation.
+    struct FailingBlobstore;
+
+    impl Blobstore for FailingBlobstore {
+        type Key = String;
+        type ValueIn = Vec<u8>;
+        type ValueOut = Vec<u8>;
+        type Error = io::Error;
+        type GetBlob = FutureResult<Option<Vec<u8>>, io::Error>;
+        type PutBlob = FutureResult<(), io::Error>;
+
+        fn get(&self, _key: &String) -> Self::GetBlob {
+            Err(io::Error::new(io::ErrorKind::Other, "get failed")).into_future()
+        }
+
+        fn put(&self, _key: String, _value: Vec<u8>) -> Self::PutBlob {
+            Err(io::Error::new(io::ErrorKind::Other, "put failed")).into_future()
+        }
+    }
+
+    /// Blobstore which counts the number of `get`s made.
+    #[derive(Clone)]
+    struct CountingBlobstore {
+        inner: Memblob,
+        gets: Arc<AtomicUsize>,
+    }
+
+    impl Blobstore for CountingBlobstore {
+        type Key = String;
+        type ValueIn = <Memblob as Blobstore>::ValueIn;
+        type ValueOut = <Memblob as Blobstore>::ValueOut;
+        type Error = <Memblob as Blobstore>::Error;
+        type GetBlob = <Memblob as Blobstore>::GetBlob;
+        type PutBlob = <Memblob as Blobstore>::PutBlob;
+
+        fn get(&self, key: &String) -> Self::GetBlob {
+            self.gets.fetch_add(1, Ordering::SeqCst);
+            self.inner.get(key)
+        }
+
+        fn put(&self, key: String, value: Self::ValueIn) -> Self::PutBlob {
+            self.inner.put(key, value)
+        }
+    }
+
+    fn node(n: u8) -> NodeHash {
+        NodeHash::from_bytes(&[n; 20]).unwrap()
+    }
+
+    fn put_node(blobstore: &Memblob, nodeid: NodeHash, node: Vec<u8>) {
+        blobstore.populate(vec![(format!("node:{}.bincode", nodeid), node)]);
+    }
+
+    fn put_content(blobstore: &Memblob, content: &[u8]) -> hash::Sha1 {
+        let sha1 = hash::Sha1::from(content);
+        blobstore.populate(vec![(format!("sha1:{}", sha1), content.to_vec())]);
+        sha1
+    }
+
+    fn put_sized_node(blobstore: &Memblob, nodeid: NodeHash, sha1: hash::Sha1, size: u64) {
+        let node = RawNodeBlob::new(Parents::None, sha1, size);
+        put_node(blobstore, nodeid, node.serialize().unwrap());
+    }
+
+    /// Store `content` as file node `nodeid`.
+    fn put_file(blobstore: &Memblob, nodeid: NodeHash, content: &[u8]) {
+        let sha1 = put_content(blobstore, content);
+        put_sized_node(blobstore, nodeid, sha1, content.len() as u64);
+    }
+
+    /// Store `content` as file node `nodeid` in a fresh blobstore.
+    fn blobstore_with_file(nodeid: NodeHash, content: &[u8]) -> Memblob {
+        let blobstore = Memblob::new();
+        put_file(&blobstore, nodeid, content);
+        blobstore
+    }
+
+    /// Store a manifest with the given entries, where each is (path, file node, flag, content).
+    /// File nodes for the entries are stored too.
+    fn put_manifest(
+        blobstore: &Memblob,
+        manifestid: NodeHash,
+        entries: &[(&str, u8, &str, &str)],
+    ) {
+        let mut manifest = Vec::new();
+        for &(path, n, flag, content) in entries {
+            put_file(blobstore, node(n), content.as_bytes());
+            manifest.extend(format!("{}\0{}{}\n", path, node(n), flag).into_bytes());
+        }
+        blobstore.populate(vec![(format!("manifest:{}", manifestid), manifest)]);
+    }
+
+    fn entry(blobstore: &Memblob, nodeid: NodeHash, ty: Type) -> BlobEntry<Memblob> {
+        BlobEntry::new(blobstore.clone(), Path::new("file").unwrap(), nodeid, ty)
+    }
+
+    #[test]
+    fn get_content() {
+        let blobstore = blobstore_with_file(node(1), b"content");
+
+        match entry(&blobstore, node(1), Type::File).get_content().wait() {
+            Ok(Content::File(blob)) => assert_eq!(blob.as_slice(), Some(&b"content"[..])),
+            Ok(_) => panic!("unexpected content type"),
+            Err(err) => panic!("unexpected error {:?}", err),
+        }
+        match entry(&blobstore, node(1), Type::Symlink).get_content().wait() {
+            Ok(Content::Symlink(path)) => assert_eq!(path, Path::new("content").unwrap()),
+            Ok(_) => panic!("unexpected content type"),
+            Err(err) => panic!("unexpected error {:?}", err),
+        }
+        assert!(entry(&blobstore, node(2), Type::File).get_content().wait().is_err());
+    }
+
+    #[test]
+    fn get_content_range() {
+        let blobstore = blobstore_with_file(node(1), b"some content");
+        let range = |ty, offset, len| {
+            entry(&blobstore, node(1), ty)
+                .get_content_range(offset, len)
+                .wait()
+        };
+
+        assert_eq!(range(Type::File, 5, 4).unwrap(), Bytes::from(&b"cont"[..]));
+        assert_eq!(range(Type::Executable, 0, 4).unwrap(), Bytes::from(&b"some"[..]));
+        assert_eq!(range(Type::Symlink, 8, 100).unwrap(), Bytes::from(&b"tent"[..]));
+        assert_eq!(range(Type::File, 5, usize::max_value()).unwrap(), Bytes::from(&b"content"[..]));
+        assert!(range(Type::File, 12, 10).unwrap().is_empty());
+        assert!(range(Type::File, 100, 10).unwrap().is_empty());
+
+        match range(Type::Tree, 0, 4) {
+            Err(Error(ErrorKind::NotAFile(nodeid), _)) => assert_eq!(nodeid, node(1)),
+            Ok(_) => panic!("unexpected success"),
+            Err(err) => panic!("unexpected error {:?}", err),
+        }
+        assert!(entry(&blobstore, node(2), Type::File).get_content_range(0, 4).wait().is_err());
+    }
+
+    #[test]
+    fn content_missing_key() {
+        let blobstore = Memblob::new();
+        let sha1 = hash::Sha1::from(&b"never stored"[..]);
+        put_sized_node(&blobstore, node(1), sha1, 12);
+        let key = format!("sha1:{}", sha1);
+
+        match entry(&blobstore, node(1), Type::File).get_content().wait() {
+            Err(Error(ErrorKind::ContentMissing(nodeid, missing, k), _)) => {
+                assert_eq!((nodeid, missing, k), (node(1), sha1, key.clone()))
+            }
+            Ok(_) => panic!("unexpected success"),
+            Err(err) => panic!("unexpected error {:?}", err),
+        }
+
+        let err = fetch_file_blob_from_blobstore(blobstore, node(1), FetchMode::default())
+            .wait()
+            .expect_err("missing content was not reported");
+        assert!(err.to_string().contains(&format!("\"{}\"", key)));
+    }
+
+    #[test]
+    fn get_content_invalid_symlink() {
+        let blobstore = Memblob::new();
+        put_file(&blobstore, node(1), b"");
+        put_file(&blobstore, node(2), b"bad\0target");
+
+        for &(n, target) in [(1, &b""[..]), (2, &b"bad\0target"[..])].iter() {
+            match entry(&blobstore, node(n), Type::Symlink).get_content().wait() {
+                Err(Error(ErrorKind::InvalidSymlinkTarget(nodeid, bytes), _)) => {
+                    assert_eq!((nodeid, &*bytes), (node(n), target))
+                }
+                Ok(_) => panic!("unexpected success"),
+                Err(err) => panic!("unexpected error {:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn get_content_lfs() {
+        let oid = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+        let pointer = format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 12345\n",
+            oid
+        );
+        let blobstore = Memblob::new();
+        let malformed = &pointer.as_bytes()[..pointer.len() - 1];
+        put_file(&blobstore, node(1), pointer.as_bytes());
+        // Malformed pointer and ordinary text, which should be returned as they are.
+        put_file(&blobstore, node(2), malformed);
+        put_file(&blobstore, node(3), b"version 1.0\n");
+
+        for ty in [Type::File, Type::Executable].iter() {
+            match entry(&blobstore, node(1), *ty).get_content().wait() {
+                Ok(Content::LfsPointer { oid: o, size }) => assert_eq!((&*o, size), (oid, 12345)),
+                Ok(_) => panic!("unexpected content type"),
+                Err(err) => panic!("unexpected error {:?}", err),
+            }
+        }
+        // Symlinks are never pointers.
+        match entry(&blobstore, node(1), Type::Symlink).get_content().wait() {
+            Ok(Content::Symlink(_)) => (),
+            Ok(_) => panic!("unexpected content type"),
+            Err(err) => panic!("unexpected error {:?}", err),
+        }
+        for &(n, expected) in [(2, malformed), (3, &b"version 1.0\n"[..])].iter() {
+            match entry(&blobstore, node(n), Type::File).get_content().wait() {
+                Ok(Content::File(blob)) => assert_eq!(blob.as_slice(), Some(expected)),
+                Ok(_) => panic!("unexpected content type"),
+                Err(err) => panic!("unexpected error {:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn executable_bit() {
+        let blobstore = blobstore_with_file(node(1), b"#!/bin/sh\n");
+        let file = entry(&blobstore, node(1), Type::File);
+        assert!(!file.is_executable());
+
+        let exec = file.with_type(Type::Executable);
+        assert!(exec.is_executable());
+        assert_eq!(exec.get_type(), Type::Executable);
+        assert_eq!(exec.get_hash(), &node(1));
+        assert_eq!(exec.get_path(), &Path::new("file").unwrap());
+        match exec.get_content().wait().unwrap() {
+            Content::Executable(blob) => assert_eq!(blob.as_slice(), Some(&b"#!/bin/sh\n"[..])),
+            _ => panic!("unexpected content type"),
+        }
+
+        let file = exec.with_type(Type::File);
+        assert!(!file.is_executable());
+        assert_eq!(file.get_type(), Type::File);
+        assert_eq!(file.get_hash(), &node(1));
+    }
+
+    #[test]
+    fn batch_fetch() {
+        let blobstore = Memblob::new();
+        let nodeids: Vec<_> = (1..4).map(node).collect();
+        put_file(&blobstore, nodeids[0], b"one");
+        put_file(&blobstore, nodeids[2], b"three");
+
+        let blobs = fetch_file_blobs(blobstore, nodeids.clone()).wait().unwrap();
+        assert_eq!(blobs.len(), 3);
+        for (&(nodeid, _), expected) in blobs.iter().zip(nodeids.iter()) {
+            assert_eq!(nodeid, *expected);
+        }
+        assert_eq!(blobs[0].1.as_ref().unwrap(), b"one");
+        match blobs[1].1 {
+            Err(Error(ErrorKind::NodeMissing(nodeid), _)) => assert_eq!(nodeid, nodeids[1]),
+            ref res => panic!("unexpected result {:?}", res),
+        }
+        assert_eq!(blobs[2].1.as_ref().unwrap(), b"three");
+    }
+
+    #[test]
+    fn verified_fetch() {
+        let blobstore = blobstore_with_file(node(1), b"content");
+
+        for verify in [false, true].iter() {
+            let blob = fetch_file_blob_verified(blobstore.clone(), node(1), *verify).wait();
+            assert_eq!(blob.unwrap(), b"content");
+        }
+
+        // Corrupt the stored content.
+        let expected = hash::Sha1::from(&b"content"[..]);
+        blobstore.populate(vec![(format!("sha1:{}", expected), b"c0ntent".to_vec())]);
+
+        let blob = fetch_file_blob_verified(blobstore.clone(), node(1), false).wait();
+        assert_eq!(blob.unwrap(), b"c0ntent");
+        match fetch_file_blob_verified(blobstore, node(1), true).wait() {
+            Err(Error(ErrorKind::ContentHashMismatch(e, actual), _)) => {
+                assert_eq!(e, expected);
+                assert_eq!(actual, hash::Sha1::from(&b"c0ntent"[..]));
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn fetch_modes() {
+        let content: Vec<u8> = b"compressible\n".iter().cycle().take(1000).cloned().collect();
+        let sha1 = hash::Sha1::from(content.as_slice());
+        let blobstore = Memblob::new();
+        // Store the content compressed, as CompressedBlobstore would, with a node pointing at
+        // the hash of the plain text.
+        CompressedBlobstore::new(blobstore.clone())
+            .put(format!("sha1:{}", sha1), content.clone())
+            .wait()
+            .unwrap();
+        put_sized_node(&blobstore, node(1), sha1, content.len() as u64);
+        let stored = blobstore.get(&format!("sha1:{}", sha1)).wait().unwrap().unwrap().to_vec();
+        assert!(stored.len() < content.len());
+
+        let fetch = |mode| {
+            fetch_file_blob_from_blobstore(blobstore.clone(), node(1), mode)
+                .wait()
+                .unwrap()
+        };
+        assert_eq!(FetchMode::default(), FetchMode::Decompressed);
+        assert_eq!(fetch(FetchMode::Decompressed), content);
+        assert_eq!(fetch(FetchMode::Raw), stored);
+        // Verification is against the plain text.
+        let blob = fetch_file_blob_verified(blobstore.clone(), node(1), true).wait();
+        assert_eq!(blob.unwrap(), content);
+        let blob = fetch_file_blob(blobstore.clone(), node(1), true, FetchMode::Raw).wait();
+        assert_eq!(blob.unwrap(), stored);
+
+        // Content stored uncompressed is the same either way.
+        put_file(&blobstore, node(2), b"plain");
+        for mode in [FetchMode::Raw, FetchMode::Decompressed].iter() {
+            let blob = fetch_file_blob_from_blobstore(blobstore.clone(), node(2), *mode).wait();
+            assert_eq!(blob.unwrap(), b"plain");
+        }
+    }
+
+    #[test]
+    fn node_exists() {
+        let blobstore = Memblob::new();
+        // Existence doesn't depend on the node being valid.
+        put_node(&blobstore, node(1), b"not bincode".to_vec());
+
+        assert!(file_node_exists(&blobstore, node(1)).wait().unwrap());
+        assert!(!file_node_exists(&blobstore, node(2)).wait().unwrap());
+
+        match file_node_exists(&FailingBlobstore, node(1)).wait() {
+            Err(Error(ErrorKind::Blobstore, _)) => (),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn node_blob_versions() {
+        let sha1 = hash::Sha1::from(&b"content"[..]);
+        let parents = Parents::One(node(1));
+
+        // Version 0, without a size.
+        let legacy = LegacyNodeBlob {
+            parents,
+            blob: sha1,
+        };
+        let data = bincode::serialize(&legacy, bincode::Infinite).unwrap();
+        let decoded = RawNodeBlob::deserialize(&data).unwrap();
+        assert_eq!((*decoded.parents(), *decoded.blob()), (parents, sha1));
+        assert_eq!(decoded.size(), None);
+
+        // Writing it back out keeps it sizeless.
+        let data = decoded.serialize().unwrap();
+        let decoded = RawNodeBlob::deserialize(&data).unwrap();
+        assert_eq!((*decoded.parents(), *decoded.blob()), (parents, sha1));
+        assert_eq!(decoded.size(), None);
+
+        // Version 0, with the size appended.
+        let sized = NodeBlobV1 {
+            parents,
+            blob: sha1,
+            size: 7,
+        };
+        let data = bincode::serialize(&sized, bincode::Infinite).unwrap();
+        assert_eq!(RawNodeBlob::deserialize(&data).unwrap().size(), Some(7));
+
+        // Version 1.
+        let mut data = vec![NODE_BLOB_MARKER, 1];
+        data.extend(bincode::serialize(&sized, bincode::Infinite).unwrap());
+        let decoded = RawNodeBlob::deserialize(&data).unwrap();
+        assert_eq!((*decoded.parents(), *decoded.blob()), (parents, sha1));
+        assert_eq!(decoded.size(), Some(7));
+
+        // Version 2.
+        let data = RawNodeBlob::new(parents, sha1, 7).serialize().unwrap();
+        assert_eq!(&data[..2], &[NODE_BLOB_MARKER, 2]);
+        let decoded = RawNodeBlob::deserialize(&data).unwrap();
+        assert_eq!((*decoded.parents(), *decoded.blob()), (parents, sha1));
+        assert_eq!(decoded.size(), Some(7));
+
+        // Unknown future version.
+        let mut data = data;
+        data[1] = 3;
+        match RawNodeBlob::deserialize(&data) {
+            Err(Error(ErrorKind::UnknownNodeBlobVersion(3), _)) => (),
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(RawNodeBlob::deserialize(&[NODE_BLOB_MARKER]).is_err());
+    }
+
+    fn resolve(blobstore: &Memblob, start: u8, max_hops: usize) -> Result<Option<Path>> {
+        resolve_symlink(blobstore.clone(), node(100), node(start), max_hops).wait()
+    }
+
+    fn is_symlink_loop(res: Result<Option<Path>>) -> bool {
+        match res {
+            Err(Error(ErrorKind::SymlinkLoop(_), _)) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn symlink_chain() {
+        let blobstore = Memblob::new();
+        put_manifest(
+            &blobstore,
+            node(100),
+            &[
+                ("a", 1, "l", "dir/b"),
+                ("dir/b", 2, "l", "./c"),
+                ("dir/c", 3, "l", "../file"),
+                ("file", 4, "", "content"),
+                ("dangling", 5, "l", "nothing"),
+                ("escape", 6, "l", "../file"),
+            ],
+        );
+
+        let file = Some(Path::new("file").unwrap());
+        assert_eq!(resolve(&blobstore, 1, 3).unwrap(), file);
+        assert_eq!(resolve(&blobstore, 3, 3).unwrap(), file);
+        assert_eq!(resolve(&blobstore, 4, 0).unwrap(), file);
+        assert!(is_symlink_loop(resolve(&blobstore, 1, 2)));
+
+        assert_eq!(resolve(&blobstore, 5, 3).unwrap(), None);
+        assert_eq!(resolve(&blobstore, 6, 3).unwrap(), None);
+        // Not in the manifest.
+        assert!(resolve(&blobstore, 9, 3).is_err());
+    }
+
+    #[test]
+    fn symlink_loops() {
+        let blobstore = Memblob::new();
+        put_manifest(
+            &blobstore,
+            node(100),
+            &[
+                ("a", 1, "l", "b"),
+                ("b", 2, "l", "a"),
+                ("self", 3, "l", "self"),
+            ],
+        );
+
+        assert!(is_symlink_loop(resolve(&blobstore, 1, 10)));
+        assert!(is_symlink_loop(resolve(&blobstore, 2, 10)));
+        assert!(is_symlink_loop(resolve(&blobstore, 3, 10)));
+        assert!(is_symlink_loop(resolve(&blobstore, 3, 0)));
+    }
+
+    fn get_size(blobstore: &Memblob, nodeid: NodeHash, ty: Type) -> Option<usize> {
+        entry(blobstore, nodeid, ty).get_size().wait().unwrap()
+    }
+
+    #[test]
+    fn size_from_node() {
+        // The content blobs are never stored, so these can only succeed by using the recorded
+        // size. Files this big can't be LFS pointers, so don't need checking for one.
+        let blobstore = Memblob::new();
+        let sha1 = hash::Sha1::from(&b"unused"[..]);
+        for (n, ty) in [Type::File, Type::Executable, Type::Symlink].iter().enumerate() {
+            put_sized_node(&blobstore, node(n as u8), sha1, 10000 + n as u64);
+            assert_eq!(get_size(&blobstore, node(n as u8), *ty), Some(10000 + n));
+        }
+
+        // Trees have no size, and don't need any blobs at all.
+        assert_eq!(get_size(&blobstore, node(9), Type::Tree), None);
+    }
+
+    #[test]
+    fn size_tree_no_fetch() {
+        let blobstore = CountingBlobstore {
+            inner: Memblob::new(),
+            gets: Arc::new(AtomicUsize::new(0)),
+        };
+        let sha1 = hash::Sha1::from(&b"unused"[..]);
+        put_sized_node(&blobstore.inner, node(1), sha1, 10000);
+
+        let get_size = |ty| {
+            BlobEntry::new(blobstore.clone(), Path::new("entry").unwrap(), node(1), ty)
+                .get_size()
+                .wait()
+                .unwrap()
+        };
+
+        assert_eq!(get_size(Type::Tree), None);
+        assert_eq!(blobstore.gets.load(Ordering::SeqCst), 0);
+
+        assert_eq!(get_size(Type::File), Some(10000));
+        assert_eq!(blobstore.gets.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn size_lfs() {
+        let pointer = format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 12345\n",
+            "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393"
+        );
+        let malformed = &pointer.as_bytes()[..pointer.len() - 1];
+        let blobstore = Memblob::new();
+        put_file(&blobstore, node(1), pointer.as_bytes());
+        put_file(&blobstore, node(2), malformed);
+
+        assert_eq!(get_size(&blobstore, node(1), Type::File), Some(12345));
+        assert_eq!(get_size(&blobstore, node(1), Type::Executable), Some(12345));
+        // Symlinks are never pointers.
+        assert_eq!(get_size(&blobstore, node(1), Type::Symlink), Some(pointer.len()));
+        assert_eq!(get_size(&blobstore, node(2), Type::File), Some(malformed.len()));
+    }
+
+    #[test]
+    fn size_legacy_node() {
+        let blobstore = Memblob::new();
+        let sha1 = put_content(&blobstore, b"target/path");
+        let node_blob = LegacyNodeBlob {
+            parents: Parents::None,
+            blob: sha1,
+        };
+        put_node(&blobstore, node(1), bincode::serialize(&node_blob, bincode::Infinite).unwrap());
+
+        assert_eq!(get_size(&blobstore, node(1), Type::File), Some(11));
+        assert_eq!(get_size(&blobstore, node(1), Type::Symlink), Some(11));
+    }
+
+    #[test]
+    fn content_stream() {
+        let content: Vec<u8> = (0..(2 * CONTENT_CHUNK_SIZE + 100))
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let blobstore = blobstore_with_file(node(1), &content);
+
+        let chunks = fetch_file_stream_chunked(blobstore.clone(), node(1), 1000)
+            .collect()
+            .wait()
+            .unwrap();
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 1000));
+        assert_eq!(chunks.concat(), content);
+
+        let chunks = entry(&blobstore, node(1), Type::File)
+            .get_content_stream()
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks.concat(), content);
+    }
+
+    #[test]
+    fn binary() {
+        assert!(!is_binary(b""));
+        assert!(!is_binary(b"text\n"));
+        assert!(is_binary(b"bin\0ary"));
+
+        // Only the start of the content counts.
+        let mut late_nul = vec![b'a'; BINARY_SNIFF_LEN];
+        late_nul.push(0);
+        assert!(!is_binary(&late_nul));
+        late_nul[BINARY_SNIFF_LEN - 1] = 0;
+        assert!(is_binary(&late_nul));
+    }
+
+    #[test]
+    fn entry_is_binary() {
+        let blobstore = Memblob::new();
+        put_file(&blobstore, node(1), b"some text\n");
+        put_file(&blobstore, node(2), b"\x7fELF\x02\x01\x01\0\0");
+        put_file(&blobstore, node(3), b"");
+
+        let is_binary = |n, ty| entry(&blobstore, node(n), ty).is_binary().wait().unwrap();
+        assert!(!is_binary(1, Type::File));
+        assert!(is_binary(2, Type::File));
+        assert!(is_binary(2, Type::Executable));
+        assert!(!is_binary(3, Type::File));
+        // Symlinks are never binary, whatever their content.
+        assert!(!is_binary(2, Type::Symlink));
+        // Nothing is fetched for symlinks, so a missing node isn't an error.
+        assert!(!is_binary(9, Type::Symlink));
+
+        assert!(entry(&blobstore, node(9), Type::File).is_binary().wait().is_err());
+    }
+
+    #[test]
+    fn content_stream_empty() {
+        let blobstore = blobstore_with_file(node(1), b"");
+
+        let chunks = fetch_file_stream_from_blobstore(blobstore, node(1))
+            .collect()
+            .wait()
+            .unwrap();
+        assert!(chunks.is_empty());
+    }
+
+    #[test]
+    fn content_stream_missing() {
+        let res = fetch_file_stream_from_blobstore(Memblob::new(), node(1))
+            .collect()
+            .wait();
+        assert!(res.is_err());
+    }
+}
@@ -12,6 +12,7 @@ extern crate error_chain;
 extern crate futures;
 
 extern crate bincode;
+extern crate bytes;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
@@ -25,6 +26,14 @@ extern crate bookmarks;
 extern crate heads;
 extern crate mercurial;
 extern crate mercurial_types;
+extern crate zstdblob;
+
+#[cfg(test)]
+extern crate cacheblob;
+#[cfg(test)]
+extern crate memblob;
+#[cfg(test)]
+extern crate membookmarks;
 
 mod repo;
 mod changeset;
@@ -37,3 +46,6 @@ pub use errors::*;
 pub use repo::BlobRepo;
 pub use changeset::BlobChangeset;
 pub use manifest::BlobManifest;
+pub use file::{BINARY_SNIFF_LEN, BlobEntry, CONTENT_CHUNK_SIZE, FetchMode, RawNodeBlob,
+               fetch_file_blob_from_blobstore, fetch_file_blob_verified, fetch_file_blobs,
+               fetch_file_stream_from_blobstore, file_node_exists, is_binary, resolve_symlink};
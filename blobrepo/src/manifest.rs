@@ -51,6 +51,16 @@ where
             files: revlog::parse(data.as_ref())?,
         })
     }
+
+    /// Return the raw manifest details for `path`, if present.
+    pub fn get_details(&self, path: &Path) -> Option<&Details> {
+        self.files.get(path)
+    }
+
+    /// Find the path and details of the entry for file node `nodeid`, if present.
+    pub fn find_node(&self, nodeid: &NodeHash) -> Option<(&Path, &Details)> {
+        self.files.iter().find(|&(_, d)| d.nodeid() == nodeid)
+    }
 }
 
 impl<B> Manifest for BlobManifest<B>
@@ -84,4 +94,15 @@ impl<B> Manifest for BlobManifest<B>
             .map(|e| Ok(e.boxed()));
         stream::iter(entries).boxed()
     }
+
+    fn lookup_name(
+        &self,
+        name: &[u8],
+    ) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Self::Error> {
+        // Entries are kept sorted by path, so there's no need to scan them.
+        match Path::new(name) {
+            Ok(path) => self.lookup(&path),
+            Err(_) => Ok(None).into_future().boxed(),
+        }
+    }
 }
@@ -9,18 +9,20 @@ use std::error;
 use std::mem;
 use std::sync::Arc;
 
+use bytes::Bytes;
 use futures::{Async, Poll};
-use futures::future::{BoxFuture, Future};
+use futures::future::{self, BoxFuture, Future};
 use futures::stream::{self, BoxStream, Stream};
 
 use blobstore::Blobstore;
 use bookmarks::{Bookmarks, BoxedBookmarks};
 use heads::Heads;
-use mercurial_types::{Changeset, Manifest, NodeHash, Repo, repo};
+use mercurial_types::{Changeset, Entry, HashResolution, Manifest, ManifestStats, NodeHash, Path,
+                      Repo, VerifyEvent, ancestors, export, repo, verify};
 
 use BlobChangeset;
 use BlobManifest;
-use file::fetch_file_blob_from_blobstore;
+use file::{FetchMode, fetch_file_blob_from_blobstore, fetch_file_blob_verified};
 use errors::*;
 
 pub struct BlobRepo<Head, Book, Blob> {
@@ -51,7 +53,17 @@ where
     Blob::ValueOut: AsRef<[u8]> + Send,
 {
     pub fn get_file_blob(&self, key: &NodeHash) -> BoxFuture<Vec<u8>, Error> {
-        fetch_file_blob_from_blobstore(self.inner.blobstore.clone(), *key)
+        self.get_file_blob_with_mode(key, FetchMode::default())
+    }
+
+    /// Like `get_file_blob`, but with the content compressed or not according to `mode`; see
+    /// `FetchMode`.
+    pub fn get_file_blob_with_mode(
+        &self,
+        key: &NodeHash,
+        mode: FetchMode,
+    ) -> BoxFuture<Vec<u8>, Error> {
+        fetch_file_blob_from_blobstore(self.inner.blobstore.clone(), *key, mode)
     }
 }
 
@@ -80,6 +92,28 @@ where
         self.inner.heads.heads().map_err(heads_err).boxed()
     }
 
+    /// Fetch each changeset and its root manifest, so that they are cached if the blobstore
+    /// caches.
+    fn prefetch(&self, nodeids: &[NodeHash]) -> BoxFuture<(), Self::Error> {
+        let prefetches: Vec<_> = nodeids
+            .iter()
+            .map(|nodeid| {
+                let blobstore = self.inner.blobstore.clone();
+                BlobChangeset::load(&self.inner.blobstore, nodeid)
+                    .and_then(move |cs| match cs {
+                        Some(cs) => BlobManifest::load(&blobstore, cs.manifestid())
+                            .map(|_| ())
+                            .boxed(),
+                        None => future::ok(()).boxed(),
+                    })
+                    // Prefetching is only a hint, so ignore anything that can't be fetched.
+                    .then(|_| Ok(()))
+            })
+            .collect();
+
+        future::join_all(prefetches).map(|_| ()).boxed()
+    }
+
     fn changeset_exists(&self, nodeid: &NodeHash) -> BoxFuture<bool, Self::Error> {
         BlobChangeset::load(&self.inner.blobstore, nodeid)
             .map(|cs| cs.is_some())
@@ -96,6 +130,37 @@ where
             .boxed()
     }
 
+    fn get_changeset_raw(&self, nodeid: &NodeHash) -> BoxFuture<Bytes, Self::Error> {
+        let nodeid = *nodeid;
+        BlobChangeset::load_raw(&self.inner.blobstore, &nodeid)
+            .and_then(move |raw| raw.ok_or(ErrorKind::ChangesetMissing(nodeid).into()))
+            .boxed()
+    }
+
+    fn get_changesets_bounded(&self, max_depth: usize) -> BoxStream<NodeHash, Self::Error> {
+        ancestors::recent(self.clone(), max_depth)
+    }
+
+    fn resolve_hash_prefix(&self, prefix: &str) -> BoxFuture<HashResolution, Self::Error> {
+        repo::resolve_hash_prefix(self, prefix)
+    }
+
+    fn is_ancestor(
+        &self,
+        ancestor: &NodeHash,
+        descendant: &NodeHash,
+    ) -> BoxFuture<bool, Self::Error> {
+        ancestors::is_ancestor(self.clone(), *ancestor, *descendant, None)
+    }
+
+    fn common_ancestor(
+        &self,
+        a: &NodeHash,
+        b: &NodeHash,
+    ) -> BoxFuture<Option<NodeHash>, Self::Error> {
+        ancestors::common_ancestor(self.clone(), *a, *b, None)
+    }
+
     fn get_manifest_by_nodeid(
         &self,
         nodeid: &NodeHash,
@@ -109,6 +174,72 @@ where
             .boxed()
     }
 
+    fn get_root_manifest(
+        &self,
+        cs: &NodeHash,
+    ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
+        repo::get_root_manifest(self.clone(), *cs)
+    }
+
+    fn get_entry_by_path(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+    ) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Self::Error> {
+        repo::get_entry_by_path(self.clone(), *cs, path.clone())
+    }
+
+    fn get_file_history(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+        limit: usize,
+    ) -> BoxStream<(NodeHash, NodeHash), Self::Error> {
+        ancestors::file_history(self.clone(), *cs, path.clone(), limit)
+    }
+
+    fn walk_manifest(
+        &self,
+        root: &NodeHash,
+    ) -> BoxStream<(Path, Box<Entry<Error = Self::Error>>), Self::Error> {
+        repo::walk_manifest(self, root)
+    }
+
+    fn manifest_stats(&self, root: &NodeHash) -> BoxFuture<ManifestStats, Self::Error> {
+        repo::manifest_stats(self, root)
+    }
+
+    fn export_bundle(
+        &self,
+        heads: &[NodeHash],
+        common: &[NodeHash],
+    ) -> BoxStream<Bytes, Self::Error> {
+        export::export(self.clone(), heads.to_vec(), common.to_vec())
+    }
+
+    /// File content is checked against the SHA1 recorded in its node, as by
+    /// `fetch_file_blob_verified`.
+    fn verify_reachable(&self, heads: &[NodeHash]) -> BoxStream<VerifyEvent, Self::Error> {
+        let blobstore = self.inner.blobstore.clone();
+        let check_file = move |entry: Box<Entry<Error = Error>>| {
+            let nodeid = *entry.get_hash();
+            fetch_file_blob_verified(blobstore.clone(), nodeid, true)
+                .then(move |res| match res {
+                    Ok(_) => Ok(VerifyEvent::Ok(nodeid)),
+                    Err(Error(ErrorKind::NodeMissing(_), _)) |
+                    Err(Error(ErrorKind::ContentMissing(..), _)) => {
+                        Ok(VerifyEvent::Missing(nodeid))
+                    }
+                    Err(Error(ErrorKind::ContentHashMismatch(..), _)) => {
+                        Ok(VerifyEvent::HashMismatch(nodeid))
+                    }
+                    Err(err) => Err(err),
+                })
+                .boxed()
+        };
+        verify::verify_reachable(self.clone(), heads.to_vec(), check_file)
+    }
+
     fn get_bookmarks(&self) -> Result<repo::BoxedBookmarks<Self::Error>> {
         let res = self.inner.bookmarks.clone();
 
@@ -196,3 +327,157 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io;
+
+    use bytes::Bytes;
+    use futures::future::FutureResult;
+
+    use cacheblob::CachingBlobstore;
+    use memblob::Memblob;
+    use membookmarks::MemBookmarks;
+    use mercurial::revlogrepo::RevlogChangeset;
+    use mercurial_types::{BlobNode, Parents, hash};
+
+    use file::RawNodeBlob;
+
+    /// Heads store with no heads, for repos which are only accessed by nodeid.
+    struct NoHeads;
+
+    impl Heads for NoHeads {
+        type Key = NodeHash;
+        type Error = io::Error;
+
+        type Unit = FutureResult<(), io::Error>;
+        type Bool = FutureResult<bool, io::Error>;
+        type Heads = stream::Empty<NodeHash, io::Error>;
+
+        fn add(&self, _head: &NodeHash) -> Self::Unit {
+            future::ok(())
+        }
+
+        fn remove(&self, _head: &NodeHash) -> Self::Unit {
+            future::ok(())
+        }
+
+        fn is_head(&self, _head: &NodeHash) -> Self::Bool {
+            future::ok(false)
+        }
+
+        fn heads(&self) -> Self::Heads {
+            stream::empty()
+        }
+    }
+
+    fn node(n: u8) -> NodeHash {
+        NodeHash::from_bytes(&[n; 20]).expect("valid hash")
+    }
+
+    #[test]
+    fn prefetch_populates_cache() {
+        let store = Memblob::new();
+        let csid = node(1);
+        let manifestid = node(2);
+
+        let text = format!("{}\nuser\n0 0\n\ncomment", manifestid);
+        let csnode = BlobNode::new(text.into_bytes(), None, None);
+        let cs = BlobChangeset::new(&csid, RevlogChangeset::new(csnode).unwrap());
+        cs.save(store.clone()).wait().unwrap();
+        let entry = format!("file\0{}\n", node(3));
+        store.populate(vec![(format!("manifest:{}", manifestid), Bytes::from(entry))]);
+
+        let blobstore = CachingBlobstore::new(store, 100, 1 << 20);
+        let repo = BlobRepo::new(NoHeads, MemBookmarks::new(), blobstore.clone());
+        let cskey = format!("changeset:{}.bincode", csid);
+        assert!(!blobstore.is_cached(&cskey));
+
+        // The missing changeset is skipped rather than failing the whole prefetch.
+        repo.prefetch(&[node(9), csid]).wait().unwrap();
+        assert!(blobstore.is_cached(&cskey));
+        assert!(blobstore.is_cached(&format!("manifest:{}", manifestid)));
+        assert_eq!(blobstore.cached_entries(), 2);
+    }
+
+    #[test]
+    fn get_changeset_raw() {
+        let store = Memblob::new();
+        let parent = node(1);
+        // Already in the form `RevlogChangeset::generate` produces, so that it's stored as is.
+        let text = format!("{}\nuser\n0 0 branch:default\nfile\n\ncomment", node(2));
+        let csnode = BlobNode::new(text.clone().into_bytes(), Some(&parent), None);
+        let csid = csnode.nodeid().unwrap();
+        let cs = BlobChangeset::new(&csid, RevlogChangeset::new(csnode).unwrap());
+        cs.save(store.clone()).wait().unwrap();
+
+        let repo = BlobRepo::new(NoHeads, MemBookmarks::new(), store);
+        let raw = repo.get_changeset_raw(&csid).wait().unwrap();
+        assert_eq!(&raw[..], text.as_bytes());
+        let rehashed = BlobNode::new(raw.to_vec(), Some(&parent), None);
+        assert_eq!(rehashed.nodeid(), Some(csid));
+
+        match repo.get_changeset_raw(&node(9)).wait() {
+            Err(Error(ErrorKind::ChangesetMissing(nodeid), _)) => assert_eq!(nodeid, node(9)),
+            Err(err) => panic!("unexpected error {:?}", err),
+            Ok(_) => panic!("unexpected success"),
+        }
+
+        let boxed: Box<Repo<Error = Error>> = Box::new(repo);
+        assert_eq!(boxed.get_changeset_raw(&csid).wait().unwrap(), raw);
+    }
+
+    #[test]
+    fn verify_reachable() {
+        let store = Memblob::new();
+        let manifestid = node(2);
+        let text = format!("{}\nuser\n0 0\n\ncomment", manifestid);
+        let csnode = BlobNode::new(text.into_bytes(), None, None);
+        let csid = csnode.nodeid().unwrap();
+        let cs = BlobChangeset::new(&csid, RevlogChangeset::new(csnode).unwrap());
+        cs.save(store.clone()).wait().unwrap();
+
+        // Three files: one intact, one whose content blob is missing, and one whose content
+        // doesn't match its hash.
+        let mut manifest = Vec::new();
+        for &(n, content) in [(3, &b"good"[..]), (4, &b"lost"[..]), (5, &b"bad"[..])].iter() {
+            let sha1 = hash::Sha1::from(content);
+            let nodeblob = RawNodeBlob::new(Parents::None, sha1, content.len() as u64);
+            let nodekey = format!("node:{}.bincode", node(n));
+            store.populate(vec![(nodekey, nodeblob.serialize().unwrap())]);
+            match n {
+                3 => store.populate(vec![(format!("sha1:{}", sha1), content.to_vec())]),
+                5 => store.populate(vec![(format!("sha1:{}", sha1), b"corrupt".to_vec())]),
+                _ => (),
+            }
+            manifest.extend(format!("file{}\0{}\n", n, node(n)).into_bytes());
+        }
+        store.populate(vec![(format!("manifest:{}", manifestid), manifest)]);
+
+        let repo = BlobRepo::new(NoHeads, MemBookmarks::new(), store);
+        let events = repo.verify_reachable(&[csid, node(9)])
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(
+            events,
+            vec![
+                VerifyEvent::Ok(csid),
+                VerifyEvent::Ok(manifestid),
+                VerifyEvent::Ok(node(3)),
+                VerifyEvent::Missing(node(4)),
+                VerifyEvent::HashMismatch(node(5)),
+                VerifyEvent::Missing(node(9)),
+            ]
+        );
+    }
+
+    #[test]
+    fn shutdown_default() {
+        let repo = BlobRepo::new(NoHeads, MemBookmarks::new(), Memblob::new());
+        // BlobRepo holds nothing which needs releasing, so this is the default no-op.
+        repo.shutdown().wait().unwrap();
+    }
+}
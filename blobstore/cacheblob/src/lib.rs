@@ -0,0 +1,179 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+#![deny(warnings)]
+
+extern crate bytes;
+extern crate futures;
+extern crate linked_hash_map;
+
+extern crate blobstore;
+#[cfg(test)]
+extern crate memblob;
+
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use futures::{BoxFuture, Future};
+use futures::future;
+use linked_hash_map::LinkedHashMap;
+
+use blobstore::Blobstore;
+
+#[cfg(test)]
+mod test;
+
+/// LRU cache of blobs, bounded by both entry count and total size.
+///
+/// The size of an entry is the length of its key plus the length of its value.
+#[derive(Debug)]
+struct Cache {
+    blobs: LinkedHashMap<String, Bytes>,
+
+    entrylimit: usize, // max number of entries
+    bytelimit: usize, // max total size of entries
+
+    bytes: usize, // total size of entries
+}
+
+fn entry_size(key: &str, value: &Bytes) -> usize {
+    key.len() + value.len()
+}
+
+impl Cache {
+    fn new(entrylimit: usize, bytelimit: usize) -> Self {
+        Cache {
+            blobs: LinkedHashMap::new(),
+            entrylimit,
+            bytelimit,
+            bytes: 0,
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<Bytes> {
+        self.blobs.get_refresh(key).cloned()
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(value) = self.blobs.remove(key) {
+            self.bytes -= entry_size(key, &value);
+        }
+    }
+
+    /// Evict least recently used entries until there's room for one more of size `additional`.
+    fn trim(&mut self, additional: usize) {
+        while !self.blobs.is_empty()
+            && (self.blobs.len() >= self.entrylimit || self.bytes + additional > self.bytelimit)
+        {
+            if let Some((key, value)) = self.blobs.pop_front() {
+                self.bytes -= entry_size(&key, &value);
+            }
+        }
+    }
+
+    /// Insert a blob, replacing any existing value for the key. Blobs which can't fit in the
+    /// cache at all are not inserted.
+    fn insert(&mut self, key: String, value: Bytes) {
+        self.remove(&key);
+
+        let size = entry_size(&key, &value);
+        if self.entrylimit == 0 || size > self.bytelimit {
+            return;
+        }
+
+        self.trim(size);
+        self.bytes += size;
+        self.blobs.insert(key, value);
+    }
+}
+
+/// Read-through caching layer over another blobstore.
+///
+/// `get` is satisfied from an in-memory LRU cache if possible, otherwise it falls through to the
+/// underlying blobstore and caches the result. Successful `put`s also update the cache. Missing
+/// keys are not cached.
+///
+/// Clones share the same cache.
+#[derive(Clone)]
+pub struct CachingBlobstore<B> {
+    blobstore: B,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl<B> CachingBlobstore<B> {
+    /// Wrap `blobstore` with a cache holding at most `entrylimit` blobs, with a total size of at
+    /// most `bytelimit` bytes.
+    pub fn new(blobstore: B, entrylimit: usize, bytelimit: usize) -> Self {
+        CachingBlobstore {
+            blobstore,
+            cache: Arc::new(Mutex::new(Cache::new(entrylimit, bytelimit))),
+        }
+    }
+
+    /// Number of blobs currently cached.
+    pub fn cached_entries(&self) -> usize {
+        self.cache.lock().expect("lock poison").blobs.len()
+    }
+
+    /// Total size of the blobs currently cached, including their keys.
+    pub fn cached_bytes(&self) -> usize {
+        self.cache.lock().expect("lock poison").bytes
+    }
+
+    /// Returns true if `key` is currently cached. This doesn't count as a use of the entry.
+    pub fn is_cached(&self, key: &str) -> bool {
+        self.cache.lock().expect("lock poison").blobs.contains_key(key)
+    }
+
+    pub fn get_ref(&self) -> &B {
+        &self.blobstore
+    }
+}
+
+impl<B> Blobstore for CachingBlobstore<B>
+where
+    B: Blobstore<Key = String>,
+    B::ValueIn: From<Bytes>,
+    B::ValueOut: Into<Bytes>,
+{
+    type Key = String;
+    type ValueIn = Bytes;
+    type ValueOut = Bytes;
+    type Error = B::Error;
+
+    type GetBlob = BoxFuture<Option<Self::ValueOut>, Self::Error>;
+    type PutBlob = BoxFuture<(), Self::Error>;
+
+    fn get(&self, key: &Self::Key) -> Self::GetBlob {
+        let cached = self.cache.lock().expect("lock poison").get(key);
+        if let Some(value) = cached {
+            return future::ok(Some(value)).boxed();
+        }
+
+        let cache = self.cache.clone();
+        let key = key.clone();
+
+        self.blobstore
+            .get(&key)
+            .map(move |value| {
+                value.map(|value| {
+                    let value = value.into();
+                    cache.lock().expect("lock poison").insert(key, value.clone());
+                    value
+                })
+            })
+            .boxed()
+    }
+
+    fn put(&self, key: Self::Key, value: Self::ValueIn) -> Self::PutBlob {
+        let cache = self.cache.clone();
+
+        self.blobstore
+            .put(key.clone(), value.clone().into())
+            .map(move |()| cache.lock().expect("lock poison").insert(key, value))
+            .boxed()
+    }
+}
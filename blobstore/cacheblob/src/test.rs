@@ -0,0 +1,91 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use memblob::Memblob;
+
+use super::*;
+
+fn get(blobstore: &CachingBlobstore<Memblob>, key: &str) -> Option<Bytes> {
+    blobstore.get(&key.into()).wait().expect("get failed")
+}
+
+#[test]
+fn hit_after_put() {
+    let inner = Memblob::new();
+    let blobstore = CachingBlobstore::new(inner.clone(), 10, 1000);
+
+    blobstore
+        .put("foo".into(), Bytes::from(&b"bar"[..]))
+        .wait()
+        .expect("put failed");
+    assert!(blobstore.is_cached("foo"));
+    assert_eq!(blobstore.cached_bytes(), 6);
+
+    // Change the underlying value behind the cache's back, so only a hit can return the original.
+    inner.populate(vec![("foo", b"changed".to_vec())]);
+    assert_eq!(get(&blobstore, "foo"), Some(Bytes::from(&b"bar"[..])));
+}
+
+#[test]
+fn miss_then_hit() {
+    let inner = Memblob::new();
+    inner.populate(vec![("foo", b"bar".to_vec())]);
+    let blobstore = CachingBlobstore::new(inner.clone(), 10, 1000);
+
+    assert!(!blobstore.is_cached("foo"));
+    assert_eq!(get(&blobstore, "foo"), Some(Bytes::from(&b"bar"[..])));
+    assert!(blobstore.is_cached("foo"));
+
+    inner.populate(vec![("foo", b"changed".to_vec())]);
+    assert_eq!(get(&blobstore, "foo"), Some(Bytes::from(&b"bar"[..])));
+
+    // Missing keys aren't cached.
+    assert_eq!(get(&blobstore, "missing"), None);
+    assert!(!blobstore.is_cached("missing"));
+    assert_eq!(blobstore.cached_entries(), 1);
+}
+
+#[test]
+fn evict_bytes() {
+    let inner = Memblob::new();
+    inner.populate((0..4).map(|i| (format!("{}", i), vec![i; 9])));
+    // Room for plenty of entries, but only two 10 byte ones.
+    let blobstore = CachingBlobstore::new(inner, 100, 25);
+
+    assert!(get(&blobstore, "0").is_some());
+    assert!(get(&blobstore, "1").is_some());
+    assert_eq!(blobstore.cached_bytes(), 20);
+
+    // Make "0" most recently used, so "1" is evicted next.
+    assert!(get(&blobstore, "0").is_some());
+    assert!(get(&blobstore, "2").is_some());
+    assert!(blobstore.is_cached("0"));
+    assert!(!blobstore.is_cached("1"));
+    assert!(blobstore.is_cached("2"));
+    assert_eq!(blobstore.cached_bytes(), 20);
+
+    // Too big to cache at all, and doesn't evict anything.
+    blobstore
+        .put("big".into(), Bytes::from(vec![0; 100]))
+        .wait()
+        .expect("put failed");
+    assert!(!blobstore.is_cached("big"));
+    assert_eq!(blobstore.cached_entries(), 2);
+    assert_eq!(get(&blobstore, "big"), Some(Bytes::from(vec![0; 100])));
+}
+
+#[test]
+fn evict_entries() {
+    let inner = Memblob::new();
+    inner.populate((0..4).map(|i| (format!("{}", i), vec![i])));
+    let blobstore = CachingBlobstore::new(inner, 2, 1000);
+
+    for key in &["0", "1", "2"] {
+        assert!(get(&blobstore, key).is_some());
+    }
+    assert_eq!(blobstore.cached_entries(), 2);
+    assert!(!blobstore.is_cached("0"));
+}
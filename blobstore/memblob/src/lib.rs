@@ -5,6 +5,7 @@
 // GNU General Public License version 2 or any later version.
 
 extern crate blobstore;
+extern crate bytes;
 #[macro_use]
 extern crate error_chain;
 extern crate futures;
@@ -12,30 +13,48 @@ extern crate futures;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
+use bytes::Bytes;
+use futures::{BoxFuture, Future};
 use futures::future::{FutureResult, IntoFuture};
+use futures::stream::{self, BoxStream, Stream};
 
-use blobstore::Blobstore;
+use blobstore::{Blobstore, ScannableBlobstore};
 
 mod errors;
 pub use errors::*;
 
 /// In-memory "blob store"
 ///
-/// Pure in-memory implementation for testing.
+/// Pure in-memory implementation for testing. Values are stored as `Bytes`, so a `get` is just a
+/// reference count bump rather than a copy. Clones share the same underlying store.
 #[derive(Clone)]
 pub struct Memblob {
-    hash: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    hash: Arc<Mutex<HashMap<String, Bytes>>>,
 }
 
 impl Memblob {
     pub fn new() -> Self {
         Memblob { hash: Arc::new(Mutex::new(HashMap::new())) }
     }
+
+    /// Synchronously insert all of `blobs`, replacing any existing values.
+    pub fn populate<I, K, V>(&self, blobs: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<Bytes>,
+    {
+        let mut inner = self.hash.lock().expect("lock poison");
+
+        for (k, v) in blobs {
+            inner.insert(k.into(), v.into());
+        }
+    }
 }
 
 impl Blobstore for Memblob {
     type Key = String;
-    type ValueIn = Vec<u8>;
+    type ValueIn = Bytes;
     type ValueOut = Self::ValueIn;
     type Error = Error;
     type PutBlob = FutureResult<(), Self::Error>;
@@ -53,22 +72,46 @@ impl Blobstore for Memblob {
 
         Ok(inner.get(k).map(Clone::clone)).into_future()
     }
+
+    fn put_if_absent(&self, k: Self::Key, v: Self::ValueIn) -> BoxFuture<bool, Self::Error> {
+        let mut inner = self.hash.lock().expect("lock poison");
+
+        let absent = !inner.contains_key(&k);
+        if absent {
+            inner.insert(k, v);
+        }
+        Ok(absent).into_future().boxed()
+    }
+}
+
+impl ScannableBlobstore for Memblob {
+    /// The matching keys are copied out up front, so the stream is a snapshot of the store.
+    fn keys(&self, prefix: &str) -> BoxStream<String, Self::Error> {
+        let inner = self.hash.lock().expect("lock poison");
+
+        let keys: Vec<_> = inner
+            .keys()
+            .filter(|k| k.starts_with(prefix))
+            .cloned()
+            .map(Ok)
+            .collect();
+        stream::iter(keys).boxed()
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use futures::Future;
 
     #[test]
     fn roundtrip() {
         let mb = Memblob::new();
 
-        let res = mb.put("hello".into(), vec![1, 2, 3, 4, 5]);
+        let res = mb.put("hello".into(), Bytes::from(vec![1, 2, 3, 4, 5]));
         assert!(res.wait().is_ok());
 
         match mb.get(&"hello".into()).wait() {
-            Ok(v) => assert_eq!(v, Some(vec![1, 2, 3, 4, 5])),
+            Ok(v) => assert_eq!(v, Some(Bytes::from(vec![1, 2, 3, 4, 5]))),
             Err(err) => panic!("Unexpected error {:?}", err),
         }
     }
@@ -83,4 +126,69 @@ mod test {
             Err(err) => panic!("Unexpected error {:?}", err),
         }
     }
+
+    #[test]
+    fn populate_shared() {
+        let mb = Memblob::new();
+        let clone = mb.clone();
+
+        clone.populate(vec![("a", b"1".to_vec()), ("b", b"2".to_vec())]);
+        clone.populate(vec![("a", b"3".to_vec())]);
+
+        assert_eq!(mb.get(&"a".into()).wait().unwrap(), Some(Bytes::from(&b"3"[..])));
+        assert_eq!(mb.get(&"b".into()).wait().unwrap(), Some(Bytes::from(&b"2"[..])));
+    }
+
+    #[test]
+    fn put_if_absent() {
+        let mb = Memblob::new();
+        let put = |value: &'static [u8]| {
+            mb.put_if_absent("sha1:1111".into(), Bytes::from(value))
+                .wait()
+                .unwrap()
+        };
+
+        assert!(put(b"first"));
+        assert!(!put(b"second"));
+        assert_eq!(
+            mb.get(&"sha1:1111".into()).wait().unwrap(),
+            Some(Bytes::from(&b"first"[..]))
+        );
+    }
+
+    #[test]
+    fn put_if_absent_default() {
+        // The arced store doesn't override `put_if_absent`, so this uses get-then-put.
+        let mb = Memblob::new().arced::<Bytes, Bytes, Error>();
+        let put = |value: &'static [u8]| {
+            mb.put_if_absent("sha1:1111".into(), Bytes::from(value))
+                .wait()
+                .unwrap()
+        };
+
+        assert!(put(b"first"));
+        assert!(!put(b"second"));
+        assert_eq!(
+            mb.get(&"sha1:1111".into()).wait().unwrap(),
+            Some(Bytes::from(&b"first"[..]))
+        );
+    }
+
+    #[test]
+    fn keys() {
+        let mb = Memblob::new();
+        mb.populate(vec![
+            ("sha1:1111", b"a".to_vec()),
+            ("sha1:2222", b"b".to_vec()),
+            ("node:1111.bincode", b"c".to_vec()),
+            ("sha1", b"d".to_vec()),
+        ]);
+
+        let mut keys = mb.keys("sha1:").collect().wait().unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["sha1:1111", "sha1:2222"]);
+
+        assert_eq!(mb.keys("").collect().wait().unwrap().len(), 4);
+        assert!(mb.keys("manifest:").collect().wait().unwrap().is_empty());
+    }
 }
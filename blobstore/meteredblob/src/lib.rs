@@ -0,0 +1,151 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+#![deny(warnings)]
+
+extern crate bytes;
+extern crate futures;
+
+extern crate blobstore;
+#[cfg(test)]
+extern crate memblob;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures::{BoxFuture, Future};
+
+use blobstore::Blobstore;
+
+#[cfg(test)]
+mod test;
+
+/// Snapshot of the counters kept by a `MeteredBlobstore`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MeteredStats {
+    pub gets: usize,
+    pub puts: usize,
+    /// Total size of the values returned by `get`. Missing keys count as 0.
+    pub bytes_read: usize,
+    /// Total size of the values passed to `put`, whether or not the put succeeded.
+    pub bytes_written: usize,
+    /// Total time from each `get` being issued to it completing, successfully or not.
+    pub get_time: Duration,
+    /// Total time from each `put` being issued to it completing, successfully or not.
+    pub put_time: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    gets: AtomicUsize,
+    puts: AtomicUsize,
+    bytes_read: AtomicUsize,
+    bytes_written: AtomicUsize,
+    get_nanos: AtomicUsize,
+    put_nanos: AtomicUsize,
+}
+
+fn add_elapsed(counter: &AtomicUsize, start: Instant) {
+    let elapsed = start.elapsed();
+    let nanos = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+    counter.fetch_add(nanos as usize, Ordering::Relaxed);
+}
+
+fn duration_from_nanos(nanos: usize) -> Duration {
+    let nanos = nanos as u64;
+    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+/// Blobstore wrapper which counts the operations issued through it, the bytes they move and
+/// the time they take, for profiling. `stats` returns a snapshot of the counters.
+///
+/// The counters are updated with relaxed atomics, so a snapshot taken while operations are in
+/// flight may be slightly inconsistent between fields. Clones share the same counters.
+#[derive(Clone)]
+pub struct MeteredBlobstore<B> {
+    blobstore: B,
+    counters: Arc<Counters>,
+}
+
+impl<B> MeteredBlobstore<B> {
+    pub fn new(blobstore: B) -> Self {
+        MeteredBlobstore {
+            blobstore,
+            counters: Arc::new(Counters::default()),
+        }
+    }
+
+    /// Return the current values of the counters.
+    pub fn stats(&self) -> MeteredStats {
+        let counters = &self.counters;
+        MeteredStats {
+            gets: counters.gets.load(Ordering::Relaxed),
+            puts: counters.puts.load(Ordering::Relaxed),
+            bytes_read: counters.bytes_read.load(Ordering::Relaxed),
+            bytes_written: counters.bytes_written.load(Ordering::Relaxed),
+            get_time: duration_from_nanos(counters.get_nanos.load(Ordering::Relaxed)),
+            put_time: duration_from_nanos(counters.put_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    pub fn get_ref(&self) -> &B {
+        &self.blobstore
+    }
+}
+
+impl<B> Blobstore for MeteredBlobstore<B>
+where
+    B: Blobstore<Key = String>,
+    B::ValueIn: From<Bytes>,
+    B::ValueOut: Into<Bytes>,
+{
+    type Key = String;
+    type ValueIn = Bytes;
+    type ValueOut = Bytes;
+    type Error = B::Error;
+
+    type GetBlob = BoxFuture<Option<Self::ValueOut>, Self::Error>;
+    type PutBlob = BoxFuture<(), Self::Error>;
+
+    fn get(&self, key: &Self::Key) -> Self::GetBlob {
+        let counters = self.counters.clone();
+        let start = Instant::now();
+        counters.gets.fetch_add(1, Ordering::Relaxed);
+
+        self.blobstore
+            .get(key)
+            .then(move |res| {
+                add_elapsed(&counters.get_nanos, start);
+                res.map(|value| {
+                    value.map(|value| {
+                        let value = value.into();
+                        counters.bytes_read.fetch_add(value.len(), Ordering::Relaxed);
+                        value
+                    })
+                })
+            })
+            .boxed()
+    }
+
+    fn put(&self, key: Self::Key, value: Self::ValueIn) -> Self::PutBlob {
+        let counters = self.counters.clone();
+        let start = Instant::now();
+        counters.puts.fetch_add(1, Ordering::Relaxed);
+        counters
+            .bytes_written
+            .fetch_add(value.len(), Ordering::Relaxed);
+
+        self.blobstore
+            .put(key, value.into())
+            .then(move |res| {
+                add_elapsed(&counters.put_nanos, start);
+                res
+            })
+            .boxed()
+    }
+}
@@ -0,0 +1,54 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use memblob::Memblob;
+
+use super::*;
+
+#[test]
+fn counts() {
+    let inner = Memblob::new();
+    inner.populate(vec![("existing", b"12345".to_vec())]);
+    let blobstore = MeteredBlobstore::new(inner);
+    assert_eq!(blobstore.stats(), MeteredStats::default());
+
+    blobstore
+        .put("foo".into(), Bytes::from(&b"bar"[..]))
+        .wait()
+        .expect("put failed");
+    blobstore
+        .put("baz".into(), Bytes::from(&b"quux"[..]))
+        .wait()
+        .expect("put failed");
+
+    let get = |key: &str| blobstore.get(&key.into()).wait().expect("get failed");
+    assert_eq!(get("foo"), Some(Bytes::from(&b"bar"[..])));
+    assert_eq!(get("existing"), Some(Bytes::from(&b"12345"[..])));
+    assert_eq!(get("missing"), None);
+
+    let stats = blobstore.stats();
+    assert_eq!(stats.gets, 3);
+    assert_eq!(stats.puts, 2);
+    assert_eq!(stats.bytes_read, 8);
+    assert_eq!(stats.bytes_written, 7);
+}
+
+#[test]
+fn clones_share_counters() {
+    let blobstore = MeteredBlobstore::new(Memblob::new());
+    let clone = blobstore.clone();
+
+    clone
+        .put("foo".into(), Bytes::from(&b"bar"[..]))
+        .wait()
+        .expect("put failed");
+    clone.get(&"foo".into()).wait().expect("get failed");
+
+    let stats = blobstore.stats();
+    assert_eq!(stats.gets, 1);
+    assert_eq!(stats.puts, 1);
+    assert_eq!(stats, clone.stats());
+}
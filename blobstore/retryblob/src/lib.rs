@@ -0,0 +1,183 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+#![deny(warnings)]
+
+extern crate futures;
+
+extern crate blobstore;
+
+use std::cmp;
+use std::error;
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use futures::{BoxFuture, Future};
+use futures::future::{self, Loop};
+use futures::sync::oneshot;
+
+use blobstore::Blobstore;
+
+#[cfg(test)]
+mod test;
+
+/// Source of the delays between retries.
+pub trait Sleep: Send + Sync + 'static {
+    /// Return a future which completes after `duration`.
+    fn sleep(&self, duration: Duration) -> BoxFuture<(), ()>;
+}
+
+/// Sleep on a new thread for each delay. Retries should be rare, so this avoids tying the
+/// blobstore to any particular event loop.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ThreadSleep;
+
+impl Sleep for ThreadSleep {
+    fn sleep(&self, duration: Duration) -> BoxFuture<(), ()> {
+        let (tx, rx) = oneshot::channel();
+        thread::spawn(move || {
+            thread::sleep(duration);
+            let _ = tx.send(());
+        });
+        rx.map_err(|_| ()).boxed()
+    }
+}
+
+/// The backoff doubles after each failed attempt, up to this many times.
+const MAX_BACKOFF_DOUBLINGS: u32 = 16;
+
+/// Default classification of errors for `RetryingBlobstore`: an `io::Error` which is timed out,
+/// interrupted or would block is worth retrying, and anything else isn't.
+pub fn is_transient_io_error<E: error::Error + 'static>(err: &E) -> bool {
+    let err: &(error::Error + 'static) = err;
+    match err.downcast_ref::<io::Error>() {
+        Some(err) => match err.kind() {
+            io::ErrorKind::TimedOut |
+            io::ErrorKind::Interrupted |
+            io::ErrorKind::WouldBlock => true,
+            _ => false,
+        },
+        None => false,
+    }
+}
+
+/// Blobstore wrapper which retries failed operations.
+///
+/// An operation is tried at most `max_attempts` times, and only retried if the error is
+/// retryable, as decided by `is_transient_io_error` or the predicate given to `with_retryable`.
+/// Other errors are returned straight away. The first retry happens after `base_delay`, and
+/// the delay doubles for each retry after that.
+pub struct RetryingBlobstore<B: Blobstore> {
+    blobstore: Arc<B>,
+    max_attempts: usize,
+    base_delay: Duration,
+    is_retryable: fn(&B::Error) -> bool,
+    sleep: Arc<Sleep>,
+}
+
+impl<B: Blobstore> Clone for RetryingBlobstore<B> {
+    fn clone(&self) -> Self {
+        RetryingBlobstore {
+            blobstore: self.blobstore.clone(),
+            max_attempts: self.max_attempts,
+            base_delay: self.base_delay,
+            is_retryable: self.is_retryable,
+            sleep: self.sleep.clone(),
+        }
+    }
+}
+
+impl<B: Blobstore> RetryingBlobstore<B> {
+    /// Wrap `blobstore`, trying each operation up to `max_attempts` times in total. An
+    /// attempt limit of 0 is treated as 1.
+    pub fn new(blobstore: B, max_attempts: usize, base_delay: Duration) -> Self {
+        RetryingBlobstore {
+            blobstore: Arc::new(blobstore),
+            max_attempts: cmp::max(max_attempts, 1),
+            base_delay,
+            is_retryable: is_transient_io_error::<B::Error>,
+            sleep: Arc::new(ThreadSleep),
+        }
+    }
+
+    /// Use `is_retryable` to decide which errors are worth retrying.
+    pub fn with_retryable(self, is_retryable: fn(&B::Error) -> bool) -> Self {
+        RetryingBlobstore {
+            is_retryable,
+            ..self
+        }
+    }
+
+    /// Use `sleep` to wait between retries instead of `ThreadSleep`.
+    pub fn with_sleep<S: Sleep>(self, sleep: S) -> Self {
+        RetryingBlobstore {
+            sleep: Arc::new(sleep),
+            ..self
+        }
+    }
+
+    pub fn get_ref(&self) -> &B {
+        &self.blobstore
+    }
+
+    /// Run `op` until it succeeds, fails with an error that isn't retryable, or has been tried
+    /// `max_attempts` times.
+    fn retry<F, R>(&self, mut op: F) -> BoxFuture<R::Item, B::Error>
+    where
+        F: FnMut(&B) -> R + Send + 'static,
+        R: Future<Error = B::Error> + Send + 'static,
+        R::Item: Send + 'static,
+        B: Sync,
+    {
+        let blobstore = self.blobstore.clone();
+        let max_attempts = self.max_attempts;
+        let base_delay = self.base_delay;
+        let is_retryable = self.is_retryable;
+        let sleep = self.sleep.clone();
+
+        future::loop_fn(1, move |attempt| {
+            let sleep = sleep.clone();
+            op(&blobstore).then(move |res| match res {
+                Ok(value) => future::ok(Loop::Break(value)).boxed(),
+                Err(err) => {
+                    if attempt >= max_attempts || !is_retryable(&err) {
+                        return future::err(err).boxed();
+                    }
+                    let doublings = cmp::min(attempt as u32 - 1, MAX_BACKOFF_DOUBLINGS);
+                    sleep
+                        .sleep(base_delay * (1 << doublings))
+                        .then(move |_| Ok(Loop::Continue(attempt + 1)))
+                        .boxed()
+                }
+            })
+        }).boxed()
+    }
+}
+
+impl<B> Blobstore for RetryingBlobstore<B>
+where
+    B: Blobstore<Key = String> + Sync,
+    B::ValueIn: Clone,
+{
+    type Key = String;
+    type ValueIn = B::ValueIn;
+    type ValueOut = B::ValueOut;
+    type Error = B::Error;
+
+    type GetBlob = BoxFuture<Option<Self::ValueOut>, Self::Error>;
+    type PutBlob = BoxFuture<(), Self::Error>;
+
+    fn get(&self, key: &Self::Key) -> Self::GetBlob {
+        let key = key.clone();
+        self.retry(move |blobstore| blobstore.get(&key))
+    }
+
+    fn put(&self, key: Self::Key, value: Self::ValueIn) -> Self::PutBlob {
+        self.retry(move |blobstore| blobstore.put(key.clone(), value.clone()))
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::future::FutureResult;
+
+use super::*;
+
+/// Blobstore which fails the first `failures` operations with an error of kind `kind`, then
+/// succeeds.
+#[derive(Clone)]
+struct FlakyBlobstore {
+    failures: Arc<AtomicUsize>,
+    calls: Arc<AtomicUsize>,
+    kind: io::ErrorKind,
+}
+
+impl FlakyBlobstore {
+    fn new(failures: usize, kind: io::ErrorKind) -> Self {
+        FlakyBlobstore {
+            failures: Arc::new(AtomicUsize::new(failures)),
+            calls: Arc::new(AtomicUsize::new(0)),
+            kind,
+        }
+    }
+
+    fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    fn attempt(&self) -> Result<(), io::Error> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        if self.failures.load(Ordering::SeqCst) > 0 {
+            self.failures.fetch_sub(1, Ordering::SeqCst);
+            Err(io::Error::new(self.kind, "flaky"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Blobstore for FlakyBlobstore {
+    type Key = String;
+    type ValueIn = Vec<u8>;
+    type ValueOut = Vec<u8>;
+    type Error = io::Error;
+    type GetBlob = FutureResult<Option<Vec<u8>>, io::Error>;
+    type PutBlob = FutureResult<(), io::Error>;
+
+    fn get(&self, key: &String) -> Self::GetBlob {
+        future::result(self.attempt().map(|()| Some(key.clone().into_bytes())))
+    }
+
+    fn put(&self, _key: String, _value: Vec<u8>) -> Self::PutBlob {
+        future::result(self.attempt())
+    }
+}
+
+/// Records the requested delays without actually waiting.
+#[derive(Clone, Default)]
+struct RecordingSleep(Arc<Mutex<Vec<Duration>>>);
+
+impl Sleep for RecordingSleep {
+    fn sleep(&self, duration: Duration) -> BoxFuture<(), ()> {
+        self.0.lock().unwrap().push(duration);
+        future::ok(()).boxed()
+    }
+}
+
+fn retrying(
+    inner: &FlakyBlobstore,
+    max_attempts: usize,
+) -> (RetryingBlobstore<FlakyBlobstore>, RecordingSleep) {
+    let sleep = RecordingSleep::default();
+    let blobstore = RetryingBlobstore::new(inner.clone(), max_attempts, Duration::from_millis(10))
+        .with_sleep(sleep.clone());
+    (blobstore, sleep)
+}
+
+#[test]
+fn succeeds_after_retries() {
+    let inner = FlakyBlobstore::new(2, io::ErrorKind::TimedOut);
+    let (blobstore, sleep) = retrying(&inner, 3);
+
+    let got = blobstore.get(&"key".into()).wait().unwrap();
+    assert_eq!(got, Some(b"key".to_vec()));
+    assert_eq!(inner.calls(), 3);
+    assert_eq!(
+        *sleep.0.lock().unwrap(),
+        vec![Duration::from_millis(10), Duration::from_millis(20)]
+    );
+}
+
+#[test]
+fn gives_up_after_max_attempts() {
+    let inner = FlakyBlobstore::new(3, io::ErrorKind::Interrupted);
+    let (blobstore, sleep) = retrying(&inner, 3);
+
+    let err = blobstore.get(&"key".into()).wait().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    assert_eq!(inner.calls(), 3);
+    assert_eq!(sleep.0.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn put_retried() {
+    let inner = FlakyBlobstore::new(1, io::ErrorKind::WouldBlock);
+    let (blobstore, _) = retrying(&inner, 2);
+
+    blobstore.put("key".into(), b"value".to_vec()).wait().unwrap();
+    assert_eq!(inner.calls(), 2);
+}
+
+#[test]
+fn not_retryable() {
+    let inner = FlakyBlobstore::new(1, io::ErrorKind::NotFound);
+    let (blobstore, sleep) = retrying(&inner, 5);
+
+    let err = blobstore.get(&"key".into()).wait().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    assert_eq!(inner.calls(), 1);
+    assert!(sleep.0.lock().unwrap().is_empty());
+}
+
+#[test]
+fn custom_retryable() {
+    fn always(_err: &io::Error) -> bool {
+        true
+    }
+
+    let inner = FlakyBlobstore::new(1, io::ErrorKind::NotFound);
+    let (blobstore, _) = retrying(&inner, 2);
+    let blobstore = blobstore.with_retryable(always);
+
+    assert!(blobstore.get(&"key".into()).wait().is_ok());
+    assert_eq!(inner.calls(), 2);
+}
+
+#[test]
+fn thread_sleep() {
+    let inner = FlakyBlobstore::new(1, io::ErrorKind::TimedOut);
+    let blobstore = RetryingBlobstore::new(inner.clone(), 2, Duration::from_millis(1));
+
+    assert!(blobstore.get(&"key".into()).wait().is_ok());
+    assert_eq!(inner.calls(), 2);
+}
@@ -9,7 +9,9 @@ extern crate futures;
 use std::sync::Arc;
 use std::error;
 
-use futures::Future;
+use futures::{BoxFuture, Future};
+use futures::future;
+use futures::stream::BoxStream;
 
 mod boxed;
 
@@ -90,6 +92,28 @@ pub trait Blobstore: Send + 'static {
     fn get(&self, key: &Self::Key) -> Self::GetBlob;
     fn put(&self, key: Self::Key, value: Self::ValueIn) -> Self::PutBlob;
 
+    /// Store `value` under `key` unless the key already has a value, returning true if it was
+    /// stored. This is meant for content-addressed keys such as `sha1:{hash}`, whose values
+    /// never change, so writing one which is already there is wasted IO.
+    ///
+    /// The default implementation is a `get` followed by a `put`, so concurrent calls for the
+    /// same key can both write, and both return true. With content-addressed keys they write the
+    /// same value, so this is harmless. Stores which can check and write atomically should
+    /// override it.
+    fn put_if_absent(&self, key: Self::Key, value: Self::ValueIn) -> BoxFuture<bool, Self::Error>
+    where
+        Self: Clone + Sized,
+        Self::Key: Clone,
+    {
+        let blobstore = self.clone();
+        self.get(&key)
+            .and_then(move |existing| match existing {
+                Some(_) => future::ok(false).boxed(),
+                None => blobstore.put(key, value).map(|()| true).boxed(),
+            })
+            .boxed()
+    }
+
     fn boxed<Vi, Vo, E>(self) -> BoxBlobstore<Self::Key, Vi, Vo, E>
     where
         Self: Sized,
@@ -115,6 +139,17 @@ pub trait Blobstore: Send + 'static {
     }
 }
 
+/// A Blobstore which can also list the keys it holds, for maintenance operations such as
+/// garbage collection which need to find every blob.
+pub trait ScannableBlobstore: Blobstore {
+    /// Return every key starting with `prefix`, such as `"sha1:"`, in no particular order.
+    ///
+    /// Stores can be very large, so implementations should fetch keys a page at a time as the
+    /// stream is consumed rather than holding the whole set in memory. Keys put while the scan
+    /// is in progress may or may not be included.
+    fn keys(&self, prefix: &str) -> BoxStream<String, Self::Error>;
+}
+
 impl<K, Vi, Vo, E, GB, PB> Blobstore
     for Arc<
         Blobstore<Key = K, ValueIn = Vi, ValueOut = Vo, Error = E, GetBlob = GB, PutBlob = PB>
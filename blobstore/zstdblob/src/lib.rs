@@ -0,0 +1,149 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+#![deny(warnings)]
+
+#[macro_use]
+extern crate error_chain;
+extern crate futures;
+extern crate zstd;
+
+extern crate blobstore;
+#[cfg(test)]
+extern crate memblob;
+
+use std::io::{Read, Write};
+
+use futures::{BoxFuture, Future};
+use futures::future;
+
+use blobstore::Blobstore;
+
+#[cfg(test)]
+mod test;
+
+mod errors {
+    error_chain! {
+        errors {
+            Blobstore {
+                description("underlying blobstore failed")
+            }
+        }
+
+        foreign_links {
+            Io(::std::io::Error);
+        }
+    }
+}
+
+use errors::*;
+pub use errors::{Error, ErrorKind};
+
+/// Default zstd compression level, trading off speed against size.
+pub const DEFAULT_LEVEL: i32 = 3;
+
+/// Header byte prepended to every compressed value.
+const HEADER: u8 = 0x01;
+
+/// Magic number which starts every zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Blobstore wrapper which zstd-compresses values on the way in and decompresses them on the way
+/// out.
+///
+/// Compressed values are stored with a `HEADER` byte in front of the zstd frame. Values which
+/// don't start with the header followed by the zstd magic are legacy uncompressed blobs, and are
+/// returned unchanged.
+#[derive(Debug, Clone)]
+pub struct CompressedBlobstore<B> {
+    blobstore: B,
+    level: i32,
+}
+
+impl<B> CompressedBlobstore<B> {
+    /// Wrap `blobstore`, compressing new values with `DEFAULT_LEVEL`.
+    pub fn new(blobstore: B) -> Self {
+        Self::with_level(blobstore, DEFAULT_LEVEL)
+    }
+
+    /// Wrap `blobstore`, compressing new values with the given zstd level.
+    pub fn with_level(blobstore: B, level: i32) -> Self {
+        CompressedBlobstore { blobstore, level }
+    }
+
+    pub fn level(&self) -> i32 {
+        self.level
+    }
+
+    pub fn get_ref(&self) -> &B {
+        &self.blobstore
+    }
+
+    pub fn into_inner(self) -> B {
+        self.blobstore
+    }
+}
+
+/// Return true if `data` is a value compressed by `CompressedBlobstore`, rather than a legacy
+/// uncompressed one.
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.len() > ZSTD_MAGIC.len() && data[0] == HEADER && data[1..].starts_with(&ZSTD_MAGIC)
+}
+
+fn compress(data: &[u8], level: i32) -> Result<Vec<u8>> {
+    let mut encoder = zstd::Encoder::new(vec![HEADER], level)?;
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Decompress a value as stored by `CompressedBlobstore`, for readers which fetch values from
+/// the underlying blobstore directly. Legacy uncompressed values are returned unchanged.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    if !is_compressed(data) {
+        return Ok(data.to_vec());
+    }
+
+    let mut decoder = zstd::Decoder::new(&data[1..])?;
+    let mut out = Vec::new();
+    decoder.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+impl<B> Blobstore for CompressedBlobstore<B>
+where
+    B: Blobstore<Key = String>,
+    B::ValueIn: From<Vec<u8>>,
+    B::ValueOut: AsRef<[u8]>,
+{
+    type Key = String;
+    type ValueIn = Vec<u8>;
+    type ValueOut = Vec<u8>;
+    type Error = Error;
+
+    type GetBlob = BoxFuture<Option<Self::ValueOut>, Self::Error>;
+    type PutBlob = BoxFuture<(), Self::Error>;
+
+    fn get(&self, key: &Self::Key) -> Self::GetBlob {
+        self.blobstore
+            .get(key)
+            .map_err(|err| Error::with_chain(err, ErrorKind::Blobstore))
+            .and_then(|blob| match blob {
+                Some(blob) => decompress(blob.as_ref()).map(Some),
+                None => Ok(None),
+            })
+            .boxed()
+    }
+
+    fn put(&self, key: Self::Key, value: Self::ValueIn) -> Self::PutBlob {
+        match compress(&value, self.level) {
+            Ok(compressed) => self.blobstore
+                .put(key, compressed.into())
+                .map_err(|err| Error::with_chain(err, ErrorKind::Blobstore))
+                .boxed(),
+            Err(err) => future::err(err).boxed(),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use memblob::Memblob;
+
+use super::*;
+
+fn content() -> Vec<u8> {
+    b"compressible text content\n".iter().cycle().take(10000).cloned().collect()
+}
+
+#[test]
+fn roundtrip() {
+    let inner = Memblob::new();
+    let blobstore = CompressedBlobstore::with_level(inner.clone(), 19);
+    assert_eq!(blobstore.level(), 19);
+
+    blobstore
+        .put("sha1:foo".into(), content())
+        .wait()
+        .expect("put failed");
+
+    let stored = inner.get(&"sha1:foo".into()).wait().unwrap().expect("missing");
+    assert!(is_compressed(&stored));
+    assert!(stored.len() < content().len());
+
+    let out = blobstore.get(&"sha1:foo".into()).wait().expect("get failed");
+    assert_eq!(out, Some(content()));
+}
+
+#[test]
+fn roundtrip_empty() {
+    let blobstore = CompressedBlobstore::new(Memblob::new());
+
+    blobstore.put("empty".into(), vec![]).wait().expect("put failed");
+    let out = blobstore.get(&"empty".into()).wait().expect("get failed");
+    assert_eq!(out, Some(vec![]));
+}
+
+#[test]
+fn legacy_uncompressed() {
+    let inner = Memblob::new();
+    // Includes a value starting with the header byte, but without the zstd magic after it.
+    inner.populate(vec![("legacy", content()), ("header", vec![HEADER, 2, 3])]);
+    let blobstore = CompressedBlobstore::new(inner);
+
+    let out = blobstore.get(&"legacy".into()).wait().expect("get failed");
+    assert_eq!(out, Some(content()));
+    let out = blobstore.get(&"header".into()).wait().expect("get failed");
+    assert_eq!(out, Some(vec![HEADER, 2, 3]));
+}
+
+#[test]
+fn missing() {
+    let blobstore = CompressedBlobstore::new(Memblob::new());
+
+    let out = blobstore.get(&"missing".into()).wait().expect("get failed");
+    assert!(out.is_none());
+}
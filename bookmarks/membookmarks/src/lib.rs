@@ -16,10 +16,11 @@ use std::collections::hash_map::Entry;
 use std::sync::Mutex;
 use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
 
-use futures::future::{FutureResult, ok};
+use futures::BoxFuture;
+use futures::future::{self, Future, FutureResult, ok};
 use futures::stream::{BoxStream, Stream, iter};
 
-use bookmarks::{Bookmarks, BookmarksMut, Version};
+use bookmarks::{BookmarkUpdate, Bookmarks, BookmarksMut, TransactionalBookmarks, Version};
 
 mod errors {
     // Create Error, ErrorKind, ResultExt, and Result types.
@@ -125,12 +126,54 @@ where
     }
 }
 
+impl<V> TransactionalBookmarks for MemBookmarks<V>
+where
+    V: Clone + Send + 'static,
+{
+    fn apply_transaction(
+        &self,
+        updates: Vec<(Vec<u8>, BookmarkUpdate<Self::Value>)>,
+    ) -> BoxFuture<bool, Self::Error> {
+        let mut bookmarks = self.bookmarks.lock().unwrap();
+
+        // Check every expected version before changing anything.
+        let matches = updates.iter().all(|&(ref key, ref update)| {
+            let current = bookmarks
+                .get(key)
+                .map_or(Version::absent(), |&(_, version)| version);
+            match *update {
+                BookmarkUpdate::Set(_, None) => true,
+                BookmarkUpdate::Set(_, Some(expected)) | BookmarkUpdate::Delete(expected) => {
+                    current == expected
+                }
+            }
+        });
+        if !matches {
+            return future::ok(false).boxed();
+        }
+
+        for (key, update) in updates {
+            match update {
+                BookmarkUpdate::Set(value, _) => {
+                    bookmarks.insert(key, (value, version_next()));
+                }
+                BookmarkUpdate::Delete(_) => {
+                    bookmarks.remove(&key);
+                }
+            }
+        }
+        future::ok(true).boxed()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use futures::Future;
     use futures::Stream;
 
+    use bookmarks::Transaction;
+
     #[test]
     fn test_single() {
         let bookmarks = MemBookmarks::new();
@@ -182,4 +225,51 @@ mod test {
         let expected = vec![b"A", b"B", b"C"];
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn test_transaction() {
+        let bookmarks = MemBookmarks::new();
+        let master_v1 = bookmarks.create(&"master", &"1").wait().unwrap().unwrap();
+        let old_v1 = bookmarks.create(&"old", &"0").wait().unwrap().unwrap();
+
+        let mut txn = Transaction::new(&bookmarks);
+        txn.set(&"master", "2", Some(master_v1));
+        txn.set(&"stable", "1", Some(Version::absent()));
+        txn.delete(&"old", old_v1);
+        assert!(txn.commit().wait().unwrap());
+
+        let (value, master_v2) = bookmarks.get(&"master").wait().unwrap().unwrap();
+        assert_eq!(value, "2");
+        assert!(master_v2 != master_v1);
+        assert_eq!(bookmarks.get(&"stable").wait().unwrap().unwrap().0, "1");
+        assert_eq!(bookmarks.get(&"old").wait().unwrap(), None);
+
+        // An update without an expected version always applies.
+        let mut txn = Transaction::new(&bookmarks);
+        txn.set(&"stable", "3", None);
+        assert!(txn.commit().wait().unwrap());
+        assert_eq!(bookmarks.get(&"stable").wait().unwrap().unwrap().0, "3");
+    }
+
+    #[test]
+    fn test_transaction_conflict() {
+        let bookmarks = MemBookmarks::new();
+        let master_v1 = bookmarks.create(&"master", &"1").wait().unwrap().unwrap();
+        let stable_v1 = bookmarks.create(&"stable", &"1").wait().unwrap().unwrap();
+
+        let mut txn = Transaction::new(&bookmarks);
+        txn.set(&"master", "2", Some(master_v1));
+        txn.set(&"stable", "2", Some(stable_v1));
+
+        // Another push moves stable in the meantime, so nothing is changed.
+        let stable_v2 = bookmarks.set(&"stable", &"3", &stable_v1).wait().unwrap().unwrap();
+        assert!(!txn.commit().wait().unwrap());
+
+        assert_eq!(bookmarks.get(&"master").wait().unwrap(), Some(("1", master_v1)));
+        assert_eq!(bookmarks.get(&"stable").wait().unwrap(), Some(("3", stable_v2)));
+
+        let mut txn = Transaction::new(&bookmarks);
+        txn.delete(&"missing", Version::from(1));
+        assert!(!txn.commit().wait().unwrap());
+    }
 }
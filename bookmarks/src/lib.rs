@@ -203,6 +203,68 @@ pub trait BookmarksMut: Bookmarks {
     }
 }
 
+/// A change to one bookmark, as part of a `Transaction`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum BookmarkUpdate<V> {
+    /// Point the bookmark at a value. If an expected version is given, the bookmark must
+    /// currently have it (`Version::absent()` if it must not exist yet); otherwise the bookmark
+    /// is set whatever its current version.
+    Set(V, Option<Version>),
+    /// Delete the bookmark, which must currently have the given version.
+    Delete(Version),
+}
+
+/// Bookmark store which can apply changes to several bookmarks at once.
+pub trait TransactionalBookmarks: BookmarksMut {
+    /// Apply all of `updates`, or none of them. Resolves to false, changing nothing, if any
+    /// bookmark's version doesn't match what its update expects.
+    fn apply_transaction(
+        &self,
+        updates: Vec<(Vec<u8>, BookmarkUpdate<Self::Value>)>,
+    ) -> BoxFuture<bool, Self::Error>;
+}
+
+/// A set of bookmark changes to be made atomically, with compare-and-swap semantics: either
+/// every bookmark still has the version its change expects and all the changes are made, or
+/// none are. This lets concurrent pushes which move several bookmarks detect that they raced.
+///
+/// Changing the same bookmark twice in one transaction replaces the earlier change.
+pub struct Transaction<'a, B: TransactionalBookmarks + 'a> {
+    bookmarks: &'a B,
+    updates: Vec<(Vec<u8>, BookmarkUpdate<B::Value>)>,
+}
+
+impl<'a, B: TransactionalBookmarks> Transaction<'a, B> {
+    pub fn new(bookmarks: &'a B) -> Self {
+        Transaction {
+            bookmarks,
+            updates: Vec::new(),
+        }
+    }
+
+    /// Point `key` at `value`. See `BookmarkUpdate::Set` for the meaning of `expected`.
+    pub fn set(&mut self, key: &AsRef<[u8]>, value: B::Value, expected: Option<Version>) {
+        self.update(key, BookmarkUpdate::Set(value, expected))
+    }
+
+    /// Delete `key`, which must currently have version `expected`.
+    pub fn delete(&mut self, key: &AsRef<[u8]>, expected: Version) {
+        self.update(key, BookmarkUpdate::Delete(expected))
+    }
+
+    fn update(&mut self, key: &AsRef<[u8]>, update: BookmarkUpdate<B::Value>) {
+        let key = key.as_ref();
+        self.updates.retain(|&(ref k, _)| k.as_slice() != key);
+        self.updates.push((key.to_vec(), update));
+    }
+
+    /// Apply the changes, resolving to true if they were all made, or false if none were
+    /// because a bookmark's version had changed.
+    pub fn commit(self) -> BoxFuture<bool, B::Error> {
+        self.bookmarks.apply_transaction(self.updates)
+    }
+}
+
 /// Ensure that trait objects can be created from the traits here.
 fn _assert_objects() {
     use std::io;
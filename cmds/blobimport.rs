@@ -25,12 +25,6 @@ extern crate heads;
 extern crate fileheads;
 extern crate blobrepo;
 
-#[macro_use]
-extern crate serde_derive;
-extern crate serde;
-
-extern crate bincode;
-
 use std::collections::HashSet;
 use std::io;
 use std::path::{Path, PathBuf};
@@ -55,19 +49,12 @@ use rocksblob::Rocksblob;
 use heads::Heads;
 use fileheads::FileHeads;
 
-use blobrepo::BlobChangeset;
+use blobrepo::{BlobChangeset, RawNodeBlob};
 
 use mercurial::{RevlogManifest, RevlogRepo};
-use mercurial_types::{Changeset, NodeHash, Parents, hash};
+use mercurial_types::{Changeset, NodeHash, hash};
 use mercurial_types::manifest::{Content, Entry, Manifest};
 
-#[derive(Debug, Copy, Clone)]
-#[derive(Serialize, Deserialize)]
-pub struct NodeBlob {
-    parents: Parents,
-    blob: hash::Sha1,
-}
-
 #[derive(Debug, Eq, PartialEq)]
 enum BlobstoreType {
     Files,
@@ -129,17 +116,18 @@ where
                 }
                 Content::Symlink(path) => Ok(path.to_vec().into()),
                 Content::Tree(_) => panic!("need tree blob"),
+                Content::LfsPointer { .. } => panic!("need LFS content"),
             };
 
             bytes.into_future().and_then(move |bytes| {
-                let nodeblob = NodeBlob {
-                    parents: parents,
-                    blob: hash::Sha1::from(bytes.as_ref()),
-                };
+                let nodeblob = RawNodeBlob::new(
+                    parents,
+                    hash::Sha1::from(bytes.as_ref()),
+                    bytes.len() as u64,
+                );
                 let nodekey = format!("node:{}.bincode", hash);
-                let blobkey = format!("sha1:{}", nodeblob.blob);
-                let nodeblob = bincode::serialize(&nodeblob, bincode::Bounded(4096))
-                    .expect("bincode serialize failed");
+                let blobkey = format!("sha1:{}", nodeblob.blob());
+                let nodeblob = nodeblob.serialize().expect("node blob serialize failed");
 
                 // TODO: blobstore.putv?
                 let node = blobstore
@@ -4,11 +4,25 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2 or any later version.
 
+use std::io;
+
 use bytes::Bytes;
 
 #[recursion_limit = "1024"]
 error_chain! {
     errors {
+        ProtocolMismatch(expected: String, got: String) {
+            description("wire protocol version mismatch")
+            display("wire protocol version mismatch: expected {}, got {}", expected, got)
+        }
+        CapabilityUnsupported(capability: String) {
+            description("unsupported capability")
+            display("unsupported capability {}", capability)
+        }
+        BackendTimeout(operation: String, millis: u64) {
+            description("backend timed out")
+            display("backend timed out after {}ms: {}", millis, operation)
+        }
     }
 
     foreign_links {
@@ -18,3 +32,57 @@ error_chain! {
         SendError(::futures::sync::mpsc::SendError<Bytes>);
     }
 }
+
+impl Error {
+    /// Whether the operation which failed might succeed if retried: backend timeouts and
+    /// transient IO errors.
+    pub fn is_retryable(&self) -> bool {
+        match *self.kind() {
+            ErrorKind::BackendTimeout(..) => true,
+            ErrorKind::Io(ref err) => match err.kind() {
+                io::ErrorKind::WouldBlock |
+                io::ErrorKind::TimedOut |
+                io::ErrorKind::Interrupted => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let err: Error = ErrorKind::ProtocolMismatch("ssh-v1".into(), "ssh-v2".into()).into();
+        assert_eq!(
+            err.to_string(),
+            "wire protocol version mismatch: expected ssh-v1, got ssh-v2"
+        );
+
+        let err: Error = ErrorKind::CapabilityUnsupported("bundle3".into()).into();
+        assert_eq!(err.to_string(), "unsupported capability bundle3");
+    }
+
+    #[test]
+    fn retryable() {
+        let err: Error = ErrorKind::BackendTimeout("get node".into(), 500).into();
+        assert_eq!(err.to_string(), "backend timed out after 500ms: get node");
+        assert!(err.is_retryable());
+
+        let err: Error = io::Error::new(io::ErrorKind::TimedOut, "slow").into();
+        assert!(err.is_retryable());
+    }
+
+    #[test]
+    fn not_retryable() {
+        let err: Error = io::Error::new(io::ErrorKind::BrokenPipe, "gone").into();
+        assert!(!err.is_retryable());
+
+        let err: Error = ErrorKind::CapabilityUnsupported("bundle3".into()).into();
+        assert!(!err.is_retryable());
+        assert!(!Error::from("other").is_retryable());
+    }
+}
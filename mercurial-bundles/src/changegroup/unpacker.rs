@@ -263,8 +263,7 @@ impl Cg2Unpacker {
             frags.push(Fragment {
                 start: start as usize,
                 end: end as usize,
-                // TODO: avoid copies here by switching this to Bytes
-                content: buf.split_to(new_len as usize).to_vec(),
+                content: buf.split_to(new_len as usize).freeze(),
             });
 
             remaining -= delta_len;
@@ -0,0 +1,52 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Benchmarks for applying deltas of various shapes to a 64KiB text.
+
+#[macro_use]
+extern crate criterion;
+extern crate mercurial_types;
+
+use criterion::Criterion;
+
+use mercurial_types::delta::{self, Delta, Fragment};
+
+const TEXT_LEN: usize = 64 * 1024;
+
+fn text() -> Vec<u8> {
+    (0..TEXT_LEN).map(|i| (i % 251) as u8).collect()
+}
+
+/// A single localized edit.
+fn single_fragment() -> Delta {
+    Delta::new(vec![Fragment::new(1000, 1100, vec![b'x'; 200]).unwrap()]).unwrap()
+}
+
+/// An edit every kilobyte throughout the text.
+fn many_fragments() -> Delta {
+    let frags = (0..TEXT_LEN / 1024)
+        .map(|i| Fragment::new(i * 1024, i * 1024 + 16, vec![b'x'; 32]).unwrap())
+        .collect();
+    Delta::new(frags).unwrap()
+}
+
+fn bench_apply(c: &mut Criterion) {
+    let cases = vec![
+        ("apply empty", Delta::default()),
+        ("apply single fragment", single_fragment()),
+        ("apply many fragments", many_fragments()),
+    ];
+
+    for (name, delta) in cases {
+        let text = text();
+        c.bench_function(name, move |b| {
+            b.iter(|| delta::try_apply(&text, &delta).unwrap())
+        });
+    }
+}
+
+criterion_group!(benches, bench_apply);
+criterion_main!(benches);
@@ -0,0 +1,74 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Benchmarks comparing `apply_chain` with `apply_chain_parallel` for chains of various lengths.
+
+#[macro_use]
+extern crate criterion;
+extern crate mercurial_types;
+
+use criterion::Criterion;
+
+use mercurial_types::delta::{self, Delta};
+
+const TEXT_LEN: usize = 64 * 1024;
+const EDITS_PER_REVISION: usize = 10;
+const THREADS: usize = 4;
+
+/// Deterministic xorshift generator, so that every run applies the same chains.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+fn text() -> Vec<u8> {
+    (0..TEXT_LEN).map(|i| (i % 251) as u8).collect()
+}
+
+/// The deltas between `len + 1` successive revisions of `text()`, each made from the one before
+/// by `EDITS_PER_REVISION` small replacements.
+fn chain(len: usize) -> Vec<Delta> {
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    let mut text = text();
+    let mut deltas = Vec::with_capacity(len);
+
+    for _ in 0..len {
+        let mut next = text.clone();
+        for _ in 0..EDITS_PER_REVISION {
+            let start = rng.next(next.len() - 32);
+            let end = start + rng.next(32);
+            let content: Vec<u8> = (0..rng.next(32)).map(|_| rng.next(256) as u8).collect();
+            next.splice(start..end, content);
+        }
+        deltas.push(delta::diff(&text, &next));
+        text = next;
+    }
+    deltas
+}
+
+fn bench_apply_chain(c: &mut Criterion) {
+    for &len in &[64, 256, 1024] {
+        let deltas = chain(len);
+        let text = text();
+        c.bench_function(&format!("apply_chain {}", len), {
+            let deltas = deltas.clone();
+            let text = text.clone();
+            move |b| b.iter(|| delta::apply_chain(&text, deltas.clone()))
+        });
+        c.bench_function(&format!("apply_chain_parallel {}", len), move |b| {
+            b.iter(|| delta::apply_chain_parallel(&text, deltas.clone(), THREADS))
+        });
+    }
+}
+
+criterion_group!(benches, bench_apply_chain);
+criterion_main!(benches);
@@ -0,0 +1,62 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Benchmark for combining a long chain of deltas, such as a revlog's, into one.
+
+#[macro_use]
+extern crate criterion;
+extern crate mercurial_types;
+
+use criterion::Criterion;
+
+use mercurial_types::delta::{self, Delta};
+
+const TEXT_LEN: usize = 200 * 1000;
+const CHAIN_LEN: usize = 500;
+const EDITS_PER_REVISION: usize = 10;
+
+/// Deterministic xorshift generator, so that every run combines the same chain.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+/// The deltas between `CHAIN_LEN + 1` successive revisions of a text of about `TEXT_LEN` bytes,
+/// each made from the one before by `EDITS_PER_REVISION` small replacements.
+fn chain() -> Vec<Delta> {
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    let mut text: Vec<u8> = (0..TEXT_LEN).map(|i| (i % 251) as u8).collect();
+    let mut deltas = Vec::with_capacity(CHAIN_LEN);
+
+    for _ in 0..CHAIN_LEN {
+        let mut next = text.clone();
+        for _ in 0..EDITS_PER_REVISION {
+            let start = rng.next(next.len() - 32);
+            let end = start + rng.next(32);
+            let content: Vec<u8> = (0..rng.next(32)).map(|_| rng.next(256) as u8).collect();
+            next.splice(start..end, content);
+        }
+        deltas.push(delta::diff(&text, &next));
+        text = next;
+    }
+    deltas
+}
+
+fn bench_combine_chain(c: &mut Criterion) {
+    let deltas = chain();
+    c.bench_function("combine_chain 500 deltas", move |b| {
+        b.iter(|| delta::combine_chain(deltas.clone()))
+    });
+}
+
+criterion_group!(benches, bench_combine_chain);
+criterion_main!(benches);
@@ -0,0 +1,484 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Ancestry queries over the changeset DAG of a `Repo`.
+//!
+//! These walk parents via `Repo::get_changeset_parents`, so they need an owned handle on the
+//! repo which can be moved into the returned future. Repo implementations use them (with a clone
+//! of themselves) to implement the corresponding `Repo` methods.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use futures::{Async, Poll};
+use futures::future::{self, BoxFuture, Future, Loop};
+use futures::stream::{self, BoxStream, Stream};
+
+use blobnode::Parents;
+use nodehash::{NodeHash, NULL_HASH};
+use path::Path;
+use repo::Repo;
+
+/// Return true if `ancestor` is reachable from `descendant` by following parents. A changeset
+/// is considered to be its own ancestor.
+///
+/// The walk proceeds breadth-first, one generation at a time, and gives up (returning false)
+/// after `max_depth` generations if a bound is given. A missing `descendant` is an error, but
+/// an `ancestor` which is simply never reached is not.
+pub fn is_ancestor<R>(
+    repo: R,
+    ancestor: NodeHash,
+    descendant: NodeHash,
+    max_depth: Option<usize>,
+) -> BoxFuture<bool, R::Error>
+where
+    R: Repo + Send,
+{
+    if ancestor == descendant {
+        // Still make sure the changeset actually exists.
+        return repo.get_changeset_parents(&descendant)
+            .map(|_| true)
+            .boxed();
+    }
+
+    let mut seen = HashSet::new();
+    seen.insert(descendant);
+
+    future::loop_fn((vec![descendant], seen, 0), move |(frontier, mut seen, depth)| {
+        let parents: Vec<_> = frontier
+            .iter()
+            .map(|nodeid| repo.get_changeset_parents(nodeid))
+            .collect();
+
+        future::join_all(parents).map(move |parents| {
+            let mut next = Vec::new();
+            for p in parents {
+                for parent in &p {
+                    if parent == ancestor {
+                        return Loop::Break(true);
+                    }
+                    if parent != NULL_HASH && seen.insert(parent) {
+                        next.push(parent);
+                    }
+                }
+            }
+
+            let depth = depth + 1;
+            if next.is_empty() || max_depth.map_or(false, |max| depth >= max) {
+                Loop::Break(false)
+            } else {
+                Loop::Continue((next, seen, depth))
+            }
+        })
+    }).boxed()
+}
+
+/// Return the changesets reachable from one of `repo`'s heads by following at most `max_depth`
+/// parents, breadth-first from the heads and each exactly once.
+///
+/// Only the changesets found are remembered, so the memory needed is proportional to the size of
+/// the result rather than of the whole history. Nothing is produced until the walk is complete.
+pub fn recent<R>(repo: R, max_depth: usize) -> BoxStream<NodeHash, R::Error>
+where
+    R: Repo + Send,
+{
+    repo.get_heads()
+        .collect()
+        .and_then(move |heads| {
+            let mut seen = HashSet::new();
+            let frontier: Vec<_> = heads.into_iter().filter(|head| seen.insert(*head)).collect();
+            let found = frontier.clone();
+            let state = (frontier, seen, found, 0);
+
+            future::loop_fn(state, move |(frontier, mut seen, mut found, depth)| {
+                if frontier.is_empty() || depth >= max_depth {
+                    return future::ok(Loop::Break(found)).boxed();
+                }
+
+                let parents: Vec<_> = frontier
+                    .iter()
+                    .map(|nodeid| repo.get_changeset_parents(nodeid))
+                    .collect();
+                future::join_all(parents)
+                    .map(move |parents| {
+                        let mut next = Vec::new();
+                        for p in parents {
+                            for parent in &p {
+                                if parent != NULL_HASH && seen.insert(parent) {
+                                    next.push(parent);
+                                }
+                            }
+                        }
+                        found.extend_from_slice(&next);
+                        Loop::Continue((next, seen, found, depth + 1))
+                    })
+                    .boxed()
+            })
+        })
+        .map(|found| stream::iter(found.into_iter().map(Ok)))
+        .flatten_stream()
+        .boxed()
+}
+
+/// Find the lowest common ancestor of `a` and `b`, or `None` if they share no history. If one
+/// is an ancestor of the other, that one is the result.
+///
+/// The whole history of both is walked, one generation at a time, to find their common
+/// ancestors. Any of those which is an ancestor of another is dropped, as a lower one exists,
+/// leaving the heads of the common ancestors (the merge bases). There is usually just one, but
+/// a criss-cross merge can have several, in which case the one nearest to both wins: the
+/// smallest total distance from `a` and `b`, then the smallest of the two distances' maximum,
+/// then the smallest hash, so the answer is always deterministic.
+///
+/// Because the walk doesn't stop at the answer, this fetches the parents of every ancestor of
+/// `a` and `b` and remembers them all until it finishes, so both time and memory grow with the
+/// size of their combined history rather than with how far back the answer is. If a bound is
+/// given, the walk instead gives up after `max_depth` generations and only the changesets it
+/// reached are considered: the result is `None` if no common ancestor lies within the bound,
+/// and may not be the lowest if the history connecting the candidates lies beyond it.
+///
+/// It is an error if either `a` or `b` doesn't exist.
+pub fn common_ancestor<R>(
+    repo: R,
+    a: NodeHash,
+    b: NodeHash,
+    max_depth: Option<usize>,
+) -> BoxFuture<Option<NodeHash>, R::Error>
+where
+    R: Repo + Send,
+{
+    let mut seen = HashSet::new();
+    seen.insert(a);
+    seen.insert(b);
+    let frontier: Vec<_> = seen.iter().cloned().collect();
+
+    future::loop_fn(
+        (frontier, seen, HashMap::new(), 0),
+        move |(frontier, mut seen, mut parents, depth)| {
+            let fetches: Vec<_> = frontier
+                .into_iter()
+                .map(|nodeid| repo.get_changeset_parents(&nodeid).map(move |p| (nodeid, p)))
+                .collect();
+
+            future::join_all(fetches).map(move |fetched| {
+                let mut next = Vec::new();
+                for (nodeid, p) in fetched {
+                    let mut ps = Vec::new();
+                    for parent in &p {
+                        if parent == NULL_HASH {
+                            continue;
+                        }
+                        if seen.insert(parent) {
+                            next.push(parent);
+                        }
+                        ps.push(parent);
+                    }
+                    parents.insert(nodeid, ps);
+                }
+
+                let depth = depth + 1;
+                if next.is_empty() || max_depth.map_or(false, |max| depth >= max) {
+                    Loop::Break(parents)
+                } else {
+                    Loop::Continue((next, seen, parents, depth))
+                }
+            })
+        },
+    ).map(move |parents| merge_base(&parents, a, b))
+        .boxed()
+}
+
+/// Pick the lowest common ancestor of `a` and `b` as described for `common_ancestor`, given the
+/// parents of every ancestor of either which the walk fetched. Changesets whose parents weren't
+/// fetched, because the walk stopped at its depth bound, are treated as having none.
+fn merge_base(
+    parents: &HashMap<NodeHash, Vec<NodeHash>>,
+    a: NodeHash,
+    b: NodeHash,
+) -> Option<NodeHash> {
+    let dist_a = distances(parents, a);
+    let dist_b = distances(parents, b);
+
+    let common: Vec<_> = dist_a
+        .keys()
+        .filter(|nodeid| dist_b.contains_key(nodeid))
+        .cloned()
+        .collect();
+    // Every ancestor of a common ancestor is also one, so a common ancestor is only a head if
+    // it isn't the parent of another.
+    let not_heads: HashSet<_> = common
+        .iter()
+        .flat_map(|nodeid| parents.get(nodeid).into_iter().flat_map(|ps| ps.iter().cloned()))
+        .collect();
+
+    common
+        .into_iter()
+        .filter(|nodeid| !not_heads.contains(nodeid))
+        .map(|nodeid| {
+            let (da, db) = (dist_a[&nodeid], dist_b[&nodeid]);
+            (da + db, cmp::max(da, db), nodeid)
+        })
+        .min()
+        .map(|(_, _, nodeid)| nodeid)
+}
+
+/// Return the generation distance from `start` to each of its ancestors (and itself).
+fn distances(
+    parents: &HashMap<NodeHash, Vec<NodeHash>>,
+    start: NodeHash,
+) -> HashMap<NodeHash, usize> {
+    let mut dist = HashMap::new();
+    dist.insert(start, 0);
+    let mut frontier = vec![start];
+    let mut depth = 0;
+
+    while !frontier.is_empty() {
+        depth += 1;
+        let mut next = Vec::new();
+        for nodeid in frontier {
+            for parent in parents.get(&nodeid).into_iter().flat_map(|ps| ps.iter()) {
+                if !dist.contains_key(parent) {
+                    dist.insert(*parent, depth);
+                    next.push(*parent);
+                }
+            }
+        }
+        frontier = next;
+    }
+    dist
+}
+
+/// Walk back through the history of the file at `path` in changeset `cs`, producing up to
+/// `limit` `(changeset, filenode)` pairs, newest first. Each pair names a version of the file
+/// and the changeset which introduced it, as a linkrev would. The file's manifests are compared
+/// along the way, so a changeset which doesn't change the file isn't reported.
+///
+/// When a merge has the file's current version in more than one parent, the walk follows the
+/// first. When the version was introduced by a merge, the walk carries on into the first
+/// parent which has the file. It stops when the file doesn't exist in any parent. Copy
+/// metadata isn't followed, so history stops at a rename.
+///
+/// The stream is empty if `path` doesn't exist in `cs`.
+pub fn file_history<R>(
+    repo: R,
+    cs: NodeHash,
+    path: Path,
+    limit: usize,
+) -> BoxStream<(NodeHash, NodeHash), R::Error>
+where
+    R: Repo + Clone + Send,
+{
+    let pending = if limit == 0 {
+        None
+    } else {
+        let (repo2, path2) = (repo.clone(), path.clone());
+        let first = repo.get_entry_by_path(&cs, &path)
+            .and_then(move |entry| match entry {
+                Some(entry) => find_introduction(repo2, path2, cs, *entry.get_hash())
+                    .map(Some)
+                    .boxed(),
+                None => future::ok(None).boxed(),
+            })
+            .boxed();
+        Some(first)
+    };
+
+    FileHistory {
+        repo,
+        path,
+        remaining: limit,
+        pending,
+    }.boxed()
+}
+
+/// A version of a file, the changeset which introduced it, and where to look for the previous
+/// version.
+struct FileVersion {
+    changeset: NodeHash,
+    filenode: NodeHash,
+    previous: Option<(NodeHash, NodeHash)>,
+}
+
+struct FileHistory<R: Repo> {
+    repo: R,
+    path: Path,
+    remaining: usize,
+    pending: Option<BoxFuture<Option<FileVersion>, R::Error>>,
+}
+
+impl<R> Stream for FileHistory<R>
+where
+    R: Repo + Clone + Send,
+{
+    type Item = (NodeHash, NodeHash);
+    type Error = R::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let version = match self.pending {
+            Some(ref mut pending) => match pending.poll()? {
+                Async::Ready(version) => version,
+                Async::NotReady => return Ok(Async::NotReady),
+            },
+            None => return Ok(Async::Ready(None)),
+        };
+        self.pending = None;
+
+        let version = match version {
+            Some(version) => version,
+            None => return Ok(Async::Ready(None)),
+        };
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            if let Some((cs, filenode)) = version.previous {
+                let repo = self.repo.clone();
+                let path = self.path.clone();
+                self.pending = Some(find_introduction(repo, path, cs, filenode).map(Some).boxed());
+            }
+        }
+
+        Ok(Async::Ready(Some((version.changeset, version.filenode))))
+    }
+}
+
+/// Starting from changeset `cs`, which has `filenode` at `path`, follow parents with the same
+/// `filenode` back to the changeset which introduced it.
+fn find_introduction<R>(
+    repo: R,
+    path: Path,
+    cs: NodeHash,
+    filenode: NodeHash,
+) -> BoxFuture<FileVersion, R::Error>
+where
+    R: Repo + Clone + Send,
+{
+    future::loop_fn(cs, move |cs| {
+        let (repo2, path) = (repo.clone(), path.clone());
+        repo.get_changeset_parents(&cs)
+            .and_then(move |parents| {
+                let lookups: Vec<_> = (&parents)
+                    .into_iter()
+                    .filter(|parent| *parent != NULL_HASH)
+                    .map(|parent| {
+                        repo2
+                            .get_entry_by_path(&parent, &path)
+                            .map(move |entry| (parent, entry.map(|entry| *entry.get_hash())))
+                    })
+                    .collect();
+                future::join_all(lookups)
+            })
+            .map(move |found| {
+                if let Some(&(parent, _)) = found.iter().find(|&&(_, f)| f == Some(filenode)) {
+                    return Loop::Continue(parent);
+                }
+                let previous = found
+                    .into_iter()
+                    .filter_map(|(parent, f)| f.map(|f| (parent, f)))
+                    .next();
+                Loop::Break(FileVersion {
+                    changeset: cs,
+                    filenode,
+                    previous,
+                })
+            })
+    }).boxed()
+}
+
+/// Find the changesets reachable from `heads` but not from `common`, as needed to bring a repo
+/// which has `common` up to date with `heads`. They are returned parents first, so each
+/// changeset comes after all of its parents which are also in the result; otherwise the order
+/// is by hash, so it is deterministic.
+///
+/// All the ancestors of `common` are walked first, so this costs O(history) however few
+/// changesets are missing. It is an error if any of `heads` or `common` doesn't exist.
+pub fn missing<R>(
+    repo: R,
+    heads: Vec<NodeHash>,
+    common: Vec<NodeHash>,
+) -> BoxFuture<Vec<NodeHash>, R::Error>
+where
+    R: Repo + Clone + Send,
+{
+    reach(repo.clone(), common, HashSet::new())
+        .and_then(move |known| reach(repo, heads, known.into_iter().map(|(n, _)| n).collect()))
+        .map(|reached| parents_first(&reached))
+        .boxed()
+}
+
+/// Walk back from `start` breadth-first, stopping at anything in `stop`, and return the
+/// parents of every changeset reached.
+fn reach<R>(
+    repo: R,
+    start: Vec<NodeHash>,
+    stop: HashSet<NodeHash>,
+) -> BoxFuture<HashMap<NodeHash, Parents>, R::Error>
+where
+    R: Repo + Send,
+{
+    let mut seen = HashSet::new();
+    let frontier: Vec<_> = start
+        .into_iter()
+        .filter(|nodeid| !stop.contains(nodeid) && seen.insert(*nodeid))
+        .collect();
+    // Shared with each generation's future rather than copied, as it may be most of the repo.
+    let stop = Arc::new(stop);
+
+    future::loop_fn((frontier, seen, HashMap::new()), move |(frontier, mut seen, mut reached)| {
+        let parents: Vec<_> = frontier
+            .iter()
+            .map(|nodeid| repo.get_changeset_parents(nodeid))
+            .collect();
+        let stop = stop.clone();
+
+        future::join_all(parents).map(move |parents| {
+            let mut next = Vec::new();
+            for (nodeid, p) in frontier.into_iter().zip(parents) {
+                for parent in &p {
+                    if parent != NULL_HASH && !stop.contains(&parent) && seen.insert(parent) {
+                        next.push(parent);
+                    }
+                }
+                reached.insert(nodeid, p);
+            }
+
+            if next.is_empty() {
+                Loop::Break(reached)
+            } else {
+                Loop::Continue((next, seen, reached))
+            }
+        })
+    }).boxed()
+}
+
+/// Order the changesets in `parents` so that each comes after its parents among them.
+fn parents_first(parents: &HashMap<NodeHash, Parents>) -> Vec<NodeHash> {
+    let mut roots: Vec<_> = parents.keys().cloned().collect();
+    roots.sort();
+
+    let mut visited = HashSet::new();
+    let mut order = Vec::with_capacity(parents.len());
+    for root in roots {
+        // Depth-first with an explicit stack, as a long linear history would overflow the real
+        // one. A changeset is pushed a second time, marked, to be output after its parents.
+        let mut stack = vec![(root, false)];
+        while let Some((nodeid, done)) = stack.pop() {
+            if done {
+                order.push(nodeid);
+                continue;
+            }
+            if !visited.insert(nodeid) {
+                continue;
+            }
+            stack.push((nodeid, true));
+            for parent in &parents[&nodeid] {
+                if parents.contains_key(&parent) && !visited.contains(&parent) {
+                    stack.push((parent, false));
+                }
+            }
+        }
+    }
+    order
+}
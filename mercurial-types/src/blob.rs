@@ -81,6 +81,16 @@ where
             &Blob::Extern(..) | &Blob::NodeId(..) => None,
         }
     }
+
+    /// Return the `Sha1` of the blob's data, computing it if the blob is `Dirty`. A `NodeId`
+    /// blob doesn't identify its data, so has none.
+    pub fn sha1(&self) -> Option<Sha1> {
+        match self {
+            &Blob::Dirty(ref data) => Some(Sha1::from(data.as_ref())),
+            &Blob::Clean(_, ref hash) | &Blob::Extern(ref hash) => Some(hash.0),
+            &Blob::NodeId(..) => None,
+        }
+    }
 }
 
 /// Construct a dirty blob from raw data.
@@ -0,0 +1,154 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Synchronous facade over a `Repo`, for tests and tools.
+
+use bytes::Bytes;
+use futures::{Future, Stream};
+
+use blobnode::Parents;
+use bookmarks::Version;
+use changeset::Changeset;
+use manifest::{Entry, Manifest, ManifestStats};
+use nodehash::NodeHash;
+use path::Path;
+use repo::{HashResolution, Repo};
+use verify::VerifyEvent;
+
+/// Wrapper around a `Repo` which blocks the calling thread until each operation is complete,
+/// using `Future::wait` and `Stream::wait`.
+///
+/// This is meant for scripts and tests which don't want to deal with futures. Don't use it
+/// on a thread which drives an event loop, as blocking it can deadlock.
+#[derive(Clone, Debug)]
+pub struct BlockingRepo<R> {
+    repo: R,
+}
+
+impl<R: Repo> BlockingRepo<R> {
+    pub fn new(repo: R) -> Self {
+        BlockingRepo { repo }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.repo
+    }
+
+    pub fn into_inner(self) -> R {
+        self.repo
+    }
+
+    pub fn get_changesets_blocking(&self) -> Result<Vec<NodeHash>, R::Error> {
+        self.repo.get_changesets().collect().wait()
+    }
+
+    pub fn get_heads_blocking(&self) -> Result<Vec<NodeHash>, R::Error> {
+        self.repo.get_heads().collect().wait()
+    }
+
+    pub fn get_bookmark_blocking(
+        &self,
+        name: &[u8],
+    ) -> Result<Option<(NodeHash, Version)>, R::Error> {
+        self.repo.get_bookmark(name).wait()
+    }
+
+    pub fn changeset_exists_blocking(&self, nodeid: &NodeHash) -> Result<bool, R::Error> {
+        self.repo.changeset_exists(nodeid).wait()
+    }
+
+    pub fn get_changeset_by_nodeid_blocking(
+        &self,
+        nodeid: &NodeHash,
+    ) -> Result<Box<Changeset>, R::Error> {
+        self.repo.get_changeset_by_nodeid(nodeid).wait()
+    }
+
+    pub fn get_changeset_raw_blocking(&self, nodeid: &NodeHash) -> Result<Bytes, R::Error> {
+        self.repo.get_changeset_raw(nodeid).wait()
+    }
+
+    pub fn get_changeset_parents_blocking(&self, nodeid: &NodeHash) -> Result<Parents, R::Error> {
+        self.repo.get_changeset_parents(nodeid).wait()
+    }
+
+    pub fn resolve_hash_prefix_blocking(&self, prefix: &str) -> Result<HashResolution, R::Error> {
+        self.repo.resolve_hash_prefix(prefix).wait()
+    }
+
+    pub fn is_ancestor_blocking(
+        &self,
+        ancestor: &NodeHash,
+        descendant: &NodeHash,
+    ) -> Result<bool, R::Error> {
+        self.repo.is_ancestor(ancestor, descendant).wait()
+    }
+
+    pub fn common_ancestor_blocking(
+        &self,
+        a: &NodeHash,
+        b: &NodeHash,
+    ) -> Result<Option<NodeHash>, R::Error> {
+        self.repo.common_ancestor(a, b).wait()
+    }
+
+    pub fn get_manifest_by_nodeid_blocking(
+        &self,
+        nodeid: &NodeHash,
+    ) -> Result<Box<Manifest<Error = R::Error> + Sync>, R::Error> {
+        self.repo.get_manifest_by_nodeid(nodeid).wait()
+    }
+
+    pub fn get_root_manifest_blocking(
+        &self,
+        cs: &NodeHash,
+    ) -> Result<Box<Manifest<Error = R::Error> + Sync>, R::Error> {
+        self.repo.get_root_manifest(cs).wait()
+    }
+
+    pub fn get_entry_by_path_blocking(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+    ) -> Result<Option<Box<Entry<Error = R::Error>>>, R::Error> {
+        self.repo.get_entry_by_path(cs, path).wait()
+    }
+
+    pub fn walk_manifest_blocking(
+        &self,
+        root: &NodeHash,
+    ) -> Result<Vec<(Path, Box<Entry<Error = R::Error>>)>, R::Error> {
+        self.repo.walk_manifest(root).collect().wait()
+    }
+
+    pub fn manifest_stats_blocking(&self, root: &NodeHash) -> Result<ManifestStats, R::Error> {
+        self.repo.manifest_stats(root).wait()
+    }
+
+    pub fn export_bundle_blocking(
+        &self,
+        heads: &[NodeHash],
+        common: &[NodeHash],
+    ) -> Result<Vec<Bytes>, R::Error> {
+        self.repo.export_bundle(heads, common).collect().wait()
+    }
+
+    pub fn verify_reachable_blocking(
+        &self,
+        heads: &[NodeHash],
+    ) -> Result<Vec<VerifyEvent>, R::Error> {
+        self.repo.verify_reachable(heads).collect().wait()
+    }
+
+    pub fn get_file_history_blocking(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+        limit: usize,
+    ) -> Result<Vec<(NodeHash, NodeHash)>, R::Error> {
+        self.repo.get_file_history(cs, path, limit).collect().wait()
+    }
+}
@@ -0,0 +1,319 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Repo wrapper which memoizes path lookups and can index changeset hashes.
+
+use std::sync::Arc;
+
+use asyncmemo::{Asyncmemo, Filler};
+use bytes::Bytes;
+use futures::future::{self, BoxFuture, Future};
+use futures::stream::BoxStream;
+
+use blobnode::Parents;
+use bookmarks::Version;
+use changeset::Changeset;
+use errors::Error;
+use hashindex::ChangesetHashIndex;
+use manifest::{self, Entry, Manifest, ManifestDiffEntry, ManifestStats};
+use nodehash::NodeHash;
+use path::Path;
+use repo::{self, BoxedBookmarks, HashResolution, Repo};
+use verify::VerifyEvent;
+
+/// Resolves `(manifest, path)` to the hash of the entry at `path` under `manifest`.
+pub struct PathFiller<R> {
+    repo: Arc<R>,
+}
+
+impl<R> Filler for PathFiller<R>
+where
+    R: Repo + Send + Sync,
+{
+    type Key = (NodeHash, Path);
+    type Value = BoxFuture<Option<NodeHash>, R::Error>;
+
+    fn fill(&self, key: &Self::Key) -> Self::Value {
+        let (ref manifestid, ref path) = *key;
+        let path = path.clone();
+        self.repo
+            .get_manifest_by_nodeid(manifestid)
+            .and_then(move |manifest| manifest::lookup_path(manifest, &path))
+            .map(|entry| entry.map(|entry| *entry.get_hash()))
+            .boxed()
+    }
+}
+
+/// Repo which caches the results of path lookups made with `get_hash_by_path`, in a cache
+/// bounded by entry count and weight. Manifests are content-addressed, so a cached result
+/// never goes stale. Everything else is passed straight through to the inner repo.
+///
+/// If given a `ChangesetHashIndex` with `with_hash_index`, hash prefixes are resolved from it
+/// instead of by the inner repo. Unlike path lookups, the index can go stale as changesets are
+/// added, so it needs refreshing with `refresh_hash_index`.
+pub struct CachingRepo<R>
+where
+    R: Repo + Send + Sync,
+{
+    repo: Arc<R>,
+    paths: Asyncmemo<PathFiller<R>>,
+    hash_index: Option<ChangesetHashIndex>,
+}
+
+impl<R> CachingRepo<R>
+where
+    R: Repo + Send + Sync,
+{
+    pub fn new(repo: R, entrylimit: usize, weightlimit: usize) -> Self {
+        let repo = Arc::new(repo);
+        let filler = PathFiller { repo: repo.clone() };
+        CachingRepo {
+            repo,
+            paths: Asyncmemo::with_limits(filler, entrylimit, weightlimit),
+            hash_index: None,
+        }
+    }
+
+    /// Resolve hash prefixes using `index`, which should have been built from the inner repo.
+    pub fn with_hash_index(mut self, index: ChangesetHashIndex) -> Self {
+        self.hash_index = Some(index);
+        self
+    }
+
+    /// Rebuild the hash index, if there is one, from the changesets now in the inner repo.
+    pub fn refresh_hash_index(&self) -> BoxFuture<(), R::Error> {
+        match self.hash_index {
+            Some(ref index) => index.refresh(&*self.repo),
+            None => future::ok(()).boxed(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.repo
+    }
+
+    /// Return the hash of the entry at `path` in the manifest `manifestid`, or `None` if
+    /// there's no such entry. `path` is normalized as by `Repo::get_entry_by_path`, so
+    /// equivalent spellings of a path share a cache entry.
+    pub fn get_hash_by_path(
+        &self,
+        manifestid: &NodeHash,
+        path: &Path,
+    ) -> BoxFuture<Option<NodeHash>, R::Error>
+    where
+        R::Error: From<Error>,
+    {
+        match path.normalized() {
+            Ok(path) => self.paths.get((*manifestid, path)).boxed(),
+            Err(err) => future::err(err.into()).boxed(),
+        }
+    }
+
+    /// Like `get_hash_by_path`, but look `path` up in the root manifest of changeset `cs`.
+    pub fn get_hash_by_changeset_path(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+    ) -> BoxFuture<Option<NodeHash>, R::Error>
+    where
+        R::Error: From<Error>,
+    {
+        let paths = self.paths.clone();
+        let path = match path.normalized() {
+            Ok(path) => path,
+            Err(err) => return future::err(err.into()).boxed(),
+        };
+        self.repo
+            .get_changeset_by_nodeid(cs)
+            .and_then(move |cs| paths.get((*cs.manifestid(), path)))
+            .boxed()
+    }
+
+    /// Number of cached lookups, including ones still in progress.
+    pub fn cached_paths(&self) -> usize {
+        self.paths.len()
+    }
+}
+
+impl<R> Clone for CachingRepo<R>
+where
+    R: Repo + Send + Sync,
+{
+    fn clone(&self) -> Self {
+        CachingRepo {
+            repo: self.repo.clone(),
+            paths: self.paths.clone(),
+            hash_index: self.hash_index.clone(),
+        }
+    }
+}
+
+impl<R> Repo for CachingRepo<R>
+where
+    R: Repo + Send + Sync,
+{
+    type Error = R::Error;
+
+    fn get_changesets(&self) -> BoxStream<NodeHash, Self::Error> {
+        self.repo.get_changesets()
+    }
+
+    fn get_changesets_range(&self, skip: usize, limit: usize) -> BoxStream<NodeHash, Self::Error> {
+        self.repo.get_changesets_range(skip, limit)
+    }
+
+    fn get_changesets_sorted(&self) -> BoxStream<NodeHash, Self::Error> {
+        self.repo.get_changesets_sorted()
+    }
+
+    fn get_changesets_bounded(&self, max_depth: usize) -> BoxStream<NodeHash, Self::Error> {
+        self.repo.get_changesets_bounded(max_depth)
+    }
+
+    fn get_changeset_count(&self) -> BoxFuture<u64, Self::Error> {
+        self.repo.get_changeset_count()
+    }
+
+    fn get_heads(&self) -> BoxStream<NodeHash, Self::Error> {
+        self.repo.get_heads()
+    }
+
+    fn get_bookmarks(&self) -> Result<BoxedBookmarks<Self::Error>, Self::Error> {
+        self.repo.get_bookmarks()
+    }
+
+    fn get_bookmark(&self, name: &[u8]) -> BoxFuture<Option<(NodeHash, Version)>, Self::Error> {
+        self.repo.get_bookmark(name)
+    }
+
+    fn get_heads_for_bookmarks(&self, names: &[Vec<u8>]) -> BoxStream<NodeHash, Self::Error> {
+        self.repo.get_heads_for_bookmarks(names)
+    }
+
+    fn prefetch(&self, nodeids: &[NodeHash]) -> BoxFuture<(), Self::Error> {
+        self.repo.prefetch(nodeids)
+    }
+
+    fn changeset_exists(&self, nodeid: &NodeHash) -> BoxFuture<bool, Self::Error> {
+        self.repo.changeset_exists(nodeid)
+    }
+
+    fn changesets_exist(
+        &self,
+        nodeids: &[NodeHash],
+    ) -> BoxFuture<Vec<(NodeHash, bool)>, Self::Error> {
+        self.repo.changesets_exist(nodeids)
+    }
+
+    fn get_changesets_by_nodeid(
+        &self,
+        nodeids: &[NodeHash],
+    ) -> BoxFuture<Vec<Box<Changeset>>, Self::Error> {
+        self.repo.get_changesets_by_nodeid(nodeids)
+    }
+
+    fn get_changeset_by_nodeid(&self, nodeid: &NodeHash) -> BoxFuture<Box<Changeset>, Self::Error> {
+        self.repo.get_changeset_by_nodeid(nodeid)
+    }
+
+    fn get_changeset_raw(&self, nodeid: &NodeHash) -> BoxFuture<Bytes, Self::Error> {
+        self.repo.get_changeset_raw(nodeid)
+    }
+
+    fn resolve_hash_prefix(&self, prefix: &str) -> BoxFuture<HashResolution, Self::Error> {
+        // Invalid prefixes are left to the inner repo, so that it reports the error.
+        match (self.hash_index.as_ref(), repo::check_hash_prefix(prefix)) {
+            (Some(index), Ok(prefix)) => future::ok(index.lookup_prefix(&prefix)).boxed(),
+            _ => self.repo.resolve_hash_prefix(prefix),
+        }
+    }
+
+    fn get_changeset_parents(&self, nodeid: &NodeHash) -> BoxFuture<Parents, Self::Error> {
+        self.repo.get_changeset_parents(nodeid)
+    }
+
+    fn is_ancestor(
+        &self,
+        ancestor: &NodeHash,
+        descendant: &NodeHash,
+    ) -> BoxFuture<bool, Self::Error> {
+        self.repo.is_ancestor(ancestor, descendant)
+    }
+
+    fn common_ancestor(
+        &self,
+        a: &NodeHash,
+        b: &NodeHash,
+    ) -> BoxFuture<Option<NodeHash>, Self::Error> {
+        self.repo.common_ancestor(a, b)
+    }
+
+    fn get_manifest_by_nodeid(
+        &self,
+        nodeid: &NodeHash,
+    ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
+        self.repo.get_manifest_by_nodeid(nodeid)
+    }
+
+    fn get_root_manifest(
+        &self,
+        cs: &NodeHash,
+    ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
+        self.repo.get_root_manifest(cs)
+    }
+
+    fn diff_manifests(
+        &self,
+        base: &NodeHash,
+        other: &NodeHash,
+    ) -> BoxStream<ManifestDiffEntry, Self::Error> {
+        self.repo.diff_manifests(base, other)
+    }
+
+    fn get_entry_by_path(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+    ) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Self::Error> {
+        self.repo.get_entry_by_path(cs, path)
+    }
+
+    fn get_file_history(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+        limit: usize,
+    ) -> BoxStream<(NodeHash, NodeHash), Self::Error> {
+        self.repo.get_file_history(cs, path, limit)
+    }
+
+    fn walk_manifest(
+        &self,
+        root: &NodeHash,
+    ) -> BoxStream<(Path, Box<Entry<Error = Self::Error>>), Self::Error> {
+        self.repo.walk_manifest(root)
+    }
+
+    fn manifest_stats(&self, root: &NodeHash) -> BoxFuture<ManifestStats, Self::Error> {
+        self.repo.manifest_stats(root)
+    }
+
+    fn export_bundle(
+        &self,
+        heads: &[NodeHash],
+        common: &[NodeHash],
+    ) -> BoxStream<Bytes, Self::Error> {
+        self.repo.export_bundle(heads, common)
+    }
+
+    fn verify_reachable(&self, heads: &[NodeHash]) -> BoxStream<VerifyEvent, Self::Error> {
+        self.repo.verify_reachable(heads)
+    }
+
+    fn shutdown(&self) -> BoxFuture<(), Self::Error> {
+        self.repo.shutdown()
+    }
+}
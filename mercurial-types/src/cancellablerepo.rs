@@ -0,0 +1,262 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Repo wrapper whose streams can be stopped early, for example when a client disconnects.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use bytes::Bytes;
+use futures::{Async, Poll, Stream};
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+
+use blobnode::Parents;
+use bookmarks::Version;
+use changeset::Changeset;
+use manifest::{Entry, Manifest, ManifestDiffEntry, ManifestStats};
+use nodehash::NodeHash;
+use path::Path;
+use repo::{BoxedBookmarks, HashResolution, Repo};
+use verify::VerifyEvent;
+
+/// Stream which ends, without polling the inner stream again, once `cancelled` is set. The
+/// flag is checked before each poll, so an item which is already being produced is finished,
+/// but nothing after it is started.
+pub struct Cancellable<S> {
+    inner: Option<S>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<S> Cancellable<S> {
+    pub fn new(inner: S, cancelled: Arc<AtomicBool>) -> Self {
+        Cancellable {
+            inner: Some(inner),
+            cancelled,
+        }
+    }
+}
+
+impl<S: Stream> Stream for Cancellable<S> {
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            // Drop the inner stream straight away, so that it lets go of whatever it holds.
+            self.inner = None;
+        }
+        match self.inner {
+            Some(ref mut inner) => inner.poll(),
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+/// Repo whose streams all end cleanly, as if they had run out of items, once its cancellation
+/// flag is set. Clones of the flag can be handed to whatever notices that the results are no
+/// longer wanted. Futures are passed straight through, as they only make a bounded number of
+/// backend calls.
+///
+/// Only the streams returned by `Repo` methods themselves are cancellable, not those from the
+/// bookmarks, manifests and entries they return.
+pub struct CancellableRepo<R> {
+    repo: R,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<R> CancellableRepo<R>
+where
+    R: Repo,
+{
+    /// Wrap `repo`, with streams which end once `cancelled` is set.
+    pub fn new(repo: R, cancelled: Arc<AtomicBool>) -> Self {
+        CancellableRepo { repo, cancelled }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.repo
+    }
+
+    /// Stop all the streams from this repo, including ones not yet started.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn stream<T>(&self, inner: BoxStream<T, R::Error>) -> BoxStream<T, R::Error>
+    where
+        T: Send + 'static,
+    {
+        Cancellable::new(inner, self.cancelled.clone()).boxed()
+    }
+}
+
+impl<R> Repo for CancellableRepo<R>
+where
+    R: Repo,
+{
+    type Error = R::Error;
+
+    fn get_changesets(&self) -> BoxStream<NodeHash, Self::Error> {
+        self.stream(self.repo.get_changesets())
+    }
+
+    fn get_changesets_range(&self, skip: usize, limit: usize) -> BoxStream<NodeHash, Self::Error> {
+        self.stream(self.repo.get_changesets_range(skip, limit))
+    }
+
+    fn get_changesets_sorted(&self) -> BoxStream<NodeHash, Self::Error> {
+        self.stream(self.repo.get_changesets_sorted())
+    }
+
+    fn get_changesets_bounded(&self, max_depth: usize) -> BoxStream<NodeHash, Self::Error> {
+        self.stream(self.repo.get_changesets_bounded(max_depth))
+    }
+
+    fn get_changeset_count(&self) -> BoxFuture<u64, Self::Error> {
+        self.repo.get_changeset_count()
+    }
+
+    fn get_heads(&self) -> BoxStream<NodeHash, Self::Error> {
+        self.stream(self.repo.get_heads())
+    }
+
+    fn get_bookmarks(&self) -> Result<BoxedBookmarks<Self::Error>, Self::Error> {
+        self.repo.get_bookmarks()
+    }
+
+    fn get_bookmark(&self, name: &[u8]) -> BoxFuture<Option<(NodeHash, Version)>, Self::Error> {
+        self.repo.get_bookmark(name)
+    }
+
+    fn get_heads_for_bookmarks(&self, names: &[Vec<u8>]) -> BoxStream<NodeHash, Self::Error> {
+        self.stream(self.repo.get_heads_for_bookmarks(names))
+    }
+
+    fn prefetch(&self, nodeids: &[NodeHash]) -> BoxFuture<(), Self::Error> {
+        self.repo.prefetch(nodeids)
+    }
+
+    fn changeset_exists(&self, nodeid: &NodeHash) -> BoxFuture<bool, Self::Error> {
+        self.repo.changeset_exists(nodeid)
+    }
+
+    fn changesets_exist(
+        &self,
+        nodeids: &[NodeHash],
+    ) -> BoxFuture<Vec<(NodeHash, bool)>, Self::Error> {
+        self.repo.changesets_exist(nodeids)
+    }
+
+    fn get_changeset_by_nodeid(&self, nodeid: &NodeHash) -> BoxFuture<Box<Changeset>, Self::Error> {
+        self.repo.get_changeset_by_nodeid(nodeid)
+    }
+
+    fn get_changesets_by_nodeid(
+        &self,
+        nodeids: &[NodeHash],
+    ) -> BoxFuture<Vec<Box<Changeset>>, Self::Error> {
+        self.repo.get_changesets_by_nodeid(nodeids)
+    }
+
+    fn get_changeset_raw(&self, nodeid: &NodeHash) -> BoxFuture<Bytes, Self::Error> {
+        self.repo.get_changeset_raw(nodeid)
+    }
+
+    fn resolve_hash_prefix(&self, prefix: &str) -> BoxFuture<HashResolution, Self::Error> {
+        self.repo.resolve_hash_prefix(prefix)
+    }
+
+    fn get_changeset_parents(&self, nodeid: &NodeHash) -> BoxFuture<Parents, Self::Error> {
+        self.repo.get_changeset_parents(nodeid)
+    }
+
+    fn is_ancestor(
+        &self,
+        ancestor: &NodeHash,
+        descendant: &NodeHash,
+    ) -> BoxFuture<bool, Self::Error> {
+        self.repo.is_ancestor(ancestor, descendant)
+    }
+
+    fn common_ancestor(
+        &self,
+        a: &NodeHash,
+        b: &NodeHash,
+    ) -> BoxFuture<Option<NodeHash>, Self::Error> {
+        self.repo.common_ancestor(a, b)
+    }
+
+    fn get_manifest_by_nodeid(
+        &self,
+        nodeid: &NodeHash,
+    ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
+        self.repo.get_manifest_by_nodeid(nodeid)
+    }
+
+    fn get_root_manifest(
+        &self,
+        cs: &NodeHash,
+    ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
+        self.repo.get_root_manifest(cs)
+    }
+
+    fn diff_manifests(
+        &self,
+        base: &NodeHash,
+        other: &NodeHash,
+    ) -> BoxStream<ManifestDiffEntry, Self::Error> {
+        self.stream(self.repo.diff_manifests(base, other))
+    }
+
+    fn get_entry_by_path(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+    ) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Self::Error> {
+        self.repo.get_entry_by_path(cs, path)
+    }
+
+    fn get_file_history(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+        limit: usize,
+    ) -> BoxStream<(NodeHash, NodeHash), Self::Error> {
+        self.stream(self.repo.get_file_history(cs, path, limit))
+    }
+
+    fn walk_manifest(
+        &self,
+        root: &NodeHash,
+    ) -> BoxStream<(Path, Box<Entry<Error = Self::Error>>), Self::Error> {
+        self.stream(self.repo.walk_manifest(root))
+    }
+
+    fn manifest_stats(&self, root: &NodeHash) -> BoxFuture<ManifestStats, Self::Error> {
+        self.repo.manifest_stats(root)
+    }
+
+    fn export_bundle(
+        &self,
+        heads: &[NodeHash],
+        common: &[NodeHash],
+    ) -> BoxStream<Bytes, Self::Error> {
+        self.stream(self.repo.export_bundle(heads, common))
+    }
+
+    fn verify_reachable(&self, heads: &[NodeHash]) -> BoxStream<VerifyEvent, Self::Error> {
+        self.stream(self.repo.verify_reachable(heads))
+    }
+
+    fn shutdown(&self) -> BoxFuture<(), Self::Error> {
+        self.repo.shutdown()
+    }
+}
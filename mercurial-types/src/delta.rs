@@ -5,14 +5,26 @@
 // GNU General Public License version 2 or any later version.
 
 use std::cmp;
-
+use std::error;
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::slice;
+use std::thread;
+use std::u32;
+
+use bytes::Bytes;
+use heapsize::HeapSizeOf;
 use itertools::{self, PutBack};
 use quickcheck::{Arbitrary, Gen};
 use rand::distributions::{IndependentSample, LogNormal};
+use serde::{self, Deserialize, Deserializer};
 
 use errors::*;
 
-#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, HeapSizeOf)]
+/// Deltas can be serialized with serde. Deserializing checks the Fragments just as `Delta::new`
+/// does, so a corrupt serialized Delta is an error rather than producing an invalid Delta.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, HeapSizeOf)]
 pub struct Delta {
     // Fragments should be in sorted order by start offset and should not overlap.
     frags: Vec<Fragment>,
@@ -26,10 +38,430 @@ impl Delta {
         Ok(Delta { frags: frags })
     }
 
+    /// Construct a Delta from Fragments which may be out of order. They are stably sorted by
+    /// start offset and then verified as by `new`, so Fragments which overlap, including ones
+    /// with the same start, are still an error.
+    pub fn from_unsorted(mut frags: Vec<Fragment>) -> Result<Self> {
+        frags.sort_by_key(|frag| frag.start);
+        Self::new(frags)
+    }
+
     pub fn fragments(&self) -> &[Fragment] {
         self.frags.as_slice()
     }
 
+    /// Return true if this Delta has no Fragments, so applying it leaves the text unchanged.
+    pub fn is_empty(&self) -> bool {
+        self.frags.is_empty()
+    }
+
+    /// Return the number of Fragments in this Delta.
+    pub fn len(&self) -> usize {
+        self.frags.len()
+    }
+
+    /// Call `f` on each Fragment in order.
+    pub fn visit<F: FnMut(&Fragment)>(&self, mut f: F) {
+        for frag in &self.frags {
+            f(frag)
+        }
+    }
+
+    /// Combine the Fragments in order into a single value, starting from `init`, for example to
+    /// find the largest Fragment.
+    pub fn fold<T, F: FnMut(T, &Fragment) -> T>(&self, init: T, f: F) -> T {
+        self.frags.iter().fold(init, f)
+    }
+
+    /// Decode a Delta from Mercurial's binary delta encoding: a sequence of records, each of
+    /// which is a big-endian `u32` start offset, end offset and content length, followed by the
+    /// content itself.
+    pub fn from_mercurial_bytes(data: &[u8]) -> Result<Delta> {
+        let mut frags = Vec::new();
+        let mut rest = data;
+
+        while !rest.is_empty() {
+            if rest.len() < 12 {
+                let msg = format!(
+                    "fragment {}: truncated header ({} of 12 bytes)",
+                    frags.len(),
+                    rest.len()
+                );
+                bail!(ErrorKind::InvalidFragmentList(msg));
+            }
+            let start = read_u32_be(&rest[0..4]) as usize;
+            let end = read_u32_be(&rest[4..8]) as usize;
+            let len = read_u32_be(&rest[8..12]) as usize;
+            rest = &rest[12..];
+
+            if rest.len() < len {
+                let msg = format!(
+                    "fragment {}: truncated content ({} of {} bytes)",
+                    frags.len(),
+                    rest.len(),
+                    len
+                );
+                bail!(ErrorKind::InvalidFragmentList(msg));
+            }
+            frags.push(Fragment {
+                start: start,
+                end: end,
+                content: Bytes::from(&rest[..len]),
+            });
+            rest = &rest[len..];
+        }
+
+        Delta::new(frags)
+    }
+
+    /// Encode this Delta in Mercurial's binary delta encoding. See `from_mercurial_bytes`.
+    /// Offsets and lengths are encoded as `u32`s, so a Fragment which ends, or has content
+    /// longer than, `u32::MAX` bytes can't be encoded and is an error.
+    pub fn to_mercurial_bytes(&self) -> Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(self.serialized_size());
+        for (idx, frag) in self.frags.iter().enumerate() {
+            // start <= end, so end is the larger offset
+            if cmp::max(frag.end, frag.content.len()) > u32::MAX as usize {
+                let msg = format!(
+                    "fragment {}: too large to encode ({}..{}, {} bytes of content)",
+                    idx,
+                    frag.start,
+                    frag.end,
+                    frag.content.len()
+                );
+                bail!(ErrorKind::InvalidFragmentList(msg));
+            }
+            write_u32_be(&mut out, frag.start as u32);
+            write_u32_be(&mut out, frag.end as u32);
+            write_u32_be(&mut out, frag.content.len() as u32);
+            out.extend_from_slice(&frag.content);
+        }
+        Ok(out)
+    }
+
+    /// Return the length of the encoding `to_mercurial_bytes()` would produce, without encoding
+    /// anything: a 12 byte header for each Fragment, plus the content.
+    pub fn serialized_size(&self) -> usize {
+        self.frags.len() * 12 + self.content_size()
+    }
+
+    /// Return the total number of bytes of new content carried by this Delta.
+    pub fn content_size(&self) -> usize {
+        self.frags.iter().map(|frag| frag.content.len()).sum()
+    }
+
+    /// Return the length of the text that results from applying this Delta to a text of length
+    /// `base_len`. A valid Delta can never shrink the text below zero, but if `base_len` is too
+    /// short for this Delta the result saturates at 0 rather than underflowing.
+    pub fn output_size(&self, base_len: usize) -> usize {
+        let change = self.frags
+            .iter()
+            .map(|frag| frag.length_change())
+            .sum::<isize>();
+        if change < 0 {
+            base_len.saturating_sub((-change) as usize)
+        } else {
+            base_len + change as usize
+        }
+    }
+
+    /// Return how many bytes this Delta stores per byte of the text it produces from a base of
+    /// length `base_len`, to help decide between storing a delta and a full snapshot. A Delta
+    /// which rewrites the whole text has a ratio of 1.0, and a small edit approaches 0.0. If
+    /// the output would be empty, the ratio is 0.0.
+    pub fn compression_ratio(&self, base_len: usize) -> f64 {
+        match self.output_size(base_len) {
+            0 => 0.0,
+            output_size => self.content_size() as f64 / output_size as f64,
+        }
+    }
+
+    /// Count this Delta's Fragments by kind. A Fragment which neither inserts nor deletes
+    /// anything isn't counted at all.
+    pub fn change_histogram(&self) -> ChangeHistogram {
+        let mut hist = ChangeHistogram::default();
+        for frag in &self.frags {
+            if frag.is_insertion() {
+                hist.insertions += 1;
+            } else if frag.is_deletion() {
+                hist.deletions += 1;
+            } else if frag.is_replacement() {
+                hist.replacements += 1;
+            }
+        }
+        hist
+    }
+
+    /// Transform the content of each Fragment with `f`, for example to expand keywords or
+    /// normalize line endings. Fragment offsets refer to the original text and are independent of
+    /// the content length, so they are left unchanged. The result is re-verified anyway.
+    pub fn map_content<F>(self, mut f: F) -> Result<Delta>
+    where
+        F: FnMut(Bytes) -> Bytes,
+    {
+        let frags = self.frags
+            .into_iter()
+            .map(|frag| {
+                Fragment {
+                    start: frag.start,
+                    end: frag.end,
+                    content: f(frag.content),
+                }
+            })
+            .collect();
+        Delta::new(frags)
+    }
+
+    /// Add `shift` to the start and end offsets of every Fragment, for example to splice a
+    /// Delta for part of a text into a Delta for the whole. It is an error for any offset to
+    /// end up negative (or to overflow).
+    pub fn rebase(self, shift: isize) -> Result<Delta> {
+        let mut frags = Vec::with_capacity(self.frags.len());
+        for (i, frag) in self.frags.into_iter().enumerate() {
+            let start = shift_offset(frag.start, shift);
+            let end = shift_offset(frag.end, shift);
+            match (start, end) {
+                (Some(start), Some(end)) => frags.push(Fragment {
+                    start: start,
+                    end: end,
+                    content: frag.content,
+                }),
+                _ => {
+                    let msg = format!(
+                        "fragment {}: range {}..{} can't be shifted by {}",
+                        i,
+                        frag.start,
+                        frag.end,
+                        shift
+                    );
+                    bail!(ErrorKind::InvalidFragmentList(msg));
+                }
+            }
+        }
+        Delta::new(frags)
+    }
+
+    /// Merge adjacent Fragments (where one Fragment ends exactly where the next one starts)
+    /// into a single Fragment. Fragments separated by unchanged text are left alone.
+    pub fn normalize(&mut self) {
+        let frags = mem::replace(&mut self.frags, Vec::new());
+        let mut merged: Vec<Fragment> = Vec::with_capacity(frags.len());
+
+        for frag in frags {
+            if let Some(prev) = merged.last_mut() {
+                if prev.end == frag.start {
+                    prev.end = frag.end;
+                    prev.content.extend_from_slice(&frag.content);
+                    continue;
+                }
+            }
+            merged.push(frag);
+        }
+
+        self.frags = merged;
+    }
+
+    /// Consuming version of `normalize`.
+    pub fn normalized(mut self) -> Delta {
+        self.normalize();
+        self
+    }
+
+    /// Return true if this Delta makes the same changes as `other`, however they're split into
+    /// Fragments. Fragments which change nothing are ignored, and adjacent ones are merged as
+    /// by `normalize`, before comparing. The derived `==` compares the Fragments exactly.
+    pub fn semantically_eq(&self, other: &Delta) -> bool {
+        let canonical = |delta: &Delta| {
+            let frags = delta
+                .frags
+                .iter()
+                .filter(|frag| frag.start != frag.end || !frag.content.is_empty())
+                .cloned()
+                .collect();
+            Delta { frags: frags }.normalized()
+        };
+        canonical(self) == canonical(other)
+    }
+
+    /// Return an equivalent Delta against `text` whose Fragments never split a line: each one
+    /// is widened to start at the beginning of a line and end just after a `\n` (or at the end
+    /// of `text`), with the widened parts copied into its content so that applying it gives the
+    /// same result. Fragments which end up touching the same line are merged, so that textual
+    /// merges see each changed line exactly once. Fragments which change nothing are dropped.
+    ///
+    /// The Fragments must fit within `text`, as for `apply`.
+    pub fn line_align(&self, text: &[u8]) -> Delta {
+        let mut frags = Vec::with_capacity(self.frags.len());
+        // Start (already aligned), unaligned end and content of the Fragment being built.
+        let mut cur: Option<(usize, usize, Vec<u8>)> = None;
+
+        for frag in &self.frags {
+            if frag.start == frag.end && frag.content.is_empty() {
+                continue;
+            }
+            let start = line_start(text, frag.start);
+
+            if let Some((_, ref mut end, ref mut content)) = cur {
+                if line_end(text, *end) > start {
+                    // Both Fragments touch the line containing `start`, so merge them.
+                    content.extend_from_slice(&text[*end..frag.start]);
+                    content.extend_from_slice(frag.content.as_ref());
+                    *end = frag.end;
+                    continue;
+                }
+            }
+            if let Some(done) = cur.take() {
+                frags.push(finish_line_aligned(text, done));
+            }
+
+            let mut content = Vec::with_capacity(frag.content.len());
+            content.extend_from_slice(&text[start..frag.start]);
+            content.extend_from_slice(frag.content.as_ref());
+            cur = Some((start, frag.end, content));
+        }
+        if let Some(done) = cur {
+            frags.push(finish_line_aligned(text, done));
+        }
+
+        Delta { frags: frags }
+    }
+
+    /// Split this Delta at `offset` in the original text, into one Delta with the changes
+    /// before `offset` and one with the changes at or after it. A Fragment straddling `offset`
+    /// is split in two, with as much of its content as fits before `offset` going to the first
+    /// half. Insertions exactly at `offset` go to the second half.
+    ///
+    /// Both halves are still against the original text, so `concat` puts them back together.
+    /// To apply them one after the other instead, the offsets of the second half need shifting
+    /// by the change in length caused by the first.
+    pub fn split_at(self, offset: usize) -> (Delta, Delta) {
+        let mut before = Vec::new();
+        let mut after = Vec::new();
+
+        for mut frag in self.frags {
+            if frag.end <= offset && frag.start < offset {
+                before.push(frag);
+            } else if frag.start >= offset {
+                after.push(frag);
+            } else {
+                let rest = match frag.split(offset) {
+                    Some(rest) => rest,
+                    None => {
+                        // The content ends before `offset`, so the rest just deletes.
+                        let end = mem::replace(&mut frag.end, offset);
+                        Fragment {
+                            start: offset,
+                            end: end,
+                            content: Bytes::new(),
+                        }
+                    }
+                };
+                before.push(frag);
+                after.push(rest);
+            }
+        }
+
+        (Delta { frags: before }, Delta { frags: after })
+    }
+
+    /// Trim this Delta so that applying it to a text of length `base_len` produces only the
+    /// first `max_output` bytes of what it produced before. Fragments beyond that point are
+    /// dropped, the one straddling it keeps only the content which fits, and the rest of the
+    /// text is deleted. If the output is no longer than `max_output` anyway, it is unchanged.
+    ///
+    /// The text length is needed because the truncated Delta has to delete everything after the
+    /// cut-off point, right up to the end of the text.
+    pub fn truncate_output(self, base_len: usize, max_output: usize) -> Delta {
+        let mut frags = Vec::with_capacity(self.frags.len());
+        let mut out = 0;
+        let mut off = 0;
+        // Offset in the text at which the output reaches `max_output`, if it's in unchanged text.
+        let mut cut = None;
+
+        for mut frag in self.frags {
+            let gap = frag.start - off;
+            if out + gap >= max_output {
+                cut = Some(off + (max_output - out));
+                break;
+            }
+            out += gap;
+
+            if out + frag.content.len() >= max_output {
+                // The output ends in this Fragment's content, so keep what fits and delete the
+                // rest of the text.
+                frag.content = frag.content.slice_to(max_output - out);
+                frag.end = base_len;
+                frags.push(frag);
+                return Delta { frags: frags };
+            }
+            out += frag.content.len();
+            off = frag.end;
+            frags.push(frag);
+        }
+
+        if cut.is_none() && out + (base_len - off) > max_output {
+            cut = Some(off + (max_output - out));
+        }
+        if let Some(cut) = cut {
+            if cut < base_len {
+                frags.push(Fragment {
+                    start: cut,
+                    end: base_len,
+                    content: Bytes::new(),
+                });
+            }
+        }
+
+        Delta { frags: frags }
+    }
+
+    /// Return an iterator over the `(start, end)` range of the original text that each Fragment
+    /// replaces, in order. Pure insertions have `start == end`.
+    pub fn touched_ranges<'a>(&'a self) -> TouchedRanges<'a> {
+        TouchedRanges(self.frags.iter())
+    }
+
+    /// Return an iterator over the pieces of the text that results from applying this Delta
+    /// to a text of length `text_len`, in output order: runs copied from the original text,
+    /// including any tail after the last Fragment, interleaved with Fragment content. Empty
+    /// pieces are skipped. Nothing checks that the Fragments fit within `text_len`; see
+    /// `try_apply` for that.
+    pub fn segments<'a>(&'a self, text_len: usize) -> Segments<'a> {
+        Segments {
+            frags: self.frags.iter(),
+            text_len: text_len,
+            off: 0,
+            pending: None,
+        }
+    }
+
+    /// Return true if this Delta and `other`, both against the same original text, modify any
+    /// of the same region of it. Ranges which merely abut don't overlap, but an insertion
+    /// strictly inside a replaced range does, as do two insertions at the same offset.
+    pub fn overlaps(&self, other: &Delta) -> bool {
+        let mut ours = self.touched_ranges().peekable();
+        let mut theirs = other.touched_ranges().peekable();
+
+        loop {
+            let (a, b) = match (ours.peek(), theirs.peek()) {
+                (Some(&a), Some(&b)) => (a, b),
+                _ => return false,
+            };
+            if ranges_overlap(a, b) {
+                return true;
+            }
+
+            // Ranges in each Delta are sorted and disjoint, so the range which ends first can't
+            // overlap anything later in the other Delta. On a tie, the earlier-starting one is
+            // non-empty, and nothing later can start strictly inside it.
+            if (a.1, a.0) < (b.1, b.0) {
+                ours.next();
+            } else {
+                theirs.next();
+            }
+        }
+    }
+
     fn verify(frags: &[Fragment]) -> Result<()> {
         let mut prev_frag: Option<&Fragment> = None;
         for (i, frag) in frags.iter().enumerate() {
@@ -54,14 +486,245 @@ impl Delta {
     }
 }
 
+/// Incrementally assemble a Delta, checking each Fragment as it is added rather than all at
+/// once in `Delta::new`, so that a bad Fragment is reported as soon as it's pushed.
+#[derive(Clone, Debug, Default)]
+pub struct DeltaBuilder {
+    frags: Vec<Fragment>,
+}
+
+impl DeltaBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        DeltaBuilder {
+            frags: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Add a Fragment replacing `start..end` of the original text with `content`. It must not
+    /// start before the end of the previously pushed Fragment.
+    pub fn push<B>(&mut self, start: usize, end: usize, content: B) -> Result<&mut Self>
+    where
+        B: Into<Bytes>,
+    {
+        let idx = self.frags.len();
+        if start > end {
+            let msg = format!("fragment {}: start {} > end {}", idx, start, end);
+            bail!(ErrorKind::InvalidFragmentList(msg));
+        }
+        if let Some(prev) = self.frags.last() {
+            if start < prev.end {
+                let msg = format!(
+                    "fragment {}: previous end {} overlaps with start {}",
+                    idx,
+                    prev.end,
+                    start
+                );
+                bail!(ErrorKind::InvalidFragmentList(msg));
+            }
+        }
+
+        self.frags.push(Fragment {
+            start: start,
+            end: end,
+            content: content.into(),
+        });
+        Ok(self)
+    }
+
+    /// Number of Fragments pushed so far.
+    pub fn len(&self) -> usize {
+        self.frags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frags.is_empty()
+    }
+
+    pub fn build(self) -> Delta {
+        Delta { frags: self.frags }
+    }
+}
+
+impl<'de> Deserialize<'de> for Delta {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // Same shape as Delta, but without the checks.
+        #[derive(Deserialize)]
+        #[serde(rename = "Delta")]
+        struct UncheckedDelta {
+            frags: Vec<Fragment>,
+        }
+
+        let delta = UncheckedDelta::deserialize(deserializer)?;
+        Delta::new(delta.frags).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Iterator over the ranges of the original text touched by a Delta; see
+/// `Delta::touched_ranges`.
+pub struct TouchedRanges<'a>(slice::Iter<'a, Fragment>);
+
+impl<'a> Iterator for TouchedRanges<'a> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|frag| (frag.start, frag.end))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+/// A piece of the output of applying a Delta; see `Delta::segments`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Segment<'a> {
+    /// `len` bytes of the original text, starting at offset `from`.
+    Copy { from: usize, len: usize },
+    /// New content from a Fragment.
+    Insert { bytes: &'a [u8] },
+}
+
+/// Iterator over the output of applying a Delta; see `Delta::segments`.
+pub struct Segments<'a> {
+    frags: slice::Iter<'a, Fragment>,
+    text_len: usize,
+    /// Offset in the original text just after the last Fragment seen.
+    off: usize,
+    /// Content of the last Fragment seen, if it's still to be produced after a copy.
+    pending: Option<&'a [u8]>,
+}
+
+impl<'a> Iterator for Segments<'a> {
+    type Item = Segment<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(bytes) = self.pending.take() {
+            return Some(Segment::Insert { bytes });
+        }
+
+        while let Some(frag) = self.frags.next() {
+            let from = self.off;
+            self.off = frag.end;
+            let content = if frag.content.is_empty() {
+                None
+            } else {
+                Some(frag.content.as_ref())
+            };
+
+            if from < frag.start {
+                self.pending = content;
+                return Some(Segment::Copy {
+                    from: from,
+                    len: frag.start - from,
+                });
+            }
+            if let Some(bytes) = content {
+                return Some(Segment::Insert { bytes });
+            }
+        }
+
+        if self.off < self.text_len {
+            let from = self.off;
+            self.off = self.text_len;
+            return Some(Segment::Copy {
+                from: from,
+                len: self.text_len - from,
+            });
+        }
+        None
+    }
+}
+
+/// Number of Fragments of each kind in a Delta; see `Delta::change_histogram`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ChangeHistogram {
+    /// Fragments which only insert new content.
+    pub insertions: usize,
+    /// Fragments which only delete part of the original text.
+    pub deletions: usize,
+    /// Fragments which replace part of the original text with new content.
+    pub replacements: usize,
+}
+
+/// Add `shift` to `off`, returning `None` if the result isn't a valid offset.
+fn shift_offset(off: usize, shift: isize) -> Option<usize> {
+    if shift >= 0 {
+        off.checked_add(shift as usize)
+    } else {
+        // Written this way so that `isize::MIN` doesn't overflow when negated.
+        off.checked_sub((-(shift + 1)) as usize + 1)
+    }
+}
+
+/// Return the offset of the start of the line in `text` containing `off`.
+fn line_start(text: &[u8], off: usize) -> usize {
+    text[..off]
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |pos| pos + 1)
+}
+
+/// Return `off` if it is at the end of a line in `text`, or otherwise the offset just after
+/// the end of the line containing it.
+fn line_end(text: &[u8], off: usize) -> usize {
+    if off == 0 || text[off - 1] == b'\n' {
+        return off;
+    }
+    text[off..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(text.len(), |pos| off + pos + 1)
+}
+
+/// Build the Fragment for `Delta::line_align` from its aligned start, unaligned end and
+/// content, widening the end to the end of the line.
+fn finish_line_aligned(
+    text: &[u8],
+    (start, end, mut content): (usize, usize, Vec<u8>),
+) -> Fragment {
+    let aligned = line_end(text, end);
+    content.extend_from_slice(&text[end..aligned]);
+    Fragment {
+        start: start,
+        end: aligned,
+        content: Bytes::from(content),
+    }
+}
+
+fn ranges_overlap(a: (usize, usize), b: (usize, usize)) -> bool {
+    match (a.0 == a.1, b.0 == b.1) {
+        (true, true) => a.0 == b.0,
+        (true, false) => b.0 < a.0 && a.0 < b.1,
+        (false, true) => a.0 < b.0 && b.0 < a.1,
+        (false, false) => a.0 < b.1 && b.0 < a.1,
+    }
+}
+
+fn read_u32_be(buf: &[u8]) -> u32 {
+    (buf[0] as u32) << 24 | (buf[1] as u32) << 16 | (buf[2] as u32) << 8 | buf[3] as u32
+}
+
+fn write_u32_be(out: &mut Vec<u8>, val: u32) {
+    out.extend_from_slice(&[(val >> 24) as u8, (val >> 16) as u8, (val >> 8) as u8, val as u8]);
+}
+
 impl Default for Delta {
     fn default() -> Delta {
         Delta { frags: Vec::new() }
     }
 }
 
-impl Arbitrary for Delta {
-    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+impl Delta {
+    /// Generate an arbitrary Delta like `Arbitrary::arbitrary`, but with Fragment content
+    /// generated according to `config`.
+    pub fn arbitrary_with_config<G: Gen>(g: &mut G, config: &FragmentGenConfig) -> Self {
         let size = g.size();
         let nfrags = g.gen_range(0, size);
 
@@ -76,13 +739,19 @@ impl Arbitrary for Delta {
                 let val = Fragment {
                     start: start,
                     end: end,
-                    content: arbitrary_frag_content(g),
+                    content: arbitrary_frag_content(g, config),
                 };
                 val
             })
             .collect();
         Delta { frags: frags }
     }
+}
+
+impl Arbitrary for Delta {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Delta::arbitrary_with_config(g, &FragmentGenConfig::default())
+    }
 
     fn shrink(&self) -> Box<Iterator<Item = Self>> {
         // Not all instances generated by Vec::shrink will be
@@ -98,14 +767,99 @@ impl Arbitrary for Delta {
 }
 
 /// Represents a single contiguous modified region of text.
-#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, HeapSizeOf)]
+///
+/// The content is held as `Bytes` so that Fragments decoded from a network buffer (or split
+/// during `combine`) can share the underlying storage rather than copying it.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct Fragment {
     pub start: usize,
     pub end: usize,
-    pub content: Vec<u8>,
+    #[serde(with = "content_serde")]
+    pub content: Bytes,
+}
+
+/// Serialize Fragment content as a byte string.
+mod content_serde {
+    use super::*;
+
+    use serde::Serializer;
+    use serde::de::{SeqAccess, Visitor};
+
+    pub fn serialize<S>(content: &Bytes, serializer: S) -> ::std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(content.as_ref())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> ::std::result::Result<Bytes, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Bytes;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a byte string")
+        }
+
+        fn visit_bytes<E>(self, v: &[u8]) -> ::std::result::Result<Bytes, E> {
+            Ok(Bytes::from(v))
+        }
+
+        fn visit_byte_buf<E>(self, v: Vec<u8>) -> ::std::result::Result<Bytes, E> {
+            Ok(Bytes::from(v))
+        }
+
+        // Formats without a native byte string type represent it as a sequence.
+        fn visit_seq<A>(self, mut seq: A) -> ::std::result::Result<Bytes, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut content = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(b) = seq.next_element()? {
+                content.push(b);
+            }
+            Ok(Bytes::from(content))
+        }
+    }
+}
+
+impl HeapSizeOf for Fragment {
+    fn heap_size_of_children(&self) -> usize {
+        // The content may be shared with other Fragments, so this is an upper bound.
+        self.content.len()
+    }
 }
 
 impl Fragment {
+    /// Create a Fragment which replaces `start..end` of the original text with `content`,
+    /// failing if the range is inverted.
+    ///
+    /// ```
+    /// use mercurial_types::delta::{self, Delta, Fragment};
+    ///
+    /// let frag = Fragment::new(4, 9, &b"slow"[..]).unwrap();
+    /// let delta = Delta::new(vec![frag]).unwrap();
+    /// assert_eq!(delta::apply(b"the quick fox", delta), b"the slow fox");
+    ///
+    /// assert!(Fragment::new(9, 4, &b""[..]).is_err());
+    /// ```
+    pub fn new<B: Into<Bytes>>(start: usize, end: usize, content: B) -> Result<Fragment> {
+        let frag = Fragment {
+            start: start,
+            end: end,
+            content: content.into(),
+        };
+        frag.verify()?;
+        Ok(frag)
+    }
+
     /// Return the end offset of this Fragment's content, after application.
     pub fn post_end(&self) -> usize {
         self.start + self.content.len()
@@ -116,6 +870,21 @@ impl Fragment {
         self.content.len() as isize - (self.end - self.start) as isize
     }
 
+    /// Return true if this Fragment only inserts new content, without replacing anything.
+    pub fn is_insertion(&self) -> bool {
+        self.start == self.end && !self.content.is_empty()
+    }
+
+    /// Return true if this Fragment only deletes part of the original text.
+    pub fn is_deletion(&self) -> bool {
+        self.start < self.end && self.content.is_empty()
+    }
+
+    /// Return true if this Fragment replaces part of the original text with new content.
+    pub fn is_replacement(&self) -> bool {
+        self.start < self.end && !self.content.is_empty()
+    }
+
     /// Return true if the given offset falls within this Fragment's content (post-application).
     pub fn contains_offset(&self, offset: usize) -> bool {
         self.start <= offset && offset < self.post_end()
@@ -152,8 +921,10 @@ impl Fragment {
     }
 }
 
-impl Arbitrary for Fragment {
-    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+impl Fragment {
+    /// Generate an arbitrary Fragment like `Arbitrary::arbitrary`, but with content generated
+    /// according to `config`.
+    pub fn arbitrary_with_config<G: Gen>(g: &mut G, config: &FragmentGenConfig) -> Self {
         let size = g.size();
 
         // Maintain invariant start <= end.
@@ -163,13 +934,19 @@ impl Arbitrary for Fragment {
         Fragment {
             start: start,
             end: end,
-            content: arbitrary_frag_content(g),
+            content: arbitrary_frag_content(g, config),
         }
     }
+}
+
+impl Arbitrary for Fragment {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Fragment::arbitrary_with_config(g, &FragmentGenConfig::default())
+    }
 
     fn shrink(&self) -> Box<Iterator<Item = Self>> {
         Box::new(
-            (self.start, self.end, self.content.clone())
+            (self.start, self.end, self.content.to_vec())
                 .shrink()
                 .filter(|&(start, end, ref _content)| {
                     // shrink could produce bad values
@@ -179,37 +956,91 @@ impl Arbitrary for Fragment {
                     Fragment {
                         start: start,
                         end: end,
-                        content: content,
+                        content: Bytes::from(content),
                     }
                 }),
         )
     }
 }
 
-fn arbitrary_frag_content<G: Gen>(g: &mut G) -> Vec<u8> {
-    let size = g.size();
-    // Using a uniform distribution over size here can lead to extremely bloated
-    // data structures. We also want to test zero-length data with more than a
-    // (1/size) probability. So use a lognormal distribution.
-    //
-    // The choice of mean and stdev are pretty arbitrary, but they work well for
-    // common sizes (~100).
+/// Parameters for generating arbitrary Fragment content.
+///
+/// Using a uniform distribution over the generator's size can lead to extremely bloated data
+/// structures, and we also want to test zero-length content with more than a (1/size)
+/// probability. So content lengths are drawn from a lognormal distribution, scaled by the
+/// generator's size and capped at `max_len`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FragmentGenConfig {
+    /// Mean of the underlying normal distribution.
+    pub mean: f64,
+    /// Standard deviation of the underlying normal distribution.
+    pub stdev: f64,
+    /// Maximum content length.
+    pub max_len: usize,
+}
+
+impl Default for FragmentGenConfig {
+    /// The choice of mean and stdev are pretty arbitrary, but they work well for common sizes
+    /// (~100).
     // TODO: make this more rigorous, e.g. by using params such that p95 = size.
-    let lognormal = LogNormal::new(-3.0, 2.0);
+    fn default() -> Self {
+        FragmentGenConfig {
+            mean: -3.0,
+            stdev: 2.0,
+            max_len: 64 * 1024,
+        }
+    }
+}
+
+fn arbitrary_frag_content<G: Gen>(g: &mut G, config: &FragmentGenConfig) -> Bytes {
+    let size = g.size();
+    let lognormal = LogNormal::new(config.mean, config.stdev);
     let content_len = ((size as f64) * lognormal.ind_sample(g)) as usize;
+    let content_len = cmp::min(content_len, config.max_len);
 
-    let mut v = Vec::with_capacity(content_len);
+    let mut v = vec![0; content_len];
     g.fill_bytes(&mut v);
-    v
+    Bytes::from(v)
 }
 
 /// Apply a Delta to an input text, returning the result.
+///
+/// Panics if the Delta's Fragments do not fit within `text`; use `try_apply` for Deltas that
+/// come from an untrusted source.
 pub fn apply(text: &[u8], delta: Delta) -> Vec<u8> {
+    try_apply(text, &delta).expect("delta does not apply to text")
+}
+
+/// Apply a Delta to an input text, returning the result. Unlike `apply`, this checks that
+/// every Fragment fits within `text` and returns an error rather than panicking if not.
+pub fn try_apply(text: &[u8], delta: &Delta) -> Result<Vec<u8>> {
+    match delta.frags.len() {
+        0 => Ok(text.to_vec()),
+        // A localized edit is by far the most common kind of delta, and needs no bookkeeping.
+        1 => apply_one(text, &delta.frags[0]),
+        _ => apply_chunks(text, delta),
+    }
+}
+
+/// Apply a single Fragment to `text`, as `try_apply` would apply a Delta containing only it.
+fn apply_one(text: &[u8], frag: &Fragment) -> Result<Vec<u8>> {
+    check_fits(text, 0, frag, 0)?;
+
+    let suffix = &text[frag.end..];
+    let mut output = Vec::with_capacity(frag.start + frag.content.len() + suffix.len());
+    output.extend_from_slice(&text[..frag.start]);
+    output.extend_from_slice(&frag.content);
+    output.extend_from_slice(suffix);
+    Ok(output)
+}
+
+/// The general case of `try_apply`, for any number of Fragments.
+fn apply_chunks(text: &[u8], delta: &Delta) -> Result<Vec<u8>> {
     let mut chunks = Vec::with_capacity(delta.frags.len() * 2);
     let mut off = 0;
 
-    for frag in &delta.frags {
-        assert!(off <= frag.start);
+    for (i, frag) in delta.frags.iter().enumerate() {
+        check_fits(text, i, frag, off)?;
         if off < frag.start {
             chunks.push(&text[off..frag.start]);
         }
@@ -227,7 +1058,463 @@ pub fn apply(text: &[u8], delta: Delta) -> Vec<u8> {
     for c in chunks {
         output.extend_from_slice(c);
     }
-    output
+    Ok(output)
+}
+
+/// Check that Fragment `i` of a Delta, `frag`, fits in `text` after offset `off`, where the
+/// previous Fragment ended.
+fn check_fits(text: &[u8], i: usize, frag: &Fragment, off: usize) -> Result<()> {
+    if frag.start < off || frag.end < frag.start || frag.end > text.len() {
+        let msg = format!(
+            "fragment {}: range {}..{} does not fit after offset {} in text of length {}",
+            i,
+            frag.start,
+            frag.end,
+            off,
+            text.len()
+        );
+        bail!(ErrorKind::FragmentOutOfBounds(msg));
+    }
+    Ok(())
+}
+
+/// Check that every Fragment of `delta` fits in `text`, as `try_apply` requires.
+fn check_all_fit(text: &[u8], delta: &Delta) -> Result<()> {
+    let mut off = 0;
+    for (i, frag) in delta.frags.iter().enumerate() {
+        check_fits(text, i, frag, off)?;
+        off = frag.end;
+    }
+    Ok(())
+}
+
+/// Apply a Delta to an input text, writing the result directly to `out`. This produces
+/// exactly the same bytes as `apply`, but without materializing the whole output in memory.
+///
+/// If the Delta's Fragments do not fit within `text`, this fails with
+/// `io::ErrorKind::InvalidData` before anything is written.
+pub fn apply_to_writer<W: Write>(text: &[u8], delta: &Delta, out: &mut W) -> io::Result<()> {
+    if let Err(err) = check_all_fit(text, delta) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, err.to_string()));
+    }
+
+    for segment in delta.segments(text.len()) {
+        match segment {
+            Segment::Copy { from, len } => out.write_all(&text[from..from + len])?,
+            Segment::Insert { bytes } => out.write_all(bytes)?,
+        }
+    }
+    Ok(())
+}
+
+/// Apply a Delta to an input text, replacing the contents of `out` with the result. This
+/// produces exactly the same bytes as `apply`, but reuses `out`'s allocation, so a loop
+/// applying many deltas needn't allocate a new buffer for each one.
+///
+/// Like `try_apply`, this returns an error if the Delta's Fragments do not fit within `text`,
+/// in which case `out` is left unchanged.
+pub fn apply_into(text: &[u8], delta: &Delta, out: &mut Vec<u8>) -> Result<()> {
+    check_all_fit(text, delta)?;
+
+    out.clear();
+    out.reserve(apply_stats(text.len(), delta).output_len);
+
+    let mut off = 0;
+    for frag in &delta.frags {
+        if off < frag.start {
+            out.extend_from_slice(&text[off..frag.start]);
+        }
+        out.extend_from_slice(frag.content.as_ref());
+        off = frag.end;
+    }
+    if off < text.len() {
+        out.extend_from_slice(&text[off..text.len()]);
+    }
+    Ok(())
+}
+
+/// Summary of what applying a Delta to a text would produce; see `apply_stats`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ApplyStats {
+    /// Length of the resulting text.
+    pub output_len: usize,
+    /// Bytes of the original text which are kept.
+    pub bytes_copied: usize,
+    /// Bytes of new content inserted by the Fragments.
+    pub bytes_inserted: usize,
+    /// Bytes of the original text replaced by the Fragments.
+    pub bytes_deleted: usize,
+}
+
+/// Work out the result of applying `delta` to a text of length `text_len`, without doing it.
+/// `output_len` is always `bytes_copied + bytes_inserted`, and is the length `apply` would
+/// produce. This only looks at Fragment offsets and lengths, so it's cheap.
+///
+/// The Delta is assumed to fit the text, as for `apply`; if the text is too short, whatever
+/// the Fragments would need beyond its end is not counted as copied.
+pub fn apply_stats(text_len: usize, delta: &Delta) -> ApplyStats {
+    let mut stats = ApplyStats::default();
+    let mut off = 0;
+
+    for frag in &delta.frags {
+        stats.bytes_copied += frag.start.saturating_sub(off);
+        stats.bytes_inserted += frag.content.len();
+        stats.bytes_deleted += frag.end - frag.start;
+        off = frag.end;
+    }
+    stats.bytes_copied += text_len.saturating_sub(off);
+    stats.output_len = stats.bytes_copied + stats.bytes_inserted;
+
+    stats
+}
+
+/// Apply a Delta to an input text which is given as a sequence of chunks, such as a file
+/// streamed from a blobstore, writing the result to `out`. This produces exactly the same bytes
+/// as `apply` on the concatenated chunks, without ever needing the whole input text in memory.
+///
+/// Chunk boundaries needn't line up with the Fragments in any way. If the chunks end before a
+/// Fragment's range does, this fails with `io::ErrorKind::UnexpectedEof`.
+pub fn apply_chunked<I, W>(base_chunks: I, delta: &Delta, out: &mut W) -> io::Result<()>
+where
+    I: Iterator<Item = Bytes>,
+    W: Write,
+{
+    let mut base = ChunkCursor {
+        chunks: base_chunks,
+        current: Bytes::new(),
+    };
+    let mut off = 0;
+
+    for frag in &delta.frags {
+        assert!(off <= frag.start);
+        base.advance(frag.start - off, Some(&mut *out))?;
+        if frag.content.len() > 0 {
+            out.write_all(frag.content.as_ref())?;
+        }
+        base.advance(frag.end - frag.start, None::<&mut W>)?;
+        off = frag.end;
+    }
+
+    // Whatever remains of the input text is unchanged.
+    out.write_all(base.current.as_ref())?;
+    for chunk in base.chunks {
+        out.write_all(chunk.as_ref())?;
+    }
+
+    Ok(())
+}
+
+/// Position within a text made of a sequence of chunks, for `apply_chunked`. `current` is
+/// the unconsumed part of the chunk the position is in.
+struct ChunkCursor<I> {
+    chunks: I,
+    current: Bytes,
+}
+
+impl<I: Iterator<Item = Bytes>> ChunkCursor<I> {
+    /// Move forward `len` bytes, writing the bytes passed over to `out` if it is given, or
+    /// discarding them otherwise.
+    fn advance<W: Write>(&mut self, mut len: usize, mut out: Option<&mut W>) -> io::Result<()> {
+        while len > 0 {
+            if self.current.is_empty() {
+                match self.chunks.next() {
+                    Some(chunk) => self.current = chunk,
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "base text ended before the end of the delta",
+                        ))
+                    }
+                }
+                continue;
+            }
+
+            let n = cmp::min(len, self.current.len());
+            let piece = self.current.split_to(n);
+            if let Some(ref mut out) = out {
+                out.write_all(piece.as_ref())?;
+            }
+            len -= n;
+        }
+        Ok(())
+    }
+}
+
+/// Compute the inverse of a Delta. Given the original `text` that `delta` applies to, return
+/// a Delta which, when applied to `apply(text, delta)`, yields back `text`.
+///
+/// Each Fragment in the inverse covers the content inserted by the corresponding forward
+/// Fragment (in post-application coordinates) and replaces it with the original bytes
+/// `text[frag.start..frag.end]`.
+pub fn invert(text: &[u8], delta: &Delta) -> Delta {
+    let mut frags = Vec::with_capacity(delta.frags.len());
+
+    // Cumulative change in length caused by the fragments processed so far. This is
+    // needed to translate offsets in the original text into offsets in the new text.
+    let mut cum_len_change = 0;
+
+    for frag in &delta.frags {
+        let start = adjust(frag.start, -cum_len_change);
+        frags.push(Fragment {
+            start: start,
+            end: start + frag.content.len(),
+            content: Bytes::from(&text[frag.start..frag.end]),
+        });
+        cum_len_change += frag.length_change();
+    }
+
+    Delta { frags: frags }
+}
+
+/// Apply a Delta to `text` like `apply`, and also return its inverse as `invert` would, in a
+/// single pass over the Fragments. Applying the inverse to the output yields `text` again.
+///
+/// Like `apply`, this panics if the Delta doesn't fit within `text`.
+pub fn apply_with_inverse(text: &[u8], delta: &Delta) -> (Vec<u8>, Delta) {
+    let mut out = Vec::with_capacity(apply_stats(text.len(), delta).output_len);
+    let mut frags = Vec::with_capacity(delta.frags.len());
+
+    let mut off = 0;
+    for frag in &delta.frags {
+        out.extend_from_slice(&text[off..frag.start]);
+        // The inverse replaces this Fragment's content, wherever it ends up in the output,
+        // with the bytes it replaced.
+        frags.push(Fragment {
+            start: out.len(),
+            end: out.len() + frag.content.len(),
+            content: Bytes::from(&text[frag.start..frag.end]),
+        });
+        out.extend_from_slice(frag.content.as_ref());
+        off = frag.end;
+    }
+    out.extend_from_slice(&text[off..]);
+
+    (out, Delta { frags: frags })
+}
+
+/// Default minimum length of a common run of bytes for `diff` to preserve it as a match. Shorter
+/// matches are folded into the surrounding changes, which yields fewer, larger Fragments.
+pub const DEFAULT_MIN_MATCH: usize = 4;
+
+/// Upper bound on the edit distance `diff` will search for. If the differing region of the two
+/// texts needs more edits than this, it is replaced wholesale by a single Fragment rather than
+/// spending quadratic time and memory looking for a minimal Delta.
+const MAX_DIFF_COST: usize = 1024;
+
+/// Compute a Delta which transforms `old` into `new`, such that `apply(old, diff(old, new))`
+/// yields `new`.
+pub fn diff(old: &[u8], new: &[u8]) -> Delta {
+    diff_with_min_match(old, new, DEFAULT_MIN_MATCH)
+}
+
+/// Compute a Delta which transforms `old` into `new`, discarding common runs of bytes shorter than
+/// `min_match`. Larger values of `min_match` produce fewer Fragments at the cost of larger
+/// Fragment content.
+pub fn diff_with_min_match(old: &[u8], new: &[u8], min_match: usize) -> Delta {
+    // Strip the common prefix and suffix first; they are always preserved and it keeps the
+    // (expensive) search confined to the region that actually changed.
+    let prefix = old.iter()
+        .zip(new.iter())
+        .take_while(|&(a, b)| a == b)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|&(a, b)| a == b)
+        .count();
+
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut frags = Vec::new();
+    let mut old_off = 0;
+    let mut new_off = 0;
+
+    let matches = myers_matches(old_mid, new_mid)
+        .into_iter()
+        .filter(|&(_, _, len)| len >= min_match)
+        .chain(Some((old_mid.len(), new_mid.len(), 0)));
+
+    for (old_start, new_start, len) in matches {
+        // Everything between the end of the previous match and the start of this one is a
+        // change. Emitting one Fragment per gap coalesces adjacent changes.
+        if old_off < old_start || new_off < new_start {
+            frags.push(Fragment {
+                start: prefix + old_off,
+                end: prefix + old_start,
+                content: Bytes::from(&new_mid[new_off..new_start]),
+            });
+        }
+        old_off = old_start + len;
+        new_off = new_start + len;
+    }
+
+    Delta { frags: frags }
+}
+
+/// Find the common runs of bytes between `a` and `b` using Myers' O(ND) algorithm. Returns a
+/// sorted list of `(a_offset, b_offset, length)` triples. If the edit distance exceeds
+/// `MAX_DIFF_COST`, no matches are returned.
+fn myers_matches(a: &[u8], b: &[u8]) -> Vec<(usize, usize, usize)> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max = cmp::min((n + m) as usize, MAX_DIFF_COST) as isize;
+
+    // `v[k + max]` is the furthest x reached on diagonal k (where k = x - y). `trace[d]` holds
+    // the values of `v` for diagonals -d..d after d edits, which is used for the backtrack.
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let idx = |k: isize| (k + max + 1) as usize;
+
+    let mut found = None;
+    let mut d = 0;
+    while d <= max {
+        let mut k = -d;
+        while k <= d {
+            let mut x = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+                v[idx(k + 1)]
+            } else {
+                v[idx(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx(k)] = x;
+            if x >= n && y >= m {
+                found = Some(d);
+                break;
+            }
+            k += 2;
+        }
+        trace.push(v[idx(-d)..idx(d) + 1].to_vec());
+        if found.is_some() {
+            break;
+        }
+        d += 1;
+    }
+
+    let d = match found {
+        Some(d) => d,
+        None => return Vec::new(),
+    };
+
+    // Walk back through the trace from the end of both texts, collecting the diagonal runs
+    // ("snakes") which correspond to matching bytes.
+    let mut matches = Vec::new();
+    let mut x = n;
+    let mut y = m;
+    for d in (1..d + 1).rev() {
+        let prev = &trace[(d - 1) as usize];
+        let prev_v = |k: isize| prev[(k + d - 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && prev_v(k - 1) < prev_v(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = prev_v(prev_k);
+        let mid_x = if prev_k == k + 1 { prev_x } else { prev_x + 1 };
+        if mid_x < x {
+            let len = x - mid_x;
+            matches.push((mid_x as usize, (mid_x - k) as usize, len as usize));
+        }
+        x = prev_x;
+        y = prev_x - prev_k;
+    }
+    if x > 0 {
+        matches.push((0, 0, x as usize));
+    }
+
+    matches.reverse();
+    matches
+}
+
+/// The changes on the two sides of a `merge3` which couldn't be merged.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Conflict {
+    /// The `(start, end)` ranges of the base text changed differently by both sides, in order.
+    /// Overlapping changes are coalesced into a single range.
+    pub ranges: Vec<(usize, usize)>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "conflicting changes at")?;
+        for &(start, end) in &self.ranges {
+            write!(fmt, " {}..{}", start, end)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for Conflict {
+    fn description(&self) -> &str {
+        "conflicting changes"
+    }
+}
+
+/// Three-way merge: apply both the changes from `base` to `ours` and those from `base` to
+/// `theirs` to `base`. If both sides change the same part of `base` (other than in exactly the
+/// same way), return the ranges that conflict instead.
+///
+/// The changes are found with `diff`, which folds short unchanged runs into the surrounding
+/// changes, so edits a few bytes apart on the two sides will conflict.
+pub fn merge3(base: &[u8], ours: &[u8], theirs: &[u8]) -> ::std::result::Result<Vec<u8>, Conflict> {
+    let ours = diff(base, ours);
+    let theirs = diff(base, theirs);
+
+    // A change made on both sides only needs to be applied once.
+    let theirs = Delta {
+        frags: theirs
+            .frags
+            .into_iter()
+            .filter(|frag| ours.frags.binary_search(frag).is_err())
+            .collect(),
+    };
+
+    if ours.overlaps(&theirs) {
+        return Err(Conflict {
+            ranges: conflicting_ranges(&ours, &theirs),
+        });
+    }
+
+    let mut frags: Vec<_> = ours.frags.into_iter().chain(theirs.frags).collect();
+    frags.sort_by_key(|frag| (frag.start, frag.end));
+    Ok(apply(base, Delta { frags: frags }))
+}
+
+/// Return the union of each set of overlapping ranges touched by both Deltas, walking them in
+/// the same way as `Delta::overlaps`.
+fn conflicting_ranges(ours: &Delta, theirs: &Delta) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    let mut ours = ours.touched_ranges().peekable();
+    let mut theirs = theirs.touched_ranges().peekable();
+
+    loop {
+        let (a, b) = match (ours.peek(), theirs.peek()) {
+            (Some(&a), Some(&b)) => (a, b),
+            _ => return ranges,
+        };
+        if ranges_overlap(a, b) {
+            let (start, end) = (cmp::min(a.0, b.0), cmp::max(a.1, b.1));
+            let coalesce = ranges.last().map_or(false, |last| start <= last.1);
+            if coalesce {
+                let last = ranges.last_mut().expect("no previous range");
+                last.1 = cmp::max(last.1, end);
+            } else {
+                ranges.push((start, end));
+            }
+        }
+
+        if (a.1, a.0) < (b.1, b.0) {
+            ours.next();
+        } else {
+            theirs.next();
+        }
+    }
 }
 
 /// Apply a chain of Deltas to an input text, returning the result.
@@ -238,16 +1525,190 @@ pub fn apply_chain<I: IntoIterator<Item = Delta>>(text: &[u8], deltas: I) -> Vec
     apply(text, combined)
 }
 
+/// Minimum number of Deltas per segment for `apply_chain_parallel`. Below this, the cost of
+/// spawning a thread outweighs the cost of combining the segment inline.
+const MIN_PARALLEL_SEGMENT: usize = 64;
+
+/// Apply a chain of Deltas to an input text like `apply_chain`, but split the chain into up to
+/// `threads` segments which are combined in parallel before the results are folded together.
+/// This is valid because `combine` is associative.
+///
+/// Whether this beats `apply_chain` depends on the length of the chain and the size of its
+/// Deltas, so measure with `benches/apply_chain.rs` before switching. Segments are never made
+/// shorter than `MIN_PARALLEL_SEGMENT` Deltas, so a short chain is combined in one segment and
+/// just pays for the extra thread. Long chains can gain even without spare cores, since folding
+/// a few combined segments together does less work than folding every Delta into one ever
+/// larger result.
+pub fn apply_chain_parallel<I>(text: &[u8], deltas: I, threads: usize) -> Vec<u8>
+where
+    I: IntoIterator<Item = Delta>,
+{
+    let mut deltas: Vec<Delta> = deltas.into_iter().collect();
+    let segment_len = cmp::max(
+        (deltas.len() + threads.saturating_sub(1)) / cmp::max(threads, 1),
+        MIN_PARALLEL_SEGMENT,
+    );
+    if deltas.len() <= segment_len {
+        return apply_chain(text, deltas);
+    }
+
+    let mut segments = Vec::new();
+    while deltas.len() > segment_len {
+        let rest = deltas.split_off(segment_len);
+        segments.push(mem::replace(&mut deltas, rest));
+    }
+    segments.push(deltas);
+
+    let handles: Vec<_> = segments
+        .into_iter()
+        .map(|segment| thread::spawn(move || combine_chain(segment)))
+        .collect();
+    let combined = handles
+        .into_iter()
+        .map(|handle| handle.join().expect("delta combine thread panicked"));
+
+    apply_chain(text, combined)
+}
+
 /// Combine a chain of Deltas into an equivalent single Delta.
 pub fn combine_chain<I: IntoIterator<Item = Delta>>(deltas: I) -> Delta {
     deltas.into_iter().fold(Delta::default(), combine)
 }
 
+/// Combine a chain of Deltas like `combine_chain`, but fail rather than produce a corrupt
+/// result if any of them are malformed. See `try_combine`.
+pub fn try_combine_chain<I: IntoIterator<Item = Delta>>(deltas: I) -> Result<Delta> {
+    let mut combined = Delta::default();
+    for delta in deltas {
+        combined = try_combine(combined, delta)?;
+    }
+    Ok(combined)
+}
+
+/// Concatenate Deltas whose Fragments are all expressed against the same original text into
+/// a single Delta. The Fragments are simply chained in order, so the Deltas must be given in
+/// order of position and must not overlap; otherwise the result is
+/// `ErrorKind::InvalidFragmentList`.
+///
+/// This is not the same as `combine_chain`, where each Delta applies to the result of the one
+/// before it. `concat` is for Deltas built independently against one text, such as separately
+/// computed hunks, and is much cheaper since there are no offsets to adjust.
+pub fn concat(deltas: Vec<Delta>) -> Result<Delta> {
+    let frags = deltas
+        .into_iter()
+        .flat_map(|delta| delta.frags)
+        .collect();
+    Delta::new(frags)
+}
+
 /// Destructively combine two Deltas into a new Delta that is equivalent to
 /// applying the original two Deltas in sequence.
+///
+/// The Fragments of the inputs are moved into the result, so this is the cheapest way to
+/// combine Deltas which aren't needed afterwards. Use `combine_ref` to keep the inputs.
 pub fn combine(first: Delta, second: Delta) -> Delta {
+    if first.is_empty() {
+        return second;
+    }
+    if second.is_empty() {
+        return first;
+    }
+
+    combine_frags(first.frags.into_iter(), second.frags.into_iter(), unchecked_adjust, None)
+        .expect("unchecked combine failed")
+}
+
+/// Where a Fragment of the result of `combine_traced` came from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FragmentOrigin {
+    /// A whole Fragment of the first Delta.
+    First,
+    /// A whole Fragment of the second Delta.
+    Second,
+    /// Part of a Fragment of the first Delta, the rest of which was split off because the
+    /// second Delta changes it.
+    SplitFirst,
+    /// A Fragment of the second Delta which changes part of a Fragment of the first, so that
+    /// one was split around it.
+    SplitSecond,
+}
+
+/// Combine two Deltas exactly as `combine` does, but also return the origin of each Fragment
+/// of the result, in order, for debugging.
+pub fn combine_traced(first: Delta, second: Delta) -> (Delta, Vec<FragmentOrigin>) {
+    if first.is_empty() || second.is_empty() {
+        let (delta, origin) = if first.is_empty() {
+            (second, FragmentOrigin::Second)
+        } else {
+            (first, FragmentOrigin::First)
+        };
+        let origins = vec![origin; delta.len()];
+        return (delta, origins);
+    }
+
+    let mut origins = Vec::with_capacity(first.len() + second.len());
+    let combined = combine_frags(
+        first.frags.into_iter(),
+        second.frags.into_iter(),
+        unchecked_adjust,
+        Some(&mut origins),
+    ).expect("unchecked combine failed");
+    (combined, origins)
+}
+
+/// Combine two Deltas like `combine`, but with every offset adjustment checked.
+///
+/// `combine` trusts its inputs, so a malformed Delta (for example, a Fragment whose start is
+/// past its end) can make it compute an out-of-range offset, which silently wraps in release
+/// builds. This returns `ErrorKind::InvalidFragmentList` instead, so use it for Deltas that
+/// come from untrusted input.
+pub fn try_combine(first: Delta, second: Delta) -> Result<Delta> {
+    if first.is_empty() {
+        return Ok(second);
+    }
+    if second.is_empty() {
+        return Ok(first);
+    }
+
+    combine_frags(first.frags.into_iter(), second.frags.into_iter(), checked_adjust, None)
+}
+
+/// Combine two Deltas like `combine`, but without consuming them.
+///
+/// Each Fragment is cloned as the algorithm reaches it. That doesn't copy any content, since
+/// it's shared `Bytes`, but it does cost a reference count update per Fragment as well as the
+/// result's allocation, so `combine` is preferable when the inputs can be given up.
+pub fn combine_ref(first: &Delta, second: &Delta) -> Delta {
+    if first.is_empty() {
+        return second.clone();
+    }
+    if second.is_empty() {
+        return first.clone();
+    }
+
+    combine_frags(
+        first.frags.iter().cloned(),
+        second.frags.iter().cloned(),
+        unchecked_adjust,
+        None,
+    ).expect("unchecked combine failed")
+}
+
+/// Combine the Fragments of two Deltas. If `origins` is given, the origin of each Fragment of
+/// the result is pushed to it as well; see `combine_traced`.
+fn combine_frags<I, J, A>(
+    first: I,
+    second: J,
+    adjust: A,
+    mut origins: Option<&mut Vec<FragmentOrigin>>,
+) -> Result<Delta>
+where
+    I: Iterator<Item = Fragment>,
+    J: Iterator<Item = Fragment>,
+    A: Fn(usize, isize) -> Result<usize> + Copy,
+{
     let mut combined = Vec::new();
-    let mut first_frags = itertools::put_back(first.frags.into_iter());
+    let mut first_frags = itertools::put_back(first);
 
     // Cumulative change in length caused by the fragments in `first` that have been
     // processed so far. We need to keep track of this because the offsets in `second`
@@ -255,70 +1716,138 @@ pub fn combine(first: Delta, second: Delta) -> Delta {
     // all of the offsets in `second` to compensate for this.
     let mut cum_len_change = 0;
 
-    for mut frag in second.frags {
+    // Whether the next frag in `first` is what's left of one that was split.
+    let mut split_pending = false;
+
+    for mut frag in second {
         // Take frags in `first` that occur before the current frag.
-        let before = take_frags(
+        let (before, split_start) = take_frags(
             Some(&mut combined),
+            origins.as_mut().map(|v| &mut **v),
             &mut first_frags,
+            &mut split_pending,
             frag.start,
             cum_len_change,
-        );
+            adjust,
+        )?;
 
         // Skip frags in `first` that overlap the current frag.
-        let after = take_frags(None, &mut first_frags, frag.end, before);
+        let (after, split_end) = take_frags(
+            None,
+            None,
+            &mut first_frags,
+            &mut split_pending,
+            frag.end,
+            before,
+            adjust,
+        )?;
 
         // Adjust offsets in the new fragment to compensate for length changes caused by
         // the taken and skipped fragments respectively.
-        frag.start = adjust(frag.start, before);
-        frag.end = adjust(frag.end, after);
+        frag.start = adjust(frag.start, before)?;
+        frag.end = adjust(frag.end, after)?;
 
         combined.push(frag);
+        if let Some(ref mut origins) = origins {
+            origins.push(if split_start || split_end {
+                FragmentOrigin::SplitSecond
+            } else {
+                FragmentOrigin::Second
+            });
+        }
         cum_len_change = after;
     }
 
     // Add any remaining fragments from `first`.
     combined.extend(first_frags);
+    if let Some(origins) = origins {
+        while origins.len() < combined.len() {
+            origins.push(if mem::replace(&mut split_pending, false) {
+                FragmentOrigin::SplitFirst
+            } else {
+                FragmentOrigin::First
+            });
+        }
+    }
 
-    Delta { frags: combined }
+    Ok(Delta { frags: combined })
 }
 
 /// Move Fragments from src to dst until the given cutoff is reached. If the last Fragment
 /// overlaps the cutoff, it will be split; the first half will be moved to dst while the
 /// remainder will be put back into src. If dst is None, then the taken Fragments are dropped.
-/// Returns the updated cumulative change of length that includes all of the taken fragments.
-fn take_frags<I>(
+/// The origin of each Fragment moved to dst is pushed to origins, if given, and split_pending
+/// tracks whether the Fragment at the front of src is the remainder of a split one.
+/// Returns the updated cumulative change of length that includes all of the taken fragments,
+/// and whether a Fragment was split at the cutoff.
+fn take_frags<I, A>(
     mut dst: Option<&mut Vec<Fragment>>,
+    mut origins: Option<&mut Vec<FragmentOrigin>>,
     src: &mut PutBack<I>,
+    split_pending: &mut bool,
     cutoff: usize,
     mut cum_len_change: isize,
-) -> isize
+    adjust: A,
+) -> Result<(isize, bool)>
 where
     I: Iterator<Item = Fragment>,
+    A: Fn(usize, isize) -> Result<usize>,
 {
+    let mut split = false;
+
     while let Some(mut frag) = src.next() {
         // Adjust cutoff offset to account for the cumulative length change so far.
-        let adjusted = adjust(cutoff, cum_len_change);
+        let adjusted = adjust(cutoff, cum_len_change)?;
+        let remainder = mem::replace(split_pending, false);
 
         // Does this fragment end after the cutoff?
-        if frag.post_end() > adjusted {
+        if post_end(&frag, &adjust)? > adjusted {
             // Split the fragment if it starts before the cutoff.
             if let Some(rest) = frag.split(adjusted) {
                 src.put_back(rest);
-                cum_len_change += frag.length_change();
+                *split_pending = true;
+                split = true;
+                cum_len_change += length_change(&frag, &adjust)?;
                 dst.as_mut().map(|v| v.push(frag));
+                origins.as_mut().map(|v| v.push(FragmentOrigin::SplitFirst));
             } else {
                 // Fragment started after the cutoff, so put it back.
                 src.put_back(frag);
+                *split_pending = remainder;
             }
             break;
         }
 
         // Push the fragment to the output and update the cumulative length change accordingly.
-        cum_len_change += frag.length_change();
+        cum_len_change += length_change(&frag, &adjust)?;
         dst.as_mut().map(|v| v.push(frag));
+        origins.as_mut().map(|v| {
+            v.push(if remainder {
+                FragmentOrigin::SplitFirst
+            } else {
+                FragmentOrigin::First
+            })
+        });
     }
 
-    cum_len_change
+    Ok((cum_len_change, split))
+}
+
+/// `Fragment::post_end`, computed with `adjust` so that `try_combine` can check it.
+fn post_end<A>(frag: &Fragment, adjust: A) -> Result<usize>
+where
+    A: Fn(usize, isize) -> Result<usize>,
+{
+    adjust(frag.start, -(frag.content.len() as isize))
+}
+
+/// `Fragment::length_change`, computed with `adjust` so that `try_combine` can check it.
+fn length_change<A>(frag: &Fragment, adjust: A) -> Result<isize>
+where
+    A: Fn(usize, isize) -> Result<usize>,
+{
+    let replaced = adjust(frag.end, frag.start as isize)?;
+    Ok(frag.content.len() as isize - replaced as isize)
 }
 
 /// Subtract the second (signed) value from the first (unsigned) value.
@@ -337,6 +1866,29 @@ fn adjust(offset: usize, adjustment: isize) -> usize {
     }
 }
 
+/// `adjust` in the form `combine_frags` expects, for trusted inputs.
+fn unchecked_adjust(offset: usize, adjustment: isize) -> Result<usize> {
+    Ok(adjust(offset, adjustment))
+}
+
+/// Like `adjust`, but return an error instead of wrapping if the result is out of range.
+fn checked_adjust(offset: usize, adjustment: isize) -> Result<usize> {
+    let adjusted = if adjustment < 0 {
+        offset.checked_add(adjustment.wrapping_neg() as usize)
+    } else {
+        offset.checked_sub(adjustment as usize)
+    };
+    match adjusted {
+        Some(adjusted) => Ok(adjusted),
+        None if adjustment < 0 => Err(
+            ErrorKind::InvalidFragmentList("offset overflow while combining".into()).into(),
+        ),
+        None => Err(
+            ErrorKind::InvalidFragmentList("offset underflow while combining".into()).into(),
+        ),
+    }
+}
+
 /// XXX: Comatibility functions for the old bdiff module for testing purposes. The delta
 /// module will replace that one once all instances of Vec<bdiff::Delta> are replaced
 /// with delta::Delta, and this compatibility module will be removed at that time.
@@ -349,46 +1901,94 @@ pub mod compat {
         T: IntoIterator<Item = bdiff::Delta>,
     {
         Delta {
-            frags: deltas
-                .into_iter()
-                .map(|delta| {
-                    Fragment {
-                        start: delta.start,
-                        end: delta.end,
-                        content: delta.content.clone(),
-                    }
-                })
-                .collect(),
+            frags: frags(deltas),
         }
     }
 
+    /// Like `convert`, but check the Fragments as `Delta::new` does, for deltas read from
+    /// untrusted input such as a revlog.
+    pub fn try_convert<T>(deltas: T) -> Result<Delta>
+    where
+        T: IntoIterator<Item = bdiff::Delta>,
+    {
+        Delta::new(frags(deltas))
+    }
+
+    fn frags<T>(deltas: T) -> Vec<Fragment>
+    where
+        T: IntoIterator<Item = bdiff::Delta>,
+    {
+        deltas
+            .into_iter()
+            .map(|delta| {
+                Fragment {
+                    start: delta.start,
+                    end: delta.end,
+                    content: Bytes::from(delta.content),
+                }
+            })
+            .collect()
+    }
+
+    /// The inverse of `convert`, for code which still consumes `bdiff::Delta`s.
+    pub fn to_bdiff(delta: &Delta) -> Vec<bdiff::Delta> {
+        delta
+            .frags
+            .iter()
+            .map(|frag| {
+                bdiff::Delta {
+                    start: frag.start,
+                    end: frag.end,
+                    content: frag.content.to_vec(),
+                }
+            })
+            .collect()
+    }
+
     pub fn apply_deltas<T>(text: &[u8], deltas: T) -> Vec<u8>
     where
         T: IntoIterator<Item = Vec<bdiff::Delta>>,
     {
         apply_chain(text, deltas.into_iter().map(convert))
     }
+
+    /// Like `apply_deltas`, but fail rather than panic or produce a corrupt result if any of
+    /// the deltas are malformed or don't fit the text they apply to.
+    pub fn try_apply_deltas<T>(text: &[u8], deltas: T) -> Result<Vec<u8>>
+    where
+        T: IntoIterator<Item = Vec<bdiff::Delta>>,
+    {
+        let deltas = deltas
+            .into_iter()
+            .map(try_convert)
+            .collect::<Result<Vec<_>>>()?;
+        try_apply(text, &try_combine_chain(deltas)?)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use bincode;
+    use quickcheck::StdGen;
+    use rand;
+
     /// Test that fragments are verified properly.
     #[test]
     #[cfg_attr(rustfmt, rustfmt_skip)]
     fn test_delta_new() {
         let test_cases = vec![
-            (vec![Fragment { start: 0, end: 0, content: vec![] }], true),
-            (vec![Fragment { start: 0, end: 5, content: vec![] }], true),
-            (vec![Fragment { start: 0, end: 5, content: vec![] },
-                  Fragment { start: 5, end: 8, content: vec![] }], true),
-            (vec![Fragment { start: 0, end: 5, content: vec![] },
-                  Fragment { start: 6, end: 9, content: vec![] }], true),
-            (vec![Fragment { start: 0, end: 5, content: vec![] },
-                  Fragment { start: 6, end: 5, content: vec![] }], false),
-            (vec![Fragment { start: 0, end: 5, content: vec![] },
-                  Fragment { start: 4, end: 8, content: vec![] }], false),
+            (vec![Fragment { start: 0, end: 0, content: Bytes::new() }], true),
+            (vec![Fragment { start: 0, end: 5, content: Bytes::new() }], true),
+            (vec![Fragment { start: 0, end: 5, content: Bytes::new() },
+                  Fragment { start: 5, end: 8, content: Bytes::new() }], true),
+            (vec![Fragment { start: 0, end: 5, content: Bytes::new() },
+                  Fragment { start: 6, end: 9, content: Bytes::new() }], true),
+            (vec![Fragment { start: 0, end: 5, content: Bytes::new() },
+                  Fragment { start: 6, end: 5, content: Bytes::new() }], false),
+            (vec![Fragment { start: 0, end: 5, content: Bytes::new() },
+                  Fragment { start: 4, end: 8, content: Bytes::new() }], false),
         ];
 
         for (frags, success) in test_cases.into_iter() {
@@ -401,35 +2001,271 @@ mod tests {
         }
     }
 
-    quickcheck! {
-        fn delta_gen(delta: Delta) -> bool {
-            Delta::verify(&delta.frags).is_ok()
-        }
+    #[test]
+    fn test_from_unsorted() {
+        let frags = vec![
+            Fragment::new(6, 9, &b"xyz"[..]).unwrap(),
+            Fragment::new(0, 2, Bytes::new()).unwrap(),
+            Fragment::new(3, 3, &b"a"[..]).unwrap(),
+            Fragment::new(3, 5, &b"b"[..]).unwrap(),
+        ];
+        assert!(Delta::new(frags.clone()).is_err());
+        let delta = Delta::from_unsorted(frags).unwrap();
+        // The insertion and replacement at 3 keep their relative order.
+        let expected = Delta::new(vec![
+            Fragment::new(0, 2, Bytes::new()).unwrap(),
+            Fragment::new(3, 3, &b"a"[..]).unwrap(),
+            Fragment::new(3, 5, &b"b"[..]).unwrap(),
+            Fragment::new(6, 9, &b"xyz"[..]).unwrap(),
+        ]).unwrap();
+        assert_eq!(delta, expected);
+
+        let overlapping = vec![
+            Fragment::new(6, 9, Bytes::new()).unwrap(),
+            Fragment::new(0, 7, Bytes::new()).unwrap(),
+        ];
+        assert!(Delta::from_unsorted(overlapping).is_err());
 
-        fn delta_shrink(delta: Delta) -> bool {
-            // This test is a bit redundant, but let's just verify.
-            delta.shrink().take(100).all(|d| {
-                Delta::verify(&d.frags).is_ok()
+        let same_start = vec![
+            Fragment::new(4, 8, Bytes::new()).unwrap(),
+            Fragment::new(0, 1, Bytes::new()).unwrap(),
+            Fragment::new(4, 6, Bytes::new()).unwrap(),
+        ];
+        assert!(Delta::from_unsorted(same_start).is_err());
+    }
+
+    /// Construct a text that is long enough for `delta` to be applied to, followed by `tail`.
+    fn base_text(delta: &Delta, tail: &[u8]) -> Vec<u8> {
+        let len = delta.frags.last().map_or(0, |frag| frag.end);
+        let mut text: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        text.extend_from_slice(tail);
+        text
+    }
+
+    /// Shift the offsets of `delta` to apply after `prev`, where both are against the same text
+    /// and `prev` only changes text before `delta` does.
+    fn shift(delta: Delta, prev: &Delta) -> Delta {
+        let change = prev.frags.iter().map(|frag| frag.length_change()).sum::<isize>();
+        let frags = delta
+            .frags
+            .into_iter()
+            .map(|frag| Fragment {
+                start: adjust(frag.start, -change),
+                end: adjust(frag.end, -change),
+                content: frag.content,
             })
+            .collect();
+        Delta { frags: frags }
+    }
+
+    /// Split `text` into chunks of the given sizes (modulo a small bound, so that chunks are
+    /// short enough for Fragments to straddle them), with whatever is left as the last chunk.
+    fn split_chunks(text: &[u8], sizes: &[usize]) -> ::std::vec::IntoIter<Bytes> {
+        let mut chunks = Vec::new();
+        let mut off = 0;
+        for size in sizes {
+            let end = cmp::min(off + size % 8, text.len());
+            chunks.push(Bytes::from(&text[off..end]));
+            off = end;
         }
+        chunks.push(Bytes::from(&text[off..]));
+        chunks.into_iter()
+    }
 
-        fn fragment_gen(fragment: Fragment) -> bool {
-            fragment.verify().is_ok()
+    quickcheck! {
+        fn compat_to_bdiff_roundtrip(delta: Delta) -> bool {
+            compat::convert(compat::to_bdiff(&delta)) == delta
         }
 
-        fn fragment_shrink(fragment: Fragment) -> bool {
-            fragment.shrink().take(100).all(|f| f.verify().is_ok())
+        fn diff_roundtrip(old: Vec<u8>, new: Vec<u8>) -> bool {
+            let delta = diff(&old, &new);
+            Delta::verify(&delta.frags).is_ok() && apply(&old, delta) == new
         }
-    }
 
-    /// Test a fragment that decreases the size of the content.
-    #[test]
-    fn test_fragment_shrink() {
-        let mut frag = Fragment {
-            start: 10,
-            end: 20,
-            content: vec![1, 2, 3, 4, 5],
-        };
+        fn diff_min_match_roundtrip(old: Vec<u8>, new: Vec<u8>, min_match: usize) -> bool {
+            let delta = diff_with_min_match(&old, &new, min_match % 16);
+            Delta::verify(&delta.frags).is_ok() && apply(&old, delta) == new
+        }
+
+        fn diff_similar_roundtrip(delta: Delta, tail: Vec<u8>) -> bool {
+            let old = base_text(&delta, &tail);
+            let new = apply(&old, delta);
+            apply(&old, diff(&old, &new)) == new
+        }
+
+        fn apply_one_matches_apply_chunks(frag: Fragment, tail: Vec<u8>) -> bool {
+            let delta = Delta { frags: vec![frag] };
+            let text = base_text(&delta, &tail);
+            let fast = try_apply(&text, &delta).ok();
+            fast.is_some() && fast == apply_chunks(&text, &delta).ok()
+        }
+
+        fn apply_to_writer_matches_apply(delta: Delta, tail: Vec<u8>) -> bool {
+            let text = base_text(&delta, &tail);
+            let mut out = Vec::new();
+            apply_to_writer(&text, &delta, &mut out).expect("write to Vec failed");
+            out == apply(&text, delta)
+        }
+
+        fn segments_match_apply(delta: Delta, tail: Vec<u8>) -> bool {
+            let text = base_text(&delta, &tail);
+            let mut out = Vec::new();
+            for segment in delta.segments(text.len()) {
+                match segment {
+                    Segment::Copy { from, len } => out.extend_from_slice(&text[from..from + len]),
+                    Segment::Insert { bytes } => out.extend_from_slice(bytes),
+                }
+            }
+            out == apply(&text, delta)
+        }
+
+        fn line_align_matches_apply(delta: Delta, tail: Vec<u8>) -> bool {
+            let text = base_text(&delta, &tail);
+            let aligned = delta.line_align(&text);
+            let at_boundary = |off: usize| off == 0 || off == text.len() || text[off - 1] == b'\n';
+            Delta::verify(&aligned.frags).is_ok()
+                && aligned.frags.iter().all(|frag| at_boundary(frag.start) && at_boundary(frag.end))
+                && apply(&text, aligned) == apply(&text, delta)
+        }
+
+        fn apply_into_matches_apply(delta: Delta, tail: Vec<u8>, stale: Vec<u8>) -> bool {
+            let text = base_text(&delta, &tail);
+            let mut out = stale;
+            apply_into(&text, &delta, &mut out).is_ok() && out == apply(&text, delta)
+        }
+
+        fn apply_chunked_matches_apply(delta: Delta, tail: Vec<u8>, sizes: Vec<usize>) -> bool {
+            let text = base_text(&delta, &tail);
+            let mut out = Vec::new();
+            apply_chunked(split_chunks(&text, &sizes), &delta, &mut out)
+                .expect("write to Vec failed");
+            out == apply(&text, delta)
+        }
+
+        fn apply_stats_matches_apply(delta: Delta, tail: Vec<u8>) -> bool {
+            let text = base_text(&delta, &tail);
+            let stats = apply_stats(text.len(), &delta);
+            stats.output_len == apply(&text, delta.clone()).len()
+                && stats.output_len == delta.output_size(text.len())
+                && stats.bytes_copied + stats.bytes_deleted == text.len()
+        }
+
+        fn truncate_output_is_prefix(delta: Delta, tail: Vec<u8>, max_output: usize) -> bool {
+            let text = base_text(&delta, &tail);
+            let full = apply(&text, delta.clone());
+            let max_output = max_output % (full.len() + 2);
+            let truncated = delta.truncate_output(text.len(), max_output);
+
+            Delta::new(truncated.frags.clone()).is_ok()
+                && apply(&text, truncated)[..] == full[..cmp::min(max_output, full.len())]
+        }
+
+        fn split_at_preserves_apply(delta: Delta, tail: Vec<u8>, offset: usize) -> bool {
+            let text = base_text(&delta, &tail);
+            let offset = offset % (text.len() + 1);
+            let (before, after) = delta.clone().split_at(offset);
+
+            let valid = before.frags.iter().all(|frag| frag.end <= offset)
+                && after.frags.iter().all(|frag| frag.start >= offset)
+                && Delta::new(before.frags.clone()).is_ok()
+                && Delta::new(after.frags.clone()).is_ok();
+            let expected = apply(&text, delta);
+            let concatenated = concat(vec![before.clone(), after.clone()]).unwrap();
+            let in_turn = apply(&apply(&text, before.clone()), shift(after, &before));
+
+            valid && apply(&text, concatenated) == expected && in_turn == expected
+        }
+
+        fn normalize_preserves_apply(delta: Delta, tail: Vec<u8>) -> bool {
+            let text = base_text(&delta, &tail);
+            let normalized = delta.clone().normalized();
+            Delta::verify(&normalized.frags).is_ok()
+                && apply(&text, delta) == apply(&text, normalized)
+        }
+
+        fn mercurial_bytes_roundtrip(delta: Delta) -> bool {
+            let bytes = delta.to_mercurial_bytes().expect("arbitrary delta is encodable");
+            Delta::from_mercurial_bytes(&bytes).ok() == Some(delta)
+        }
+
+        fn apply_with_inverse_matches(delta: Delta, tail: Vec<u8>) -> bool {
+            let text = base_text(&delta, &tail);
+            let (out, inverse) = apply_with_inverse(&text, &delta);
+            inverse == invert(&text, &delta)
+                && out == apply(&text, delta)
+                && apply(&out, inverse) == text
+        }
+
+        fn serialized_size_matches(delta: Delta) -> bool {
+            let bytes = delta.to_mercurial_bytes().expect("arbitrary delta is encodable");
+            delta.serialized_size() == bytes.len()
+        }
+
+        fn apply_chain_parallel_matches(texts: Vec<Vec<u8>>) -> bool {
+            if texts.is_empty() {
+                return true;
+            }
+            // Walk through the texts and back again, and repeat that cycle so that the chain is
+            // long enough to actually be split into segments.
+            let texts = &texts[..cmp::min(texts.len(), 8)];
+            let deltas: Vec<Delta> = texts
+                .iter()
+                .chain(texts.iter().rev())
+                .collect::<Vec<_>>()
+                .windows(2)
+                .map(|pair| diff(pair[0], pair[1]))
+                .cycle()
+                .take(4 * MIN_PARALLEL_SEGMENT)
+                .collect();
+            let expected = apply_chain(&texts[0], deltas.clone());
+            (1..9).all(|threads| {
+                apply_chain_parallel(&texts[0], deltas.clone(), threads) == expected
+            })
+        }
+
+        fn combine_ref_matches_combine(first: Delta, tail: Vec<u8>, new: Vec<u8>) -> bool {
+            let text = base_text(&first, &tail);
+            let second = diff(&apply(&text, first.clone()), &new);
+
+            let combined = combine_ref(&first, &second);
+            combined == combine(first.clone(), second.clone()) && apply(&text, combined) == new
+        }
+
+        fn invert_roundtrip(delta: Delta, tail: Vec<u8>) -> bool {
+            let text = base_text(&delta, &tail);
+            let inverse = invert(&text, &delta);
+            Delta::verify(&inverse.frags).is_ok()
+                && apply(&apply(&text, delta), inverse) == text
+        }
+
+        fn delta_gen(delta: Delta) -> bool {
+            Delta::verify(&delta.frags).is_ok()
+        }
+
+        fn delta_shrink(delta: Delta) -> bool {
+            // This test is a bit redundant, but let's just verify.
+            delta.shrink().take(100).all(|d| {
+                Delta::verify(&d.frags).is_ok()
+            })
+        }
+
+        fn fragment_gen(fragment: Fragment) -> bool {
+            fragment.verify().is_ok()
+        }
+
+        fn fragment_shrink(fragment: Fragment) -> bool {
+            fragment.shrink().take(100).all(|f| f.verify().is_ok())
+        }
+    }
+
+    /// Test a fragment that decreases the size of the content.
+    #[test]
+    fn test_fragment_shrink() {
+        let mut frag = Fragment {
+            start: 10,
+            end: 20,
+            content: vec![1, 2, 3, 4, 5].into(),
+        };
 
         assert_eq!(frag.post_end(), 15);
         assert_eq!(frag.length_change(), -5);
@@ -445,7 +2281,7 @@ mod tests {
             Fragment {
                 start: 10,
                 end: 12,
-                content: vec![1, 2],
+                content: vec![1, 2].into(),
             }
         );
         assert_eq!(
@@ -453,7 +2289,7 @@ mod tests {
             Fragment {
                 start: 12,
                 end: 20,
-                content: vec![3, 4, 5],
+                content: vec![3, 4, 5].into(),
             }
         );
     }
@@ -464,7 +2300,7 @@ mod tests {
         let mut frag = Fragment {
             start: 10,
             end: 15,
-            content: vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
+            content: vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10].into(),
         };
 
         assert_eq!(frag.post_end(), 20);
@@ -482,7 +2318,7 @@ mod tests {
             Fragment {
                 start: 10,
                 end: 15,
-                content: vec![1, 2, 3, 4, 5, 6, 7],
+                content: vec![1, 2, 3, 4, 5, 6, 7].into(),
             }
         );
         assert_eq!(
@@ -490,61 +2326,1475 @@ mod tests {
             Fragment {
                 start: 15,
                 end: 15,
-                content: vec![8, 9, 10],
+                content: vec![8, 9, 10].into(),
             }
         );
     }
 
-    /// Test combining two Deltas with overlapping fragments.
+    /// A delta produced by Mercurial's bdiff, taken from the manifest revision aa93dc3435cb in
+    /// mercurial-bundles' `fixtures/uncompressed.bin`. It replaces the single entry of its base,
+    /// `MERCURIAL_DELTA_BASE`, with a new one.
+    #[cfg_attr(rustfmt, rustfmt_skip)]
+    const MERCURIAL_DELTA: &[u8] = b"\
+        \x00\x00\x00\x00\x00\x00\x00\x2d\x00\x00\x00\x2d\
+        def\x00bb969a19e8853962b4347bea4c24796324f10d8b\n";
+
+    const MERCURIAL_DELTA_BASE: &[u8] = b"abc\x00b80de5d138758541c5f05265ad144ab9fa86d1db\n";
+
     #[test]
-    fn test_combine() {
-        let delta1 = Delta {
+    fn test_from_mercurial_bytes() {
+        let expected = Delta {
             frags: vec![
                 Fragment {
-                    start: 3,
-                    end: 6,
-                    content: vec![1, 2, 3, 4, 5],
+                    start: 0,
+                    end: 45,
+                    content: (&b"def\x00bb969a19e8853962b4347bea4c24796324f10d8b\n"[..]).into(),
                 },
+            ],
+        };
+
+        let delta = Delta::from_mercurial_bytes(MERCURIAL_DELTA).expect("valid delta");
+        assert_eq!(delta, expected);
+        assert_eq!(
+            &delta.to_mercurial_bytes().expect("encodable delta")[..],
+            MERCURIAL_DELTA
+        );
+        assert_eq!(delta.serialized_size(), MERCURIAL_DELTA.len());
+        assert_eq!(Delta::default().serialized_size(), 0);
+
+        let res = apply(MERCURIAL_DELTA_BASE, delta);
+        assert_eq!(
+            &res[..],
+            &b"def\x00bb969a19e8853962b4347bea4c24796324f10d8b\n"[..]
+        );
+    }
+
+    #[test]
+    fn test_from_mercurial_bytes_truncated() {
+        // Truncated in the middle of the header.
+        assert!(Delta::from_mercurial_bytes(&MERCURIAL_DELTA[..8]).is_err());
+        // Truncated in the middle of the content.
+        let len = MERCURIAL_DELTA.len();
+        assert!(Delta::from_mercurial_bytes(&MERCURIAL_DELTA[..len - 1]).is_err());
+    }
+
+    #[test]
+    fn test_from_mercurial_bytes_overlapping() {
+        // The same fragment twice overlaps itself.
+        let mut data = Vec::new();
+        data.extend_from_slice(MERCURIAL_DELTA);
+        data.extend_from_slice(MERCURIAL_DELTA);
+        assert!(Delta::from_mercurial_bytes(&data).is_err());
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_to_mercurial_bytes_too_large() {
+        let delta = Delta {
+            frags: vec![
                 Fragment {
-                    start: 8,
-                    end: 16,
-                    content: vec![6, 7, 8, 9],
+                    start: 0,
+                    end: u32::MAX as usize + 1,
+                    content: Bytes::new(),
                 },
             ],
         };
+        assert!(delta.to_mercurial_bytes().is_err());
+    }
 
-        let delta2 = Delta {
+    #[test]
+    fn test_visit_fold() {
+        let delta = Delta {
             frags: vec![
                 Fragment {
-                    start: 7,
-                    end: 12,
-                    content: vec![10, 11, 12, 13],
+                    start: 0,
+                    end: 2,
+                    content: vec![1, 2].into(),
+                },
+                Fragment {
+                    start: 4,
+                    end: 9,
+                    content: vec![3, 4, 5, 6].into(),
+                },
+                Fragment {
+                    start: 9,
+                    end: 10,
+                    content: Bytes::new(),
                 },
             ],
         };
 
-        let expected = Delta {
+        let largest = delta.fold(0, |largest, frag| cmp::max(largest, frag.content.len()));
+        assert_eq!(largest, 4);
+        assert_eq!(Delta::default().fold(0, |n, _| n + 1), 0);
+
+        let mut starts = Vec::new();
+        delta.visit(|frag| starts.push(frag.start));
+        assert_eq!(starts, vec![0, 4, 9]);
+    }
+
+    #[test]
+    fn test_size_grow() {
+        let delta = Delta {
             frags: vec![
                 Fragment {
-                    start: 3,
+                    start: 0,
+                    end: 0,
+                    content: vec![1, 2, 3].into(),
+                },
+                Fragment {
+                    start: 5,
                     end: 6,
-                    content: vec![1, 2, 3, 4],
+                    content: vec![4, 5].into(),
+                },
+            ],
+        };
+
+        assert_eq!(delta.content_size(), 5);
+        assert_eq!(delta.output_size(10), 14);
+    }
+
+    #[test]
+    fn test_size_shrink() {
+        let delta = Delta {
+            frags: vec![
+                Fragment {
+                    start: 0,
+                    end: 3,
+                    content: Bytes::new(),
+                },
+                Fragment {
+                    start: 5,
+                    end: 8,
+                    content: vec![1].into(),
+                },
+            ],
+        };
+
+        assert_eq!(delta.content_size(), 1);
+        assert_eq!(delta.output_size(10), 5);
+        // A base that is too short for the delta saturates rather than underflowing.
+        assert_eq!(delta.output_size(2), 0);
+    }
+
+    #[test]
+    fn test_size_mixed() {
+        let delta = Delta {
+            frags: vec![
+                Fragment {
+                    start: 0,
+                    end: 4,
+                    content: vec![1].into(),
                 },
                 Fragment {
                     start: 6,
+                    end: 6,
+                    content: vec![2, 3, 4, 5, 6].into(),
+                },
+            ],
+        };
+
+        assert_eq!(delta.content_size(), 6);
+        assert_eq!(delta.output_size(10), 12);
+        assert_eq!(delta.output_size(10), apply(&[0; 10], delta).len());
+    }
+
+    #[test]
+    fn test_compression_ratio() {
+        let text = vec![b'a'; 1000];
+
+        let edit = diff(&text, &[&text[..500], b"b", &text[501..]].concat());
+        let ratio = edit.compression_ratio(text.len());
+        assert!(ratio > 0.0 && ratio < 0.01, "ratio {}", ratio);
+
+        let rewrite = Delta {
+            frags: vec![
+                Fragment {
+                    start: 0,
+                    end: text.len(),
+                    content: vec![b'b'; 800].into(),
+                },
+            ],
+        };
+        assert_eq!(rewrite.compression_ratio(text.len()), 1.0);
+
+        assert_eq!(Delta::default().compression_ratio(0), 0.0);
+        let delete_all = Delta {
+            frags: vec![
+                Fragment {
+                    start: 0,
                     end: 10,
-                    content: vec![10, 11, 12, 13],
+                    content: Bytes::new(),
+                },
+            ],
+        };
+        assert_eq!(delete_all.compression_ratio(10), 0.0);
+    }
+
+    #[test]
+    fn test_map_content() {
+        let text = b"aaaa\nbbbb\ncccc\n";
+        let delta = Delta {
+            frags: vec![
+                Fragment {
+                    start: 0,
+                    end: 5,
+                    content: (&b"xx\n"[..]).into(),
                 },
                 Fragment {
                     start: 10,
-                    end: 16,
-                    content: vec![8, 9],
+                    end: 10,
+                    content: (&b"yyyyyy\n"[..]).into(),
                 },
             ],
         };
 
-        let combined = combine(delta1, delta2);
-        assert_eq!(combined, expected);
+        let upper = delta
+            .map_content(|content| Bytes::from(content.to_ascii_uppercase()))
+            .expect("map_content failed");
+        let res = apply(text, upper);
+        assert_eq!(&res[..], b"XX\nbbbb\nYYYYYY\ncccc\n");
+    }
+
+    #[test]
+    fn test_rebase() {
+        let delta = Delta::new(vec![
+            Fragment::new(3, 5, &b"ab"[..]).unwrap(),
+            Fragment::new(7, 7, &b"c"[..]).unwrap(),
+        ]).unwrap();
+
+        let forward = delta.clone().rebase(10).unwrap();
+        assert_eq!(
+            forward,
+            Delta::new(vec![
+                Fragment::new(13, 15, &b"ab"[..]).unwrap(),
+                Fragment::new(17, 17, &b"c"[..]).unwrap(),
+            ]).unwrap()
+        );
+
+        let to_zero = forward.rebase(-13).unwrap();
+        assert_eq!(
+            to_zero,
+            Delta::new(vec![
+                Fragment::new(0, 2, &b"ab"[..]).unwrap(),
+                Fragment::new(4, 4, &b"c"[..]).unwrap(),
+            ]).unwrap()
+        );
+        assert_eq!(to_zero.clone().rebase(0).unwrap(), to_zero);
+
+        assert!(delta.clone().rebase(-4).is_err());
+        assert!(delta.clone().rebase(isize::min_value()).is_err());
+        assert!(delta.rebase(isize::max_value()).is_ok());
+        assert!(Delta::default().rebase(-100).unwrap().is_empty());
+    }
+
+    /// Test that Deltas which differ only by no-op or split Fragments are semantically equal,
+    /// while ones which do something different are not.
+    #[test]
+    fn test_semantically_eq() {
+        let delta = Delta::new(vec![
+            Fragment::new(0, 2, &b"ab"[..]).unwrap(),
+            Fragment::new(5, 7, Bytes::new()).unwrap(),
+        ]).unwrap();
+        let with_noop = Delta::new(vec![
+            Fragment::new(0, 2, &b"ab"[..]).unwrap(),
+            Fragment::new(3, 3, Bytes::new()).unwrap(),
+            Fragment::new(5, 7, Bytes::new()).unwrap(),
+        ]).unwrap();
+        assert!(with_noop != delta);
+        assert!(with_noop.semantically_eq(&delta));
+        assert!(delta.semantically_eq(&with_noop));
+
+        // Splitting a Fragment in two doesn't change what it does.
+        let split = Delta::new(vec![
+            Fragment::new(0, 1, &b"a"[..]).unwrap(),
+            Fragment::new(1, 2, &b"b"[..]).unwrap(),
+            Fragment::new(5, 7, Bytes::new()).unwrap(),
+        ]).unwrap();
+        assert!(split.semantically_eq(&delta));
+
+        let different = Delta::new(vec![Fragment::new(0, 2, &b"ab"[..]).unwrap()]).unwrap();
+        assert!(!different.semantically_eq(&delta));
+        assert!(Delta::new(vec![Fragment::new(4, 4, Bytes::new()).unwrap()])
+            .unwrap()
+            .semantically_eq(&Delta::default()));
+    }
+
+    #[test]
+    fn test_line_align() {
+        let text = b"foo\nbar baz\nqux\n";
+        let check = |frags: Vec<Fragment>, expected: Vec<Fragment>| {
+            let delta = Delta::new(frags).unwrap();
+            let aligned = delta.line_align(text);
+            assert_eq!(aligned.fragments(), expected.as_slice());
+            assert_eq!(apply(text, aligned), apply(text, delta));
+        };
+
+        // An edit in the middle of a line is widened to the whole line.
+        check(
+            vec![Fragment::new(4, 7, &b"BAR"[..]).unwrap()],
+            vec![Fragment::new(4, 12, &b"BAR baz\n"[..]).unwrap()],
+        );
+        // As is an insertion.
+        check(
+            vec![Fragment::new(13, 13, &b"Z"[..]).unwrap()],
+            vec![Fragment::new(12, 16, &b"qZux\n"[..]).unwrap()],
+        );
+        // Two edits in the same line are merged once widened.
+        check(
+            vec![
+                Fragment::new(4, 7, &b"BAR"[..]).unwrap(),
+                Fragment::new(8, 11, &b"BAZ"[..]).unwrap(),
+            ],
+            vec![Fragment::new(4, 12, &b"BAR BAZ\n"[..]).unwrap()],
+        );
+        // An edit spanning a line break is widened to both lines.
+        check(
+            vec![Fragment::new(1, 6, &b"X"[..]).unwrap()],
+            vec![Fragment::new(0, 12, &b"fXr baz\n"[..]).unwrap()],
+        );
+        // Edits in neighbouring lines stay separate.
+        check(
+            vec![
+                Fragment::new(1, 2, &b"O"[..]).unwrap(),
+                Fragment::new(5, 6, &b"A"[..]).unwrap(),
+            ],
+            vec![
+                Fragment::new(0, 4, &b"fOo\n"[..]).unwrap(),
+                Fragment::new(4, 12, &b"bAr baz\n"[..]).unwrap(),
+            ],
+        );
+        // Edits which are already aligned are left alone, and no-ops are dropped.
+        check(
+            vec![
+                Fragment::new(4, 12, &b"new\n"[..]).unwrap(),
+                Fragment::new(12, 12, &b"ins\n"[..]).unwrap(),
+                Fragment::new(14, 14, Bytes::new()).unwrap(),
+            ],
+            vec![
+                Fragment::new(4, 12, &b"new\n"[..]).unwrap(),
+                Fragment::new(12, 12, &b"ins\n"[..]).unwrap(),
+            ],
+        );
+
+        // The last line needn't end with a newline.
+        let delta = Delta::new(vec![Fragment::new(4, 5, &b"D"[..]).unwrap()]).unwrap();
+        assert_eq!(
+            delta.line_align(b"ab\ncd").fragments(),
+            &[Fragment::new(3, 5, &b"cD"[..]).unwrap()]
+        );
+    }
+
+    /// Test that adjacent fragments are merged, but fragments separated by a gap are not.
+    #[test]
+    fn test_normalize() {
+        let delta = Delta {
+            frags: vec![
+                Fragment {
+                    start: 0,
+                    end: 2,
+                    content: vec![1, 2].into(),
+                },
+                Fragment {
+                    start: 2,
+                    end: 2,
+                    content: vec![3].into(),
+                },
+                Fragment {
+                    start: 2,
+                    end: 5,
+                    content: vec![4, 5].into(),
+                },
+                Fragment {
+                    start: 7,
+                    end: 8,
+                    content: vec![6].into(),
+                },
+            ],
+        };
+
+        let expected = Delta {
+            frags: vec![
+                Fragment {
+                    start: 0,
+                    end: 5,
+                    content: vec![1, 2, 3, 4, 5].into(),
+                },
+                Fragment {
+                    start: 7,
+                    end: 8,
+                    content: vec![6].into(),
+                },
+            ],
+        };
+
+        assert_eq!(delta.normalized(), expected);
+    }
+
+    #[test]
+    fn test_truncate_output() {
+        let text = b"aaaa\nbbbb\ncccc\n";
+        let delta = Delta {
+            frags: vec![
+                Fragment {
+                    start: 5,
+                    end: 10,
+                    content: (&b"xxxxxx\n"[..]).into(),
+                },
+            ],
+        };
+        let full = apply(text, delta.clone());
+        assert_eq!(full, b"aaaa\nxxxxxx\ncccc\n");
+
+        // Inside the unchanged text before the Fragment.
+        let truncated = delta.clone().truncate_output(text.len(), 3);
+        assert_eq!(apply(text, truncated), b"aaa");
+
+        // Inside the Fragment's inserted content.
+        let truncated = delta.clone().truncate_output(text.len(), 8);
+        assert_eq!(
+            truncated.fragments(),
+            &[
+                Fragment {
+                    start: 5,
+                    end: text.len(),
+                    content: (&b"xxx"[..]).into(),
+                },
+            ]
+        );
+        assert_eq!(apply(text, truncated), &full[..8]);
+
+        // Inside the unchanged text after the Fragment.
+        let truncated = delta.clone().truncate_output(text.len(), 15);
+        assert_eq!(apply(text, truncated), &full[..15]);
+
+        assert_eq!(apply(text, delta.clone().truncate_output(text.len(), 0)), b"");
+        let truncated = delta.clone().truncate_output(text.len(), 100);
+        assert_eq!(truncated, delta);
+    }
+
+    #[test]
+    fn test_split_at() {
+        let text = b"aaaa\nbbbb\ncccc\n";
+        let delta = Delta::new(vec![
+            Fragment::new(2, 7, &b"xyz"[..]).unwrap(),
+            Fragment::new(10, 10, &b"ins"[..]).unwrap(),
+            Fragment::new(11, 13, Bytes::new()).unwrap(),
+        ]).unwrap();
+        let expected = apply(text, delta.clone());
+
+        // Inside a Fragment, where its content reaches past the split point.
+        let (before, after) = delta.clone().split_at(4);
+        assert_eq!(
+            before.fragments(),
+            &[Fragment::new(2, 4, &b"xy"[..]).unwrap()]
+        );
+        assert_eq!(
+            after.fragments(),
+            &[
+                Fragment::new(4, 7, &b"z"[..]).unwrap(),
+                Fragment::new(10, 10, &b"ins"[..]).unwrap(),
+                Fragment::new(11, 13, Bytes::new()).unwrap(),
+            ]
+        );
+        assert_eq!(apply(&apply(text, before.clone()), shift(after, &before)), expected);
+
+        // Inside a Fragment, where its content doesn't reach the split point.
+        let (before, after) = delta.clone().split_at(6);
+        assert_eq!(
+            before.fragments(),
+            &[Fragment::new(2, 6, &b"xyz"[..]).unwrap()]
+        );
+        assert_eq!(after.fragments()[0], Fragment::new(6, 7, Bytes::new()).unwrap());
+        assert_eq!(apply(&apply(text, before.clone()), shift(after, &before)), expected);
+
+        // At a Fragment boundary, with an insertion at the split point.
+        let (before, after) = delta.clone().split_at(10);
+        assert_eq!(before.len(), 1);
+        assert_eq!(after.len(), 2);
+        assert_eq!(concat(vec![before, after]).unwrap(), delta);
+
+        // At the ends.
+        let (before, after) = delta.clone().split_at(0);
+        assert!(before.is_empty());
+        assert_eq!(after, delta);
+        let (before, after) = delta.clone().split_at(text.len());
+        assert_eq!(before, delta);
+        assert!(after.is_empty());
+    }
+
+    #[test]
+    fn test_merge3() {
+        let base = b"one\ntwo\nthree\nfour\nfive\n";
+
+        let merged = merge3(
+            base,
+            b"one\nTWO\nthree\nfour\nfive\n",
+            b"one\ntwo\nthree\nFOUR\nfive\nsix\n",
+        );
+        assert_eq!(merged.unwrap(), &b"one\nTWO\nthree\nFOUR\nfive\nsix\n"[..]);
+
+        // The same change on both sides isn't a conflict.
+        let same = b"zero\none\ntwo\nthree\nfour\nfive\n";
+        assert_eq!(merge3(base, same, same).unwrap(), &same[..]);
+        assert_eq!(merge3(base, base, base).unwrap(), &base[..]);
+    }
+
+    #[test]
+    fn test_merge3_conflict() {
+        let base = b"one\ntwo\nthree\nfour\nfive\nsix\nseven\n";
+
+        let conflict = merge3(
+            base,
+            b"one\ntwo\n3\nfour\nfive\nsix\n7\n",
+            b"one\ntwo\nIII\nfour\nfive\nsix\nVII\n",
+        ).unwrap_err();
+        assert_eq!(conflict.ranges, vec![(8, 13), (28, 33)]);
+        assert_eq!(conflict.to_string(), "conflicting changes at 8..13 28..33");
+
+        // Overlapping but different changes are coalesced into one range.
+        let conflict = merge3(
+            base,
+            b"one\ntwo\nTHREE\nFOUR\nfive\nsix\nseven\n",
+            b"one\ntwo\nthree\nFOUR\nFIVE\nsix\nseven\n",
+        ).unwrap_err();
+        assert_eq!(conflict.ranges.len(), 1);
+    }
+
+    #[test]
+    fn test_apply_stats() {
+        let text = b"aaaa\nbbbb\ncccc\n";
+        let stats = |frags| apply_stats(text.len(), &Delta::new(frags).unwrap());
+
+        // Pure insertion.
+        let insert = vec![Fragment::new(5, 5, &b"xyz"[..]).unwrap()];
+        assert_eq!(
+            stats(insert),
+            ApplyStats {
+                output_len: 18,
+                bytes_copied: 15,
+                bytes_inserted: 3,
+                bytes_deleted: 0,
+            }
+        );
+
+        // Pure deletion.
+        let delete = vec![
+            Fragment::new(0, 5, Bytes::new()).unwrap(),
+            Fragment::new(10, 15, Bytes::new()).unwrap(),
+        ];
+        assert_eq!(
+            stats(delete),
+            ApplyStats {
+                output_len: 5,
+                bytes_copied: 5,
+                bytes_inserted: 0,
+                bytes_deleted: 10,
+            }
+        );
+
+        // Replacement.
+        let replace = vec![Fragment::new(5, 9, &b"bb"[..]).unwrap()];
+        assert_eq!(
+            stats(replace),
+            ApplyStats {
+                output_len: 13,
+                bytes_copied: 11,
+                bytes_inserted: 2,
+                bytes_deleted: 4,
+            }
+        );
+
+        assert_eq!(
+            apply_stats(text.len(), &Delta::default()),
+            ApplyStats {
+                output_len: 15,
+                bytes_copied: 15,
+                bytes_inserted: 0,
+                bytes_deleted: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_apply_into() {
+        let text = b"aaaa\nbbbb\ncccc\n";
+        let mut out = Vec::new();
+
+        let grow = Delta {
+            frags: vec![
+                Fragment {
+                    start: 0,
+                    end: text.len(),
+                    content: (&b"a much longer replacement text\n"[..]).into(),
+                },
+            ],
+        };
+        apply_into(text, &grow, &mut out).expect("apply_into failed");
+        assert_eq!(out, apply(text, grow));
+        let capacity = out.capacity();
+
+        // A shorter result reuses the buffer without leaving any of the previous output behind.
+        let shrink = Delta {
+            frags: vec![
+                Fragment {
+                    start: 2,
+                    end: 12,
+                    content: (&b"x"[..]).into(),
+                },
+            ],
+        };
+        apply_into(text, &shrink, &mut out).expect("apply_into failed");
+        assert_eq!(out, b"aaxcc\n");
+        assert_eq!(out.capacity(), capacity);
+
+        // A Fragment past the end of the text is an error, and leaves the output alone.
+        let past_end = Delta {
+            frags: vec![
+                Fragment {
+                    start: 10,
+                    end: text.len() + 1,
+                    content: (&b"x"[..]).into(),
+                },
+            ],
+        };
+        assert!(try_apply(text, &past_end).is_err());
+        assert!(apply_into(text, &past_end, &mut out).is_err());
+        assert_eq!(out, b"aaxcc\n");
+    }
+
+    #[test]
+    fn test_apply_to_writer_out_of_bounds() {
+        let text = b"aaaa\nbbbb\ncccc\n";
+        // Fragments out of order, so the second starts before the first ends.
+        let overlapping = Delta {
+            frags: vec![
+                Fragment {
+                    start: 5,
+                    end: 10,
+                    content: (&b"x"[..]).into(),
+                },
+                Fragment {
+                    start: 2,
+                    end: 4,
+                    content: (&b"y"[..]).into(),
+                },
+            ],
+        };
+        assert!(try_apply(text, &overlapping).is_err());
+
+        let mut out = Vec::new();
+        let err = apply_to_writer(text, &overlapping, &mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_apply_chunked() {
+        let text = b"aaaa\nbbbb\ncccc\n";
+        let delta = Delta {
+            frags: vec![
+                Fragment {
+                    start: 2,
+                    end: 7,
+                    content: (&b"xx"[..]).into(),
+                },
+                Fragment {
+                    start: 9,
+                    end: 9,
+                    content: (&b"yyy"[..]).into(),
+                },
+                Fragment {
+                    start: 10,
+                    end: 15,
+                    content: (&b"z"[..]).into(),
+                },
+            ],
+        };
+        let expected = apply(text, delta.clone());
+
+        // Fragments straddle the chunk edges, and one falls entirely within a chunk.
+        let chunks = vec![
+            Bytes::from(&text[..4]),
+            Bytes::from(&text[4..8]),
+            Bytes::new(),
+            Bytes::from(&text[8..12]),
+            Bytes::from(&text[12..]),
+        ];
+        let mut out = Vec::new();
+        apply_chunked(chunks.into_iter(), &delta, &mut out).unwrap();
+        assert_eq!(out, expected);
+
+        // A single byte per chunk.
+        let mut out = Vec::new();
+        apply_chunked(text.iter().map(|b| Bytes::from(vec![*b])), &delta, &mut out).unwrap();
+        assert_eq!(out, expected);
+
+        // The base text is too short for the last Fragment.
+        let mut out = Vec::new();
+        let err = apply_chunked(Some(Bytes::from(&text[..12])).into_iter(), &delta, &mut out)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_fragment_new() {
+        let frag = Fragment::new(2, 5, vec![1, 2]).unwrap();
+        assert_eq!(
+            frag,
+            Fragment {
+                start: 2,
+                end: 5,
+                content: vec![1, 2].into(),
+            }
+        );
+        assert!(Fragment::new(5, 5, Bytes::new()).is_ok());
+
+        let err = Fragment::new(10, 5, Bytes::new()).unwrap_err();
+        assert_eq!(err.to_string(), "invalid fragment: start 10 > end 5");
+    }
+
+    #[test]
+    fn test_serde_roundtrip() {
+        let delta = Delta::new(vec![
+            Fragment::new(0, 3, &b"abc"[..]).unwrap(),
+            Fragment::new(5, 5, &b"\0\xff"[..]).unwrap(),
+            Fragment::new(7, 10, Bytes::new()).unwrap(),
+        ]).unwrap();
+
+        let encoded = bincode::serialize(&delta, bincode::Infinite).unwrap();
+        let decoded: Delta = bincode::deserialize(&encoded).unwrap();
+        assert_eq!(decoded, delta);
+
+        let encoded = bincode::serialize(&Delta::default(), bincode::Infinite).unwrap();
+        let decoded: Delta = bincode::deserialize(&encoded).unwrap();
+        assert_eq!(decoded, Delta::default());
+    }
+
+    #[test]
+    fn test_serde_invalid() {
+        // Fragments serialize individually, so they can be used to craft an invalid Delta.
+        let overlapping = vec![
+            Fragment::new(0, 5, &b"abc"[..]).unwrap(),
+            Fragment::new(3, 8, &b"def"[..]).unwrap(),
+        ];
+        let encoded = bincode::serialize(&overlapping, bincode::Infinite).unwrap();
+        assert!(bincode::deserialize::<Delta>(&encoded).is_err());
+
+        let inverted = vec![
+            Fragment {
+                start: 5,
+                end: 2,
+                content: Bytes::new(),
+            },
+        ];
+        let encoded = bincode::serialize(&inverted, bincode::Infinite).unwrap();
+        assert!(bincode::deserialize::<Delta>(&encoded).is_err());
+    }
+
+    #[test]
+    fn test_fragment_kinds() {
+        let kinds = |frag: Fragment| {
+            (frag.is_insertion(), frag.is_deletion(), frag.is_replacement())
+        };
+
+        assert_eq!(kinds(Fragment::new(3, 3, &b"abc"[..]).unwrap()), (true, false, false));
+        assert_eq!(kinds(Fragment::new(3, 6, Bytes::new()).unwrap()), (false, true, false));
+        assert_eq!(kinds(Fragment::new(3, 6, &b"a"[..]).unwrap()), (false, false, true));
+        // A Fragment which changes nothing is none of them.
+        assert_eq!(kinds(Fragment::new(3, 3, Bytes::new()).unwrap()), (false, false, false));
+    }
+
+    #[test]
+    fn test_change_histogram() {
+        let delta = Delta::new(vec![
+            Fragment::new(0, 2, &b"ab"[..]).unwrap(),
+            Fragment::new(3, 3, &b"c"[..]).unwrap(),
+            Fragment::new(5, 5, Bytes::new()).unwrap(),
+            Fragment::new(6, 9, Bytes::new()).unwrap(),
+        ]).unwrap();
+        assert_eq!(
+            delta.change_histogram(),
+            ChangeHistogram {
+                insertions: 1,
+                deletions: 1,
+                replacements: 1,
+            }
+        );
+
+        assert_eq!(Delta::default().change_histogram(), ChangeHistogram::default());
+    }
+
+    #[test]
+    fn test_delta_builder() {
+        let mut builder = DeltaBuilder::new();
+        builder
+            .push(0, 3, &b"abc"[..])
+            .unwrap()
+            .push(3, 3, &b"d"[..])
+            .unwrap()
+            .push(5, 8, Bytes::new())
+            .unwrap();
+        assert_eq!(builder.len(), 3);
+
+        let delta = builder.build();
+        assert_eq!(delta.fragments()[1].start, 3);
+        assert_eq!(Delta::new(delta.fragments().to_vec()).unwrap(), delta);
+        assert_eq!(apply(b"0123456789", delta), b"abcd3489");
+
+        assert_eq!(DeltaBuilder::new().build(), Delta::default());
+    }
+
+    #[test]
+    fn test_delta_builder_invalid() {
+        let mut builder = DeltaBuilder::new();
+        builder.push(2, 5, &b"x"[..]).unwrap();
+
+        // The first bad push fails straight away, and leaves the builder as it was.
+        match builder.push(4, 6, &b"y"[..]) {
+            Err(Error(ErrorKind::InvalidFragmentList(ref msg), _)) => {
+                assert_eq!(msg, "fragment 1: previous end 5 overlaps with start 4")
+            }
+            bad => panic!("unexpected result {:?}", bad.map(|_| ())),
+        }
+        match builder.push(7, 6, &b"y"[..]) {
+            Err(Error(ErrorKind::InvalidFragmentList(ref msg), _)) => {
+                assert_eq!(msg, "fragment 1: start 7 > end 6")
+            }
+            bad => panic!("unexpected result {:?}", bad.map(|_| ())),
+        }
+        assert_eq!(builder.len(), 1);
+
+        builder.push(5, 6, &b"y"[..]).unwrap();
+        assert_eq!(builder.build().len(), 2);
+    }
+
+    /// Test combining two Deltas with overlapping fragments.
+    #[test]
+    fn test_combine() {
+        let delta1 = Delta {
+            frags: vec![
+                Fragment {
+                    start: 3,
+                    end: 6,
+                    content: vec![1, 2, 3, 4, 5].into(),
+                },
+                Fragment {
+                    start: 8,
+                    end: 16,
+                    content: vec![6, 7, 8, 9].into(),
+                },
+            ],
+        };
+
+        let delta2 = Delta {
+            frags: vec![
+                Fragment {
+                    start: 7,
+                    end: 12,
+                    content: vec![10, 11, 12, 13].into(),
+                },
+            ],
+        };
+
+        let expected = Delta {
+            frags: vec![
+                Fragment {
+                    start: 3,
+                    end: 6,
+                    content: vec![1, 2, 3, 4].into(),
+                },
+                Fragment {
+                    start: 6,
+                    end: 10,
+                    content: vec![10, 11, 12, 13].into(),
+                },
+                Fragment {
+                    start: 10,
+                    end: 16,
+                    content: vec![8, 9].into(),
+                },
+            ],
+        };
+
+        let combined = combine(delta1, delta2);
+        assert_eq!(combined, expected);
+    }
+
+    #[test]
+    fn test_compat_to_bdiff() {
+        use bdiff;
+
+        // The result of `test_combine`.
+        let delta = Delta::new(vec![
+            Fragment::new(3, 6, vec![1, 2, 3, 4]).unwrap(),
+            Fragment::new(6, 10, vec![10, 11, 12, 13]).unwrap(),
+            Fragment::new(10, 16, vec![8, 9]).unwrap(),
+        ]).unwrap();
+
+        let deltas = compat::to_bdiff(&delta);
+        assert_eq!(
+            deltas,
+            vec![
+                bdiff::Delta {
+                    start: 3,
+                    end: 6,
+                    content: vec![1, 2, 3, 4],
+                },
+                bdiff::Delta {
+                    start: 6,
+                    end: 10,
+                    content: vec![10, 11, 12, 13],
+                },
+                bdiff::Delta {
+                    start: 10,
+                    end: 16,
+                    content: vec![8, 9],
+                },
+            ]
+        );
+        assert_eq!(compat::convert(deltas), delta);
+        assert!(compat::to_bdiff(&Delta::default()).is_empty());
+    }
+
+    /// Malformed deltas, as might be read from a corrupt revlog, are errors for
+    /// `try_apply_deltas` rather than panics.
+    #[test]
+    fn test_compat_try_apply_deltas() {
+        use bdiff;
+
+        let delta = |start, end, content: &[u8]| {
+            bdiff::Delta {
+                start: start,
+                end: end,
+                content: content.to_vec(),
+            }
+        };
+        let text = b"aaaa\nbbbb\n";
+
+        assert_eq!(
+            compat::try_apply_deltas(text, vec![vec![delta(5, 10, b"cccc\n")]]).unwrap(),
+            b"aaaa\ncccc\n".to_vec()
+        );
+
+        // Start past the end.
+        assert!(compat::try_convert(vec![delta(5, 2, b"")]).is_err());
+        assert!(compat::try_apply_deltas(text, vec![vec![delta(5, 2, b"")]]).is_err());
+        let chain = vec![vec![delta(5, 10, b"cccc\n")], vec![delta(5, 2, b"")]];
+        assert!(compat::try_apply_deltas(text, chain).is_err());
+
+        // Fragments out of order.
+        let unsorted = vec![delta(5, 10, b"cccc\n"), delta(0, 0, b"x")];
+        assert!(compat::try_apply_deltas(text, vec![unsorted]).is_err());
+
+        // Past the end of the text.
+        assert!(compat::try_apply_deltas(text, vec![vec![delta(8, 20, b"")]]).is_err());
+    }
+
+    #[test]
+    fn test_combine_traced() {
+        let delta1 = Delta::new(vec![
+            Fragment::new(3, 6, vec![1, 2, 3, 4, 5]).unwrap(),
+            Fragment::new(8, 16, vec![6, 7, 8, 9]).unwrap(),
+        ]).unwrap();
+        let delta2 = Delta::new(vec![Fragment::new(7, 12, vec![10, 11, 12, 13]).unwrap()]).unwrap();
+
+        let (combined, origins) = combine_traced(delta1.clone(), delta2.clone());
+        assert_eq!(combined, combine(delta1.clone(), delta2.clone()));
+        assert_eq!(
+            origins,
+            vec![
+                FragmentOrigin::SplitFirst,
+                FragmentOrigin::SplitSecond,
+                FragmentOrigin::SplitFirst,
+            ]
+        );
+
+        // Fragments which don't interact keep their origin.
+        let delta3 = Delta::new(vec![Fragment::new(0, 1, vec![0]).unwrap()]).unwrap();
+        let (combined, origins) = combine_traced(delta1.clone(), delta3.clone());
+        assert_eq!(combined, combine(delta1, delta3));
+        assert_eq!(
+            origins,
+            vec![FragmentOrigin::Second, FragmentOrigin::First, FragmentOrigin::First]
+        );
+
+        assert_eq!(
+            combine_traced(Delta::default(), delta2.clone()),
+            (delta2, vec![FragmentOrigin::Second])
+        );
+    }
+
+    /// A malformed Delta that would make `combine` compute a negative offset is an error for
+    /// `try_combine`.
+    #[test]
+    fn test_try_combine_underflow() {
+        let delta1 = Delta {
+            frags: vec![
+                Fragment {
+                    start: 0,
+                    end: 0,
+                    content: vec![1, 2, 3, 4].into(),
+                },
+            ],
+        };
+
+        // Start is past the end, which `Delta::new` would reject.
+        let delta2 = Delta {
+            frags: vec![
+                Fragment {
+                    start: 1,
+                    end: 0,
+                    content: Bytes::new(),
+                },
+            ],
+        };
+
+        match try_combine(delta1.clone(), delta2.clone()) {
+            Err(Error(ErrorKind::InvalidFragmentList(ref msg), _)) => {
+                assert_eq!(msg, "offset underflow while combining")
+            }
+            bad => panic!("unexpected result {:?}", bad),
+        }
+        assert!(try_combine_chain(vec![delta1.clone(), delta2]).is_err());
+
+        // A malformed Fragment in the first Delta is caught when its length change is taken.
+        let backwards = Delta {
+            frags: vec![
+                Fragment {
+                    start: 2,
+                    end: 1,
+                    content: Bytes::new(),
+                },
+            ],
+        };
+        let delta4 = Delta {
+            frags: vec![
+                Fragment {
+                    start: 5,
+                    end: 5,
+                    content: vec![6].into(),
+                },
+            ],
+        };
+        match try_combine(backwards, delta4) {
+            Err(Error(ErrorKind::InvalidFragmentList(ref msg), _)) => {
+                assert_eq!(msg, "offset underflow while combining")
+            }
+            bad => panic!("unexpected result {:?}", bad),
+        }
+
+        // Well-formed Deltas combine just as with `combine`.
+        let delta3 = Delta {
+            frags: vec![
+                Fragment {
+                    start: 2,
+                    end: 4,
+                    content: vec![5].into(),
+                },
+            ],
+        };
+        assert_eq!(
+            try_combine(delta1.clone(), delta3.clone()).unwrap(),
+            combine(delta1, delta3)
+        );
+    }
+
+    /// Test that the empty Delta leaves a text alone and is an identity for `combine`.
+    #[test]
+    fn test_empty() {
+        let empty = Delta::default();
+        assert!(empty.is_empty());
+        assert_eq!(empty.len(), 0);
+        assert_eq!(apply(b"text", empty.clone()), b"text");
+        assert_eq!(try_apply(b"", &empty).unwrap(), b"");
+
+        let delta = Delta {
+            frags: vec![
+                Fragment {
+                    start: 0,
+                    end: 1,
+                    content: vec![1].into(),
+                },
+                Fragment {
+                    start: 2,
+                    end: 2,
+                    content: vec![2, 3].into(),
+                },
+            ],
+        };
+        assert!(!delta.is_empty());
+        assert_eq!(delta.len(), 2);
+
+        assert_eq!(combine(empty.clone(), delta.clone()), delta);
+        assert_eq!(combine(delta.clone(), empty.clone()), delta);
+        assert_eq!(combine(empty.clone(), empty.clone()), empty);
+        assert_eq!(combine_chain(vec![empty.clone(), delta.clone(), empty]), delta);
+    }
+
+    fn ranges(ranges: &[(usize, usize)]) -> Delta {
+        let frags = ranges
+            .iter()
+            .map(|&(start, end)| {
+                Fragment {
+                    start: start,
+                    end: end,
+                    content: vec![0].into(),
+                }
+            })
+            .collect();
+        Delta::new(frags).unwrap()
+    }
+
+    #[test]
+    fn test_touched_ranges() {
+        let delta = ranges(&[(0, 2), (4, 4), (5, 9)]);
+        assert_eq!(
+            delta.touched_ranges().collect::<Vec<_>>(),
+            vec![(0, 2), (4, 4), (5, 9)]
+        );
+        assert_eq!(Delta::default().touched_ranges().count(), 0);
+    }
+
+    #[test]
+    fn test_segments() {
+        let delta = Delta::new(vec![
+            Fragment::new(0, 0, &b"AB"[..]).unwrap(),
+            Fragment::new(3, 5, Bytes::new()).unwrap(),
+            Fragment::new(6, 7, &b"C"[..]).unwrap(),
+        ]).unwrap();
+        let text = b"0123456789";
+
+        let segments: Vec<_> = delta.segments(text.len()).collect();
+        assert_eq!(
+            segments,
+            vec![
+                Segment::Insert { bytes: b"AB" },
+                Segment::Copy { from: 0, len: 3 },
+                Segment::Copy { from: 5, len: 1 },
+                Segment::Insert { bytes: b"C" },
+                Segment::Copy { from: 7, len: 3 },
+            ]
+        );
+
+        let mut out = Vec::new();
+        for segment in segments {
+            match segment {
+                Segment::Copy { from, len } => out.extend_from_slice(&text[from..from + len]),
+                Segment::Insert { bytes } => out.extend_from_slice(bytes),
+            }
+        }
+        assert_eq!(out, apply(text, delta.clone()));
+
+        // With no tail, the last segment is the last Fragment's content.
+        assert_eq!(delta.segments(7).last(), Some(Segment::Insert { bytes: b"C" }));
+        assert_eq!(
+            Delta::default().segments(4).collect::<Vec<_>>(),
+            vec![Segment::Copy { from: 0, len: 4 }]
+        );
+        assert_eq!(Delta::default().segments(0).count(), 0);
+    }
+
+    #[test]
+    fn test_overlaps() {
+        let cases = vec![
+            // overlapping
+            (vec![(0, 4)], vec![(3, 6)], true),
+            (vec![(0, 2), (10, 20)], vec![(3, 5), (12, 13)], true),
+            (vec![(2, 8)], vec![(4, 4)], true),
+            (vec![(4, 4)], vec![(4, 4)], true),
+            (vec![(3, 5), (5, 5)], vec![(5, 5)], true),
+            // adjacent
+            (vec![(0, 4)], vec![(4, 6)], false),
+            (vec![(0, 4), (8, 10)], vec![(4, 8), (10, 12)], false),
+            (vec![(2, 8)], vec![(2, 2), (8, 8)], false),
+            // disjoint
+            (vec![(0, 2)], vec![(5, 6)], false),
+            (vec![(0, 2), (6, 7)], vec![(3, 5), (9, 9)], false),
+            (vec![], vec![(0, 10)], false),
+        ];
+
+        for (a, b, expected) in cases {
+            let (a, b) = (ranges(&a), ranges(&b));
+            assert_eq!(a.overlaps(&b), expected, "{:?} {:?}", a, b);
+            assert_eq!(b.overlaps(&a), expected, "{:?} {:?}", b, a);
+        }
+    }
+
+    #[test]
+    fn test_arbitrary_max_len() {
+        let mut g = StdGen::new(rand::thread_rng(), 1000);
+        // Heavily skewed towards large content.
+        let config = FragmentGenConfig {
+            mean: 2.0,
+            stdev: 2.0,
+            max_len: 50,
+        };
+
+        let mut capped = false;
+        for _ in 0..100 {
+            let frag = Fragment::arbitrary_with_config(&mut g, &config);
+            assert!(frag.content.len() <= config.max_len);
+            capped |= frag.content.len() == config.max_len;
+
+            let delta = Delta::arbitrary_with_config(&mut g, &config);
+            assert!(delta.fragments().iter().all(|frag| frag.content.len() <= config.max_len));
+        }
+        assert!(capped);
+    }
+
+    #[test]
+    fn test_concat() {
+        let text = b"the quick brown fox";
+        let hunk = |start, end, content: &[u8]| {
+            Delta {
+                frags: vec![
+                    Fragment {
+                        start: start,
+                        end: end,
+                        content: content.to_vec().into(),
+                    },
+                ],
+            }
+        };
+
+        let delta = concat(vec![
+            hunk(0, 3, b"a"),
+            Delta::default(),
+            hunk(4, 9, b"slow"),
+            hunk(16, 19, b"cat"),
+        ]).unwrap();
+        assert_eq!(delta.len(), 3);
+        assert_eq!(apply(text, delta), b"a slow brown cat");
+
+        assert_eq!(concat(vec![]).unwrap(), Delta::default());
+        // Abutting hunks are fine.
+        assert!(concat(vec![hunk(0, 4, b""), hunk(4, 9, b"")]).is_ok());
+    }
+
+    #[test]
+    fn test_concat_overlap() {
+        let hunk = |start, end| {
+            Delta {
+                frags: vec![
+                    Fragment {
+                        start: start,
+                        end: end,
+                        content: vec![0].into(),
+                    },
+                ],
+            }
+        };
+
+        for deltas in vec![
+            vec![hunk(0, 5), hunk(4, 8)],
+            vec![hunk(4, 8), hunk(0, 2)],
+            vec![hunk(0, 2), hunk(3, 4), hunk(3, 6)],
+        ] {
+            match concat(deltas) {
+                Err(Error(ErrorKind::InvalidFragmentList(_), _)) => (),
+                res => panic!("unexpected result {:?}", res),
+            }
+        }
+    }
+
+    /// Test inverting a Delta with a pure insertion, a pure deletion and a replacement.
+    #[test]
+    fn test_invert() {
+        let text = b"aaaa\nbbbb\ncccc\n";
+        let delta = Delta {
+            frags: vec![
+                Fragment {
+                    start: 0,
+                    end: 0,
+                    content: (&b"zzzz\n"[..]).into(),
+                },
+                Fragment {
+                    start: 5,
+                    end: 10,
+                    content: (&b""[..]).into(),
+                },
+                Fragment {
+                    start: 10,
+                    end: 15,
+                    content: (&b"xx\n"[..]).into(),
+                },
+            ],
+        };
+
+        let expected = Delta {
+            frags: vec![
+                Fragment {
+                    start: 0,
+                    end: 5,
+                    content: (&b""[..]).into(),
+                },
+                Fragment {
+                    start: 10,
+                    end: 10,
+                    content: (&b"bbbb\n"[..]).into(),
+                },
+                Fragment {
+                    start: 10,
+                    end: 13,
+                    content: (&b"cccc\n"[..]).into(),
+                },
+            ],
+        };
+
+        let inverse = invert(text, &delta);
+        assert_eq!(inverse, expected);
+
+        let res = apply(text, delta);
+        assert_eq!(&res[..], b"zzzz\naaaa\nxx\n");
+        assert_eq!(&apply(&res, inverse)[..], &text[..]);
+    }
+
+    #[test]
+    fn test_apply_with_inverse() {
+        let text = b"the quick brown fox";
+        let delta = Delta::new(vec![
+            Fragment::new(4, 9, &b"slow"[..]).unwrap(),
+            Fragment::new(16, 19, &b"turtle"[..]).unwrap(),
+        ]).unwrap();
+
+        let (out, inverse) = apply_with_inverse(text, &delta);
+        assert_eq!(&out[..], b"the slow brown turtle");
+        assert_eq!(
+            inverse,
+            Delta::new(vec![
+                Fragment::new(4, 8, &b"quick"[..]).unwrap(),
+                Fragment::new(15, 21, &b"fox"[..]).unwrap(),
+            ]).unwrap()
+        );
+        assert_eq!(&apply(&out, inverse)[..], &text[..]);
+
+        let (out, inverse) = apply_with_inverse(text, &Delta::default());
+        assert_eq!(&out[..], &text[..]);
+        assert!(inverse.is_empty());
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = b"aaaa\nbbbb\ncccc\n";
+        let new = b"aaaa\nxxxx\ncccc\ndddd\n";
+
+        let expected = Delta {
+            frags: vec![
+                Fragment {
+                    start: 5,
+                    end: 9,
+                    content: (&b"xxxx"[..]).into(),
+                },
+                // The common suffix "\n" is matched first, so the insertion is shifted left.
+                Fragment {
+                    start: 14,
+                    end: 14,
+                    content: (&b"\ndddd"[..]).into(),
+                },
+            ],
+        };
+
+        let delta = diff(old, new);
+        assert_eq!(delta, expected);
+        assert_eq!(&apply(old, delta)[..], &new[..]);
+
+        assert_eq!(diff(old, old), Delta::default());
+    }
+
+    /// Test that short matches are folded into the surrounding change.
+    #[test]
+    fn test_diff_min_match() {
+        let old = b"aaaa\nbbbb\ncccc\n";
+        let new = b"aaaa\nxbxx\ncccc\n";
+
+        let delta = diff_with_min_match(old, new, 1);
+        assert_eq!(delta.fragments().len(), 2);
+        assert_eq!(&apply(old, delta)[..], &new[..]);
+
+        let delta = diff_with_min_match(old, new, 2);
+        assert_eq!(
+            delta,
+            Delta {
+                frags: vec![
+                    Fragment {
+                        start: 5,
+                        end: 9,
+                        content: (&b"xbxx"[..]).into(),
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_try_apply_start_out_of_bounds() {
+        let text = b"aaaa\nbbbb\n";
+        let delta = Delta {
+            frags: vec![
+                Fragment {
+                    start: 12,
+                    end: 12,
+                    content: (&b"xxxx\n"[..]).into(),
+                },
+            ],
+        };
+
+        assert!(try_apply(text, &delta).is_err());
+    }
+
+    #[test]
+    fn test_try_apply_end_out_of_bounds() {
+        let text = b"aaaa\nbbbb\n";
+        let delta = Delta {
+            frags: vec![
+                Fragment {
+                    start: 5,
+                    end: 11,
+                    content: (&b"xxxx\n"[..]).into(),
+                },
+            ],
+        };
+
+        assert!(try_apply(text, &delta).is_err());
+    }
+
+    #[test]
+    fn test_try_apply_unsorted() {
+        let text = b"aaaa\nbbbb\n";
+        let delta = Delta {
+            frags: vec![
+                Fragment {
+                    start: 5,
+                    end: 10,
+                    content: (&b"xxxx\n"[..]).into(),
+                },
+                Fragment {
+                    start: 0,
+                    end: 5,
+                    content: (&b"yyyy\n"[..]).into(),
+                },
+            ],
+        };
+
+        assert!(try_apply(text, &delta).is_err());
+    }
+
+    #[test]
+    fn test_try_apply_end_of_text() {
+        let text = b"aaaa\nbbbb\n";
+        let delta = Delta {
+            frags: vec![
+                Fragment {
+                    start: 10,
+                    end: 10,
+                    content: (&b"cccc\n"[..]).into(),
+                },
+            ],
+        };
+
+        let res = try_apply(text, &delta).expect("fragment at end of text should apply");
+        assert_eq!(&res[..], b"aaaa\nbbbb\ncccc\n");
     }
 
     #[test]
@@ -19,6 +19,22 @@ error_chain! {
             description("invalid fragment list")
             display("invalid fragment list: {}", msg)
         }
+        FragmentOutOfBounds(msg: String) {
+            description("fragment out of bounds")
+            display("fragment out of bounds: {}", msg)
+        }
+        InvalidHashPrefix(prefix: String, msg: String) {
+            description("invalid hash prefix")
+            display("invalid hash prefix '{}': {}", prefix, msg)
+        }
+        ManifestTooDeep(depth: usize) {
+            description("manifest trees nested too deeply")
+            display("manifest trees nested more than {} deep", depth)
+        }
+        InvalidExport(msg: String) {
+            description("invalid export")
+            display("invalid export: {}", msg)
+        }
     }
 
     foreign_links {
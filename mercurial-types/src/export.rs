@@ -0,0 +1,303 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Streaming export of part of a repo's history, for backups.
+//!
+//! An export starts with a header of the 4 bytes `EXPORT_MAGIC` followed by the format version
+//! as a big-endian `u32`, currently `EXPORT_VERSION`. After that comes a sequence of records,
+//! each of which is:
+//!
+//! - the record kind, one byte as given by `RecordKind`
+//! - the 20-byte nodeid the record describes
+//! - the payload length as a big-endian `u32`, then the payload itself
+//!
+//! The last record is always an `End` record with a null nodeid and no payload, so that a
+//! truncated export can be told apart from a complete one.
+//!
+//! Changesets are exported parents first. Each changeset record is followed by a record for its
+//! manifest, then by a record for each of the files the changeset lists as changed which still
+//! exist in that manifest. The payloads are:
+//!
+//! - `Changeset`: the two parent nodeids (null if absent), then the changeset in Mercurial's
+//!   text format.
+//! - `Manifest`: a line `<path>\0<hex nodeid><flag>\n` for every file, executable and symlink
+//!   in the manifest, sorted by path, as in a Mercurial flat manifest.
+//! - `File`: the content of the file. For a symlink this is its target, and for a file stored
+//!   in LFS it is the LFS pointer.
+
+use bytes::Bytes;
+use futures::future::Future;
+use futures::stream::{self, BoxStream, Stream};
+
+use ancestors;
+use changeset::Changeset;
+use errors::*;
+use lfs::LfsPointer;
+use manifest::{Content, Entry};
+use nodehash::{NodeHash, NULL_HASH};
+use path::Path;
+use repo::Repo;
+
+/// Bytes at the very start of an export.
+pub const EXPORT_MAGIC: &[u8; 4] = b"MNXP";
+
+/// Version of the format written by `export`.
+pub const EXPORT_VERSION: u32 = 1;
+
+/// Length of the header before the first record.
+const HEADER_LEN: usize = 8;
+
+/// Length of each record's kind, nodeid and payload length, before the payload.
+const RECORD_HEADER_LEN: usize = 25;
+
+/// What a record in an export describes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum RecordKind {
+    Changeset,
+    Manifest,
+    File,
+    /// Marks the end of the export.
+    End,
+}
+
+impl RecordKind {
+    fn to_byte(&self) -> u8 {
+        match *self {
+            RecordKind::Changeset => b'c',
+            RecordKind::Manifest => b'm',
+            RecordKind::File => b'f',
+            RecordKind::End => b'e',
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            b'c' => Some(RecordKind::Changeset),
+            b'm' => Some(RecordKind::Manifest),
+            b'f' => Some(RecordKind::File),
+            b'e' => Some(RecordKind::End),
+            _ => None,
+        }
+    }
+}
+
+/// One record read back from an export by `parse`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Record {
+    pub kind: RecordKind,
+    pub nodeid: NodeHash,
+    pub payload: Bytes,
+}
+
+/// Export the changesets reachable from `heads` but not from `common`, along with their
+/// manifests and the files they change, in the format described in the module documentation.
+///
+/// The stream produces the header first, then each record as a separate chunk, so the chunks
+/// can simply be written out one after the other. It is an error if any of `heads` or `common`
+/// doesn't exist.
+pub fn export<R>(repo: R, heads: Vec<NodeHash>, common: Vec<NodeHash>) -> BoxStream<Bytes, R::Error>
+where
+    R: Repo + Clone + Send,
+    R::Error: From<Error>,
+{
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(EXPORT_MAGIC);
+    write_u32_be(&mut header, EXPORT_VERSION);
+
+    let body = ancestors::missing(repo.clone(), heads, common)
+        .map(|csids| stream::iter(csids.into_iter().map(Ok)))
+        .flatten_stream()
+        .map(move |csid| export_changeset(repo.clone(), csid))
+        .flatten();
+
+    stream::once(Ok(Bytes::from(header)))
+        .chain(body)
+        .chain(stream::once(Ok(record(RecordKind::End, &NULL_HASH, &[]))))
+        .boxed()
+}
+
+/// Produce the records for changeset `csid`: the changeset itself, its manifest, then the
+/// files it changes.
+fn export_changeset<R>(repo: R, csid: NodeHash) -> BoxStream<Bytes, R::Error>
+where
+    R: Repo + Clone + Send,
+    R::Error: From<Error>,
+{
+    repo.get_changeset_by_nodeid(&csid)
+        .map(move |cs| {
+            let changeset = record(RecordKind::Changeset, &csid, &changeset_payload(&cs));
+            let manifestid = *cs.manifestid();
+
+            let manifest = repo.walk_manifest(&manifestid)
+                .collect()
+                .map(move |entries| {
+                    record(RecordKind::Manifest, &manifestid, &manifest_payload(entries))
+                })
+                .into_stream();
+
+            // A file which was removed by the changeset is listed but no longer exists.
+            let files = stream::iter(cs.files().to_vec().into_iter().map(Ok))
+                .and_then(move |path| repo.get_entry_by_path(&csid, &path))
+                .filter_map(|entry| entry)
+                .and_then(|entry| {
+                    let nodeid = *entry.get_hash();
+                    entry
+                        .get_content()
+                        .map(move |content| file_payload(content).map(|payload| (nodeid, payload)))
+                })
+                .filter_map(|file| file)
+                .map(|(nodeid, payload)| record(RecordKind::File, &nodeid, &payload));
+
+            stream::once(Ok(changeset)).chain(manifest).chain(files)
+        })
+        .flatten_stream()
+        .boxed()
+}
+
+fn changeset_payload(cs: &Changeset) -> Vec<u8> {
+    let mut out = Vec::new();
+    let (p1, p2) = cs.parents().get_nodes();
+    out.extend_from_slice(p1.unwrap_or(&NULL_HASH).sha1().as_ref());
+    out.extend_from_slice(p2.unwrap_or(&NULL_HASH).sha1().as_ref());
+
+    out.extend_from_slice(format!("{}\n", cs.manifestid()).as_bytes());
+    out.extend_from_slice(cs.user());
+    out.push(b'\n');
+    let time = cs.time();
+    out.extend_from_slice(format!("{} {}", time.time, time.tz).as_bytes());
+    // Extras are only written if there are any, as Mercurial does.
+    for (i, (key, value)) in cs.extra().iter().enumerate() {
+        out.push(if i == 0 { b' ' } else { b'\0' });
+        escape_into(&mut out, key);
+        out.push(b':');
+        escape_into(&mut out, value);
+    }
+    out.push(b'\n');
+    for path in cs.files() {
+        out.extend_from_slice(&path.to_vec());
+        out.push(b'\n');
+    }
+    out.push(b'\n');
+    out.extend_from_slice(cs.comments());
+
+    out
+}
+
+fn manifest_payload<E>(mut entries: Vec<(Path, Box<Entry<Error = E>>)>) -> Vec<u8>
+where
+    E: Send + 'static,
+{
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut out = Vec::new();
+    for (path, entry) in entries {
+        out.extend_from_slice(&path.to_vec());
+        out.push(b'\0');
+        out.extend_from_slice(format!("{}{}\n", entry.get_hash(), entry.get_type()).as_bytes());
+    }
+    out
+}
+
+/// Return the bytes to export for a file, or `None` if it's actually a tree.
+fn file_payload<E>(content: Content<E>) -> Option<Vec<u8>> {
+    match content {
+        Content::File(blob) | Content::Executable(blob) => {
+            Some(blob.as_slice().unwrap_or(&[]).to_vec())
+        }
+        Content::Symlink(path) => Some(path.to_vec()),
+        Content::LfsPointer { oid, size } => Some(lfs_pointer(&LfsPointer { oid, size })),
+        Content::Tree(_) => None,
+    }
+}
+
+fn lfs_pointer(pointer: &LfsPointer) -> Vec<u8> {
+    format!(
+        "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n",
+        pointer.oid,
+        pointer.size
+    ).into_bytes()
+}
+
+/// Escape changeset extras the way Mercurial does.
+fn escape_into(out: &mut Vec<u8>, s: &[u8]) {
+    for &c in s {
+        match c {
+            b'\0' => out.extend_from_slice(b"\\0"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn record(kind: RecordKind, nodeid: &NodeHash, payload: &[u8]) -> Bytes {
+    let mut out = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+    out.push(kind.to_byte());
+    out.extend_from_slice(nodeid.sha1().as_ref());
+    write_u32_be(&mut out, payload.len() as u32);
+    out.extend_from_slice(payload);
+    Bytes::from(out)
+}
+
+/// Read back the records of a complete export produced by `export`, up to and including the
+/// `End` record. It is an error if the header is wrong, the version isn't one this code
+/// understands, a record is truncated or of an unknown kind, or anything follows the `End`
+/// record.
+pub fn parse(data: &[u8]) -> Result<Vec<Record>> {
+    let err = |msg: String| Err(ErrorKind::InvalidExport(msg).into());
+
+    if data.len() < HEADER_LEN || &data[..4] != EXPORT_MAGIC {
+        return err("missing header".into());
+    }
+    let version = read_u32_be(&data[4..]);
+    if version != EXPORT_VERSION {
+        return err(format!("unsupported version {}", version));
+    }
+
+    let data = Bytes::from(&data[HEADER_LEN..]);
+    let mut records = Vec::new();
+    let mut off = 0;
+
+    loop {
+        if data.len() - off < RECORD_HEADER_LEN {
+            return err(format!("record {}: truncated header", records.len()));
+        }
+        let kind = match RecordKind::from_byte(data[off]) {
+            Some(kind) => kind,
+            None => return err(format!("record {}: unknown kind {}", records.len(), data[off])),
+        };
+        let nodeid = NodeHash::from_bytes(&data[off + 1..off + 21])?;
+        let len = read_u32_be(&data[off + 21..]) as usize;
+        off += RECORD_HEADER_LEN;
+
+        if data.len() - off < len {
+            return err(format!("record {}: truncated payload", records.len()));
+        }
+        records.push(Record {
+            kind,
+            nodeid,
+            payload: data.slice(off, off + len),
+        });
+        off += len;
+
+        if kind == RecordKind::End {
+            break;
+        }
+    }
+
+    if off != data.len() {
+        return err("data after end record".into());
+    }
+    Ok(records)
+}
+
+fn read_u32_be(buf: &[u8]) -> u32 {
+    (buf[0] as u32) << 24 | (buf[1] as u32) << 16 | (buf[2] as u32) << 8 | buf[3] as u32
+}
+
+fn write_u32_be(out: &mut Vec<u8>, val: u32) {
+    out.extend_from_slice(&[(val >> 24) as u8, (val >> 16) as u8, (val >> 8) as u8, val as u8]);
+}
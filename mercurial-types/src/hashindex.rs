@@ -0,0 +1,104 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Sorted in-memory index of changeset hashes, for resolving hash prefixes without scanning
+//! every changeset each time.
+
+use std::iter;
+use std::sync::{Arc, RwLock};
+
+use futures::future::{BoxFuture, Future};
+use futures::stream::Stream;
+
+use nodehash::NodeHash;
+use repo::{HashResolution, Repo, MAX_AMBIGUOUS_CANDIDATES};
+
+/// The hashes of every changeset in a repo, sorted so that the ones with a given prefix can
+/// be found with a binary search.
+///
+/// The index is a snapshot taken by `build` or the last `refresh`; changesets added to the repo
+/// since then aren't found. Clones share the same index, so refreshing one refreshes them all,
+/// and lookups can be made concurrently from any number of threads.
+#[derive(Clone, Debug, Default)]
+pub struct ChangesetHashIndex {
+    hashes: Arc<RwLock<Vec<NodeHash>>>,
+}
+
+impl ChangesetHashIndex {
+    /// Construct an index of the given hashes, which may be in any order.
+    pub fn new<I: IntoIterator<Item = NodeHash>>(hashes: I) -> Self {
+        ChangesetHashIndex {
+            hashes: Arc::new(RwLock::new(sorted(hashes))),
+        }
+    }
+
+    /// Build an index of every changeset in `repo`, reading `get_changesets` once.
+    pub fn build<R: Repo>(repo: &R) -> BoxFuture<Self, R::Error> {
+        repo.get_changesets().collect().map(Self::new).boxed()
+    }
+
+    /// Replace the contents of the index with every changeset now in `repo`. Lookups made
+    /// while this is in progress use the old contents.
+    pub fn refresh<R: Repo>(&self, repo: &R) -> BoxFuture<(), R::Error> {
+        let index = self.hashes.clone();
+        repo.get_changesets()
+            .collect()
+            .map(move |hashes| {
+                let hashes = sorted(hashes);
+                *index.write().expect("lock poison") = hashes;
+            })
+            .boxed()
+    }
+
+    /// Number of changesets in the index.
+    pub fn len(&self) -> usize {
+        self.hashes.read().expect("lock poison").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Find the changesets whose hex hash starts with `prefix`, returning at most
+    /// `MAX_AMBIGUOUS_CANDIDATES` of them, in hash order, if it's ambiguous. Case is ignored,
+    /// and a prefix which isn't hex or is too long matches nothing. `MIN_HASH_PREFIX_LEN`
+    /// isn't enforced here; see `repo::check_hash_prefix`.
+    pub fn lookup_prefix(&self, prefix: &str) -> HashResolution {
+        let prefix = prefix.to_lowercase();
+        if prefix.len() > 40 {
+            return HashResolution::NotFound;
+        }
+        // Hex digits sort in the same order as the bytes they encode, so the first hash with
+        // the prefix is the first one not below the prefix padded out with zeros.
+        let padding: String = iter::repeat('0').take(40 - prefix.len()).collect();
+        let low: NodeHash = match format!("{}{}", prefix, padding).parse() {
+            Ok(low) => low,
+            Err(_) => return HashResolution::NotFound,
+        };
+
+        let hashes = self.hashes.read().expect("lock poison");
+        let first = hashes.binary_search(&low).unwrap_or_else(|idx| idx);
+        let mut found: Vec<_> = hashes[first..]
+            .iter()
+            .take_while(|hash| hash.to_hex().as_str().starts_with(&prefix))
+            .take(MAX_AMBIGUOUS_CANDIDATES)
+            .cloned()
+            .collect();
+
+        match found.len() {
+            0 => HashResolution::NotFound,
+            1 => HashResolution::Unique(found.remove(0)),
+            _ => HashResolution::Ambiguous(found),
+        }
+    }
+}
+
+fn sorted<I: IntoIterator<Item = NodeHash>>(hashes: I) -> Vec<NodeHash> {
+    let mut hashes: Vec<_> = hashes.into_iter().collect();
+    hashes.sort();
+    hashes.dedup();
+    hashes
+}
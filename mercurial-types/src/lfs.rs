@@ -0,0 +1,239 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Git LFS pointers
+//!
+//! Files tracked with LFS are stored in the repo as a small pointer blob naming the real content
+//! by its sha256 oid, such as:
+//!
+//! ```text
+//! version https://git-lfs.github.com/spec/v1
+//! oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393
+//! size 12345
+//! ```
+//!
+//! Parsing is strict, so that ordinary text files which merely look similar aren't mistaken for
+//! pointers.
+
+use std::collections::HashSet;
+use std::str;
+
+/// Pointers are required to be smaller than this.
+pub const MAX_POINTER_SIZE: usize = 1024;
+
+/// Accepted values of the leading `version` key.
+const VERSIONS: &[&str] = &[
+    "https://git-lfs.github.com/spec/v1",
+    "https://hawser.github.com/spec/v1",
+];
+
+const OID_PREFIX: &str = "sha256:";
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct LfsPointer {
+    /// Lowercase hex sha256 of the real content.
+    pub oid: String,
+    /// Size of the real content.
+    pub size: u64,
+}
+
+impl LfsPointer {
+    /// Parse `data` as an LFS pointer, returning `None` if it isn't a well-formed one.
+    ///
+    /// A pointer is a sequence of `key value\n` lines. The first must be `version` with a known
+    /// spec URL, the rest must have unique keys in sorted order, and `oid` and `size` must be
+    /// present. Unknown keys are allowed, as Mercurial adds its own `x-` keys.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() >= MAX_POINTER_SIZE || !data.ends_with(b"\n") {
+            return None;
+        }
+        let data = match str::from_utf8(data) {
+            Ok(data) => data,
+            Err(_) => return None,
+        };
+
+        let mut lines = data[..data.len() - 1].split('\n');
+
+        match lines.next().and_then(|line| split_line(line)) {
+            Some(("version", version)) if VERSIONS.contains(&version) => (),
+            _ => return None,
+        }
+
+        let mut seen = HashSet::new();
+        let mut prev = "";
+        let mut oid = None;
+        let mut size = None;
+
+        for line in lines {
+            let (key, value) = match split_line(line) {
+                Some(kv) => kv,
+                None => return None,
+            };
+            if key == "version" || key <= prev || !seen.insert(key) {
+                return None;
+            }
+            prev = key;
+
+            match key {
+                "oid" => match parse_oid(value) {
+                    Some(value) => oid = Some(value),
+                    None => return None,
+                },
+                "size" => match parse_size(value) {
+                    Some(value) => size = Some(value),
+                    None => return None,
+                },
+                _ => (),
+            }
+        }
+
+        match (oid, size) {
+            (Some(oid), Some(size)) => Some(LfsPointer { oid, size }),
+            _ => None,
+        }
+    }
+}
+
+/// Split a `key value` line, checking the key is made of valid characters.
+fn split_line(line: &str) -> Option<(&str, &str)> {
+    let mut parts = line.splitn(2, ' ');
+    let (key, value) = match (parts.next(), parts.next()) {
+        (Some(key), Some(value)) => (key, value),
+        _ => return None,
+    };
+
+    let valid_key = !key.is_empty() && key.bytes().all(|b| match b {
+        b'a'...b'z' | b'0'...b'9' | b'.' | b'-' => true,
+        _ => false,
+    });
+
+    if valid_key && !value.is_empty() {
+        Some((key, value))
+    } else {
+        None
+    }
+}
+
+fn parse_oid(value: &str) -> Option<String> {
+    if !value.starts_with(OID_PREFIX) {
+        return None;
+    }
+    let hex = &value[OID_PREFIX.len()..];
+
+    let valid = hex.len() == 64 && hex.bytes().all(|b| match b {
+        b'0'...b'9' | b'a'...b'f' => true,
+        _ => false,
+    });
+
+    if valid { Some(hex.to_string()) } else { None }
+}
+
+fn parse_size(value: &str) -> Option<u64> {
+    // u64's FromStr accepts a leading '+', which the spec doesn't
+    if value.bytes().all(|b| b >= b'0' && b <= b'9') {
+        value.parse().ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const OID: &str = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+
+    fn pointer(lines: &[&str]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for line in lines {
+            data.extend_from_slice(line.as_bytes());
+            data.push(b'\n');
+        }
+        data
+    }
+
+    #[test]
+    fn valid() {
+        let oid = format!("oid sha256:{}", OID);
+        let expected = Some(LfsPointer {
+            oid: OID.to_string(),
+            size: 12345,
+        });
+
+        let data = pointer(&[
+            "version https://git-lfs.github.com/spec/v1",
+            &oid,
+            "size 12345",
+        ]);
+        assert_eq!(LfsPointer::parse(&data), expected);
+
+        // Mercurial's extra keys and the legacy spec URL
+        let data = pointer(&[
+            "version https://hawser.github.com/spec/v1",
+            &oid,
+            "size 12345",
+            "x-hg-copy foo",
+            "x-is-binary 0",
+        ]);
+        assert_eq!(LfsPointer::parse(&data), expected);
+    }
+
+    #[test]
+    fn malformed() {
+        let oid = format!("oid sha256:{}", OID);
+        let version = "version https://git-lfs.github.com/spec/v1";
+        let bad = vec![
+            // missing size
+            pointer(&[version, &oid]),
+            // unsorted
+            pointer(&[version, "size 12345", &oid]),
+            // duplicate key
+            pointer(&[version, &oid, &oid, "size 12345"]),
+            // bad size
+            pointer(&[version, &oid, "size +12345"]),
+            pointer(&[version, &oid, "size 12k"]),
+            // bad oid
+            pointer(&[version, "oid sha256:1234", "size 12345"]),
+            pointer(&[version, &format!("oid md5:{}", OID), "size 12345"]),
+            pointer(&[version, &oid.to_uppercase(), "size 12345"]),
+            // unknown version
+            pointer(&["version https://example.com/spec/v2", &oid, "size 12345"]),
+            // version not first
+            pointer(&[&oid, "size 12345", version]),
+            // no trailing newline
+            pointer(&[version, &oid, "size 12345"])[..].split_last().unwrap().1.to_vec(),
+            // CRLF line endings
+            format!("{}\r\n{}\r\nsize 12345\r\n", version, oid).into_bytes(),
+        ];
+
+        for data in bad {
+            assert_eq!(LfsPointer::parse(&data), None, "{:?}", String::from_utf8_lossy(&data));
+        }
+    }
+
+    #[test]
+    fn ordinary_text() {
+        let texts: &[&[u8]] = &[
+            b"",
+            b"version 1.2.3\n",
+            b"version https://git-lfs.github.com/spec/v1\nsome notes about LFS\n",
+            b"hello world\n",
+            b"\xff\xfe binary",
+        ];
+
+        for text in texts {
+            assert_eq!(LfsPointer::parse(text), None);
+        }
+
+        let mut big = pointer(&[
+            "version https://git-lfs.github.com/spec/v1",
+            &format!("oid sha256:{}", OID),
+            "size 12345",
+        ]);
+        big.extend(vec![b'\n'; MAX_POINTER_SIZE]);
+        assert_eq!(LfsPointer::parse(&big), None);
+    }
+}
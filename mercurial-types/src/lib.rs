@@ -7,6 +7,7 @@
 #![deny(warnings)]
 
 extern crate ascii;
+extern crate bytes;
 extern crate itertools;
 #[macro_use]
 extern crate lazy_static;
@@ -23,6 +24,7 @@ extern crate error_chain;
 #[cfg_attr(test, macro_use)]
 extern crate quickcheck;
 
+extern crate asyncmemo;
 extern crate bookmarks;
 
 #[macro_use]
@@ -33,29 +35,52 @@ extern crate heapsize;
 extern crate serde_derive;
 extern crate serde;
 
+#[cfg(test)]
+extern crate bincode;
+
+pub mod ancestors;
 pub mod bdiff;
+pub mod cachingrepo;
+pub mod cancellablerepo;
 pub mod delta;
 pub mod errors;
+pub mod export;
 pub mod hash;
+pub mod hashindex;
+pub mod lfs;
 pub mod nodehash;
 pub mod path;
 pub mod utils;
+pub mod verify;
 pub mod repo;
+pub mod timeoutrepo;
+pub mod tracingrepo;
 pub mod manifest;
 pub mod blob;
 pub mod blobnode;
+pub mod blockingrepo;
 pub mod changeset;
 mod node;
 
+#[cfg(test)]
+mod test;
+
 pub use blob::{Blob, BlobHash};
 pub use blobnode::{BlobNode, Parents};
+pub use blockingrepo::BlockingRepo;
+pub use cachingrepo::CachingRepo;
+pub use cancellablerepo::CancellableRepo;
 pub use changeset::{Changeset, Time};
 pub use delta::Delta;
-pub use manifest::{Entry, Manifest, Type};
+pub use hashindex::ChangesetHashIndex;
+pub use manifest::{Entry, Manifest, ManifestDiffEntry, ManifestStats, Type};
 pub use node::Node;
 pub use nodehash::{NodeHash, NULL_HASH};
 pub use path::Path;
-pub use repo::{BoxRepo, Repo};
+pub use repo::{BoxRepo, HashResolution, Repo};
+pub use timeoutrepo::{TimeoutRepo, TimeoutRepoError};
+pub use tracingrepo::{TraceEvent, TracingRepo};
 pub use utils::percent_encode;
+pub use verify::VerifyEvent;
 
 pub use errors::{Error, ErrorKind};
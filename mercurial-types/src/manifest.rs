@@ -4,14 +4,17 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2 or any later version.
 
+use std::cmp::Ordering;
 use std::fmt::{self, Display};
 use std::marker::PhantomData;
 
-use futures::future::{BoxFuture, Future};
-use futures::stream::{BoxStream, Stream};
+use futures::future::{self, BoxFuture, Future, Loop};
+use futures::stream::{self, BoxStream, Stream};
 
 use blob::Blob;
 use blobnode::Parents;
+use errors::{Error, ErrorKind};
+use hash::Sha1;
 use nodehash::NodeHash;
 use path::Path;
 
@@ -25,6 +28,22 @@ pub trait Manifest: Send + 'static {
     ) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Self::Error>;
     fn list(&self) -> BoxStream<Box<Entry<Error = Self::Error>>, Self::Error>;
 
+    /// Return the entry in this manifest whose path is exactly `name`, without descending into
+    /// subtrees. The default scans `list` until it finds the entry, so manifests which can find
+    /// it directly, such as ones with sorted entries, should override this.
+    fn lookup_name(
+        &self,
+        name: &[u8],
+    ) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Self::Error> {
+        let name = name.to_vec();
+        self.list()
+            .filter(move |entry| entry.get_path().to_vec() == name)
+            .into_future()
+            .map(|(entry, _)| entry)
+            .map_err(|(err, _)| err)
+            .boxed()
+    }
+
     fn boxed(self) -> Box<Manifest<Error = Self::Error> + Sync>
     where
         Self: Sync + Sized,
@@ -104,6 +123,19 @@ where
             .map_err(cvterr)
             .boxed()
     }
+
+    fn lookup_name(
+        &self,
+        name: &[u8],
+    ) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Self::Error> {
+        let cvterr = self.cvterr;
+
+        self.manifest
+            .lookup_name(name)
+            .map(move |oe| oe.map(|e| BoxEntry::new_with_cvterr(e, cvterr)))
+            .map_err(cvterr)
+            .boxed()
+    }
 }
 
 impl<E: Send + 'static> Manifest for Box<Manifest<Error = E> + Sync> {
@@ -119,6 +151,277 @@ impl<E: Send + 'static> Manifest for Box<Manifest<Error = E> + Sync> {
     fn list(&self) -> BoxStream<Box<Entry<Error = Self::Error>>, Self::Error> {
         (**self).list()
     }
+
+    fn lookup_name(
+        &self,
+        name: &[u8],
+    ) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Self::Error> {
+        (**self).lookup_name(name)
+    }
+}
+
+/// Look up `path` starting from `manifest`, descending into subtree manifests one component at
+/// a time.
+///
+/// At each level the first component is looked up. If it's a tree we descend into it; if it's
+/// anything else and there are still components left then the path doesn't exist. If the
+/// component isn't present at all, the rest of the path is looked up in one go, which is how
+/// flat manifests (keyed by full path) are handled.
+pub fn lookup_path<E>(
+    manifest: Box<Manifest<Error = E> + Sync>,
+    path: &Path,
+) -> BoxFuture<Option<Box<Entry<Error = E>>>, E>
+where
+    E: Send + 'static,
+{
+    future::loop_fn((manifest, path.clone()), |(manifest, path)| {
+        let (first, rest) = match path.split_first() {
+            Some(split) => split,
+            None => return future::ok(Loop::Break(None)).boxed(),
+        };
+
+        manifest
+            .lookup(&first)
+            .and_then(move |entry| match entry {
+                Some(entry) => {
+                    if rest.is_empty() {
+                        future::ok(Loop::Break(Some(entry))).boxed()
+                    } else if entry.get_type() == Type::Tree {
+                        entry
+                            .get_content()
+                            .map(move |content| match content {
+                                Content::Tree(subtree) => Loop::Continue((subtree, rest)),
+                                _ => Loop::Break(None),
+                            })
+                            .boxed()
+                    } else {
+                        future::ok(Loop::Break(None)).boxed()
+                    }
+                }
+                None if rest.is_empty() => future::ok(Loop::Break(None)).boxed(),
+                None => manifest.lookup(&path).map(Loop::Break).boxed(),
+            })
+            .boxed()
+    }).boxed()
+}
+
+/// Maximum depth of nested trees `walk` will descend into. Real trees are nowhere near this
+/// deep, so reaching it means a manifest (directly or indirectly) contains itself.
+pub const MAX_WALK_DEPTH: usize = 1024;
+
+/// Recursively list every non-tree entry under `manifest`, breadth-first, along with its full
+/// path from the root of `manifest`.
+///
+/// Each level of the tree is listed concurrently, and the leaves are produced once the whole
+/// walk is complete. If trees are nested more than `MAX_WALK_DEPTH` deep the walk fails with
+/// `ErrorKind::ManifestTooDeep`.
+pub fn walk<E>(
+    manifest: Box<Manifest<Error = E> + Sync>,
+) -> BoxStream<(Path, Box<Entry<Error = E>>), E>
+where
+    E: From<Error> + Send + 'static,
+{
+    walk_entries(manifest, false)
+}
+
+/// Counts of what's under a manifest; see `stats`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ManifestStats {
+    /// Number of files, executables and symlinks.
+    pub file_count: usize,
+    /// Number of trees, not counting the root.
+    pub dir_count: usize,
+    /// Total size of the files, executables and symlinks. Entries whose size isn't known count
+    /// as 0.
+    pub total_size: u64,
+}
+
+/// Count the entries under `manifest` by walking it like `walk`, and add up the sizes of the
+/// non-tree entries. The walk is bounded by `MAX_WALK_DEPTH` in the same way.
+pub fn stats<E>(manifest: Box<Manifest<Error = E> + Sync>) -> BoxFuture<ManifestStats, E>
+where
+    E: From<Error> + Send + 'static,
+{
+    walk_entries(manifest, true)
+        .and_then(|(_, entry)| -> BoxFuture<Option<Option<usize>>, E> {
+            match entry.get_type() {
+                // Trees have no size, so don't bother asking for one.
+                Type::Tree => future::ok(None).boxed(),
+                _ => entry.get_size().map(Some).boxed(),
+            }
+        })
+        .collect()
+        .map(|sizes| {
+            let mut stats = ManifestStats::default();
+            for size in sizes {
+                match size {
+                    None => stats.dir_count += 1,
+                    Some(size) => {
+                        stats.file_count += 1;
+                        stats.total_size += size.unwrap_or(0) as u64;
+                    }
+                }
+            }
+            stats
+        })
+        .boxed()
+}
+
+/// Walk `manifest` as described for `walk`, also producing the tree entries if `trees` is set.
+fn walk_entries<E>(
+    manifest: Box<Manifest<Error = E> + Sync>,
+    trees: bool,
+) -> BoxStream<(Path, Box<Entry<Error = E>>), E>
+where
+    E: From<Error> + Send + 'static,
+{
+    let root = vec![(Path::new("").expect("empty path is valid"), manifest)];
+
+    future::loop_fn((root, 0, Vec::new()), move |(frontier, depth, mut leaves)| {
+        if frontier.is_empty() {
+            return future::ok(Loop::Break(leaves)).boxed();
+        }
+        if depth > MAX_WALK_DEPTH {
+            let err = Error::from(ErrorKind::ManifestTooDeep(MAX_WALK_DEPTH));
+            return future::err(err.into()).boxed();
+        }
+
+        let listings: Vec<_> = frontier
+            .into_iter()
+            .map(|(prefix, manifest)| manifest.list().collect().map(move |e| (prefix, e)))
+            .collect();
+
+        future::join_all(listings)
+            .and_then(move |listings| {
+                let mut subtrees = Vec::new();
+
+                for (prefix, entries) in listings {
+                    for entry in entries {
+                        let path = prefix.append(entry.get_path());
+                        if entry.get_type() != Type::Tree {
+                            leaves.push((path, entry));
+                            continue;
+                        }
+
+                        let subtree = entry.get_content().and_then({
+                            let path = path.clone();
+                            move |content| match content {
+                                Content::Tree(subtree) => Ok((path, subtree)),
+                                _ => {
+                                    let msg = format!("tree entry {} has non-tree content", path);
+                                    Err(Error::from(msg).into())
+                                }
+                            }
+                        });
+                        subtrees.push(subtree);
+                        if trees {
+                            leaves.push((path, entry));
+                        }
+                    }
+                }
+
+                future::join_all(subtrees)
+                    .map(move |next| Loop::Continue((next, depth + 1, leaves)))
+            })
+            .boxed()
+    }).map(|leaves| stream::iter(leaves.into_iter().map(Ok)))
+        .flatten_stream()
+        .boxed()
+}
+
+/// One difference between two manifests, as produced by `diff`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ManifestDiffEntry {
+    /// Present only in the other manifest, with the given nodeid.
+    Added(Path, NodeHash),
+    /// Present only in the base manifest.
+    Removed(Path),
+    /// Present in both with a different nodeid or type, with the base then other nodeids. The
+    /// flag is set if the type changed, such as a file being replaced by a directory.
+    Modified(Path, NodeHash, NodeHash, bool),
+}
+
+impl ManifestDiffEntry {
+    pub fn path(&self) -> &Path {
+        match *self {
+            ManifestDiffEntry::Added(ref path, _) |
+            ManifestDiffEntry::Removed(ref path) |
+            ManifestDiffEntry::Modified(ref path, _, _, _) => path,
+        }
+    }
+}
+
+/// Compare the entries listed by `base` and `other`, producing the differences in path order.
+///
+/// Only the entries each manifest lists directly are compared: subtrees aren't descended into,
+/// so a changed directory in a tree manifest is reported as a single `Modified` entry.
+pub fn diff<E>(
+    base: Box<Manifest<Error = E> + Sync>,
+    other: Box<Manifest<Error = E> + Sync>,
+) -> BoxStream<ManifestDiffEntry, E>
+where
+    E: Send + 'static,
+{
+    fn sorted_entries<E>(
+        manifest: &Box<Manifest<Error = E> + Sync>,
+    ) -> BoxFuture<Vec<(Path, NodeHash, Type)>, E>
+    where
+        E: Send + 'static,
+    {
+        manifest
+            .list()
+            .map(|entry| (entry.get_path().clone(), *entry.get_hash(), entry.get_type()))
+            .collect()
+            .map(|mut entries| {
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                entries
+            })
+            .boxed()
+    }
+
+    sorted_entries(&base)
+        .join(sorted_entries(&other))
+        .map(|(base, other)| {
+            let mut base = base.into_iter().peekable();
+            let mut other = other.into_iter().peekable();
+            let mut changes = Vec::new();
+
+            loop {
+                let order = match (base.peek(), other.peek()) {
+                    (Some(b), Some(o)) => b.0.cmp(&o.0),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => break,
+                };
+
+                match order {
+                    Ordering::Less => {
+                        let (path, _, _) = base.next().unwrap();
+                        changes.push(ManifestDiffEntry::Removed(path));
+                    }
+                    Ordering::Greater => {
+                        let (path, hash, _) = other.next().unwrap();
+                        changes.push(ManifestDiffEntry::Added(path, hash));
+                    }
+                    Ordering::Equal => {
+                        let (path, base_hash, base_ty) = base.next().unwrap();
+                        let (_, other_hash, other_ty) = other.next().unwrap();
+                        if base_hash != other_hash || base_ty != other_ty {
+                            changes.push(ManifestDiffEntry::Modified(
+                                path,
+                                base_hash,
+                                other_hash,
+                                base_ty != other_ty,
+                            ));
+                        }
+                    }
+                }
+            }
+
+            stream::iter(changes.into_iter().map(Ok))
+        })
+        .flatten_stream()
+        .boxed()
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize)]
@@ -134,6 +437,8 @@ pub enum Content<E> {
     Executable(Blob<Vec<u8>>), // TODO stream
     Symlink(Path),
     Tree(Box<Manifest<Error = E> + Sync>),
+    /// A file whose content is stored in LFS; see `lfs::LfsPointer`.
+    LfsPointer { oid: String, size: u64 },
 }
 
 impl<E> Content<E>
@@ -149,10 +454,63 @@ where
             Content::File(b) => Content::File(b),
             Content::Executable(b) => Content::Executable(b),
             Content::Symlink(p) => Content::Symlink(p),
+            Content::LfsPointer { oid, size } => Content::LfsPointer { oid, size },
         }
     }
 }
 
+impl<E> Content<E> {
+    /// Return the `Sha1` of this content's bytes, as used for the `sha1:` keys of stored file
+    /// contents, so that identical content can be found without storing it again. Files and
+    /// executables with the same bytes have the same hash, as they're stored identically, and a
+    /// symlink hashes its target path.
+    ///
+    /// Trees, LFS pointers and blobs only known by nodeid have no bytes to hash, so give `None`.
+    pub fn content_sha1(&self) -> Option<Sha1> {
+        match self {
+            &Content::File(ref blob) | &Content::Executable(ref blob) => blob.sha1(),
+            &Content::Symlink(ref path) => Some(Sha1::from(path.to_vec().as_slice())),
+            &Content::Tree(..) | &Content::LfsPointer { .. } => None,
+        }
+    }
+}
+
+/// Contents are equal if they're the same kind of content with the same bytes, however the
+/// blobs holding those bytes are represented. A `Tree` doesn't know its manifest id, so can't be
+/// compared and is never equal to anything, not even itself.
+impl<E> PartialEq for Content<E> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (&Content::File(ref a), &Content::File(ref b))
+            | (&Content::Executable(ref a), &Content::Executable(ref b)) => blob_eq(a, b),
+            (&Content::Symlink(ref a), &Content::Symlink(ref b)) => a == b,
+            (
+                &Content::LfsPointer {
+                    oid: ref a_oid,
+                    size: a_size,
+                },
+                &Content::LfsPointer {
+                    oid: ref b_oid,
+                    size: b_size,
+                },
+            ) => a_oid == b_oid && a_size == b_size,
+            _ => false,
+        }
+    }
+}
+
+/// Compare blobs by their bytes if both have them, otherwise by hash.
+fn blob_eq(a: &Blob<Vec<u8>>, b: &Blob<Vec<u8>>) -> bool {
+    match (a.as_slice(), b.as_slice()) {
+        (Some(a), Some(b)) => a == b,
+        _ => match (a.sha1(), b.sha1()) {
+            (Some(a), Some(b)) => a == b,
+            // At least one is only known by nodeid.
+            _ => a == b,
+        },
+    }
+}
+
 pub trait Entry: Send + 'static {
     type Error: Send + 'static;
 
@@ -289,3 +647,67 @@ impl Display for Type {
         write!(fmt, "{}", s)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use blob::BlobHash;
+
+    type TestContent = Content<Error>;
+
+    #[test]
+    fn content_sha1() {
+        let data = b"some file\n";
+        let file = TestContent::File(Blob::from(&data[..]));
+        let sha1 = Sha1::from(&data[..]);
+
+        assert_eq!(file.content_sha1(), Some(sha1));
+        assert_eq!(TestContent::File(Blob::from(data.to_vec())).content_sha1(), Some(sha1));
+        // Mercurial stores executables just like files.
+        let exec = TestContent::Executable(Blob::from(&data[..]));
+        assert_eq!(exec.content_sha1(), Some(sha1));
+        // A blob whose hash is known needn't have its data.
+        let blob = Blob::Extern(BlobHash::from(&data[..]));
+        assert_eq!(TestContent::File(blob).content_sha1(), Some(sha1));
+
+        let target = Path::new("dir/target").unwrap();
+        assert_eq!(
+            TestContent::Symlink(target).content_sha1(),
+            Some(Sha1::from(&b"dir/target"[..]))
+        );
+
+        let lfs = TestContent::LfsPointer {
+            oid: "abcd".into(),
+            size: 10,
+        };
+        assert_eq!(lfs.content_sha1(), None);
+        let nodeid = NodeHash::from_bytes(&[1; 20]).unwrap();
+        assert_eq!(TestContent::File(Blob::NodeId(nodeid)).content_sha1(), None);
+        assert!(TestContent::File(Blob::from(&b"other"[..])).content_sha1() != Some(sha1));
+    }
+
+    #[test]
+    fn content_eq() {
+        let file = |data: &[u8]| TestContent::File(Blob::from(data));
+        assert!(file(b"abc") == file(b"abc"));
+        assert!(file(b"abc") != file(b"abd"));
+        // However the blob is represented.
+        assert!(file(b"abc") == TestContent::File(Blob::from(&b"abc"[..]).clean()));
+        assert!(file(b"abc") == TestContent::File(Blob::Extern(BlobHash::from(&b"abc"[..]))));
+
+        // The kind of content matters, even where the hash is the same.
+        let exec = TestContent::Executable(Blob::from(&b"abc"[..]));
+        assert_eq!(exec.content_sha1(), file(b"abc").content_sha1());
+        assert!(exec != file(b"abc"));
+        assert!(exec == TestContent::Executable(Blob::from(&b"abc"[..])));
+        let symlink = |target: &str| TestContent::Symlink(Path::new(target).unwrap());
+        assert!(symlink("abc") == symlink("abc"));
+        assert!(symlink("abc") != file(b"abc"));
+
+        let nodeid = NodeHash::from_bytes(&[1; 20]).unwrap();
+        let by_nodeid = TestContent::File(Blob::NodeId(nodeid));
+        assert!(by_nodeid == TestContent::File(Blob::NodeId(nodeid)));
+        assert!(by_nodeid != file(b"abc"));
+    }
+}
@@ -105,6 +105,113 @@ impl Path {
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    /// Return the path formed by appending all of `other`'s components to this one.
+    pub fn append(&self, other: &Path) -> Path {
+        if self.is_empty() {
+            return other.clone();
+        }
+        if other.is_empty() {
+            return self.clone();
+        }
+
+        let mut elements = self.elements.clone();
+        elements.extend(other.elements.iter().cloned());
+        Path {
+            elements: elements,
+            total_len: self.total_len + 1 + other.total_len,
+        }
+    }
+
+    /// Return the path of the entry `component` in the directory this path names. Joining onto
+    /// the empty (root) path gives just the component. It is an error for `component` to be
+    /// empty or contain `/` or `\0`; use `append` to add several components at once.
+    pub fn join(&self, component: &[u8]) -> Result<Path> {
+        if component.is_empty() {
+            bail!(ErrorKind::InvalidPath("path components cannot be empty".into()))
+        }
+        if component.contains(&b'/') {
+            bail!(ErrorKind::InvalidPath("path components cannot contain '/'".into()))
+        }
+        Self::verify(component)?;
+
+        let mut elements = self.elements.clone();
+        elements.push(PathElement(component.to_vec()));
+        let total_len = if self.is_empty() {
+            component.len()
+        } else {
+            self.total_len + 1 + component.len()
+        };
+        Ok(Path {
+            elements,
+            total_len,
+        })
+    }
+
+    /// Return the path of the directory containing this one, which is the empty path for a
+    /// single component. Returns `None` for the empty path.
+    pub fn parent(&self) -> Option<Path> {
+        self.elements.split_last().map(|(last, rest)| {
+            let total_len = if rest.is_empty() {
+                0
+            } else {
+                self.total_len - last.0.len() - 1
+            };
+            Path {
+                elements: rest.to_vec(),
+                total_len,
+            }
+        })
+    }
+
+    /// Return the equivalent path with empty and `.` components removed, and `..` components
+    /// resolved against the component before them, so that `./a//b/../c` becomes `a/c`. It is
+    /// an error for a `..` to go above the root of the repo.
+    pub fn normalized(&self) -> Result<Path> {
+        let mut elements: Vec<PathElement> = Vec::with_capacity(self.elements.len());
+        for elem in &self.elements {
+            match elem.0.as_slice() {
+                b"" | b"." => {}
+                b".." => if elements.pop().is_none() {
+                    let msg = format!("'{}' is outside the repo root", self);
+                    bail!(ErrorKind::InvalidPath(msg));
+                },
+                _ => elements.push(elem.clone()),
+            }
+        }
+
+        let total_len = if elements.is_empty() {
+            0
+        } else {
+            elements.iter().map(|elem| elem.0.len()).sum::<usize>() + elements.len() - 1
+        };
+        Ok(Path {
+            elements,
+            total_len,
+        })
+    }
+
+    /// Split off the first component of the path, returning it along with the (possibly empty)
+    /// remainder. Returns `None` for the empty path.
+    pub fn split_first(&self) -> Option<(Path, Path)> {
+        self.elements.split_first().map(|(first, rest)| {
+            let first_len = first.0.len();
+            let rest_len = if rest.is_empty() {
+                0
+            } else {
+                self.total_len - first_len - 1
+            };
+            let first = Path {
+                elements: vec![first.clone()],
+                total_len: first_len,
+            };
+            let rest = Path {
+                elements: rest.to_vec(),
+                total_len: rest_len,
+            };
+            (first, rest)
+        })
+    }
 }
 
 lazy_static! {
@@ -293,6 +400,22 @@ mod test {
             let path = Path::new(joined).unwrap();
             elements == path.elements && path.len() == expected_len
         }
+
+        fn split_first_roundtrip(p: Path) -> bool {
+            match p.split_first() {
+                None => p.is_empty(),
+                Some((first, rest)) => {
+                    let mut joined = first.to_vec();
+                    if !rest.is_empty() {
+                        joined.push(b'/');
+                        joined.extend(rest.to_vec());
+                    }
+                    first.elements.len() == 1 && first.len() == first.to_vec().len()
+                        && rest.len() == rest.to_vec().len()
+                        && joined == p.to_vec()
+                }
+            }
+        }
     }
 
     #[test]
@@ -302,6 +425,69 @@ mod test {
         assert_eq!(path.unwrap().len(), 7);
     }
 
+    #[test]
+    fn split_first() {
+        let path = Path::new(b"dir/sub/file").unwrap();
+        let (first, rest) = path.split_first().unwrap();
+        assert_eq!(first, Path::new(b"dir").unwrap());
+        assert_eq!(rest, Path::new(b"sub/file").unwrap());
+
+        let (first, rest) = Path::new(b"file").unwrap().split_first().unwrap();
+        assert_eq!(first, Path::new(b"file").unwrap());
+        assert!(rest.is_empty());
+
+        assert!(Path::new(b"").unwrap().split_first().is_none());
+    }
+
+    #[test]
+    fn join() {
+        let root = Path::new(b"").unwrap();
+        let file = root.join(b"file").unwrap();
+        assert_eq!(file, Path::new(b"file").unwrap());
+        assert_eq!(file.len(), 4);
+
+        let nested = Path::new(b"dir/sub").unwrap().join(b"file").unwrap();
+        assert_eq!(nested, Path::new(b"dir/sub/file").unwrap());
+        assert_eq!(nested.len(), 12);
+
+        assert!(root.join(b"dir/file").is_err());
+        assert!(nested.join(b"a/").is_err());
+        assert!(nested.join(b"a\0b").is_err());
+        assert!(nested.join(b"").is_err());
+    }
+
+    #[test]
+    fn parent() {
+        let path = Path::new(b"dir/sub/file").unwrap();
+        let parent = path.parent().unwrap();
+        assert_eq!(parent, Path::new(b"dir/sub").unwrap());
+        assert_eq!(parent.len(), 7);
+
+        let top = Path::new(b"dir").unwrap().parent().unwrap();
+        assert!(top.is_empty());
+        assert!(top.parent().is_none());
+    }
+
+    #[test]
+    fn normalized() {
+        let normalized = |p: &str| Path::new(p).unwrap().normalized().map(|p| p.to_vec());
+        let expected = b"a/b".to_vec();
+
+        assert_eq!(normalized("a/b").unwrap(), expected);
+        assert_eq!(normalized("./a/b").unwrap(), expected);
+        assert_eq!(normalized("a//b").unwrap(), expected);
+        assert_eq!(normalized("a/./b/").unwrap(), expected);
+        assert_eq!(normalized("a/c/../b").unwrap(), expected);
+        let path = Path::new("a//b").unwrap().normalized().unwrap();
+        assert_eq!(path, Path::new("a/b").unwrap());
+        assert_eq!(path.len(), 3);
+
+        assert!(normalized("a/..").unwrap().is_empty());
+        assert!(normalized("").unwrap().is_empty());
+        assert!(normalized("../etc").is_err());
+        assert!(normalized("a/../../etc").is_err());
+    }
+
     #[test]
     fn bad_path() {
         assert!(Path::new(b"\0").is_err());
@@ -4,16 +4,22 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2 or any later version.
 
+use std::collections::HashSet;
 use std::marker::PhantomData;
 use std::sync::Arc;
 
-use futures::future::{BoxFuture, Future};
-use futures::stream::{BoxStream, Stream};
+use bytes::Bytes;
+use futures::future::{self, BoxFuture, Future};
+use futures::stream::{self, BoxStream, Stream};
 
+use blobnode::Parents;
 use bookmarks::{self, Bookmarks, Version};
 use changeset::Changeset;
-use manifest::{BoxManifest, Manifest};
+use errors::{Error, ErrorKind};
+use manifest::{self, BoxEntry, BoxManifest, Entry, Manifest, ManifestDiffEntry, ManifestStats};
 use nodehash::NodeHash;
+use path::Path;
+use verify::VerifyEvent;
 
 pub type BoxedBookmarks<E> = Box<
     Bookmarks<
@@ -37,15 +43,269 @@ pub trait Repo: 'static {
     /// will be O(changesets) in size. Probably OK up to 10-100M changesets.
     fn get_changesets(&self) -> BoxStream<NodeHash, Self::Error>;
 
+    /// Return a window of the changeset ids produced by `get_changesets`, skipping the first
+    /// `skip` and producing at most `limit`.
+    ///
+    /// The default implementation walks `get_changesets` from the start; implementations with
+    /// an index should override it to seek directly to the window.
+    fn get_changesets_range(&self, skip: usize, limit: usize) -> BoxStream<NodeHash, Self::Error> {
+        self.get_changesets()
+            .skip(skip as u64)
+            .take(limit as u64)
+            .boxed()
+    }
+
+    /// Return the changeset ids produced by `get_changesets`, sorted in ascending order, for
+    /// consumers which need a deterministic order.
+    ///
+    /// Nothing is produced until every changeset id has been read and sorted, so this needs
+    /// O(changesets) memory; avoid it on very large repos.
+    fn get_changesets_sorted(&self) -> BoxStream<NodeHash, Self::Error> {
+        self.get_changesets()
+            .collect()
+            .map(|mut nodeids| {
+                nodeids.sort();
+                stream::iter(nodeids.into_iter().map(Ok))
+            })
+            .flatten_stream()
+            .boxed()
+    }
+
+    /// Return the changesets within `max_depth` parent hops of any head, breadth-first from the
+    /// heads and each exactly once, so that consumers of a large repo can get its recent history
+    /// without the memory cost of walking all of it. A `max_depth` of 0 gives just the heads.
+    /// Implementations can use `ancestors::recent`.
+    fn get_changesets_bounded(&self, max_depth: usize) -> BoxStream<NodeHash, Self::Error>;
+
+    /// Return the number of changesets produced by `get_changesets`.
+    ///
+    /// The default implementation counts them by walking `get_changesets`; implementations
+    /// with an index should override it to answer directly.
+    fn get_changeset_count(&self) -> BoxFuture<u64, Self::Error> {
+        self.get_changesets()
+            .fold(0, |count, _| Ok::<_, Self::Error>(count + 1))
+            .boxed()
+    }
+
+    /// Return the changeset ids produced by `get_changesets` for which `pred` is true, for
+    /// views such as "changesets by one author". Each changeset is fetched with
+    /// `get_changeset_by_nodeid` and `pred` is applied to it; if one fails to load, the stream
+    /// fails with that error and `pred` isn't called for it.
+    fn get_changesets_filtered<P>(&self, pred: P) -> BoxStream<NodeHash, Self::Error>
+    where
+        Self: Clone + Send + Sized,
+        P: Fn(&Changeset) -> bool + Send + 'static,
+    {
+        let repo = self.clone();
+        self.get_changesets()
+            .and_then(move |nodeid| {
+                repo.get_changeset_by_nodeid(&nodeid)
+                    .map(move |cs| (nodeid, cs))
+            })
+            .filter(move |&(_, ref cs)| pred(&**cs))
+            .map(|(nodeid, _)| nodeid)
+            .boxed()
+    }
+
     fn get_heads(&self) -> BoxStream<NodeHash, Self::Error>;
     fn get_bookmarks(&self) -> Result<BoxedBookmarks<Self::Error>, Self::Error>;
+
+    /// Look up a single bookmark by name. A bookmark which doesn't exist resolves to `None`.
+    fn get_bookmark(&self, name: &[u8]) -> BoxFuture<Option<(NodeHash, Version)>, Self::Error> {
+        match self.get_bookmarks() {
+            Ok(bookmarks) => bookmarks.get(&name),
+            Err(err) => future::err(err).boxed(),
+        }
+    }
+
+    /// Resolve each of the named bookmarks, producing the distinct changesets they point to in
+    /// the order the names are given. Names which don't resolve are skipped.
+    fn get_heads_for_bookmarks(&self, names: &[Vec<u8>]) -> BoxStream<NodeHash, Self::Error> {
+        let lookups: Vec<_> = names.iter().map(|name| self.get_bookmark(name)).collect();
+
+        future::join_all(lookups)
+            .map(|resolved| {
+                let mut seen = HashSet::new();
+                let heads: Vec<_> = resolved
+                    .into_iter()
+                    .filter_map(|bookmark| bookmark.map(|(nodeid, _)| nodeid))
+                    .filter(|nodeid| seen.insert(*nodeid))
+                    .map(Ok)
+                    .collect();
+                stream::iter(heads)
+            })
+            .flatten_stream()
+            .boxed()
+    }
+
+    /// Hint that the changesets `nodeids` are about to be fetched, for example before they are
+    /// streamed to a client, so that implementations with a cache can warm it.
+    ///
+    /// This is best-effort: failing to prefetch something, or it not existing, is not an error.
+    /// The default implementation does nothing.
+    fn prefetch(&self, _nodeids: &[NodeHash]) -> BoxFuture<(), Self::Error> {
+        future::ok(()).boxed()
+    }
+
     fn changeset_exists(&self, nodeid: &NodeHash) -> BoxFuture<bool, Self::Error>;
+
+    /// Check whether each of `nodeids` exists, returning the results in the same order as the
+    /// input.
+    ///
+    /// The default implementation issues one `changeset_exists` per node; implementations which
+    /// can do a batched lookup should override it.
+    fn changesets_exist(
+        &self,
+        nodeids: &[NodeHash],
+    ) -> BoxFuture<Vec<(NodeHash, bool)>, Self::Error> {
+        let checks: Vec<_> = nodeids
+            .iter()
+            .map(|nodeid| {
+                let nodeid = *nodeid;
+                self.changeset_exists(&nodeid)
+                    .map(move |exists| (nodeid, exists))
+            })
+            .collect();
+        future::join_all(checks).boxed()
+    }
+
     fn get_changeset_by_nodeid(&self, nodeid: &NodeHash) -> BoxFuture<Box<Changeset>, Self::Error>;
+
+    /// Return the serialized changeset `nodeid` exactly as it's stored, without parsing it, for
+    /// relaying to clients. Together with the parents, these are the bytes the nodeid is the
+    /// hash of. A missing changeset fails as for `get_changeset_by_nodeid`.
+    fn get_changeset_raw(&self, nodeid: &NodeHash) -> BoxFuture<Bytes, Self::Error>;
+
+    /// Fetch each of `nodeids`, returning the changesets in the same order as the input. If
+    /// any of them is missing, the whole batch fails with the same error as
+    /// `get_changeset_by_nodeid` would give for it.
+    ///
+    /// The default implementation issues one `get_changeset_by_nodeid` per node;
+    /// implementations which can batch the underlying reads should override it.
+    fn get_changesets_by_nodeid(
+        &self,
+        nodeids: &[NodeHash],
+    ) -> BoxFuture<Vec<Box<Changeset>>, Self::Error> {
+        let fetches: Vec<_> = nodeids
+            .iter()
+            .map(|nodeid| self.get_changeset_by_nodeid(nodeid))
+            .collect();
+        future::join_all(fetches).boxed()
+    }
+
+    /// Resolve a hex prefix of a changeset id, as typed by a user, to the full id. It is an
+    /// error if the prefix isn't valid hex or is shorter than `MIN_HASH_PREFIX_LEN`.
+    /// Implementations without an index can use `repo::resolve_hash_prefix`.
+    fn resolve_hash_prefix(&self, prefix: &str) -> BoxFuture<HashResolution, Self::Error>;
+
+    /// Return the parents of a changeset, failing in the same way as `get_changeset_by_nodeid`
+    /// if it doesn't exist.
+    ///
+    /// The default implementation fetches the whole changeset; implementations which store
+    /// parents separately should override it with a cheaper lookup.
+    fn get_changeset_parents(&self, nodeid: &NodeHash) -> BoxFuture<Parents, Self::Error> {
+        self.get_changeset_by_nodeid(nodeid)
+            .map(|cs| *cs.parents())
+            .boxed()
+    }
+
+    /// Return true if `ancestor` is an ancestor of (or the same as) `descendant`. It is an error
+    /// if `descendant` doesn't exist. Implementations can use `ancestors::is_ancestor`.
+    fn is_ancestor(
+        &self,
+        ancestor: &NodeHash,
+        descendant: &NodeHash,
+    ) -> BoxFuture<bool, Self::Error>;
+
+    /// Return the lowest common ancestor of `a` and `b`, or `None` if they have no common
+    /// history. It is an error if either doesn't exist. See `ancestors::common_ancestor` for
+    /// how ties between equally near candidates are broken, and for what the search costs.
+    fn common_ancestor(
+        &self,
+        a: &NodeHash,
+        b: &NodeHash,
+    ) -> BoxFuture<Option<NodeHash>, Self::Error>;
+
     fn get_manifest_by_nodeid(
         &self,
         nodeid: &NodeHash,
     ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error>;
 
+    /// Return the root manifest of changeset `cs`. A missing changeset is reported in the same
+    /// way as by `get_changeset_by_nodeid`, and a missing manifest as by
+    /// `get_manifest_by_nodeid`. Implementations can use `repo::get_root_manifest`.
+    fn get_root_manifest(
+        &self,
+        cs: &NodeHash,
+    ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error>;
+
+    /// Compare the manifests with ids `base` and `other` using `manifest::diff`. To diff two
+    /// changesets, pass their `manifestid`s.
+    fn diff_manifests(
+        &self,
+        base: &NodeHash,
+        other: &NodeHash,
+    ) -> BoxStream<ManifestDiffEntry, Self::Error> {
+        self.get_manifest_by_nodeid(base)
+            .join(self.get_manifest_by_nodeid(other))
+            .map(|(base, other)| manifest::diff(base, other))
+            .flatten_stream()
+            .boxed()
+    }
+
+    /// Look up `path` in the manifest of changeset `cs`. Returns `None` if the path doesn't
+    /// exist (including when one of its directories is actually a file), and an error if the
+    /// changeset doesn't. `path` is normalized first, so `./a//b` finds `a/b`, and a path which
+    /// goes above the root is an error. Implementations can use `repo::get_entry_by_path`.
+    fn get_entry_by_path(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+    ) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Self::Error>;
+
+    /// Return up to `limit` `(changeset, filenode)` pairs for the versions of the file at `path`
+    /// in changeset `cs`, newest first, each with the changeset which introduced it. Renames
+    /// aren't followed. Implementations can use `ancestors::file_history`.
+    fn get_file_history(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+        limit: usize,
+    ) -> BoxStream<(NodeHash, NodeHash), Self::Error>;
+
+    /// Recursively list every file, executable and symlink under the manifest with id `root`,
+    /// with their full paths. Implementations can use `repo::walk_manifest`.
+    fn walk_manifest(
+        &self,
+        root: &NodeHash,
+    ) -> BoxStream<(Path, Box<Entry<Error = Self::Error>>), Self::Error>;
+
+    /// Count the files and directories under the manifest with id `root`, and add up the sizes
+    /// of the files. Implementations can use `repo::manifest_stats`.
+    fn manifest_stats(&self, root: &NodeHash) -> BoxFuture<ManifestStats, Self::Error>;
+
+    /// Export the changesets reachable from `heads` but not from `common`, with their manifests
+    /// and the files they change, as a stream of chunks in the format described in `export`.
+    /// Implementations can use `export::export`.
+    fn export_bundle(
+        &self,
+        heads: &[NodeHash],
+        common: &[NodeHash],
+    ) -> BoxStream<Bytes, Self::Error>;
+
+    /// Check everything reachable from `heads`: the changesets, their root manifests and the
+    /// file nodes in them, producing a `VerifyEvent` for each. Missing or corrupt data is
+    /// reported as an event rather than ending the stream. Implementations can use
+    /// `verify::verify_reachable`.
+    fn verify_reachable(&self, heads: &[NodeHash]) -> BoxStream<VerifyEvent, Self::Error>;
+
+    /// Release any resources held by the repo, such as connection pools or open files. Once
+    /// the returned future resolves, no more methods will be called on the repo. The default
+    /// implementation does nothing.
+    fn shutdown(&self) -> BoxFuture<(), Self::Error> {
+        future::ok(()).boxed()
+    }
+
     fn boxed(self) -> Box<Repo<Error = Self::Error> + Sync>
     where
         Self: Sync + Sized,
@@ -54,6 +314,121 @@ pub trait Repo: 'static {
     }
 }
 
+/// Fetch changeset `cs`, then the manifest it refers to.
+pub fn get_root_manifest<R>(
+    repo: R,
+    cs: NodeHash,
+) -> BoxFuture<Box<Manifest<Error = R::Error> + Sync>, R::Error>
+where
+    R: Repo + Send,
+{
+    repo.get_changeset_by_nodeid(&cs)
+        .and_then(move |cs| repo.get_manifest_by_nodeid(cs.manifestid()))
+        .boxed()
+}
+
+/// Normalize `path` with `Path::normalized`, then resolve the root manifest of changeset `cs`
+/// and look up `path` in it with `manifest::lookup_path`.
+pub fn get_entry_by_path<R>(
+    repo: R,
+    cs: NodeHash,
+    path: Path,
+) -> BoxFuture<Option<Box<Entry<Error = R::Error>>>, R::Error>
+where
+    R: Repo + Send,
+    R::Error: From<Error>,
+{
+    let path = match path.normalized() {
+        Ok(path) => path,
+        Err(err) => return future::err(err.into()).boxed(),
+    };
+
+    get_root_manifest(repo, cs)
+        .and_then(move |manifest| manifest::lookup_path(manifest, &path))
+        .boxed()
+}
+
+/// Fetch manifest `root` and walk it with `manifest::walk`.
+pub fn walk_manifest<R>(
+    repo: &R,
+    root: &NodeHash,
+) -> BoxStream<(Path, Box<Entry<Error = R::Error>>), R::Error>
+where
+    R: Repo,
+    R::Error: From<Error>,
+{
+    repo.get_manifest_by_nodeid(root)
+        .map(manifest::walk)
+        .flatten_stream()
+        .boxed()
+}
+
+/// Fetch manifest `root` and count what's under it with `manifest::stats`.
+pub fn manifest_stats<R>(repo: &R, root: &NodeHash) -> BoxFuture<ManifestStats, R::Error>
+where
+    R: Repo,
+    R::Error: From<Error>,
+{
+    repo.get_manifest_by_nodeid(root)
+        .and_then(manifest::stats)
+        .boxed()
+}
+
+/// Shortest hash prefix `Repo::resolve_hash_prefix` will accept.
+pub const MIN_HASH_PREFIX_LEN: usize = 4;
+
+/// Most candidates `Repo::resolve_hash_prefix` will return for an ambiguous prefix.
+pub const MAX_AMBIGUOUS_CANDIDATES: usize = 10;
+
+/// Result of `Repo::resolve_hash_prefix`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum HashResolution {
+    Unique(NodeHash),
+    /// Some of the changesets matching the prefix, at most `MAX_AMBIGUOUS_CANDIDATES`.
+    Ambiguous(Vec<NodeHash>),
+    NotFound,
+}
+
+/// Check that `prefix` is acceptable to `Repo::resolve_hash_prefix`, returning it in lowercase
+/// for comparison with `NodeHash::to_hex`.
+pub fn check_hash_prefix(prefix: &str) -> Result<String, Error> {
+    let err = |msg: &str| Err(ErrorKind::InvalidHashPrefix(prefix.into(), msg.into()).into());
+    if prefix.len() < MIN_HASH_PREFIX_LEN {
+        return err("too short");
+    }
+    if prefix.len() > 40 {
+        return err("too long");
+    }
+    if !prefix.chars().all(|c| c.is_digit(16)) {
+        return err("not hex");
+    }
+    Ok(prefix.to_lowercase())
+}
+
+/// Resolve a hash prefix by scanning every changeset from `get_changesets`. Which candidates
+/// are returned for an ambiguous prefix depends on the order they are produced in.
+pub fn resolve_hash_prefix<R>(repo: &R, prefix: &str) -> BoxFuture<HashResolution, R::Error>
+where
+    R: Repo,
+    R::Error: From<Error>,
+{
+    let prefix = match check_hash_prefix(prefix) {
+        Ok(prefix) => prefix,
+        Err(err) => return future::err(err.into()).boxed(),
+    };
+
+    repo.get_changesets()
+        .filter(move |nodeid| nodeid.to_hex().as_str().starts_with(&prefix))
+        .take(MAX_AMBIGUOUS_CANDIDATES as u64)
+        .collect()
+        .map(|mut found| match found.len() {
+            0 => HashResolution::NotFound,
+            1 => HashResolution::Unique(found.remove(0)),
+            _ => HashResolution::Ambiguous(found),
+        })
+        .boxed()
+}
+
 pub struct BoxRepo<R, E>
 where
     R: Repo,
@@ -107,6 +482,28 @@ where
         self.repo.get_changesets().map_err(self.cvterr).boxed()
     }
 
+    fn get_changesets_range(&self, skip: usize, limit: usize) -> BoxStream<NodeHash, Self::Error> {
+        self.repo
+            .get_changesets_range(skip, limit)
+            .map_err(self.cvterr)
+            .boxed()
+    }
+
+    fn get_changesets_sorted(&self) -> BoxStream<NodeHash, Self::Error> {
+        self.repo.get_changesets_sorted().map_err(self.cvterr).boxed()
+    }
+
+    fn get_changesets_bounded(&self, max_depth: usize) -> BoxStream<NodeHash, Self::Error> {
+        self.repo
+            .get_changesets_bounded(max_depth)
+            .map_err(self.cvterr)
+            .boxed()
+    }
+
+    fn get_changeset_count(&self) -> BoxFuture<u64, Self::Error> {
+        self.repo.get_changeset_count().map_err(self.cvterr).boxed()
+    }
+
     fn get_heads(&self) -> BoxStream<NodeHash, Self::Error> {
         self.repo.get_heads().map_err(self.cvterr).boxed()
     }
@@ -117,12 +514,48 @@ where
         Ok(bookmarks::BoxedBookmarks::new_cvt(bookmarks, self.cvterr))
     }
 
+    fn get_bookmark(&self, name: &[u8]) -> BoxFuture<Option<(NodeHash, Version)>, Self::Error> {
+        self.repo.get_bookmark(name).map_err(self.cvterr).boxed()
+    }
+
+    fn get_heads_for_bookmarks(&self, names: &[Vec<u8>]) -> BoxStream<NodeHash, Self::Error> {
+        self.repo
+            .get_heads_for_bookmarks(names)
+            .map_err(self.cvterr)
+            .boxed()
+    }
+
+    fn prefetch(&self, nodeids: &[NodeHash]) -> BoxFuture<(), Self::Error> {
+        self.repo.prefetch(nodeids).map_err(self.cvterr).boxed()
+    }
+
     fn changeset_exists(&self, nodeid: &NodeHash) -> BoxFuture<bool, Self::Error> {
         let cvterr = self.cvterr;
 
         self.repo.changeset_exists(nodeid).map_err(cvterr).boxed()
     }
 
+    fn changesets_exist(
+        &self,
+        nodeids: &[NodeHash],
+    ) -> BoxFuture<Vec<(NodeHash, bool)>, Self::Error> {
+        let cvterr = self.cvterr;
+
+        self.repo.changesets_exist(nodeids).map_err(cvterr).boxed()
+    }
+
+    fn get_changesets_by_nodeid(
+        &self,
+        nodeids: &[NodeHash],
+    ) -> BoxFuture<Vec<Box<Changeset>>, Self::Error> {
+        let cvterr = self.cvterr;
+
+        self.repo
+            .get_changesets_by_nodeid(nodeids)
+            .map_err(cvterr)
+            .boxed()
+    }
+
     fn get_changeset_by_nodeid(&self, nodeid: &NodeHash) -> BoxFuture<Box<Changeset>, Self::Error> {
         let cvterr = self.cvterr;
 
@@ -132,6 +565,49 @@ where
             .boxed()
     }
 
+    fn get_changeset_raw(&self, nodeid: &NodeHash) -> BoxFuture<Bytes, Self::Error> {
+        self.repo
+            .get_changeset_raw(nodeid)
+            .map_err(self.cvterr)
+            .boxed()
+    }
+
+    fn resolve_hash_prefix(&self, prefix: &str) -> BoxFuture<HashResolution, Self::Error> {
+        self.repo
+            .resolve_hash_prefix(prefix)
+            .map_err(self.cvterr)
+            .boxed()
+    }
+
+    fn get_changeset_parents(&self, nodeid: &NodeHash) -> BoxFuture<Parents, Self::Error> {
+        self.repo
+            .get_changeset_parents(nodeid)
+            .map_err(self.cvterr)
+            .boxed()
+    }
+
+    fn is_ancestor(
+        &self,
+        ancestor: &NodeHash,
+        descendant: &NodeHash,
+    ) -> BoxFuture<bool, Self::Error> {
+        self.repo
+            .is_ancestor(ancestor, descendant)
+            .map_err(self.cvterr)
+            .boxed()
+    }
+
+    fn common_ancestor(
+        &self,
+        a: &NodeHash,
+        b: &NodeHash,
+    ) -> BoxFuture<Option<NodeHash>, Self::Error> {
+        self.repo
+            .common_ancestor(a, b)
+            .map_err(self.cvterr)
+            .boxed()
+    }
+
     fn get_manifest_by_nodeid(
         &self,
         nodeid: &NodeHash,
@@ -144,6 +620,95 @@ where
             .map_err(cvterr)
             .boxed()
     }
+
+    fn get_root_manifest(
+        &self,
+        cs: &NodeHash,
+    ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
+        let cvterr = self.cvterr;
+
+        self.repo
+            .get_root_manifest(cs)
+            .map(move |m| BoxManifest::new_with_cvterr(m, cvterr))
+            .map_err(cvterr)
+            .boxed()
+    }
+
+    fn diff_manifests(
+        &self,
+        base: &NodeHash,
+        other: &NodeHash,
+    ) -> BoxStream<ManifestDiffEntry, Self::Error> {
+        self.repo
+            .diff_manifests(base, other)
+            .map_err(self.cvterr)
+            .boxed()
+    }
+
+    fn get_entry_by_path(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+    ) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Self::Error> {
+        let cvterr = self.cvterr;
+
+        self.repo
+            .get_entry_by_path(cs, path)
+            .map(move |oe| oe.map(|e| BoxEntry::new_with_cvterr(e, cvterr)))
+            .map_err(cvterr)
+            .boxed()
+    }
+
+    fn get_file_history(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+        limit: usize,
+    ) -> BoxStream<(NodeHash, NodeHash), Self::Error> {
+        self.repo
+            .get_file_history(cs, path, limit)
+            .map_err(self.cvterr)
+            .boxed()
+    }
+
+    fn walk_manifest(
+        &self,
+        root: &NodeHash,
+    ) -> BoxStream<(Path, Box<Entry<Error = Self::Error>>), Self::Error> {
+        let cvterr = self.cvterr;
+
+        self.repo
+            .walk_manifest(root)
+            .map(move |(path, e)| (path, BoxEntry::new_with_cvterr(e, cvterr)))
+            .map_err(cvterr)
+            .boxed()
+    }
+
+    fn manifest_stats(&self, root: &NodeHash) -> BoxFuture<ManifestStats, Self::Error> {
+        self.repo.manifest_stats(root).map_err(self.cvterr).boxed()
+    }
+
+    fn export_bundle(
+        &self,
+        heads: &[NodeHash],
+        common: &[NodeHash],
+    ) -> BoxStream<Bytes, Self::Error> {
+        self.repo
+            .export_bundle(heads, common)
+            .map_err(self.cvterr)
+            .boxed()
+    }
+
+    fn verify_reachable(&self, heads: &[NodeHash]) -> BoxStream<VerifyEvent, Self::Error> {
+        self.repo
+            .verify_reachable(heads)
+            .map_err(self.cvterr)
+            .boxed()
+    }
+
+    fn shutdown(&self) -> BoxFuture<(), Self::Error> {
+        self.repo.shutdown().map_err(self.cvterr).boxed()
+    }
 }
 
 
@@ -157,6 +722,22 @@ where
         (**self).get_changesets()
     }
 
+    fn get_changesets_range(&self, skip: usize, limit: usize) -> BoxStream<NodeHash, Self::Error> {
+        (**self).get_changesets_range(skip, limit)
+    }
+
+    fn get_changesets_sorted(&self) -> BoxStream<NodeHash, Self::Error> {
+        (**self).get_changesets_sorted()
+    }
+
+    fn get_changesets_bounded(&self, max_depth: usize) -> BoxStream<NodeHash, Self::Error> {
+        (**self).get_changesets_bounded(max_depth)
+    }
+
+    fn get_changeset_count(&self) -> BoxFuture<u64, Self::Error> {
+        (**self).get_changeset_count()
+    }
+
     fn get_heads(&self) -> BoxStream<NodeHash, Self::Error> {
         (**self).get_heads()
     }
@@ -165,20 +746,133 @@ where
         (**self).get_bookmarks()
     }
 
+    fn get_bookmark(&self, name: &[u8]) -> BoxFuture<Option<(NodeHash, Version)>, Self::Error> {
+        (**self).get_bookmark(name)
+    }
+
+    fn get_heads_for_bookmarks(&self, names: &[Vec<u8>]) -> BoxStream<NodeHash, Self::Error> {
+        (**self).get_heads_for_bookmarks(names)
+    }
+
+    fn prefetch(&self, nodeids: &[NodeHash]) -> BoxFuture<(), Self::Error> {
+        (**self).prefetch(nodeids)
+    }
+
     fn changeset_exists(&self, nodeid: &NodeHash) -> BoxFuture<bool, Self::Error> {
         (**self).changeset_exists(nodeid)
     }
 
+    fn changesets_exist(
+        &self,
+        nodeids: &[NodeHash],
+    ) -> BoxFuture<Vec<(NodeHash, bool)>, Self::Error> {
+        (**self).changesets_exist(nodeids)
+    }
+
+    fn get_changesets_by_nodeid(
+        &self,
+        nodeids: &[NodeHash],
+    ) -> BoxFuture<Vec<Box<Changeset>>, Self::Error> {
+        (**self).get_changesets_by_nodeid(nodeids)
+    }
+
     fn get_changeset_by_nodeid(&self, nodeid: &NodeHash) -> BoxFuture<Box<Changeset>, Self::Error> {
         (**self).get_changeset_by_nodeid(nodeid)
     }
 
+    fn get_changeset_raw(&self, nodeid: &NodeHash) -> BoxFuture<Bytes, Self::Error> {
+        (**self).get_changeset_raw(nodeid)
+    }
+
+    fn resolve_hash_prefix(&self, prefix: &str) -> BoxFuture<HashResolution, Self::Error> {
+        (**self).resolve_hash_prefix(prefix)
+    }
+
+    fn get_changeset_parents(&self, nodeid: &NodeHash) -> BoxFuture<Parents, Self::Error> {
+        (**self).get_changeset_parents(nodeid)
+    }
+
+    fn is_ancestor(
+        &self,
+        ancestor: &NodeHash,
+        descendant: &NodeHash,
+    ) -> BoxFuture<bool, Self::Error> {
+        (**self).is_ancestor(ancestor, descendant)
+    }
+
+    fn common_ancestor(
+        &self,
+        a: &NodeHash,
+        b: &NodeHash,
+    ) -> BoxFuture<Option<NodeHash>, Self::Error> {
+        (**self).common_ancestor(a, b)
+    }
+
     fn get_manifest_by_nodeid(
         &self,
         nodeid: &NodeHash,
     ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
         (**self).get_manifest_by_nodeid(nodeid)
     }
+
+    fn get_root_manifest(
+        &self,
+        cs: &NodeHash,
+    ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
+        (**self).get_root_manifest(cs)
+    }
+
+    fn diff_manifests(
+        &self,
+        base: &NodeHash,
+        other: &NodeHash,
+    ) -> BoxStream<ManifestDiffEntry, Self::Error> {
+        (**self).diff_manifests(base, other)
+    }
+
+    fn get_entry_by_path(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+    ) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Self::Error> {
+        (**self).get_entry_by_path(cs, path)
+    }
+
+    fn get_file_history(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+        limit: usize,
+    ) -> BoxStream<(NodeHash, NodeHash), Self::Error> {
+        (**self).get_file_history(cs, path, limit)
+    }
+
+    fn walk_manifest(
+        &self,
+        root: &NodeHash,
+    ) -> BoxStream<(Path, Box<Entry<Error = Self::Error>>), Self::Error> {
+        (**self).walk_manifest(root)
+    }
+
+    fn manifest_stats(&self, root: &NodeHash) -> BoxFuture<ManifestStats, Self::Error> {
+        (**self).manifest_stats(root)
+    }
+
+    fn export_bundle(
+        &self,
+        heads: &[NodeHash],
+        common: &[NodeHash],
+    ) -> BoxStream<Bytes, Self::Error> {
+        (**self).export_bundle(heads, common)
+    }
+
+    fn verify_reachable(&self, heads: &[NodeHash]) -> BoxStream<VerifyEvent, Self::Error> {
+        (**self).verify_reachable(heads)
+    }
+
+    fn shutdown(&self) -> BoxFuture<(), Self::Error> {
+        (**self).shutdown()
+    }
 }
 
 impl<RE> Repo for Arc<Repo<Error = RE>>
@@ -191,6 +885,22 @@ where
         (**self).get_changesets()
     }
 
+    fn get_changesets_range(&self, skip: usize, limit: usize) -> BoxStream<NodeHash, Self::Error> {
+        (**self).get_changesets_range(skip, limit)
+    }
+
+    fn get_changesets_sorted(&self) -> BoxStream<NodeHash, Self::Error> {
+        (**self).get_changesets_sorted()
+    }
+
+    fn get_changesets_bounded(&self, max_depth: usize) -> BoxStream<NodeHash, Self::Error> {
+        (**self).get_changesets_bounded(max_depth)
+    }
+
+    fn get_changeset_count(&self) -> BoxFuture<u64, Self::Error> {
+        (**self).get_changeset_count()
+    }
+
     fn get_heads(&self) -> BoxStream<NodeHash, Self::Error> {
         (**self).get_heads()
     }
@@ -199,18 +909,131 @@ where
         (**self).get_bookmarks()
     }
 
+    fn get_bookmark(&self, name: &[u8]) -> BoxFuture<Option<(NodeHash, Version)>, Self::Error> {
+        (**self).get_bookmark(name)
+    }
+
+    fn get_heads_for_bookmarks(&self, names: &[Vec<u8>]) -> BoxStream<NodeHash, Self::Error> {
+        (**self).get_heads_for_bookmarks(names)
+    }
+
+    fn prefetch(&self, nodeids: &[NodeHash]) -> BoxFuture<(), Self::Error> {
+        (**self).prefetch(nodeids)
+    }
+
     fn changeset_exists(&self, nodeid: &NodeHash) -> BoxFuture<bool, Self::Error> {
         (**self).changeset_exists(nodeid)
     }
 
+    fn changesets_exist(
+        &self,
+        nodeids: &[NodeHash],
+    ) -> BoxFuture<Vec<(NodeHash, bool)>, Self::Error> {
+        (**self).changesets_exist(nodeids)
+    }
+
+    fn get_changesets_by_nodeid(
+        &self,
+        nodeids: &[NodeHash],
+    ) -> BoxFuture<Vec<Box<Changeset>>, Self::Error> {
+        (**self).get_changesets_by_nodeid(nodeids)
+    }
+
     fn get_changeset_by_nodeid(&self, nodeid: &NodeHash) -> BoxFuture<Box<Changeset>, Self::Error> {
         (**self).get_changeset_by_nodeid(nodeid)
     }
 
+    fn get_changeset_raw(&self, nodeid: &NodeHash) -> BoxFuture<Bytes, Self::Error> {
+        (**self).get_changeset_raw(nodeid)
+    }
+
+    fn resolve_hash_prefix(&self, prefix: &str) -> BoxFuture<HashResolution, Self::Error> {
+        (**self).resolve_hash_prefix(prefix)
+    }
+
+    fn get_changeset_parents(&self, nodeid: &NodeHash) -> BoxFuture<Parents, Self::Error> {
+        (**self).get_changeset_parents(nodeid)
+    }
+
+    fn is_ancestor(
+        &self,
+        ancestor: &NodeHash,
+        descendant: &NodeHash,
+    ) -> BoxFuture<bool, Self::Error> {
+        (**self).is_ancestor(ancestor, descendant)
+    }
+
+    fn common_ancestor(
+        &self,
+        a: &NodeHash,
+        b: &NodeHash,
+    ) -> BoxFuture<Option<NodeHash>, Self::Error> {
+        (**self).common_ancestor(a, b)
+    }
+
     fn get_manifest_by_nodeid(
         &self,
         nodeid: &NodeHash,
     ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
         (**self).get_manifest_by_nodeid(nodeid)
     }
+
+    fn get_root_manifest(
+        &self,
+        cs: &NodeHash,
+    ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
+        (**self).get_root_manifest(cs)
+    }
+
+    fn diff_manifests(
+        &self,
+        base: &NodeHash,
+        other: &NodeHash,
+    ) -> BoxStream<ManifestDiffEntry, Self::Error> {
+        (**self).diff_manifests(base, other)
+    }
+
+    fn get_entry_by_path(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+    ) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Self::Error> {
+        (**self).get_entry_by_path(cs, path)
+    }
+
+    fn get_file_history(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+        limit: usize,
+    ) -> BoxStream<(NodeHash, NodeHash), Self::Error> {
+        (**self).get_file_history(cs, path, limit)
+    }
+
+    fn walk_manifest(
+        &self,
+        root: &NodeHash,
+    ) -> BoxStream<(Path, Box<Entry<Error = Self::Error>>), Self::Error> {
+        (**self).walk_manifest(root)
+    }
+
+    fn manifest_stats(&self, root: &NodeHash) -> BoxFuture<ManifestStats, Self::Error> {
+        (**self).manifest_stats(root)
+    }
+
+    fn export_bundle(
+        &self,
+        heads: &[NodeHash],
+        common: &[NodeHash],
+    ) -> BoxStream<Bytes, Self::Error> {
+        (**self).export_bundle(heads, common)
+    }
+
+    fn verify_reachable(&self, heads: &[NodeHash]) -> BoxStream<VerifyEvent, Self::Error> {
+        (**self).verify_reachable(heads)
+    }
+
+    fn shutdown(&self) -> BoxFuture<(), Self::Error> {
+        (**self).shutdown()
+    }
 }
@@ -0,0 +1,1869 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Tests for the default and forwarding implementations of the `Repo` trait, using a small
+//! in-memory repo.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use bytes::Bytes;
+use futures::future::{self, BoxFuture, Future};
+use futures::stream::{self, BoxStream, Stream};
+
+use bookmarks::{Bookmarks, Version};
+
+use super::*;
+use ancestors;
+use errors::*;
+use export;
+use manifest::{Content, ManifestDiffEntry, ManifestStats};
+use repo::{self, BoxedBookmarks};
+use timeoutrepo::{ThreadTimer, Timer};
+use verify;
+
+/// Construct a distinct NodeHash for test changeset number `n`.
+fn node(n: u8) -> NodeHash {
+    NodeHash::from_bytes(&[n; 20]).expect("valid hash")
+}
+
+struct FakeChangeset {
+    manifestid: NodeHash,
+    parents: Parents,
+    user: Vec<u8>,
+    extra: BTreeMap<Vec<u8>, Vec<u8>>,
+    files: Vec<Path>,
+    time: Time,
+}
+
+impl Changeset for FakeChangeset {
+    fn manifestid(&self) -> &NodeHash {
+        &self.manifestid
+    }
+
+    fn user(&self) -> &[u8] {
+        &self.user
+    }
+
+    fn extra(&self) -> &BTreeMap<Vec<u8>, Vec<u8>> {
+        &self.extra
+    }
+
+    fn comments(&self) -> &[u8] {
+        b""
+    }
+
+    fn files(&self) -> &[Path] {
+        &self.files
+    }
+
+    fn time(&self) -> &Time {
+        &self.time
+    }
+
+    fn parents(&self) -> &Parents {
+        &self.parents
+    }
+}
+
+struct FakeBookmarks {
+    bookmarks: HashMap<Vec<u8>, NodeHash>,
+}
+
+impl Bookmarks for FakeBookmarks {
+    type Value = NodeHash;
+    type Error = Error;
+    type Get = BoxFuture<Option<(NodeHash, Version)>, Error>;
+    type Keys = BoxStream<Vec<u8>, Error>;
+
+    fn get(&self, key: &AsRef<[u8]>) -> Self::Get {
+        let value = self.bookmarks
+            .get(key.as_ref())
+            .map(|nodeid| (*nodeid, Version::from(1)));
+        future::ok(value).boxed()
+    }
+
+    fn keys(&self) -> Self::Keys {
+        let keys: Vec<_> = self.bookmarks.keys().cloned().collect();
+        stream::iter(keys.into_iter().map(Ok)).boxed()
+    }
+}
+
+/// In-memory manifest. Entries may be keyed by a single path component (with subtrees for
+/// directories, like a tree manifest) or by a full path (like a flat manifest).
+#[derive(Clone, Default)]
+struct FakeManifest {
+    entries: BTreeMap<Path, (Type, NodeHash, Option<FakeManifest>)>,
+}
+
+impl FakeManifest {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn with_file(mut self, path: &str) -> Self {
+        let path = Path::new(path).unwrap();
+        self.entries.insert(path, (Type::File, NULL_HASH, None));
+        self
+    }
+
+    /// Add an entry with nodeid `node(n)`. Trees added this way are empty.
+    fn with_entry(mut self, path: &str, ty: Type, n: u8) -> Self {
+        let path = Path::new(path).unwrap();
+        let subtree = if ty == Type::Tree {
+            Some(FakeManifest::new())
+        } else {
+            None
+        };
+        self.entries.insert(path, (ty, node(n), subtree));
+        self
+    }
+
+    fn with_tree(mut self, path: &str, tree: FakeManifest) -> Self {
+        let path = Path::new(path).unwrap();
+        self.entries.insert(path, (Type::Tree, NULL_HASH, Some(tree)));
+        self
+    }
+}
+
+impl Manifest for FakeManifest {
+    type Error = Error;
+
+    fn lookup(&self, path: &Path) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Error> {
+        let entry = self.entries
+            .get(path)
+            .map(|&(ty, hash, ref subtree)| FakeEntry::new(path, ty, hash, subtree));
+        future::ok(entry).boxed()
+    }
+
+    fn list(&self) -> BoxStream<Box<Entry<Error = Self::Error>>, Self::Error> {
+        let entries: Vec<_> = self.entries
+            .iter()
+            .map(|(path, &(ty, hash, ref subtree))| Ok(FakeEntry::new(path, ty, hash, subtree)))
+            .collect();
+        stream::iter(entries).boxed()
+    }
+}
+
+struct FakeEntry {
+    path: Path,
+    ty: Type,
+    hash: NodeHash,
+    subtree: Option<FakeManifest>,
+}
+
+impl FakeEntry {
+    fn new(
+        path: &Path,
+        ty: Type,
+        hash: NodeHash,
+        subtree: &Option<FakeManifest>,
+    ) -> Box<Entry<Error = Error>> {
+        FakeEntry {
+            path: path.clone(),
+            ty,
+            hash,
+            subtree: subtree.clone(),
+        }.boxed()
+    }
+}
+
+impl Entry for FakeEntry {
+    type Error = Error;
+
+    fn get_type(&self) -> Type {
+        self.ty
+    }
+
+    fn get_parents(&self) -> BoxFuture<Parents, Self::Error> {
+        future::ok(Parents::None).boxed()
+    }
+
+    fn get_content(&self) -> BoxFuture<Content<Self::Error>, Self::Error> {
+        let content = match self.subtree {
+            Some(ref tree) => Content::Tree(tree.clone().boxed()),
+            None => Content::File(Blob::from(self.path.to_vec())),
+        };
+        future::ok(content).boxed()
+    }
+
+    fn get_size(&self) -> BoxFuture<Option<usize>, Self::Error> {
+        // Files have their path as content, so that's their size.
+        let size = match self.subtree {
+            Some(_) => None,
+            None => Some(self.path.len()),
+        };
+        future::ok(size).boxed()
+    }
+
+    fn get_hash(&self) -> &NodeHash {
+        &self.hash
+    }
+
+    fn get_path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// In-memory repo. Changesets are produced by `get_changesets` in the order they were added.
+#[derive(Clone, Default)]
+struct FakeRepo {
+    order: Vec<NodeHash>,
+    parents: HashMap<NodeHash, Parents>,
+    heads: Vec<NodeHash>,
+    /// Authors of changesets which aren't by "test".
+    users: HashMap<NodeHash, Vec<u8>>,
+    bookmarks: HashMap<Vec<u8>, NodeHash>,
+    /// Root manifests, keyed by the nodeid of the changeset they belong to.
+    manifests: HashMap<NodeHash, FakeManifest>,
+    /// Set by `shutdown`; shared between clones.
+    shut_down: Arc<AtomicBool>,
+    /// Number of calls to `get_manifest_by_nodeid`; shared between clones.
+    manifest_fetches: Arc<AtomicUsize>,
+    /// Number of calls to `get_changesets`; shared between clones.
+    changeset_scans: Arc<AtomicUsize>,
+    /// If set, `get_changesets` never ends, and fetching this changeset never completes.
+    stalled: Option<NodeHash>,
+}
+
+impl FakeRepo {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn with_changeset(mut self, n: u8, parents: &[u8]) -> Self {
+        let p1 = parents.get(0).map(|p| node(*p));
+        let p2 = parents.get(1).map(|p| node(*p));
+        self.order.push(node(n));
+        self.parents
+            .insert(node(n), Parents::new(p1.as_ref(), p2.as_ref()));
+        self
+    }
+
+    fn with_manifest(mut self, n: u8, manifest: FakeManifest) -> Self {
+        self.manifests.insert(node(n), manifest);
+        self
+    }
+
+    fn with_user(mut self, n: u8, user: &str) -> Self {
+        self.users.insert(node(n), user.as_bytes().to_vec());
+        self
+    }
+
+    fn with_head(mut self, n: u8) -> Self {
+        self.heads.push(node(n));
+        self
+    }
+
+    fn with_stalled(mut self, n: u8) -> Self {
+        self.stalled = Some(node(n));
+        self
+    }
+
+    fn with_bookmark(mut self, name: &str, n: u8) -> Self {
+        self.bookmarks.insert(name.as_bytes().to_vec(), node(n));
+        self
+    }
+
+    /// A linear history of `count` changesets, numbered from 1.
+    fn linear(count: u8) -> Self {
+        (1..count + 1)
+            .fold(FakeRepo::new(), |repo, n| {
+                if n == 1 {
+                    repo.with_changeset(n, &[])
+                } else {
+                    repo.with_changeset(n, &[n - 1])
+                }
+            })
+            .with_head(count)
+    }
+}
+
+impl Repo for FakeRepo {
+    type Error = Error;
+
+    fn get_changesets(&self) -> BoxStream<NodeHash, Self::Error> {
+        self.changeset_scans.fetch_add(1, Ordering::SeqCst);
+        let changesets = stream::iter(self.order.clone().into_iter().map(Ok));
+        if self.stalled.is_some() {
+            changesets.chain(future::empty().into_stream()).boxed()
+        } else {
+            changesets.boxed()
+        }
+    }
+
+    fn get_heads(&self) -> BoxStream<NodeHash, Self::Error> {
+        stream::iter(self.heads.clone().into_iter().map(Ok)).boxed()
+    }
+
+    fn get_bookmarks(&self) -> Result<BoxedBookmarks<Self::Error>> {
+        Ok(Box::new(FakeBookmarks {
+            bookmarks: self.bookmarks.clone(),
+        }))
+    }
+
+    fn changeset_exists(&self, nodeid: &NodeHash) -> BoxFuture<bool, Self::Error> {
+        future::ok(self.parents.contains_key(nodeid)).boxed()
+    }
+
+    fn get_changeset_by_nodeid(&self, nodeid: &NodeHash) -> BoxFuture<Box<Changeset>, Self::Error> {
+        if self.stalled == Some(*nodeid) {
+            return future::empty().boxed();
+        }
+        match self.parents.get(nodeid) {
+            Some(parents) => {
+                // Every file at the top level of the changeset's manifest counts as changed.
+                let files = self.manifests.get(nodeid).map_or(Vec::new(), |manifest| {
+                    manifest
+                        .entries
+                        .iter()
+                        .filter(|&(_, &(ty, _, _))| ty != Type::Tree)
+                        .map(|(path, _)| path.clone())
+                        .collect()
+                });
+                let cs = FakeChangeset {
+                    manifestid: *nodeid,
+                    parents: *parents,
+                    user: self.users
+                        .get(nodeid)
+                        .map_or(b"test".to_vec(), |user| user.clone()),
+                    extra: BTreeMap::new(),
+                    files,
+                    time: Time { time: 0, tz: 0 },
+                };
+                future::ok(cs.boxed()).boxed()
+            }
+            None => future::err(format!("changeset {} missing", nodeid).into()).boxed(),
+        }
+    }
+
+    fn get_changeset_raw(&self, nodeid: &NodeHash) -> BoxFuture<Bytes, Self::Error> {
+        // Fake changesets aren't stored anywhere, so make up a minimal serialization.
+        match self.parents.get(nodeid) {
+            Some(_) => future::ok(Bytes::from(format!("{}\n\n0 0\n\n", nodeid))).boxed(),
+            None => future::err(format!("changeset {} missing", nodeid).into()).boxed(),
+        }
+    }
+
+    fn get_changesets_bounded(&self, max_depth: usize) -> BoxStream<NodeHash, Self::Error> {
+        ancestors::recent(self.clone(), max_depth)
+    }
+
+    fn resolve_hash_prefix(&self, prefix: &str) -> BoxFuture<HashResolution, Self::Error> {
+        repo::resolve_hash_prefix(self, prefix)
+    }
+
+    fn is_ancestor(
+        &self,
+        ancestor: &NodeHash,
+        descendant: &NodeHash,
+    ) -> BoxFuture<bool, Self::Error> {
+        ancestors::is_ancestor(self.clone(), *ancestor, *descendant, None)
+    }
+
+    fn common_ancestor(
+        &self,
+        a: &NodeHash,
+        b: &NodeHash,
+    ) -> BoxFuture<Option<NodeHash>, Self::Error> {
+        ancestors::common_ancestor(self.clone(), *a, *b, None)
+    }
+
+    fn get_manifest_by_nodeid(
+        &self,
+        nodeid: &NodeHash,
+    ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
+        self.manifest_fetches.fetch_add(1, Ordering::SeqCst);
+        match self.manifests.get(nodeid) {
+            Some(manifest) => future::ok(manifest.clone().boxed()).boxed(),
+            None => future::err(format!("manifest {} missing", nodeid).into()).boxed(),
+        }
+    }
+
+    fn get_root_manifest(
+        &self,
+        cs: &NodeHash,
+    ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
+        repo::get_root_manifest(self.clone(), *cs)
+    }
+
+    fn get_entry_by_path(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+    ) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Self::Error> {
+        repo::get_entry_by_path(self.clone(), *cs, path.clone())
+    }
+
+    fn get_file_history(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+        limit: usize,
+    ) -> BoxStream<(NodeHash, NodeHash), Self::Error> {
+        ancestors::file_history(self.clone(), *cs, path.clone(), limit)
+    }
+
+    fn walk_manifest(
+        &self,
+        root: &NodeHash,
+    ) -> BoxStream<(Path, Box<Entry<Error = Self::Error>>), Self::Error> {
+        repo::walk_manifest(self, root)
+    }
+
+    fn manifest_stats(&self, root: &NodeHash) -> BoxFuture<ManifestStats, Self::Error> {
+        repo::manifest_stats(self, root)
+    }
+
+    fn export_bundle(
+        &self,
+        heads: &[NodeHash],
+        common: &[NodeHash],
+    ) -> BoxStream<Bytes, Self::Error> {
+        export::export(self.clone(), heads.to_vec(), common.to_vec())
+    }
+
+    fn verify_reachable(&self, heads: &[NodeHash]) -> BoxStream<VerifyEvent, Self::Error> {
+        verify::verify_reachable(self.clone(), heads.to_vec(), verify::check_content)
+    }
+
+    fn shutdown(&self) -> BoxFuture<(), Self::Error> {
+        self.shut_down.store(true, Ordering::SeqCst);
+        future::ok(()).boxed()
+    }
+}
+
+/// `FakeRepo` without its own `shutdown`, to exercise the trait's default.
+struct DefaultShutdownRepo(FakeRepo);
+
+impl Repo for DefaultShutdownRepo {
+    type Error = Error;
+
+    fn get_changesets(&self) -> BoxStream<NodeHash, Self::Error> {
+        self.0.get_changesets()
+    }
+
+    fn get_changesets_bounded(&self, max_depth: usize) -> BoxStream<NodeHash, Self::Error> {
+        self.0.get_changesets_bounded(max_depth)
+    }
+
+    fn get_heads(&self) -> BoxStream<NodeHash, Self::Error> {
+        self.0.get_heads()
+    }
+
+    fn get_bookmarks(&self) -> Result<BoxedBookmarks<Self::Error>> {
+        self.0.get_bookmarks()
+    }
+
+    fn changeset_exists(&self, nodeid: &NodeHash) -> BoxFuture<bool, Self::Error> {
+        self.0.changeset_exists(nodeid)
+    }
+
+    fn get_changeset_by_nodeid(&self, nodeid: &NodeHash) -> BoxFuture<Box<Changeset>, Self::Error> {
+        self.0.get_changeset_by_nodeid(nodeid)
+    }
+
+    fn get_changeset_raw(&self, nodeid: &NodeHash) -> BoxFuture<Bytes, Self::Error> {
+        self.0.get_changeset_raw(nodeid)
+    }
+
+    fn resolve_hash_prefix(&self, prefix: &str) -> BoxFuture<HashResolution, Self::Error> {
+        self.0.resolve_hash_prefix(prefix)
+    }
+
+    fn is_ancestor(
+        &self,
+        ancestor: &NodeHash,
+        descendant: &NodeHash,
+    ) -> BoxFuture<bool, Self::Error> {
+        self.0.is_ancestor(ancestor, descendant)
+    }
+
+    fn common_ancestor(
+        &self,
+        a: &NodeHash,
+        b: &NodeHash,
+    ) -> BoxFuture<Option<NodeHash>, Self::Error> {
+        self.0.common_ancestor(a, b)
+    }
+
+    fn get_manifest_by_nodeid(
+        &self,
+        nodeid: &NodeHash,
+    ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
+        self.0.get_manifest_by_nodeid(nodeid)
+    }
+
+    fn get_root_manifest(
+        &self,
+        cs: &NodeHash,
+    ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
+        self.0.get_root_manifest(cs)
+    }
+
+    fn get_entry_by_path(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+    ) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Self::Error> {
+        self.0.get_entry_by_path(cs, path)
+    }
+
+    fn get_file_history(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+        limit: usize,
+    ) -> BoxStream<(NodeHash, NodeHash), Self::Error> {
+        self.0.get_file_history(cs, path, limit)
+    }
+
+    fn walk_manifest(
+        &self,
+        root: &NodeHash,
+    ) -> BoxStream<(Path, Box<Entry<Error = Self::Error>>), Self::Error> {
+        self.0.walk_manifest(root)
+    }
+
+    fn manifest_stats(&self, root: &NodeHash) -> BoxFuture<ManifestStats, Self::Error> {
+        self.0.manifest_stats(root)
+    }
+
+    fn export_bundle(
+        &self,
+        heads: &[NodeHash],
+        common: &[NodeHash],
+    ) -> BoxStream<Bytes, Self::Error> {
+        self.0.export_bundle(heads, common)
+    }
+
+    fn verify_reachable(&self, heads: &[NodeHash]) -> BoxStream<VerifyEvent, Self::Error> {
+        self.0.verify_reachable(heads)
+    }
+}
+
+#[test]
+fn changesets_sorted() {
+    let mut repo = FakeRepo::new();
+    for n in &[7, 2, 9, 1, 4] {
+        repo = repo.with_changeset(*n, &[]);
+    }
+    assert_ne!(
+        repo.get_changesets().collect().wait().unwrap(),
+        repo.get_changesets_sorted().collect().wait().unwrap()
+    );
+
+    let sorted = repo.get_changesets_sorted().collect().wait().unwrap();
+    assert!(sorted.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(sorted, vec![node(1), node(2), node(4), node(7), node(9)]);
+
+    let boxed = BoxRepo::<_, Error>::new(repo);
+    assert_eq!(boxed.get_changesets_sorted().collect().wait().unwrap(), sorted);
+    assert!(
+        BoxRepo::<_, Error>::new(FakeRepo::new())
+            .get_changesets_sorted()
+            .collect()
+            .wait()
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[test]
+fn changesets_range() {
+    let repo = FakeRepo::linear(10);
+    let all: Vec<_> = (1..11).map(node).collect();
+
+    let window = repo.get_changesets_range(3, 4).collect().wait().unwrap();
+    assert_eq!(window, &all[3..7]);
+
+    let tail = repo.get_changesets_range(8, 4).collect().wait().unwrap();
+    assert_eq!(tail, &all[8..]);
+
+    let past_end = repo.get_changesets_range(20, 4).collect().wait().unwrap();
+    assert!(past_end.is_empty());
+}
+
+#[test]
+fn changesets_range_forwarded() {
+    let all: Vec<_> = (1..11).map(node).collect();
+
+    let boxed = BoxRepo::<_, Error>::new(FakeRepo::linear(10));
+    let window = boxed.get_changesets_range(2, 3).collect().wait().unwrap();
+    assert_eq!(window, &all[2..5]);
+
+    let boxed: Box<Repo<Error = Error>> = Box::new(FakeRepo::linear(10));
+    let window = boxed.get_changesets_range(0, 2).collect().wait().unwrap();
+    assert_eq!(window, &all[..2]);
+}
+
+#[test]
+fn changesets_bounded() {
+    let bounded = |repo: &FakeRepo, max_depth| {
+        repo.get_changesets_bounded(max_depth)
+            .collect()
+            .wait()
+            .unwrap()
+    };
+
+    let repo = FakeRepo::linear(5);
+    assert_eq!(bounded(&repo, 2), vec![node(5), node(4), node(3)]);
+    assert_eq!(bounded(&repo, 0), vec![node(5)]);
+    assert_eq!(bounded(&repo, 10).len(), 5);
+
+    // Depth is counted from each head, and changesets reachable from both are produced once.
+    let repo = repo.with_changeset(6, &[2]).with_head(6);
+    assert_eq!(
+        bounded(&repo, 2),
+        vec![node(5), node(6), node(4), node(2), node(3), node(1)]
+    );
+
+    let boxed = BoxRepo::<_, Error>::new(repo);
+    assert_eq!(boxed.get_changesets_bounded(1).collect().wait().unwrap().len(), 4);
+}
+
+#[test]
+fn changeset_count() {
+    assert_eq!(FakeRepo::linear(7).get_changeset_count().wait().unwrap(), 7);
+    assert_eq!(branchy().get_changeset_count().wait().unwrap(), 6);
+    assert_eq!(FakeRepo::new().get_changeset_count().wait().unwrap(), 0);
+
+    let boxed = BoxRepo::<_, Error>::new(FakeRepo::linear(7));
+    assert_eq!(boxed.get_changeset_count().wait().unwrap(), 7);
+
+    let boxed: Box<Repo<Error = Error>> = Box::new(FakeRepo::new());
+    assert_eq!(boxed.get_changeset_count().wait().unwrap(), 0);
+}
+
+#[test]
+fn changesets_exist() {
+    let repo = FakeRepo::linear(3);
+    let query = [node(4), node(1), node(3), node(5), node(2)];
+    let expected = vec![
+        (node(4), false),
+        (node(1), true),
+        (node(3), true),
+        (node(5), false),
+        (node(2), true),
+    ];
+
+    assert_eq!(repo.changesets_exist(&query).wait().unwrap(), expected);
+
+    let boxed = BoxRepo::<_, Error>::new(FakeRepo::linear(3));
+    assert_eq!(boxed.changesets_exist(&query).wait().unwrap(), expected);
+
+    assert!(repo.changesets_exist(&[]).wait().unwrap().is_empty());
+}
+
+#[test]
+fn get_changesets_by_nodeid() {
+    let repo = FakeRepo::linear(3);
+    let query = [node(3), node(1), node(2)];
+
+    let parents = |changesets: Vec<Box<Changeset>>| -> Vec<Parents> {
+        changesets.iter().map(|cs| *cs.parents()).collect()
+    };
+    let expected = vec![
+        Parents::One(node(2)),
+        Parents::None,
+        Parents::One(node(1)),
+    ];
+    let found = repo.get_changesets_by_nodeid(&query).wait().unwrap();
+    assert_eq!(parents(found), expected);
+
+    let boxed = BoxRepo::<_, Error>::new(repo.clone());
+    let found = boxed.get_changesets_by_nodeid(&query).wait().unwrap();
+    assert_eq!(parents(found), expected);
+
+    // One missing changeset fails the whole batch, naming the missing one.
+    let query = [node(1), node(2), node(9), node(3)];
+    match repo.get_changesets_by_nodeid(&query).wait() {
+        Ok(_) => panic!("missing changeset was not reported"),
+        Err(err) => assert_eq!(err.to_string(), format!("changeset {} missing", node(9))),
+    }
+
+    assert!(repo.get_changesets_by_nodeid(&[]).wait().unwrap().is_empty());
+}
+
+#[test]
+fn get_changesets_filtered() {
+    let repo = FakeRepo::linear(4)
+        .with_user(2, "alice")
+        .with_user(4, "alice")
+        .with_user(3, "bob");
+
+    let by_alice = repo.get_changesets_filtered(|cs| cs.user() == b"alice")
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!(by_alice, vec![node(2), node(4)]);
+
+    let nobody = repo.get_changesets_filtered(|cs| cs.user() == b"carol")
+        .collect()
+        .wait()
+        .unwrap();
+    assert!(nobody.is_empty());
+
+    // A changeset which fails to load fails the stream without reaching the predicate.
+    let mut broken = repo.clone();
+    broken.order.push(node(9));
+    let called = Arc::new(AtomicUsize::new(0));
+    let counter = called.clone();
+    let res = broken
+        .get_changesets_filtered(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            true
+        })
+        .collect()
+        .wait();
+    match res {
+        Ok(_) => panic!("missing changeset was not reported"),
+        Err(err) => assert_eq!(err.to_string(), format!("changeset {} missing", node(9))),
+    }
+    assert_eq!(called.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn get_bookmark() {
+    let repo = FakeRepo::linear(3)
+        .with_bookmark("master", 3)
+        .with_bookmark("stable", 1);
+
+    let master = repo.get_bookmark(b"master").wait().unwrap();
+    assert_eq!(master.map(|(nodeid, _)| nodeid), Some(node(3)));
+
+    let stable = repo.get_bookmark(b"stable").wait().unwrap();
+    assert_eq!(stable.map(|(nodeid, _)| nodeid), Some(node(1)));
+
+    assert!(repo.get_bookmark(b"missing").wait().unwrap().is_none());
+
+    let boxed = BoxRepo::<_, Error>::new(repo);
+    let master = boxed.get_bookmark(b"master").wait().unwrap();
+    assert_eq!(master.map(|(nodeid, _)| nodeid), Some(node(3)));
+    assert!(boxed.get_bookmark(b"missing").wait().unwrap().is_none());
+}
+
+#[test]
+fn get_heads_for_bookmarks() {
+    let repo = FakeRepo::linear(3)
+        .with_bookmark("master", 3)
+        .with_bookmark("release", 1)
+        .with_bookmark("@", 3);
+    let names = |names: &[&str]| -> Vec<Vec<u8>> {
+        names.iter().map(|name| name.as_bytes().to_vec()).collect()
+    };
+
+    let heads = repo.get_heads_for_bookmarks(&names(&["master", "release", "@"]))
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!(heads, vec![node(3), node(1)]);
+
+    // Unknown names are skipped.
+    let heads = repo.get_heads_for_bookmarks(&names(&["missing", "release"]))
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!(heads, vec![node(1)]);
+
+    let boxed = BoxRepo::<_, Error>::new(repo);
+    let heads = boxed
+        .get_heads_for_bookmarks(&names(&["@", "master"]))
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!(heads, vec![node(3)]);
+    assert!(boxed.get_heads_for_bookmarks(&[]).collect().wait().unwrap().is_empty());
+}
+
+/// Two branches off a common root, merged back together:
+///
+/// ```text
+///     6
+///    / \
+///   3   5
+///   |   |
+///   2   4
+///    \ /
+///     1
+/// ```
+fn branchy() -> FakeRepo {
+    FakeRepo::new()
+        .with_changeset(1, &[])
+        .with_changeset(2, &[1])
+        .with_changeset(3, &[2])
+        .with_changeset(4, &[1])
+        .with_changeset(5, &[4])
+        .with_changeset(6, &[3, 5])
+        .with_head(6)
+}
+
+#[test]
+fn is_ancestor() {
+    let repo = branchy();
+    let check = |a, d| repo.is_ancestor(&node(a), &node(d)).wait().unwrap();
+
+    // Direct parent.
+    assert!(check(2, 3));
+    assert!(!check(3, 2));
+    // Transitive, including through either side of a merge.
+    assert!(check(1, 3));
+    assert!(check(2, 6));
+    assert!(check(4, 6));
+    // Unrelated branches.
+    assert!(!check(3, 5));
+    assert!(!check(4, 3));
+    // Self.
+    assert!(check(3, 3));
+    // An unknown ancestor is simply not reached.
+    assert!(!check(9, 6));
+
+    // A missing descendant is an error.
+    assert!(repo.is_ancestor(&node(1), &node(9)).wait().is_err());
+    assert!(repo.is_ancestor(&node(9), &node(9)).wait().is_err());
+
+    let boxed = BoxRepo::<_, Error>::new(branchy());
+    assert!(boxed.is_ancestor(&node(4), &node(6)).wait().unwrap());
+}
+
+#[test]
+fn is_ancestor_max_depth() {
+    let repo = FakeRepo::linear(10);
+    let check = |depth| {
+        ancestors::is_ancestor(repo.clone(), node(1), node(10), depth)
+            .wait()
+            .unwrap()
+    };
+
+    assert!(check(None));
+    assert!(check(Some(9)));
+    assert!(!check(Some(8)));
+}
+
+#[test]
+fn common_ancestor() {
+    let repo = branchy().with_changeset(7, &[]).with_changeset(8, &[7]);
+    let lca = |a, b| repo.common_ancestor(&node(a), &node(b)).wait().unwrap();
+
+    assert_eq!(lca(3, 5), Some(node(1)));
+    assert_eq!(lca(5, 3), Some(node(1)));
+    assert_eq!(lca(2, 4), Some(node(1)));
+    // One is an ancestor of the other.
+    assert_eq!(lca(2, 3), Some(node(2)));
+    assert_eq!(lca(6, 4), Some(node(4)));
+    assert_eq!(lca(3, 3), Some(node(3)));
+    // Disjoint histories.
+    assert_eq!(lca(8, 6), None);
+
+    assert!(repo.common_ancestor(&node(3), &node(9)).wait().is_err());
+    assert!(repo.common_ancestor(&node(9), &node(9)).wait().is_err());
+
+    let boxed = BoxRepo::<_, Error>::new(repo.clone());
+    assert_eq!(boxed.common_ancestor(&node(3), &node(5)).wait().unwrap(), Some(node(1)));
+}
+
+#[test]
+fn common_ancestor_max_depth() {
+    // 10 is five generations from 5, so the walk from 10 only reaches it on the fifth.
+    let repo = FakeRepo::linear(10);
+    let check = |depth| {
+        ancestors::common_ancestor(repo.clone(), node(10), node(5), depth)
+            .wait()
+            .unwrap()
+    };
+
+    assert_eq!(check(None), Some(node(5)));
+    assert_eq!(check(Some(5)), Some(node(5)));
+    assert_eq!(check(Some(4)), None);
+}
+
+#[test]
+fn common_ancestor_nearest() {
+    // 1 is a common ancestor of 5 and 6, but 4 is much nearer to both.
+    //
+    //   5   6
+    //   |  /|
+    //   4 / 3
+    //   |/  |
+    //   2   |
+    //    \  /
+    //     1
+    let repo = FakeRepo::new()
+        .with_changeset(1, &[])
+        .with_changeset(2, &[1])
+        .with_changeset(3, &[1])
+        .with_changeset(4, &[2])
+        .with_changeset(5, &[4])
+        .with_changeset(6, &[3, 2]);
+
+    assert_eq!(repo.common_ancestor(&node(5), &node(6)).wait().unwrap(), Some(node(2)));
+}
+
+#[test]
+fn common_ancestor_criss_cross() {
+    // Criss-cross merge: 2 and 3 are both equally good common ancestors of 4 and 5, so the
+    // smaller hash wins whichever way round the query is made.
+    //
+    //   4   5
+    //   |\ /|
+    //   | X |
+    //   |/ \|
+    //   2   3
+    //    \ /
+    //     1
+    let repo = FakeRepo::new()
+        .with_changeset(1, &[])
+        .with_changeset(2, &[1])
+        .with_changeset(3, &[1])
+        .with_changeset(4, &[2, 3])
+        .with_changeset(5, &[3, 2]);
+
+    assert_eq!(repo.common_ancestor(&node(4), &node(5)).wait().unwrap(), Some(node(2)));
+    assert_eq!(repo.common_ancestor(&node(5), &node(4)).wait().unwrap(), Some(node(2)));
+}
+
+#[test]
+fn common_ancestor_merge_shortcut() {
+    // 2 is an ancestor of 7 through the long chain 6-5-4-3, while 1, its parent, is only one
+    // step from 7 through the merge. 2 is still the lowest common ancestor.
+    //
+    //   7
+    //   |\
+    //   6 |
+    //   | |
+    //   5 |
+    //   | |
+    //   4 |
+    //   | |
+    //   3 |
+    //   | |
+    //   2 |
+    //   |/
+    //   1
+    let repo = FakeRepo::new()
+        .with_changeset(1, &[])
+        .with_changeset(2, &[1])
+        .with_changeset(3, &[2])
+        .with_changeset(4, &[3])
+        .with_changeset(5, &[4])
+        .with_changeset(6, &[5])
+        .with_changeset(7, &[6, 1]);
+
+    assert_eq!(repo.common_ancestor(&node(7), &node(2)).wait().unwrap(), Some(node(2)));
+    assert_eq!(repo.common_ancestor(&node(2), &node(7)).wait().unwrap(), Some(node(2)));
+    assert_eq!(repo.common_ancestor(&node(7), &node(3)).wait().unwrap(), Some(node(3)));
+}
+
+#[test]
+fn prefetch_default() {
+    let repo = FakeRepo::linear(3);
+    // Nothing to do, and changesets which don't exist aren't an error.
+    repo.prefetch(&[node(1), node(3), node(9)]).wait().unwrap();
+
+    let boxed = BoxRepo::<_, Error>::new(repo);
+    boxed.prefetch(&[node(2)]).wait().unwrap();
+}
+
+#[test]
+fn resolve_hash_prefix() {
+    let hash = |s: &str| s.parse::<NodeHash>().expect("valid hash");
+    let mut repo = FakeRepo::new();
+    repo.order = vec![
+        hash("abcd000000000000000000000000000000000001"),
+        hash("abcd100000000000000000000000000000000002"),
+        hash("abce000000000000000000000000000000000003"),
+    ];
+    let resolve = |prefix| repo.resolve_hash_prefix(prefix).wait().unwrap();
+
+    assert_eq!(
+        resolve("abcd1"),
+        HashResolution::Unique(hash("abcd100000000000000000000000000000000002"))
+    );
+    assert_eq!(
+        resolve("ABCE0000"),
+        HashResolution::Unique(hash("abce000000000000000000000000000000000003"))
+    );
+    assert_eq!(
+        resolve("abcd100000000000000000000000000000000002"),
+        HashResolution::Unique(hash("abcd100000000000000000000000000000000002"))
+    );
+    assert_eq!(
+        resolve("abcd"),
+        HashResolution::Ambiguous(vec![
+            hash("abcd000000000000000000000000000000000001"),
+            hash("abcd100000000000000000000000000000000002"),
+        ])
+    );
+    assert_eq!(resolve("abcf"), HashResolution::NotFound);
+    assert_eq!(resolve("abcd2"), HashResolution::NotFound);
+
+    let boxed = BoxRepo::<_, Error>::new(repo.clone());
+    assert_eq!(
+        boxed.resolve_hash_prefix("abce").wait().unwrap(),
+        resolve("abce")
+    );
+}
+
+#[test]
+fn resolve_hash_prefix_ambiguous_capped() {
+    let mut repo = FakeRepo::linear(repo::MAX_AMBIGUOUS_CANDIDATES as u8 + 5);
+    // Give every changeset the same first few digits.
+    repo.order = repo.order
+        .iter()
+        .map(|nodeid| {
+            let hex = nodeid.to_string();
+            format!("123456{}", &hex[6..]).parse().unwrap()
+        })
+        .collect();
+
+    match repo.resolve_hash_prefix("123456").wait().unwrap() {
+        HashResolution::Ambiguous(found) => {
+            assert_eq!(found.len(), repo::MAX_AMBIGUOUS_CANDIDATES)
+        }
+        res => panic!("unexpected resolution {:?}", res),
+    }
+}
+
+#[test]
+fn resolve_hash_prefix_invalid() {
+    let repo = FakeRepo::linear(3);
+    for prefix in &["", "010", "0101g", "0101010101010101010101010101010101010101a"] {
+        match repo.resolve_hash_prefix(prefix).wait() {
+            Err(Error(ErrorKind::InvalidHashPrefix(..), _)) => (),
+            res => panic!("unexpected result for {:?}: {:?}", prefix, res),
+        }
+    }
+}
+
+#[test]
+fn changeset_hash_index() {
+    // Hashes abXXYY..., where each XX is shared by four hashes.
+    let hashes: Vec<_> = (0..100u8)
+        .map(|i| {
+            let mut bytes = [i; 20];
+            bytes[0] = 0xab;
+            bytes[1] = i / 4;
+            bytes[2] = i % 4;
+            NodeHash::from_bytes(&bytes).expect("valid hash")
+        })
+        .collect();
+    let mut shuffled = hashes.clone();
+    shuffled.reverse();
+    let index = ChangesetHashIndex::new(shuffled);
+    assert_eq!(index.len(), 100);
+
+    assert_eq!(index.lookup_prefix("ab0a02"), HashResolution::Unique(hashes[42]));
+    assert_eq!(index.lookup_prefix("AB0A02"), HashResolution::Unique(hashes[42]));
+    assert_eq!(
+        index.lookup_prefix(&hashes[99].to_string()),
+        HashResolution::Unique(hashes[99])
+    );
+    assert_eq!(
+        index.lookup_prefix("ab0a"),
+        HashResolution::Ambiguous(hashes[40..44].to_vec())
+    );
+    // Only the first few candidates are returned.
+    assert_eq!(
+        index.lookup_prefix("ab"),
+        HashResolution::Ambiguous(hashes[..repo::MAX_AMBIGUOUS_CANDIDATES].to_vec())
+    );
+    assert_eq!(index.lookup_prefix("ab19"), HashResolution::NotFound);
+    assert_eq!(index.lookup_prefix("abcd"), HashResolution::NotFound);
+    assert_eq!(index.lookup_prefix("ab0g"), HashResolution::NotFound);
+    assert_eq!(index.lookup_prefix(&"a".repeat(41)), HashResolution::NotFound);
+
+    // Lookups can be made from several threads at once.
+    let threads: Vec<_> = (0..4)
+        .map(|t| {
+            let index = index.clone();
+            let expected = hashes[t * 25];
+            thread::spawn(move || {
+                let prefix = expected.to_string();
+                let expected = HashResolution::Unique(expected);
+                (0..100).all(|_| index.lookup_prefix(&prefix[..6]) == expected)
+            })
+        })
+        .collect();
+    for handle in threads {
+        assert!(handle.join().unwrap());
+    }
+
+    let empty = ChangesetHashIndex::default();
+    assert!(empty.is_empty());
+    assert_eq!(empty.lookup_prefix("ab0a"), HashResolution::NotFound);
+}
+
+#[test]
+fn caching_repo_hash_index() {
+    let inner = FakeRepo::linear(3);
+    let fetches = inner.changeset_scans.clone();
+    // Start with an index which is missing a changeset, as if it had been added since.
+    let index = ChangesetHashIndex::new(vec![node(1), node(2)]);
+    let repo = CachingRepo::new(inner, 100, 1 << 20).with_hash_index(index.clone());
+    let resolve = |n: u8| {
+        let prefix = node(n).to_string();
+        repo.resolve_hash_prefix(&prefix[..8]).wait().unwrap()
+    };
+
+    assert_eq!(resolve(1), HashResolution::Unique(node(1)));
+    assert_eq!(resolve(3), HashResolution::NotFound);
+    assert_eq!(fetches.load(Ordering::SeqCst), 0);
+
+    repo.refresh_hash_index().wait().unwrap();
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    assert_eq!(index.len(), 3);
+    assert_eq!(resolve(3), HashResolution::Unique(node(3)));
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+    // Invalid prefixes are still errors.
+    match repo.resolve_hash_prefix("01").wait() {
+        Err(Error(ErrorKind::InvalidHashPrefix(..), _)) => (),
+        res => panic!("unexpected result {:?}", res),
+    }
+}
+
+#[test]
+fn get_changeset_parents() {
+    let repo = branchy();
+    let parents = |n| repo.get_changeset_parents(&node(n)).wait().unwrap();
+
+    assert_eq!(parents(1), Parents::None);
+    assert_eq!(parents(3), Parents::One(node(2)));
+    assert_eq!(parents(6), Parents::Two(node(3), node(5)));
+    assert!(repo.get_changeset_parents(&node(9)).wait().is_err());
+
+    let boxed = BoxRepo::<_, Error>::new(branchy());
+    assert_eq!(
+        boxed.get_changeset_parents(&node(6)).wait().unwrap(),
+        Parents::Two(node(3), node(5))
+    );
+}
+
+#[test]
+fn get_root_manifest() {
+    let tree = FakeManifest::new()
+        .with_file("README")
+        .with_tree("src", FakeManifest::new().with_file("main.rs"));
+    let repo = FakeRepo::linear(2).with_manifest(1, tree);
+
+    let paths = |manifest: Box<Manifest<Error = Error> + Sync>| {
+        manifest
+            .list()
+            .map(|entry| entry.get_path().clone())
+            .collect()
+            .wait()
+            .unwrap()
+    };
+
+    let root = repo.get_root_manifest(&node(1)).wait().unwrap();
+    let two_step = repo.get_changeset_by_nodeid(&node(1))
+        .and_then(|cs| repo.get_manifest_by_nodeid(cs.manifestid()))
+        .wait()
+        .unwrap();
+    assert_eq!(paths(root), paths(two_step));
+
+    let boxed = BoxRepo::<_, Error>::new(repo.clone());
+    let root = boxed.get_root_manifest(&node(1)).wait().unwrap();
+    assert_eq!(root.list().collect().wait().unwrap().len(), 2);
+
+    // A missing changeset and a missing manifest are different errors.
+    let err = repo.get_root_manifest(&node(9)).wait().err().unwrap();
+    assert_eq!(err.to_string(), format!("changeset {} missing", node(9)));
+    let err = repo.get_root_manifest(&node(2)).wait().err().unwrap();
+    assert_eq!(err.to_string(), format!("manifest {} missing", node(2)));
+}
+
+#[test]
+fn get_entry_by_path() {
+    let tree = FakeManifest::new()
+        .with_file("README")
+        .with_tree(
+            "src",
+            FakeManifest::new()
+                .with_file("main.rs")
+                .with_tree("bin", FakeManifest::new().with_file("tool.rs")),
+        );
+    let flat = FakeManifest::new()
+        .with_file("README")
+        .with_file("src/main.rs")
+        .with_file("src/bin/tool.rs");
+    let repo = FakeRepo::linear(2)
+        .with_manifest(1, tree)
+        .with_manifest(2, flat);
+
+    let lookup = |cs, path: &str| {
+        repo.get_entry_by_path(&node(cs), &Path::new(path).unwrap())
+            .wait()
+            .unwrap()
+            .map(|entry| (entry.get_type(), entry.get_path().to_vec()))
+    };
+
+    for cs in 1..3 {
+        // Nested files.
+        let (ty, _) = lookup(cs, "src/bin/tool.rs").expect("nested file");
+        assert_eq!(ty, Type::File);
+        assert!(lookup(cs, "README").is_some());
+        // Missing files.
+        assert!(lookup(cs, "src/lib.rs").is_none());
+        assert!(lookup(cs, "docs/README").is_none());
+        // Traversing through a file.
+        assert!(lookup(cs, "README/foo").is_none());
+        assert!(lookup(cs, "src/main.rs/foo").is_none());
+    }
+    assert_eq!(lookup(1, "src/bin"), Some((Type::Tree, b"bin".to_vec())));
+    assert_eq!(lookup(1, ""), None);
+
+    // Paths are normalized before lookup.
+    assert!(lookup(1, "./src/main.rs").is_some());
+    assert!(lookup(1, "src//bin/tool.rs").is_some());
+    assert!(lookup(2, "src/../README").is_some());
+    let escaping = repo.get_entry_by_path(&node(1), &Path::new("../etc").unwrap());
+    assert!(escaping.wait().is_err());
+
+    // Missing changeset.
+    let missing = repo.get_entry_by_path(&node(9), &Path::new("README").unwrap());
+    assert!(missing.wait().is_err());
+
+    let boxed = BoxRepo::<_, Error>::new(repo.clone());
+    let entry = boxed
+        .get_entry_by_path(&node(2), &Path::new("src/main.rs").unwrap())
+        .wait()
+        .unwrap();
+    assert_eq!(entry.map(|e| e.get_path().to_vec()), Some(b"src/main.rs".to_vec()));
+}
+
+#[test]
+fn caching_repo_paths() {
+    let tree = FakeManifest::new()
+        .with_file("README")
+        .with_tree("src", FakeManifest::new().with_file("main.rs"));
+    let inner = FakeRepo::linear(2)
+        .with_manifest(1, tree)
+        .with_manifest(2, FakeManifest::new().with_file("README"));
+    let fetches = inner.manifest_fetches.clone();
+    let repo = CachingRepo::new(inner, 100, 1 << 20);
+
+    let lookup = |manifest, path: &str| {
+        repo.get_hash_by_path(&node(manifest), &Path::new(path).unwrap())
+            .wait()
+            .unwrap()
+    };
+
+    let main = lookup(1, "src/main.rs").expect("file exists");
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    // The second lookup is answered from the cache.
+    assert_eq!(lookup(1, "src/main.rs"), Some(main));
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+    // Distinct paths and manifests miss, including paths which don't exist.
+    assert!(lookup(1, "README").is_some());
+    assert_eq!(lookup(1, "src/lib.rs"), None);
+    assert!(lookup(2, "README").is_some());
+    assert_eq!(fetches.load(Ordering::SeqCst), 4);
+    assert_eq!(lookup(1, "src/lib.rs"), None);
+    assert_eq!(fetches.load(Ordering::SeqCst), 4);
+    assert_eq!(repo.cached_paths(), 4);
+
+    // Equivalent spellings of a path share an entry.
+    assert_eq!(lookup(1, "./src//main.rs"), Some(main));
+    assert_eq!(fetches.load(Ordering::SeqCst), 4);
+
+    // Lookups by changeset share the cache.
+    let path = Path::new("src/main.rs").unwrap();
+    let found = repo.get_hash_by_changeset_path(&node(1), &path).wait();
+    assert_eq!(found.unwrap(), Some(main));
+    assert_eq!(fetches.load(Ordering::SeqCst), 4);
+
+    // Errors aren't cached.
+    assert!(repo.get_hash_by_path(&node(9), &path).wait().is_err());
+    assert!(repo.get_hash_by_path(&node(9), &path).wait().is_err());
+    assert_eq!(fetches.load(Ordering::SeqCst), 6);
+
+    // Everything else goes to the inner repo.
+    let heads = repo.get_changesets().collect().wait().unwrap();
+    assert_eq!(heads, vec![node(1), node(2)]);
+}
+
+#[test]
+fn get_file_history() {
+    let manifest = |file: Option<u8>, other: u8| {
+        let manifest = FakeManifest::new().with_entry("other", Type::File, other);
+        match file {
+            Some(n) => {
+                let dir = FakeManifest::new().with_entry("file", Type::File, n);
+                manifest.with_tree("dir", dir)
+            }
+            None => manifest,
+        }
+    };
+    // The file is added in 2, modified in 3 and 5, and untouched in 4 and 6.
+    let repo = FakeRepo::linear(6)
+        .with_manifest(1, manifest(None, 101))
+        .with_manifest(2, manifest(Some(12), 101))
+        .with_manifest(3, manifest(Some(13), 101))
+        .with_manifest(4, manifest(Some(13), 104))
+        .with_manifest(5, manifest(Some(15), 104))
+        .with_manifest(6, manifest(Some(15), 106));
+    let path = Path::new("dir/file").unwrap();
+
+    let history = |cs, limit| {
+        repo.get_file_history(&node(cs), &path, limit)
+            .collect()
+            .wait()
+            .unwrap()
+    };
+    let expected = vec![
+        (node(5), node(15)),
+        (node(3), node(13)),
+        (node(2), node(12)),
+    ];
+
+    assert_eq!(history(6, 10), expected);
+    assert_eq!(history(5, 10), expected);
+    assert_eq!(history(6, 2), expected[..2].to_vec());
+    assert_eq!(history(4, 10), expected[1..].to_vec());
+    assert!(history(6, 0).is_empty());
+    // The file doesn't exist yet.
+    assert!(history(1, 10).is_empty());
+
+    let boxed = BoxRepo::<_, Error>::new(repo.clone());
+    let found = boxed.get_file_history(&node(6), &path, 10).collect().wait();
+    assert_eq!(found.unwrap(), expected);
+
+    assert!(repo.get_file_history(&node(9), &path, 10).collect().wait().is_err());
+}
+
+#[test]
+fn get_file_history_merge() {
+    let manifest = |n| FakeManifest::new().with_entry("file", Type::File, n);
+    // 4 merges 2 and 3, which both modified the file, and 5 takes 3's version.
+    let repo = FakeRepo::new()
+        .with_changeset(1, &[])
+        .with_changeset(2, &[1])
+        .with_changeset(3, &[1])
+        .with_changeset(4, &[2, 3])
+        .with_changeset(5, &[2, 3])
+        .with_manifest(1, manifest(11))
+        .with_manifest(2, manifest(12))
+        .with_manifest(3, manifest(13))
+        .with_manifest(4, manifest(14))
+        .with_manifest(5, manifest(13));
+    let path = Path::new("file").unwrap();
+    let history = |cs| {
+        repo.get_file_history(&node(cs), &path, 10)
+            .collect()
+            .wait()
+            .unwrap()
+    };
+
+    // A merge which changes the file introduces a version, and the walk follows p1.
+    assert_eq!(
+        history(4),
+        vec![(node(4), node(14)), (node(2), node(12)), (node(1), node(11))]
+    );
+    // A merge which takes one side's version doesn't.
+    assert_eq!(history(5), vec![(node(3), node(13)), (node(1), node(11))]);
+}
+
+#[test]
+fn diff_manifests() {
+    let repo = FakeRepo::linear(2)
+        .with_manifest(
+            1,
+            FakeManifest::new()
+                .with_entry("changed", Type::File, 10)
+                .with_entry("exec", Type::File, 11)
+                .with_entry("removed", Type::File, 12)
+                .with_entry("same", Type::File, 13)
+                .with_entry("was_file", Type::File, 14),
+        )
+        .with_manifest(
+            2,
+            FakeManifest::new()
+                .with_entry("added", Type::Symlink, 20)
+                .with_entry("changed", Type::File, 21)
+                .with_entry("exec", Type::Executable, 11)
+                .with_entry("same", Type::File, 13)
+                .with_entry("was_file", Type::Tree, 22),
+        );
+    let path = |p| Path::new(p).unwrap();
+
+    let diff = repo.diff_manifests(&node(1), &node(2))
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!(
+        diff,
+        vec![
+            ManifestDiffEntry::Added(path("added"), node(20)),
+            ManifestDiffEntry::Modified(path("changed"), node(10), node(21), false),
+            ManifestDiffEntry::Modified(path("exec"), node(11), node(11), true),
+            ManifestDiffEntry::Removed(path("removed")),
+            ManifestDiffEntry::Modified(path("was_file"), node(14), node(22), true),
+        ]
+    );
+
+    // And the other way round, through a BoxRepo.
+    let boxed = BoxRepo::<_, Error>::new(repo);
+    let diff = boxed
+        .diff_manifests(&node(2), &node(1))
+        .map(|entry| entry.path().to_string())
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!(diff, vec!["added", "changed", "exec", "removed", "was_file"]);
+
+    assert!(boxed.diff_manifests(&node(1), &node(1)).collect().wait().unwrap().is_empty());
+    assert!(boxed.diff_manifests(&node(1), &node(9)).collect().wait().is_err());
+}
+
+#[test]
+fn walk_manifest() {
+    let tree = FakeManifest::new()
+        .with_file("README")
+        .with_tree(
+            "src",
+            FakeManifest::new()
+                .with_file("main.rs")
+                .with_tree("bin", FakeManifest::new().with_file("tool.rs"))
+                .with_tree("empty", FakeManifest::new()),
+        )
+        .with_tree("docs", FakeManifest::new().with_file("index.md"));
+    let repo = FakeRepo::linear(1).with_manifest(1, tree);
+
+    let walked = repo.walk_manifest(&node(1))
+        .map(|(path, entry)| {
+            assert_eq!(entry.get_type(), Type::File);
+            path.to_string()
+        })
+        .collect()
+        .wait()
+        .unwrap();
+    // Breadth first, and in manifest order within each level.
+    assert_eq!(
+        walked,
+        vec!["README", "docs/index.md", "src/main.rs", "src/bin/tool.rs"]
+    );
+
+    let boxed = BoxRepo::<_, Error>::new(repo);
+    assert_eq!(boxed.walk_manifest(&node(1)).collect().wait().unwrap().len(), 4);
+    assert!(boxed.walk_manifest(&node(9)).collect().wait().is_err());
+}
+
+#[test]
+fn walk_manifest_too_deep() {
+    let leaf = FakeManifest::new().with_file("leaf");
+    let tree = (0..manifest::MAX_WALK_DEPTH + 1)
+        .fold(leaf, |tree, _| FakeManifest::new().with_tree("d", tree));
+    let repo = FakeRepo::linear(1).with_manifest(1, tree);
+
+    match repo.walk_manifest(&node(1)).collect().wait() {
+        Err(Error(ErrorKind::ManifestTooDeep(_), _)) => (),
+        Err(err) => panic!("unexpected error {:?}", err),
+        Ok(_) => panic!("unexpected success"),
+    }
+}
+
+#[test]
+fn manifest_stats() {
+    let tree = FakeManifest::new()
+        .with_file("README")
+        .with_tree(
+            "src",
+            FakeManifest::new()
+                .with_file("main.rs")
+                .with_tree("bin", FakeManifest::new().with_file("tool.rs"))
+                .with_tree("empty", FakeManifest::new()),
+        )
+        .with_tree("docs", FakeManifest::new().with_file("index.md"));
+    let repo = FakeRepo::linear(1).with_manifest(1, tree);
+
+    let expected = ManifestStats {
+        file_count: 4,
+        dir_count: 4,
+        total_size: 6 + 7 + 7 + 8,
+    };
+    assert_eq!(repo.manifest_stats(&node(1)).wait().unwrap(), expected);
+
+    let boxed = BoxRepo::<_, Error>::new(repo);
+    assert_eq!(boxed.manifest_stats(&node(1)).wait().unwrap(), expected);
+    assert!(boxed.manifest_stats(&node(9)).wait().is_err());
+}
+
+#[test]
+fn manifest_stats_too_deep() {
+    let leaf = FakeManifest::new().with_file("leaf");
+    let tree = (0..manifest::MAX_WALK_DEPTH + 1)
+        .fold(leaf, |tree, _| FakeManifest::new().with_tree("d", tree));
+    let repo = FakeRepo::linear(1).with_manifest(1, tree);
+
+    match repo.manifest_stats(&node(1)).wait() {
+        Err(Error(ErrorKind::ManifestTooDeep(_), _)) => (),
+        Err(err) => panic!("unexpected error {:?}", err),
+        Ok(_) => panic!("unexpected success"),
+    }
+}
+
+#[test]
+fn verify_reachable() {
+    let repo = FakeRepo::linear(3)
+        .with_manifest(1, FakeManifest::new().with_entry("a", Type::File, 10))
+        .with_manifest(
+            3,
+            FakeManifest::new()
+                .with_entry("a", Type::File, 10)
+                .with_entry("b", Type::Symlink, 11),
+        );
+
+    // Fake manifests have the same ids as their changesets. Changeset 2 has no manifest, and
+    // file node 10 is only checked the first time it's reached.
+    let expected = vec![
+        VerifyEvent::Ok(node(3)),
+        VerifyEvent::Ok(node(3)),
+        VerifyEvent::Ok(node(10)),
+        VerifyEvent::Ok(node(11)),
+        VerifyEvent::Missing(node(9)),
+        VerifyEvent::Ok(node(2)),
+        VerifyEvent::Missing(node(2)),
+        VerifyEvent::Ok(node(1)),
+        VerifyEvent::Ok(node(1)),
+    ];
+    let events = repo.verify_reachable(&[node(3), node(9)])
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!(events, expected);
+
+    let boxed = BoxRepo::<_, Error>::new(repo);
+    let events = boxed
+        .verify_reachable(&[node(3), node(9)])
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!(events, expected);
+}
+
+#[test]
+fn export_bundle() {
+    let repo = (1..7).fold(branchy(), |repo, n| {
+        repo.with_manifest(n, FakeManifest::new().with_entry("f", Type::File, n))
+    });
+
+    let chunks = repo.export_bundle(&[node(6)], &[node(3)])
+        .collect()
+        .wait()
+        .unwrap();
+    let data = chunks.concat();
+    let records = export::parse(&data).unwrap();
+
+    // The header is a chunk of its own, followed by one chunk per record.
+    assert_eq!(chunks.len(), records.len() + 1);
+    assert_eq!(&chunks[0][..4], export::EXPORT_MAGIC);
+    for (chunk, record) in chunks[1..].iter().zip(&records) {
+        assert_eq!(chunk.len(), 25 + record.payload.len());
+    }
+
+    // Parents first, each changeset followed by its manifest and the file it changed.
+    let mut expected = Vec::new();
+    for n in 4..7 {
+        expected.push((export::RecordKind::Changeset, node(n)));
+        expected.push((export::RecordKind::Manifest, node(n)));
+        expected.push((export::RecordKind::File, node(n)));
+    }
+    expected.push((export::RecordKind::End, NULL_HASH));
+    let kinds: Vec<_> = records
+        .iter()
+        .map(|record| (record.kind, record.nodeid))
+        .collect();
+    assert_eq!(kinds, expected);
+    assert_eq!(&records[1].payload[..], format!("f\0{}\n", node(4)).as_bytes());
+    assert_eq!(&records[2].payload[..], b"f");
+    // The changeset payload starts with its parents.
+    assert_eq!(&records[6].payload[..20], node(3).sha1().as_ref());
+    assert_eq!(&records[6].payload[20..40], node(5).sha1().as_ref());
+
+    // Without anything in common, everything is exported.
+    let boxed = BoxRepo::<_, Error>::new(repo);
+    let data: Vec<u8> = boxed
+        .export_bundle(&[node(6)], &[])
+        .collect()
+        .wait()
+        .unwrap()
+        .concat();
+    let records = export::parse(&data).unwrap();
+    assert_eq!(records.len(), 6 * 3 + 1);
+
+    assert!(boxed.export_bundle(&[node(9)], &[]).collect().wait().is_err());
+}
+
+#[test]
+fn export_parse_invalid() {
+    let repo = FakeRepo::linear(1).with_manifest(1, FakeManifest::new().with_file("f"));
+    let data: Vec<u8> = repo.export_bundle(&[node(1)], &[])
+        .collect()
+        .wait()
+        .unwrap()
+        .concat();
+    assert!(export::parse(&data).is_ok());
+
+    let check = |data: &[u8], msg: &str| match export::parse(data) {
+        Err(Error(ErrorKind::InvalidExport(ref m), _)) => assert_eq!(m, msg),
+        Err(err) => panic!("unexpected error {:?}", err),
+        Ok(_) => panic!("unexpected success"),
+    };
+
+    check(&data[..6], "missing header");
+    let mut bad_version = data.clone();
+    bad_version[7] = 2;
+    check(&bad_version, "unsupported version 2");
+    // Dropping the end record leaves the export truncated.
+    check(&data[..data.len() - 25], "record 3: truncated header");
+    check(&data[..data.len() - 26], "record 2: truncated payload");
+    let mut extra = data.clone();
+    extra.push(0);
+    check(&extra, "data after end record");
+}
+
+#[test]
+fn shutdown() {
+    let repo = FakeRepo::linear(1);
+    let shut_down = repo.shut_down.clone();
+
+    let boxed = BoxRepo::<_, Error>::new(repo);
+    assert!(!shut_down.load(Ordering::SeqCst));
+    boxed.shutdown().wait().unwrap();
+    assert!(shut_down.load(Ordering::SeqCst));
+
+    // Through trait objects.
+    let repo = FakeRepo::linear(1);
+    let shut_down = repo.shut_down.clone();
+    let boxed: Box<Repo<Error = Error>> = Box::new(repo);
+    boxed.shutdown().wait().unwrap();
+    assert!(shut_down.load(Ordering::SeqCst));
+
+    let repo = FakeRepo::linear(1);
+    let shut_down = repo.shut_down.clone();
+    let shared: Arc<Repo<Error = Error>> = Arc::new(repo);
+    shared.shutdown().wait().unwrap();
+    assert!(shut_down.load(Ordering::SeqCst));
+}
+
+#[test]
+fn shutdown_default() {
+    let repo = FakeRepo::linear(1);
+    let shut_down = repo.shut_down.clone();
+
+    let repo = DefaultShutdownRepo(repo);
+    assert!(repo.shutdown().wait().is_ok());
+    // The default doesn't reach the wrapped repo's own shutdown.
+    assert!(!shut_down.load(Ordering::SeqCst));
+}
+
+#[test]
+fn blocking_repo() {
+    let src = FakeManifest::new().with_entry("main.rs", Type::File, 7);
+    let repo = branchy().with_manifest(1, FakeManifest::new().with_tree("src", src));
+    let blocking = BlockingRepo::new(repo.clone());
+
+    assert_eq!(
+        blocking.get_changesets_blocking().unwrap(),
+        repo.get_changesets().collect().wait().unwrap()
+    );
+    assert_eq!(
+        blocking.get_heads_blocking().unwrap(),
+        repo.get_heads().collect().wait().unwrap()
+    );
+    assert!(blocking.changeset_exists_blocking(&node(1)).unwrap());
+    assert!(!blocking.changeset_exists_blocking(&node(99)).unwrap());
+    assert_eq!(
+        blocking.get_changeset_parents_blocking(&node(2)).unwrap(),
+        repo.get_changeset_parents(&node(2)).wait().unwrap()
+    );
+    assert_eq!(
+        blocking.common_ancestor_blocking(&node(3), &node(5)).unwrap(),
+        repo.common_ancestor(&node(3), &node(5)).wait().unwrap()
+    );
+
+    let path = Path::new("src/main.rs").unwrap();
+    let entry = blocking.get_entry_by_path_blocking(&node(1), &path).unwrap();
+    assert_eq!(entry.map(|e| *e.get_hash()), Some(node(7)));
+    let walked = blocking.walk_manifest_blocking(&node(1)).unwrap();
+    assert_eq!(walked.len(), 1);
+
+    // Errors are returned rather than panicking.
+    assert!(blocking.get_changeset_by_nodeid_blocking(&node(99)).is_err());
+}
+
+/// Timer which expires immediately, recording the durations it was asked for.
+#[derive(Clone, Default)]
+struct ImmediateTimer(Arc<Mutex<Vec<Duration>>>);
+
+impl Timer for ImmediateTimer {
+    fn sleep(&self, duration: Duration) -> BoxFuture<(), ()> {
+        self.0.lock().unwrap().push(duration);
+        future::ok(()).boxed()
+    }
+}
+
+#[test]
+fn timeout_repo() {
+    let timer = ImmediateTimer::default();
+    let duration = Duration::from_secs(5);
+    let repo = TimeoutRepo::new(FakeRepo::linear(3).with_stalled(2), timer.clone(), duration);
+
+    // Operations which complete straight away aren't affected.
+    assert!(repo.changeset_exists(&node(2)).wait().unwrap());
+    let parents = repo.get_changeset_parents(&node(3)).wait().unwrap();
+    assert_eq!(parents, Parents::One(node(2)));
+    match repo.get_changeset_by_nodeid(&node(9)).wait() {
+        Err(TimeoutRepoError::Inner(err)) => {
+            assert_eq!(err.to_string(), format!("changeset {} missing", node(9)))
+        }
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(_) => panic!("missing changeset was found"),
+    }
+
+    match repo.get_changeset_by_nodeid(&node(2)).wait() {
+        Err(TimeoutRepoError::Timeout(operation)) => {
+            assert_eq!(operation, "get_changeset_by_nodeid")
+        }
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(_) => panic!("stalled changeset was found"),
+    }
+    assert!(timer.0.lock().unwrap().iter().all(|d| *d == duration));
+
+    // Streams produce everything that's available, and time out once they stall.
+    let mut changesets = repo.get_changesets().wait();
+    for n in 1..4 {
+        assert_eq!(changesets.next().unwrap().unwrap(), node(n));
+    }
+    match changesets.next() {
+        Some(Err(TimeoutRepoError::Timeout(operation))) => {
+            assert_eq!(operation, "get_changesets")
+        }
+        _ => panic!("stalled stream didn't time out"),
+    }
+    assert!(changesets.next().is_none());
+}
+
+#[test]
+fn timeout_repo_thread_timer() {
+    let duration = Duration::from_millis(20);
+    let repo = TimeoutRepo::new(FakeRepo::linear(3).with_stalled(2), ThreadTimer, duration);
+
+    let start = Instant::now();
+    let res = repo.get_changeset_by_nodeid(&node(2)).wait();
+    assert!(start.elapsed() >= duration);
+    match res {
+        Err(TimeoutRepoError::Timeout(_)) => {}
+        _ => panic!("stalled changeset didn't time out"),
+    }
+}
+
+#[test]
+fn cancellable_repo() {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let repo = CancellableRepo::new(FakeRepo::linear(5), cancelled.clone());
+
+    let mut changesets = repo.get_changesets().wait();
+    let mut heads = repo.get_heads().wait();
+    for n in 1..3 {
+        assert_eq!(changesets.next().unwrap().unwrap(), node(n));
+    }
+
+    // Streams in progress end without producing the rest of their items, and streams started
+    // afterwards are empty. Futures still work.
+    cancelled.store(true, Ordering::SeqCst);
+    assert!(repo.is_cancelled());
+    assert!(changesets.next().is_none());
+    assert!(changesets.next().is_none());
+    assert!(heads.next().is_none());
+    assert!(repo.get_changesets().collect().wait().unwrap().is_empty());
+    assert!(repo.changeset_exists(&node(5)).wait().unwrap());
+}
+
+#[test]
+fn cancellable_repo_stalled() {
+    let repo = CancellableRepo::new(
+        FakeRepo::linear(3).with_stalled(2),
+        Arc::new(AtomicBool::new(false)),
+    );
+
+    // Without cancelling, this stream would never end.
+    let mut changesets = repo.get_changesets().wait();
+    for n in 1..4 {
+        assert_eq!(changesets.next().unwrap().unwrap(), node(n));
+    }
+    repo.cancel();
+    assert!(changesets.next().is_none());
+}
+
+#[test]
+fn tracing_repo() {
+    let repo = TracingRepo::new(branchy());
+    let start = SystemTime::now();
+
+    assert!(repo.changeset_exists(&node(2)).wait().unwrap());
+    assert!(!repo.changeset_exists(&node(9)).wait().unwrap());
+    let heads = repo.get_heads().collect().wait().unwrap();
+    assert_eq!(heads, branchy().get_heads().collect().wait().unwrap());
+    assert!(repo.is_ancestor(&node(1), &node(3)).wait().unwrap());
+
+    let trace = repo.trace();
+    let calls: Vec<_> = trace
+        .iter()
+        .map(|event| (event.method, event.nodeids.clone()))
+        .collect();
+    assert_eq!(
+        calls,
+        vec![
+            ("changeset_exists", vec![node(2)]),
+            ("changeset_exists", vec![node(9)]),
+            ("get_heads", vec![]),
+            ("is_ancestor", vec![node(1), node(3)]),
+        ]
+    );
+    assert!(trace[0].time >= start);
+    assert!(trace.windows(2).all(|pair| pair[0].time <= pair[1].time));
+
+    // Errors are passed through unchanged, and still traced.
+    assert!(repo.get_changeset_by_nodeid(&node(9)).wait().is_err());
+    assert_eq!(repo.take_trace().len(), 5);
+    assert!(repo.trace().is_empty());
+}
+
+#[test]
+fn manifest_lookup_name() {
+    let manifest = FakeManifest::new()
+        .with_entry("README", Type::File, 1)
+        .with_entry("bin", Type::Executable, 2)
+        .with_entry("src", Type::Tree, 3)
+        .with_entry("src/lib.rs", Type::File, 4);
+    let lookup = |manifest: &Manifest<Error = Error>, name: &[u8]| {
+        manifest
+            .lookup_name(name)
+            .wait()
+            .unwrap()
+            .map(|entry| (entry.get_type(), *entry.get_hash()))
+    };
+
+    assert_eq!(lookup(&manifest, b"README"), Some((Type::File, node(1))));
+    assert_eq!(lookup(&manifest, b"bin"), Some((Type::Executable, node(2))));
+    assert_eq!(lookup(&manifest, b"src"), Some((Type::Tree, node(3))));
+    // Entries keyed by a full path, as in a flat manifest, are found by it.
+    assert_eq!(lookup(&manifest, b"src/lib.rs"), Some((Type::File, node(4))));
+    assert_eq!(lookup(&manifest, b"readme"), None);
+    assert_eq!(lookup(&manifest, b"lib.rs"), None);
+    assert_eq!(lookup(&manifest, b""), None);
+
+    // Boxed manifests pass it through.
+    let boxed = manifest.boxed();
+    assert_eq!(lookup(&boxed, b"bin"), Some((Type::Executable, node(2))));
+    assert_eq!(lookup(&boxed, b"missing"), None);
+}
@@ -0,0 +1,424 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Repo wrapper which bounds how long each operation can take.
+
+use std::error;
+use std::fmt::{self, Display};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::{Async, Future, Poll, Stream};
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::sync::oneshot;
+
+use blobnode::Parents;
+use bookmarks::{self, Version};
+use changeset::Changeset;
+use manifest::{BoxEntry, BoxManifest, Entry, Manifest, ManifestDiffEntry, ManifestStats};
+use nodehash::NodeHash;
+use path::Path;
+use repo::{BoxedBookmarks, HashResolution, Repo};
+use verify::VerifyEvent;
+
+/// Source of the deadlines used by `TimeoutRepo`.
+pub trait Timer: Send + Sync + 'static {
+    /// Return a future which completes after `duration`.
+    fn sleep(&self, duration: Duration) -> BoxFuture<(), ()>;
+}
+
+/// Sleep on a new thread for each deadline, so that `TimeoutRepo` works without any particular
+/// event loop. Servers with a timer of their own should use that instead.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ThreadTimer;
+
+impl Timer for ThreadTimer {
+    fn sleep(&self, duration: Duration) -> BoxFuture<(), ()> {
+        let (tx, rx) = oneshot::channel();
+        thread::spawn(move || {
+            thread::sleep(duration);
+            let _ = tx.send(());
+        });
+        rx.map_err(|_| ()).boxed()
+    }
+}
+
+#[derive(Debug)]
+pub enum TimeoutRepoError<E> {
+    /// The named `Repo` method took too long.
+    Timeout(&'static str),
+    /// The inner repo failed.
+    Inner(E),
+}
+
+impl<E: Display> Display for TimeoutRepoError<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &TimeoutRepoError::Timeout(operation) => write!(fmt, "{} timed out", operation),
+            &TimeoutRepoError::Inner(ref err) => err.fmt(fmt),
+        }
+    }
+}
+
+impl<E: error::Error> error::Error for TimeoutRepoError<E> {
+    fn description(&self) -> &str {
+        match self {
+            &TimeoutRepoError::Timeout(_) => "repo operation timed out",
+            &TimeoutRepoError::Inner(ref err) => err.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match self {
+            &TimeoutRepoError::Timeout(_) => None,
+            &TimeoutRepoError::Inner(ref err) => Some(err),
+        }
+    }
+}
+
+/// Future which fails with `TimeoutRepoError::Timeout` if the inner future doesn't complete
+/// before the timer does.
+pub struct Timeout<F> {
+    inner: F,
+    timer: BoxFuture<(), ()>,
+    operation: &'static str,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Item = F::Item;
+    type Error = TimeoutRepoError<F::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll() {
+            Ok(Async::NotReady) => {}
+            Ok(Async::Ready(item)) => return Ok(Async::Ready(item)),
+            Err(err) => return Err(TimeoutRepoError::Inner(err)),
+        }
+
+        // A timer which fails can't fire, so it's as if there were no timeout.
+        match self.timer.poll() {
+            Ok(Async::Ready(())) => Err(TimeoutRepoError::Timeout(self.operation)),
+            Ok(Async::NotReady) | Err(()) => Ok(Async::NotReady),
+        }
+    }
+}
+
+/// Stream which fails with `TimeoutRepoError::Timeout` if the inner stream goes for too long
+/// without producing anything. The stream ends after a timeout.
+pub struct TimeoutStream<S> {
+    inner: S,
+    timer: Arc<Timer>,
+    duration: Duration,
+    /// Deadline for the next item, started when the inner stream is first not ready.
+    deadline: Option<BoxFuture<(), ()>>,
+    operation: &'static str,
+    timed_out: bool,
+}
+
+impl<S: Stream> Stream for TimeoutStream<S> {
+    type Item = S::Item;
+    type Error = TimeoutRepoError<S::Error>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.timed_out {
+            return Ok(Async::Ready(None));
+        }
+
+        match self.inner.poll() {
+            Ok(Async::NotReady) => {}
+            Ok(Async::Ready(item)) => {
+                self.deadline = None;
+                return Ok(Async::Ready(item));
+            }
+            Err(err) => {
+                self.deadline = None;
+                return Err(TimeoutRepoError::Inner(err));
+            }
+        }
+
+        if self.deadline.is_none() {
+            self.deadline = Some(self.timer.sleep(self.duration));
+        }
+        match self.deadline.as_mut().expect("deadline just set").poll() {
+            Ok(Async::Ready(())) => {
+                self.timed_out = true;
+                Err(TimeoutRepoError::Timeout(self.operation))
+            }
+            Ok(Async::NotReady) | Err(()) => Ok(Async::NotReady),
+        }
+    }
+}
+
+/// Repo which fails any operation that takes longer than a fixed duration with
+/// `TimeoutRepoError::Timeout`, naming the `Repo` method. Futures are limited in their total
+/// duration, while streams are only limited in how long they can go between items.
+///
+/// Only the futures and streams returned by `Repo` methods themselves are limited, not those
+/// from the bookmarks, manifests and entries they return.
+pub struct TimeoutRepo<R> {
+    repo: R,
+    timer: Arc<Timer>,
+    duration: Duration,
+}
+
+impl<R> TimeoutRepo<R>
+where
+    R: Repo,
+{
+    /// Wrap `repo`, using `timer` to limit each operation to `duration`.
+    pub fn new<T: Timer>(repo: R, timer: T, duration: Duration) -> Self {
+        TimeoutRepo {
+            repo,
+            timer: Arc::new(timer),
+            duration,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.repo
+    }
+
+    fn future<F>(
+        &self,
+        operation: &'static str,
+        inner: F,
+    ) -> BoxFuture<F::Item, TimeoutRepoError<R::Error>>
+    where
+        F: Future<Error = R::Error> + Send + 'static,
+        F::Item: Send + 'static,
+    {
+        Timeout {
+            inner,
+            timer: self.timer.sleep(self.duration),
+            operation,
+        }.boxed()
+    }
+
+    fn stream<S>(
+        &self,
+        operation: &'static str,
+        inner: S,
+    ) -> BoxStream<S::Item, TimeoutRepoError<R::Error>>
+    where
+        S: Stream<Error = R::Error> + Send + 'static,
+        S::Item: Send + 'static,
+    {
+        TimeoutStream {
+            inner,
+            timer: self.timer.clone(),
+            duration: self.duration,
+            deadline: None,
+            operation,
+            timed_out: false,
+        }.boxed()
+    }
+}
+
+impl<R> Repo for TimeoutRepo<R>
+where
+    R: Repo,
+{
+    type Error = TimeoutRepoError<R::Error>;
+
+    fn get_changesets(&self) -> BoxStream<NodeHash, Self::Error> {
+        self.stream("get_changesets", self.repo.get_changesets())
+    }
+
+    fn get_changesets_range(&self, skip: usize, limit: usize) -> BoxStream<NodeHash, Self::Error> {
+        self.stream(
+            "get_changesets_range",
+            self.repo.get_changesets_range(skip, limit),
+        )
+    }
+
+    fn get_changesets_sorted(&self) -> BoxStream<NodeHash, Self::Error> {
+        self.stream("get_changesets_sorted", self.repo.get_changesets_sorted())
+    }
+
+    fn get_changesets_bounded(&self, max_depth: usize) -> BoxStream<NodeHash, Self::Error> {
+        self.stream(
+            "get_changesets_bounded",
+            self.repo.get_changesets_bounded(max_depth),
+        )
+    }
+
+    fn get_changeset_count(&self) -> BoxFuture<u64, Self::Error> {
+        self.future("get_changeset_count", self.repo.get_changeset_count())
+    }
+
+    fn get_heads(&self) -> BoxStream<NodeHash, Self::Error> {
+        self.stream("get_heads", self.repo.get_heads())
+    }
+
+    fn get_bookmarks(&self) -> Result<BoxedBookmarks<Self::Error>, Self::Error> {
+        let bookmarks = self.repo
+            .get_bookmarks()
+            .map_err(TimeoutRepoError::Inner)?;
+
+        Ok(bookmarks::BoxedBookmarks::new_cvt(
+            bookmarks,
+            TimeoutRepoError::Inner,
+        ))
+    }
+
+    fn get_bookmark(&self, name: &[u8]) -> BoxFuture<Option<(NodeHash, Version)>, Self::Error> {
+        self.future("get_bookmark", self.repo.get_bookmark(name))
+    }
+
+    fn get_heads_for_bookmarks(&self, names: &[Vec<u8>]) -> BoxStream<NodeHash, Self::Error> {
+        self.stream(
+            "get_heads_for_bookmarks",
+            self.repo.get_heads_for_bookmarks(names),
+        )
+    }
+
+    fn prefetch(&self, nodeids: &[NodeHash]) -> BoxFuture<(), Self::Error> {
+        self.future("prefetch", self.repo.prefetch(nodeids))
+    }
+
+    fn changeset_exists(&self, nodeid: &NodeHash) -> BoxFuture<bool, Self::Error> {
+        self.future("changeset_exists", self.repo.changeset_exists(nodeid))
+    }
+
+    fn changesets_exist(
+        &self,
+        nodeids: &[NodeHash],
+    ) -> BoxFuture<Vec<(NodeHash, bool)>, Self::Error> {
+        self.future("changesets_exist", self.repo.changesets_exist(nodeids))
+    }
+
+    fn get_changeset_by_nodeid(&self, nodeid: &NodeHash) -> BoxFuture<Box<Changeset>, Self::Error> {
+        self.future(
+            "get_changeset_by_nodeid",
+            self.repo.get_changeset_by_nodeid(nodeid),
+        )
+    }
+
+    fn get_changesets_by_nodeid(
+        &self,
+        nodeids: &[NodeHash],
+    ) -> BoxFuture<Vec<Box<Changeset>>, Self::Error> {
+        self.future(
+            "get_changesets_by_nodeid",
+            self.repo.get_changesets_by_nodeid(nodeids),
+        )
+    }
+
+    fn get_changeset_raw(&self, nodeid: &NodeHash) -> BoxFuture<Bytes, Self::Error> {
+        self.future("get_changeset_raw", self.repo.get_changeset_raw(nodeid))
+    }
+
+    fn resolve_hash_prefix(&self, prefix: &str) -> BoxFuture<HashResolution, Self::Error> {
+        self.future("resolve_hash_prefix", self.repo.resolve_hash_prefix(prefix))
+    }
+
+    fn get_changeset_parents(&self, nodeid: &NodeHash) -> BoxFuture<Parents, Self::Error> {
+        self.future(
+            "get_changeset_parents",
+            self.repo.get_changeset_parents(nodeid),
+        )
+    }
+
+    fn is_ancestor(
+        &self,
+        ancestor: &NodeHash,
+        descendant: &NodeHash,
+    ) -> BoxFuture<bool, Self::Error> {
+        self.future("is_ancestor", self.repo.is_ancestor(ancestor, descendant))
+    }
+
+    fn common_ancestor(
+        &self,
+        a: &NodeHash,
+        b: &NodeHash,
+    ) -> BoxFuture<Option<NodeHash>, Self::Error> {
+        self.future("common_ancestor", self.repo.common_ancestor(a, b))
+    }
+
+    fn get_manifest_by_nodeid(
+        &self,
+        nodeid: &NodeHash,
+    ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
+        let manifest = self.repo
+            .get_manifest_by_nodeid(nodeid)
+            .map(|m| BoxManifest::new_with_cvterr(m, TimeoutRepoError::Inner));
+        self.future("get_manifest_by_nodeid", manifest)
+    }
+
+    fn get_root_manifest(
+        &self,
+        cs: &NodeHash,
+    ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
+        let manifest = self.repo
+            .get_root_manifest(cs)
+            .map(|m| BoxManifest::new_with_cvterr(m, TimeoutRepoError::Inner));
+        self.future("get_root_manifest", manifest)
+    }
+
+    fn diff_manifests(
+        &self,
+        base: &NodeHash,
+        other: &NodeHash,
+    ) -> BoxStream<ManifestDiffEntry, Self::Error> {
+        self.stream("diff_manifests", self.repo.diff_manifests(base, other))
+    }
+
+    fn get_entry_by_path(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+    ) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Self::Error> {
+        let entry = self.repo
+            .get_entry_by_path(cs, path)
+            .map(|oe| oe.map(|e| BoxEntry::new_with_cvterr(e, TimeoutRepoError::Inner)));
+        self.future("get_entry_by_path", entry)
+    }
+
+    fn get_file_history(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+        limit: usize,
+    ) -> BoxStream<(NodeHash, NodeHash), Self::Error> {
+        self.stream(
+            "get_file_history",
+            self.repo.get_file_history(cs, path, limit),
+        )
+    }
+
+    fn walk_manifest(
+        &self,
+        root: &NodeHash,
+    ) -> BoxStream<(Path, Box<Entry<Error = Self::Error>>), Self::Error> {
+        let entries = self.repo
+            .walk_manifest(root)
+            .map(|(path, e)| (path, BoxEntry::new_with_cvterr(e, TimeoutRepoError::Inner)));
+        self.stream("walk_manifest", entries)
+    }
+
+    fn manifest_stats(&self, root: &NodeHash) -> BoxFuture<ManifestStats, Self::Error> {
+        self.future("manifest_stats", self.repo.manifest_stats(root))
+    }
+
+    fn export_bundle(
+        &self,
+        heads: &[NodeHash],
+        common: &[NodeHash],
+    ) -> BoxStream<Bytes, Self::Error> {
+        self.stream("export_bundle", self.repo.export_bundle(heads, common))
+    }
+
+    fn verify_reachable(&self, heads: &[NodeHash]) -> BoxStream<VerifyEvent, Self::Error> {
+        self.stream("verify_reachable", self.repo.verify_reachable(heads))
+    }
+
+    fn shutdown(&self) -> BoxFuture<(), Self::Error> {
+        self.future("shutdown", self.repo.shutdown())
+    }
+}
@@ -0,0 +1,278 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Repo wrapper which records every call made to it, for debugging.
+
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+
+use blobnode::Parents;
+use bookmarks::Version;
+use changeset::Changeset;
+use manifest::{Entry, Manifest, ManifestDiffEntry, ManifestStats};
+use nodehash::NodeHash;
+use path::Path;
+use repo::{BoxedBookmarks, HashResolution, Repo};
+use verify::VerifyEvent;
+
+/// One call to a `TracingRepo`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceEvent {
+    /// Name of the `Repo` method called.
+    pub method: &'static str,
+    /// The changeset, manifest or other node hashes passed to it, in argument order.
+    pub nodeids: Vec<NodeHash>,
+    /// When the call was made.
+    pub time: SystemTime,
+}
+
+/// Repo which records a `TraceEvent` for each `Repo` method called on it, before passing the
+/// call straight through to the inner repo, to show which operations led to which fetches. The
+/// trace can be read with `trace` once the operation is done.
+///
+/// Events are recorded when the method is called, not when its future or stream is polled, and
+/// calls made to the bookmarks, manifests and entries it returns aren't recorded.
+pub struct TracingRepo<R> {
+    repo: R,
+    trace: Arc<Mutex<Vec<TraceEvent>>>,
+}
+
+impl<R> TracingRepo<R>
+where
+    R: Repo,
+{
+    pub fn new(repo: R) -> Self {
+        TracingRepo {
+            repo,
+            trace: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.repo
+    }
+
+    /// Return a copy of the events recorded so far, in the order the calls were made.
+    pub fn trace(&self) -> Vec<TraceEvent> {
+        self.trace.lock().expect("lock poison").clone()
+    }
+
+    /// Return the events recorded so far, and start a new trace.
+    pub fn take_trace(&self) -> Vec<TraceEvent> {
+        mem::replace(&mut *self.trace.lock().expect("lock poison"), Vec::new())
+    }
+
+    /// Return the trace itself, for reading while the repo is still in use elsewhere.
+    pub fn shared_trace(&self) -> Arc<Mutex<Vec<TraceEvent>>> {
+        self.trace.clone()
+    }
+
+    fn record(&self, method: &'static str, nodeids: &[NodeHash]) {
+        let event = TraceEvent {
+            method,
+            nodeids: nodeids.to_vec(),
+            time: SystemTime::now(),
+        };
+        self.trace.lock().expect("lock poison").push(event);
+    }
+}
+
+impl<R> Repo for TracingRepo<R>
+where
+    R: Repo,
+{
+    type Error = R::Error;
+
+    fn get_changesets(&self) -> BoxStream<NodeHash, Self::Error> {
+        self.record("get_changesets", &[]);
+        self.repo.get_changesets()
+    }
+
+    fn get_changesets_range(&self, skip: usize, limit: usize) -> BoxStream<NodeHash, Self::Error> {
+        self.record("get_changesets_range", &[]);
+        self.repo.get_changesets_range(skip, limit)
+    }
+
+    fn get_changesets_sorted(&self) -> BoxStream<NodeHash, Self::Error> {
+        self.record("get_changesets_sorted", &[]);
+        self.repo.get_changesets_sorted()
+    }
+
+    fn get_changesets_bounded(&self, max_depth: usize) -> BoxStream<NodeHash, Self::Error> {
+        self.record("get_changesets_bounded", &[]);
+        self.repo.get_changesets_bounded(max_depth)
+    }
+
+    fn get_changeset_count(&self) -> BoxFuture<u64, Self::Error> {
+        self.record("get_changeset_count", &[]);
+        self.repo.get_changeset_count()
+    }
+
+    fn get_heads(&self) -> BoxStream<NodeHash, Self::Error> {
+        self.record("get_heads", &[]);
+        self.repo.get_heads()
+    }
+
+    fn get_bookmarks(&self) -> Result<BoxedBookmarks<Self::Error>, Self::Error> {
+        self.record("get_bookmarks", &[]);
+        self.repo.get_bookmarks()
+    }
+
+    fn get_bookmark(&self, name: &[u8]) -> BoxFuture<Option<(NodeHash, Version)>, Self::Error> {
+        self.record("get_bookmark", &[]);
+        self.repo.get_bookmark(name)
+    }
+
+    fn get_heads_for_bookmarks(&self, names: &[Vec<u8>]) -> BoxStream<NodeHash, Self::Error> {
+        self.record("get_heads_for_bookmarks", &[]);
+        self.repo.get_heads_for_bookmarks(names)
+    }
+
+    fn prefetch(&self, nodeids: &[NodeHash]) -> BoxFuture<(), Self::Error> {
+        self.record("prefetch", nodeids);
+        self.repo.prefetch(nodeids)
+    }
+
+    fn changeset_exists(&self, nodeid: &NodeHash) -> BoxFuture<bool, Self::Error> {
+        self.record("changeset_exists", &[*nodeid]);
+        self.repo.changeset_exists(nodeid)
+    }
+
+    fn changesets_exist(
+        &self,
+        nodeids: &[NodeHash],
+    ) -> BoxFuture<Vec<(NodeHash, bool)>, Self::Error> {
+        self.record("changesets_exist", nodeids);
+        self.repo.changesets_exist(nodeids)
+    }
+
+    fn get_changeset_by_nodeid(&self, nodeid: &NodeHash) -> BoxFuture<Box<Changeset>, Self::Error> {
+        self.record("get_changeset_by_nodeid", &[*nodeid]);
+        self.repo.get_changeset_by_nodeid(nodeid)
+    }
+
+    fn get_changesets_by_nodeid(
+        &self,
+        nodeids: &[NodeHash],
+    ) -> BoxFuture<Vec<Box<Changeset>>, Self::Error> {
+        self.record("get_changesets_by_nodeid", nodeids);
+        self.repo.get_changesets_by_nodeid(nodeids)
+    }
+
+    fn get_changeset_raw(&self, nodeid: &NodeHash) -> BoxFuture<Bytes, Self::Error> {
+        self.record("get_changeset_raw", &[*nodeid]);
+        self.repo.get_changeset_raw(nodeid)
+    }
+
+    fn resolve_hash_prefix(&self, prefix: &str) -> BoxFuture<HashResolution, Self::Error> {
+        self.record("resolve_hash_prefix", &[]);
+        self.repo.resolve_hash_prefix(prefix)
+    }
+
+    fn get_changeset_parents(&self, nodeid: &NodeHash) -> BoxFuture<Parents, Self::Error> {
+        self.record("get_changeset_parents", &[*nodeid]);
+        self.repo.get_changeset_parents(nodeid)
+    }
+
+    fn is_ancestor(
+        &self,
+        ancestor: &NodeHash,
+        descendant: &NodeHash,
+    ) -> BoxFuture<bool, Self::Error> {
+        self.record("is_ancestor", &[*ancestor, *descendant]);
+        self.repo.is_ancestor(ancestor, descendant)
+    }
+
+    fn common_ancestor(
+        &self,
+        a: &NodeHash,
+        b: &NodeHash,
+    ) -> BoxFuture<Option<NodeHash>, Self::Error> {
+        self.record("common_ancestor", &[*a, *b]);
+        self.repo.common_ancestor(a, b)
+    }
+
+    fn get_manifest_by_nodeid(
+        &self,
+        nodeid: &NodeHash,
+    ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
+        self.record("get_manifest_by_nodeid", &[*nodeid]);
+        self.repo.get_manifest_by_nodeid(nodeid)
+    }
+
+    fn get_root_manifest(
+        &self,
+        cs: &NodeHash,
+    ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
+        self.record("get_root_manifest", &[*cs]);
+        self.repo.get_root_manifest(cs)
+    }
+
+    fn diff_manifests(
+        &self,
+        base: &NodeHash,
+        other: &NodeHash,
+    ) -> BoxStream<ManifestDiffEntry, Self::Error> {
+        self.record("diff_manifests", &[*base, *other]);
+        self.repo.diff_manifests(base, other)
+    }
+
+    fn get_entry_by_path(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+    ) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Self::Error> {
+        self.record("get_entry_by_path", &[*cs]);
+        self.repo.get_entry_by_path(cs, path)
+    }
+
+    fn get_file_history(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+        limit: usize,
+    ) -> BoxStream<(NodeHash, NodeHash), Self::Error> {
+        self.record("get_file_history", &[*cs]);
+        self.repo.get_file_history(cs, path, limit)
+    }
+
+    fn walk_manifest(
+        &self,
+        root: &NodeHash,
+    ) -> BoxStream<(Path, Box<Entry<Error = Self::Error>>), Self::Error> {
+        self.record("walk_manifest", &[*root]);
+        self.repo.walk_manifest(root)
+    }
+
+    fn manifest_stats(&self, root: &NodeHash) -> BoxFuture<ManifestStats, Self::Error> {
+        self.record("manifest_stats", &[*root]);
+        self.repo.manifest_stats(root)
+    }
+
+    fn export_bundle(
+        &self,
+        heads: &[NodeHash],
+        common: &[NodeHash],
+    ) -> BoxStream<Bytes, Self::Error> {
+        self.record("export_bundle", &[heads, common].concat());
+        self.repo.export_bundle(heads, common)
+    }
+
+    fn verify_reachable(&self, heads: &[NodeHash]) -> BoxStream<VerifyEvent, Self::Error> {
+        self.record("verify_reachable", heads);
+        self.repo.verify_reachable(heads)
+    }
+
+    fn shutdown(&self) -> BoxFuture<(), Self::Error> {
+        self.record("shutdown", &[]);
+        self.repo.shutdown()
+    }
+}
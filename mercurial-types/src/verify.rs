@@ -0,0 +1,216 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Consistency checking of everything reachable from a set of heads, for `fsck`-style tools.
+//!
+//! The walk goes from each changeset to its root manifest, then to every file node the manifest
+//! refers to, and produces a `VerifyEvent` for each node as it's checked. Problems with the data
+//! are reported as events rather than ending the walk, so that one run finds all of them.
+
+use std::collections::{HashSet, VecDeque};
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use futures::{Async, Poll};
+use futures::future::{self, BoxFuture, Future};
+use futures::stream::{self, BoxStream, Stream};
+
+use changeset::Changeset;
+use manifest::Entry;
+use nodehash::NodeHash;
+use repo::Repo;
+
+/// The result of checking one changeset, manifest or file node.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum VerifyEvent {
+    /// The node exists and is intact.
+    Ok(NodeHash),
+    /// The node, or data it needs such as a file's content, doesn't exist.
+    Missing(NodeHash),
+    /// The node's data doesn't match the hash it's stored under.
+    HashMismatch(NodeHash),
+}
+
+/// Check the changesets reachable from `heads`, their root manifests and the file nodes in
+/// those manifests, producing a `VerifyEvent` for each as it goes.
+///
+/// Changesets are visited breadth-first from the heads, and each changeset's event is followed
+/// by those for its manifest and files. Each node is only checked once, however many changesets
+/// refer to it. The parents of a missing changeset can't be found, so aren't visited through it.
+///
+/// File nodes are checked with `check_file`, so that implementations can verify content
+/// hashes with whatever their storage provides; `check_content` is a generic fallback. The
+/// `Repo` interface doesn't distinguish missing manifests from other failures, so any error
+/// walking a manifest, including one of its subdirectories being missing, reports the manifest
+/// as `Missing`. Other errors, such as a changeset which exists failing to load, end the stream.
+pub fn verify_reachable<R, F>(
+    repo: R,
+    heads: Vec<NodeHash>,
+    check_file: F,
+) -> BoxStream<VerifyEvent, R::Error>
+where
+    R: Repo + Clone + Send,
+    F: Fn(Box<Entry<Error = R::Error>>) -> BoxFuture<VerifyEvent, R::Error>
+        + Send
+        + Sync
+        + 'static,
+{
+    let check_file = Arc::new(check_file);
+    // Manifests and file nodes which have already been checked.
+    let checked = Arc::new(Mutex::new(HashSet::new()));
+
+    let mut seen = HashSet::new();
+    let pending: VecDeque<_> = heads.into_iter().filter(|head| seen.insert(*head)).collect();
+
+    let walk = ChangesetWalk {
+        repo: repo.clone(),
+        pending,
+        seen,
+        state: WalkState::Idle,
+    };
+
+    walk.map(move |(csid, manifestid)| match manifestid {
+        None => stream::once(Ok(VerifyEvent::Missing(csid))).boxed(),
+        Some(manifestid) => {
+            let first = checked.lock().expect("lock poison").insert(manifestid);
+            let manifest = if first {
+                verify_manifest(repo.clone(), manifestid, check_file.clone(), checked.clone())
+            } else {
+                stream::empty().boxed()
+            };
+            stream::once(Ok(VerifyEvent::Ok(csid)))
+                .chain(manifest)
+                .boxed()
+        }
+    }).flatten()
+        .boxed()
+}
+
+/// Check a file node by fetching its content, which is reported as `Missing` if that fails.
+/// This can't detect corrupt content; implementations which can should do so in the function
+/// they pass to `verify_reachable`.
+pub fn check_content<E>(entry: Box<Entry<Error = E>>) -> BoxFuture<VerifyEvent, E>
+where
+    E: Send + 'static,
+{
+    let nodeid = *entry.get_hash();
+    entry
+        .get_content()
+        .then(move |res| match res {
+            Ok(_) => Ok(VerifyEvent::Ok(nodeid)),
+            Err(_) => Ok(VerifyEvent::Missing(nodeid)),
+        })
+        .boxed()
+}
+
+/// Produce the events for manifest `manifestid` and the file nodes in it which haven't been
+/// checked yet.
+fn verify_manifest<R, F>(
+    repo: R,
+    manifestid: NodeHash,
+    check_file: Arc<F>,
+    checked: Arc<Mutex<HashSet<NodeHash>>>,
+) -> BoxStream<VerifyEvent, R::Error>
+where
+    R: Repo + Send,
+    F: Fn(Box<Entry<Error = R::Error>>) -> BoxFuture<VerifyEvent, R::Error>
+        + Send
+        + Sync
+        + 'static,
+{
+    repo.walk_manifest(&manifestid)
+        .map(|(_, entry)| entry)
+        .collect()
+        .then(move |res| {
+            let entries = match res {
+                Ok(entries) => entries,
+                Err(_) => return Ok(stream::once(Ok(VerifyEvent::Missing(manifestid))).boxed()),
+            };
+
+            let unchecked: Vec<_> = {
+                let mut checked = checked.lock().expect("lock poison");
+                entries
+                    .into_iter()
+                    .filter(|entry| checked.insert(*entry.get_hash()))
+                    .collect()
+            };
+            let files = stream::iter(unchecked.into_iter().map(Ok))
+                .and_then(move |entry| check_file(entry));
+            Ok(stream::once(Ok(VerifyEvent::Ok(manifestid)))
+                .chain(files)
+                .boxed())
+        })
+        .flatten_stream()
+        .boxed()
+}
+
+enum WalkState<E> {
+    Idle,
+    Fetching(NodeHash, BoxFuture<Option<Box<Changeset>>, E>),
+}
+
+/// Breadth-first walk of the changesets reachable from some heads, producing each one's id
+/// with its manifest id, or `None` if it doesn't exist.
+struct ChangesetWalk<R: Repo> {
+    repo: R,
+    pending: VecDeque<NodeHash>,
+    seen: HashSet<NodeHash>,
+    state: WalkState<R::Error>,
+}
+
+impl<R> ChangesetWalk<R>
+where
+    R: Repo + Clone + Send,
+{
+    /// Fetch changeset `csid`, resolving to `None` if it doesn't exist. The existence check is
+    /// only made if fetching it fails, to avoid loading every changeset twice.
+    fn fetch(&self, csid: NodeHash) -> BoxFuture<Option<Box<Changeset>>, R::Error> {
+        let repo = self.repo.clone();
+        self.repo
+            .get_changeset_by_nodeid(&csid)
+            .then(move |res| match res {
+                Ok(cs) => future::ok(Some(cs)).boxed(),
+                Err(err) => repo.changeset_exists(&csid)
+                    .and_then(move |exists| if exists { Err(err) } else { Ok(None) })
+                    .boxed(),
+            })
+            .boxed()
+    }
+}
+
+impl<R> Stream for ChangesetWalk<R>
+where
+    R: Repo + Clone + Send,
+{
+    type Item = (NodeHash, Option<NodeHash>);
+    type Error = R::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            match mem::replace(&mut self.state, WalkState::Idle) {
+                WalkState::Idle => match self.pending.pop_front() {
+                    Some(csid) => self.state = WalkState::Fetching(csid, self.fetch(csid)),
+                    None => return Ok(Async::Ready(None)),
+                },
+                WalkState::Fetching(csid, mut fut) => match fut.poll()? {
+                    Async::NotReady => {
+                        self.state = WalkState::Fetching(csid, fut);
+                        return Ok(Async::NotReady);
+                    }
+                    Async::Ready(None) => return Ok(Async::Ready(Some((csid, None)))),
+                    Async::Ready(Some(cs)) => {
+                        for parent in cs.parents() {
+                            if self.seen.insert(parent) {
+                                self.pending.push_back(parent);
+                            }
+                        }
+                        return Ok(Async::Ready(Some((csid, Some(*cs.manifestid())))));
+                    }
+                },
+            }
+        }
+    }
+}
@@ -100,22 +100,34 @@ fn unescape<'a, S: IntoIterator<Item = &'a u8>>(s: S) -> Vec<u8> {
     ret
 }
 
-impl Extra {
-    fn from_slice<S: AsRef<[u8]>>(s: Option<S>) -> Result<Extra> {
-        let mut ret = BTreeMap::new();
-
-        if let Some(s) = s {
-            let s = s.as_ref();
+/// Decode the extras field of a changeset: `key:value` pairs separated by `\0`, with `\\`,
+/// `\0` and `\n` escaped in both keys and values. If any pair is malformed, the whole field
+/// is treated as empty.
+pub fn parse_extras(s: &[u8]) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    let mut ret = BTreeMap::new();
+    if s.is_empty() {
+        return ret;
+    }
 
-            for kv in s.split(|c| *c == b'\0') {
-                let kv: Vec<_> = kv.splitn(2, |c| *c == b':').collect();
-                if kv.len() == 2 {
-                    ret.insert(unescape(kv[0]), unescape(kv[1]));
-                }
-            }
+    for kv in s.split(|c| *c == b'\0') {
+        let kv: Vec<_> = kv.splitn(2, |c| *c == b':').collect();
+        if kv.len() != 2 {
+            return BTreeMap::new();
         }
+        ret.insert(unescape(kv[0]), unescape(kv[1]));
+    }
+
+    ret
+}
+
+impl Extra {
+    fn from_slice<S: AsRef<[u8]>>(s: Option<S>) -> Result<Extra> {
+        let extra = match s {
+            Some(s) => parse_extras(s.as_ref()),
+            None => BTreeMap::new(),
+        };
 
-        Ok(Extra(ret))
+        Ok(Extra(extra))
     }
 
     pub fn generate<W: Write>(&self, out: &mut W) -> io::Result<()> {
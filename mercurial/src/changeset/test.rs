@@ -9,9 +9,9 @@ use std::str;
 
 use quickcheck::{QuickCheck, TestResult};
 
-use mercurial_types::{Blob, BlobNode, NodeHash, Path};
+use mercurial_types::{Blob, BlobNode, Changeset, NodeHash, Path};
 
-use changeset::{escape, unescape, Extra, RevlogChangeset, Time};
+use changeset::{escape, parse_extras, unescape, Extra, RevlogChangeset, Time};
 
 const CHANGESET: &[u8] = include_bytes!("cset.bin");
 const CHANGESETBLOB: Blob<&[u8]> = Blob::Dirty(CHANGESET);
@@ -66,6 +66,50 @@ fn test_generate() {
     assert_eq!(new, CHANGESET);
 }
 
+fn extras(kv: &[(&str, &[u8])]) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    kv.iter()
+        .map(|&(k, v)| (k.as_bytes().to_vec(), v.to_vec()))
+        .collect()
+}
+
+#[test]
+fn test_parse_extras() {
+    assert_eq!(parse_extras(b"branch:stable"), extras(&[("branch", b"stable")]));
+    assert_eq!(
+        parse_extras(b"branch:default\0rebase_source:0849d280663e\0close:1"),
+        extras(&[
+            ("branch", b"default"),
+            ("close", b"1"),
+            ("rebase_source", b"0849d280663e"),
+        ])
+    );
+    // Values can contain colons, and escaped newlines, NULs and backslashes.
+    assert_eq!(
+        parse_extras(b"note:a:b\\nc\\0d\\\\e"),
+        extras(&[("note", b"a:b\nc\0d\\e")])
+    );
+
+    assert!(parse_extras(b"").is_empty());
+    // Malformed fields are ignored entirely.
+    assert!(parse_extras(b"branch:stable\0garbage").is_empty());
+    assert!(parse_extras(b"\0").is_empty());
+}
+
+#[test]
+fn test_parse_extras_changeset() {
+    let text = [
+        &b"497522ef3706a1665bf4140497c65b467454e962\nuser\n"[..],
+        &b"0 0 branch:stable\0amend_source:a\\\\b\n\ncomment"[..],
+    ].concat();
+    let node = BlobNode::new(Blob::Dirty(text.as_slice()), None, None);
+    let cset = RevlogChangeset::parse(node).expect("parsed");
+
+    assert_eq!(
+        cset.extra(),
+        &extras(&[("amend_source", b"a\\b"), ("branch", b"stable")])
+    );
+}
+
 quickcheck! {
     fn escape_roundtrip(s: Vec<u8>) -> bool {
         let esc = escape(&s);
@@ -7,6 +7,7 @@
 #![deny(warnings)]
 
 // External dependencies
+extern crate bytes;
 extern crate flate2;
 extern crate futures;
 
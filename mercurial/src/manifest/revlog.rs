@@ -231,6 +231,17 @@ impl Manifest for RevlogManifest {
         ).map(|e| e.boxed())
             .boxed()
     }
+
+    fn lookup_name(
+        &self,
+        name: &[u8],
+    ) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Self::Error> {
+        // Entries are kept sorted by path, so there's no need to scan them.
+        match Path::new(name) {
+            Ok(path) => Manifest::lookup(self, &path),
+            Err(_) => Ok(None).into_future().boxed(),
+        }
+    }
 }
 
 impl Entry for RevlogEntry {
@@ -285,6 +296,7 @@ impl Entry for RevlogEntry {
                 },
                 Content::Symlink(path) => Ok(Some(path.len())),
                 Content::Tree(_) => Ok(None),
+                Content::LfsPointer { size, .. } => Ok(Some(size as usize)),
             })
             .boxed()
     }
@@ -495,7 +495,7 @@ impl RevlogInner {
             }
         }
 
-        delta::compat::apply_deltas(data.as_ref(), chain);
+        data = delta::compat::try_apply_deltas(data.as_ref(), chain)?;
 
         Ok(data)
     }
@@ -543,7 +543,7 @@ impl RevlogInner {
             }
         });
 
-        data = delta::compat::apply_deltas(data.as_ref(), chain);
+        data = delta::compat::try_apply_deltas(data.as_ref(), chain)?;
 
         Ok(data)
     }
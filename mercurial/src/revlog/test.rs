@@ -17,3 +17,81 @@ fn emptyrev() {
 
     assert_eq!(node.size(), Some(0));
 }
+
+fn be_u32(v: u32) -> [u8; 4] {
+    [(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]
+}
+
+// An inline RevlogNG index entry for `rev`, whose parent is the previous rev, followed by its
+// chunk.
+fn inline_entry(rev: u32, baserev: u32, len: usize, chunk: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::new();
+    if rev == 0 {
+        // The header (INLINE, RevlogNG) overlays the first entry's offset
+        entry.extend_from_slice(&[0, 1, 0, 1, 0, 0]);
+    } else {
+        entry.extend_from_slice(&[0; 6]);
+    }
+    entry.extend_from_slice(&[0, 0]); // flags
+    entry.extend_from_slice(&be_u32(chunk.len() as u32));
+    entry.extend_from_slice(&be_u32(len as u32));
+    entry.extend_from_slice(&be_u32(baserev));
+    entry.extend_from_slice(&be_u32(rev)); // linkrev
+    entry.extend_from_slice(&be_u32(if rev == 0 { !0 } else { rev - 1 })); // p1
+    entry.extend_from_slice(&be_u32(!0)); // p2
+    entry.extend_from_slice(&[rev as u8 + 1; 20]);
+    entry.extend_from_slice(&[0; 12]);
+    entry.extend_from_slice(chunk);
+    entry
+}
+
+#[test]
+fn simple_delta_chain() {
+    // rev 0 is literal; revs 1 and 2 are uncompressed ('u') deltas, each against the rev before
+    // it, with rev 0 as the base of the chain.
+    let mut idx = Vec::new();
+    idx.extend(inline_entry(0, 0, 15, b"uaaaa\nbbbb\ncccc\n"));
+    idx.extend(inline_entry(
+        1,
+        0,
+        15,
+        b"u\x00\x00\x00\x05\x00\x00\x00\x0a\x00\x00\x00\x05xxxx\n",
+    ));
+    idx.extend(inline_entry(
+        2,
+        0,
+        20,
+        b"u\x00\x00\x00\x0f\x00\x00\x00\x0f\x00\x00\x00\x05dddd\n",
+    ));
+
+    let revlog = Revlog::new(idx, None).expect("construction failed");
+    assert!(!revlog.get_header().features.contains(parser::GENERAL_DELTA));
+
+    let text = |rev: u32| {
+        let node = revlog
+            .get_rev(RevIdx::from(rev))
+            .expect("failed to get rev");
+        node.as_blob().as_slice().expect("no data").to_vec()
+    };
+
+    assert_eq!(text(0), b"aaaa\nbbbb\ncccc\n".to_vec());
+    assert_eq!(text(1), b"aaaa\nxxxx\ncccc\n".to_vec());
+    assert_eq!(text(2), b"aaaa\nxxxx\ncccc\ndddd\n".to_vec());
+}
+
+#[test]
+fn simple_delta_chain_corrupt() {
+    // rev 1's delta has its start past its end.
+    let mut idx = Vec::new();
+    idx.extend(inline_entry(0, 0, 15, b"uaaaa\nbbbb\ncccc\n"));
+    idx.extend(inline_entry(
+        1,
+        0,
+        15,
+        b"u\x00\x00\x00\x0a\x00\x00\x00\x05\x00\x00\x00\x05xxxx\n",
+    ));
+
+    let revlog = Revlog::new(idx, None).expect("construction failed");
+    assert!(revlog.get_rev(RevIdx::from(0u32)).is_ok());
+    assert!(revlog.get_rev(RevIdx::from(1u32)).is_err());
+}
@@ -5,7 +5,7 @@
 // GNU General Public License version 2 or any later version.
 
 use std::collections::HashSet;
-use std::collections::hash_map::{Entry, HashMap};
+use std::collections::hash_map::{self, HashMap};
 use std::fmt::{self, Display};
 use std::fs;
 use std::io::{BufRead, BufReader};
@@ -13,13 +13,16 @@ use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 
+use bytes::Bytes;
 use futures::{Async, Future, IntoFuture, Poll, Stream};
 use futures::future::{self, BoxFuture};
 use futures::stream::{self, BoxStream};
 
 use asyncmemo::Filler;
 use bookmarks::{Bookmarks, BoxedBookmarks, Version};
-use mercurial_types::{BlobNode, Changeset, Manifest, NodeHash, Path, Repo};
+use mercurial_types::{BlobNode, Changeset, Entry, Manifest, ManifestStats, NodeHash, Parents, Path,
+                      Repo};
+use mercurial_types::{HashResolution, VerifyEvent, ancestors, export, repo, verify};
 use stockbookmarks::StockBookmarks;
 
 pub use changeset::RevlogChangeset;
@@ -173,6 +176,18 @@ impl RevlogRepo {
             .into_future()
     }
 
+    /// Return a changeset's parents straight from the changelog index, without reading or
+    /// parsing the changeset itself.
+    pub fn get_changeset_parents(&self, nodeid: &NodeHash) -> FutureResult<Parents> {
+        let inner = self.inner.lock().expect("poisoned lock");
+
+        inner
+            .changelog
+            .get_node_by_nodeid(nodeid, false)
+            .map(|node| *node.parents())
+            .into_future()
+    }
+
     pub fn get_changeset_by_nodeid(&self, nodeid: &NodeHash) -> BoxFuture<RevlogChangeset, Error> {
         // TODO: (jsgf) T17932873 distinguish between not existing vs some other error
         self.get_changeset_blob_by_nodeid(nodeid)
@@ -210,9 +225,9 @@ impl RevlogRepo {
 
     fn get_file_revlog_inner(&self, inner: &mut RevlogInner, path: &Path) -> Result<Revlog> {
         match inner.filelogcache.entry(path.clone()) {
-            Entry::Occupied(log) => Ok(log.get().clone()),
+            hash_map::Entry::Occupied(log) => Ok(log.get().clone()),
 
-            Entry::Vacant(missing) => {
+            hash_map::Entry::Vacant(missing) => {
                 let dotencode = self.requirements.contains(&Required::Dotencode);
                 let mut path = self.basepath
                     .join("store")
@@ -338,6 +353,45 @@ impl Repo for RevlogRepo {
             .boxed()
     }
 
+    fn get_changeset_raw(&self, nodeid: &NodeHash) -> BoxFuture<Bytes, Self::Error> {
+        self.get_changeset_blob_by_nodeid(nodeid)
+            .and_then(|node| {
+                node.as_blob()
+                    .as_slice()
+                    .map(Bytes::from)
+                    .ok_or("changeset has no data".into())
+            })
+            .boxed()
+    }
+
+    fn get_changesets_bounded(&self, max_depth: usize) -> BoxStream<NodeHash, Self::Error> {
+        ancestors::recent(self.clone(), max_depth)
+    }
+
+    fn resolve_hash_prefix(&self, prefix: &str) -> BoxFuture<HashResolution, Self::Error> {
+        repo::resolve_hash_prefix(self, prefix)
+    }
+
+    fn get_changeset_parents(&self, nodeid: &NodeHash) -> BoxFuture<Parents, Self::Error> {
+        RevlogRepo::get_changeset_parents(self, nodeid).boxed()
+    }
+
+    fn is_ancestor(
+        &self,
+        ancestor: &NodeHash,
+        descendant: &NodeHash,
+    ) -> BoxFuture<bool, Self::Error> {
+        ancestors::is_ancestor(self.clone(), *ancestor, *descendant, None)
+    }
+
+    fn common_ancestor(
+        &self,
+        a: &NodeHash,
+        b: &NodeHash,
+    ) -> BoxFuture<Option<NodeHash>, Self::Error> {
+        ancestors::common_ancestor(self.clone(), *a, *b, None)
+    }
+
     fn get_manifest_by_nodeid(
         &self,
         nodeid: &NodeHash,
@@ -346,4 +400,51 @@ impl Repo for RevlogRepo {
             .map(|m| m.boxed())
             .boxed()
     }
+
+    fn get_root_manifest(
+        &self,
+        cs: &NodeHash,
+    ) -> BoxFuture<Box<Manifest<Error = Self::Error> + Sync>, Self::Error> {
+        repo::get_root_manifest(self.clone(), *cs)
+    }
+
+    fn get_entry_by_path(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+    ) -> BoxFuture<Option<Box<Entry<Error = Self::Error>>>, Self::Error> {
+        repo::get_entry_by_path(self.clone(), *cs, path.clone())
+    }
+
+    fn get_file_history(
+        &self,
+        cs: &NodeHash,
+        path: &Path,
+        limit: usize,
+    ) -> BoxStream<(NodeHash, NodeHash), Self::Error> {
+        ancestors::file_history(self.clone(), *cs, path.clone(), limit)
+    }
+
+    fn walk_manifest(
+        &self,
+        root: &NodeHash,
+    ) -> BoxStream<(Path, Box<Entry<Error = Self::Error>>), Self::Error> {
+        repo::walk_manifest(self, root)
+    }
+
+    fn manifest_stats(&self, root: &NodeHash) -> BoxFuture<ManifestStats, Self::Error> {
+        repo::manifest_stats(self, root)
+    }
+
+    fn export_bundle(
+        &self,
+        heads: &[NodeHash],
+        common: &[NodeHash],
+    ) -> BoxStream<Bytes, Self::Error> {
+        export::export(self.clone(), heads.to_vec(), common.to_vec())
+    }
+
+    fn verify_reachable(&self, heads: &[NodeHash]) -> BoxStream<VerifyEvent, Self::Error> {
+        verify::verify_reachable(self.clone(), heads.to_vec(), verify::check_content)
+    }
 }